@@ -21,6 +21,7 @@ use thiserror::Error;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
+use gstprintnanny::control;
 use printnanny_settings::cam::{
     CameraVideoSource, MediaVideoSource, PrintNannyCamSettings, VideoSource, VideoSrcType,
 };
@@ -63,6 +64,14 @@ pub struct PipelineApp {
 }
 
 impl PipelineApp {
+    // Identifies the video source in dataframe metadata
+    fn camera_id(&self) -> String {
+        match &self.settings.video_src {
+            VideoSource::Uri(src) | VideoSource::File(src) => src.uri.clone(),
+            VideoSource::CSI(src) | VideoSource::USB(src) => src.device_name.clone(),
+        }
+    }
+
     async fn make_common_pipeline(&self) -> Result<gst::Pipeline, Error> {
         let start = SystemTime::now();
         let ts = start
@@ -434,10 +443,15 @@ impl PipelineApp {
             .name("dataframe_agg__df")
             .property("filter-threshold", nms_threshold as f32 / 100_f32)
             .property_from_str("output-type", "json")
+            .property("model-file", &tflite_model_file)
+            .property("label-file", &tflite_label_file)
+            .property("camera-id", self.camera_id())
             .build()?;
 
         let nats_sink = gst::ElementFactory::make("nats_sink")
+            .name("nats_sink__df")
             .property("nats-address", &nats_server_uri)
+            .property("camera-id", self.camera_id())
             .build()?;
 
         let df_elements = &[
@@ -570,6 +584,16 @@ impl PipelineApp {
         Ok(pipeline)
     }
 
+    // Control requests are answered in the background. The pipeline runs without the control plane until the NATS server is reachable
+    fn serve_control(&self, pipeline: &gst::Pipeline, subject: &str) {
+        let nats_server_uri = &self.settings.nats_server_uri;
+        control::serve(pipeline.upcast_ref(), nats_server_uri, subject);
+        info!(
+            "Serving control requests on {} {}",
+            nats_server_uri, subject
+        );
+    }
+
     pub async fn create_pipeline(&self) -> Result<gst::Pipeline, Error> {
         gst::init()?;
 
@@ -713,6 +737,12 @@ async fn main() {
                 .takes_value(false)
                 .help("Show preview using autovideosink"),
         )
+        .arg(
+            Arg::new("control_subject")
+                .long("control-subject")
+                .takes_value(true)
+                .help("Answer get/set property requests published to this NATS subject, to update element properties without restarting the pipeline"),
+        )
         .arg(
             Arg::new("nats_server_uri")
                 .long("--nats-server-uri")
@@ -878,7 +908,14 @@ async fn main() {
         None => PipelineApp::from(&args),
     };
 
-    match app.create_pipeline().await.and_then(run) {
+    let control_subject = args.value_of("control_subject");
+    let pipeline = app.create_pipeline().await.map(|pipeline| {
+        if let Some(subject) = control_subject {
+            app.serve_control(&pipeline, subject);
+        }
+        pipeline
+    });
+    match pipeline.and_then(run) {
         Ok(r) => r,
         Err(e) => error!("Error running pipeline: {:?}", e),
     }
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+
+use polars::export::arrow::datatypes::Schema as ArrowSchema;
+
+use crate::ipc::IpcCompression;
+
+// Media types of serialized dataframes
+pub const ARROW_STREAM_MEDIA_TYPE: &str = "application/vnd.apache.arrow.stream";
+pub const ARROW_FILE_MEDIA_TYPE: &str = "application/vnd.apache.arrow.file";
+pub const PARQUET_MEDIA_TYPE: &str = "application/vnd.apache.parquet";
+pub const JSON_MEDIA_TYPE: &str = "application/json";
+pub const NDJSON_MEDIA_TYPE: &str = "application/x-ndjson";
+// nnstreamer's tensor_decoder mode=custom-code always outputs application/octet-stream, so sinks accept it and detect the format from buffer contents
+pub const LEGACY_MEDIA_TYPE: &str = "application/octet-stream";
+
+// Caps fields
+pub const COMPRESSION_FIELD: &str = "compression"; // arrow ipc body compression: none, lz4, zstd
+pub const SCHEMA_FINGERPRINT_FIELD: &str = "schema-fingerprint"; // see schema_fingerprint()
+pub const FRAMING_FIELD: &str = "framing";
+pub const FRAMING_LENGTH_PREFIXED: &str = "length-prefixed";
+pub const LAYOUT_FIELD: &str = "layout"; // json layout, absent for row-oriented json
+pub const LAYOUT_COLUMNS: &str = "columns";
+pub const METADATA_FIELD: &str = "metadata"; // schema metadata of json output, which has no schema to carry it
+
+// Caps of all serialized dataframe formats, used by src pad templates
+pub fn dataframe_caps() -> gst::Caps {
+    gst::Caps::builder_full()
+        .structure(gst::Structure::new_empty(ARROW_STREAM_MEDIA_TYPE))
+        .structure(gst::Structure::new_empty(ARROW_FILE_MEDIA_TYPE))
+        .structure(gst::Structure::new_empty(PARQUET_MEDIA_TYPE))
+        .structure(gst::Structure::new_empty(JSON_MEDIA_TYPE))
+        .structure(gst::Structure::new_empty(NDJSON_MEDIA_TYPE))
+        .build()
+}
+
+// Caps accepted by dataframe sink pads: all dataframe formats, and legacy application/octet-stream
+pub fn dataframe_sink_caps() -> gst::Caps {
+    let mut caps = dataframe_caps();
+    caps.make_mut()
+        .append_structure(gst::Structure::new_empty(LEGACY_MEDIA_TYPE));
+    caps
+}
+
+pub fn arrow_stream_caps(compression: IpcCompression, fingerprint: &str) -> gst::Caps {
+    gst::Caps::builder(ARROW_STREAM_MEDIA_TYPE)
+        .field(COMPRESSION_FIELD, compression.nick())
+        .field(SCHEMA_FINGERPRINT_FIELD, fingerprint)
+        .build()
+}
+
+// Add schema metadata to caps as a structure field, for formats without a schema
+pub fn caps_with_metadata(mut caps: gst::Caps, metadata: &BTreeMap<String, String>) -> gst::Caps {
+    if metadata.is_empty() {
+        return caps;
+    }
+    let mut structure = gst::Structure::new_empty(METADATA_FIELD);
+    for (key, value) in metadata {
+        structure.set(key.as_str(), value);
+    }
+    caps.make_mut().set(METADATA_FIELD, structure);
+    caps
+}
+
+// Schema metadata added by caps_with_metadata, empty if caps have no metadata field
+pub fn metadata_from_caps(caps: &gst::CapsRef) -> BTreeMap<String, String> {
+    caps.structure(0)
+        .and_then(|s| s.get::<gst::Structure>(METADATA_FIELD).ok())
+        .map(|metadata| {
+            metadata
+                .iter()
+                .filter_map(|(key, value)| {
+                    value
+                        .get::<String>()
+                        .ok()
+                        .map(|value| (key.to_string(), value))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// FNV-1a 64-bit hash of field names, data types and nullability, formatted as hex
+// Schema metadata is not included, so dataframes with identical columns share a fingerprint
+pub fn schema_fingerprint(schema: &ArrowSchema) -> String {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let hash = schema
+        .fields
+        .iter()
+        .map(|field| {
+            format!(
+                "{}:{:?}:{};",
+                field.name, field.data_type, field.is_nullable
+            )
+        })
+        .flat_map(|field| field.into_bytes())
+        .fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+        });
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::*;
+
+    #[test]
+    fn test_schema_fingerprint() {
+        let df = df!(
+            "x0" => vec![0; 10],
+            "x1" => vec![1; 10]
+        )
+        .unwrap();
+        let other = df!(
+            "x0" => vec![0.0; 10],
+            "x1" => vec![1; 10]
+        )
+        .unwrap();
+
+        let fingerprint = schema_fingerprint(&df.schema().to_arrow());
+        assert_eq!(fingerprint.len(), 16);
+        assert_eq!(fingerprint, schema_fingerprint(&df.schema().to_arrow()));
+        // metadata does not change fingerprint
+        let metadata = BTreeMap::from([("model".to_string(), "test".to_string())]);
+        assert_eq!(
+            fingerprint,
+            schema_fingerprint(&df.schema().to_arrow().with_metadata(metadata))
+        );
+        assert_ne!(fingerprint, schema_fingerprint(&other.schema().to_arrow()));
+    }
+
+    #[test]
+    fn test_caps_with_metadata() {
+        gst::init().unwrap();
+
+        let metadata = BTreeMap::from([
+            ("printnanny:camera_id".to_string(), "camera0".to_string()),
+            ("frame_rate_n".to_string(), "15".to_string()),
+        ]);
+        let caps = caps_with_metadata(gst::Caps::new_empty_simple(JSON_MEDIA_TYPE), &metadata);
+        assert_eq!(caps.structure(0).unwrap().name(), JSON_MEDIA_TYPE);
+        assert_eq!(metadata_from_caps(&caps), metadata);
+        // caps survive serialization, for example in gst-launch
+        let caps: gst::Caps = caps.to_string().parse().unwrap();
+        assert_eq!(metadata_from_caps(&caps), metadata);
+
+        let caps = caps_with_metadata(
+            gst::Caps::new_empty_simple(JSON_MEDIA_TYPE),
+            &BTreeMap::new(),
+        );
+        assert!(metadata_from_caps(&caps).is_empty());
+    }
+}
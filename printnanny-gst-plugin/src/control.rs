@@ -0,0 +1,457 @@
+use std::thread;
+use std::time::Duration;
+
+use gst::glib;
+use gst::prelude::*;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ControlError;
+use crate::nats_common::reconnect_delay;
+
+const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "printnanny_control",
+        gst::DebugColorFlags::empty(),
+        Some("PrintNanny pipeline control plane"),
+    )
+});
+
+// Request addressed to an element of the pipeline by name, for example
+// {"op": "set", "element": "dataframe_agg__df", "property": "filter-threshold", "value": 0.6}
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub enum ControlRequest {
+    Get {
+        element: String,
+        property: String,
+    },
+    Set {
+        element: String,
+        property: String,
+        value: serde_json::Value,
+    },
+}
+
+// Replies carry the property's value after the request was applied, or an error
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ControlReply {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<Result<serde_json::Value, ControlError>> for ControlReply {
+    fn from(result: Result<serde_json::Value, ControlError>) -> Self {
+        match result {
+            Ok(value) => ControlReply {
+                ok: true,
+                value: Some(value),
+                error: None,
+            },
+            Err(err) => ControlReply {
+                ok: false,
+                value: None,
+                error: Some(err.to_string()),
+            },
+        }
+    }
+}
+
+fn find_property(
+    bin: &gst::Bin,
+    element: &str,
+    property: &str,
+) -> Result<(gst::Element, glib::ParamSpec), ControlError> {
+    let element = bin
+        .by_name(element)
+        .ok_or_else(|| ControlError::UnknownElement(element.to_string()))?;
+    let pspec = element.find_property(property).ok_or_else(|| {
+        ControlError::UnknownProperty(element.name().to_string(), property.to_string())
+    })?;
+    Ok((element, pspec))
+}
+
+// Convert json to a value of the property's type. Enums accept nicks, names or numbers, other types without a json representation (caps, structures) accept their serialized string form
+fn value_from_json(
+    pspec: &glib::ParamSpec,
+    json: &serde_json::Value,
+) -> Result<glib::Value, ControlError> {
+    let invalid = |reason: String| ControlError::InvalidValue(pspec.name().to_string(), reason);
+    let value_type = pspec.value_type();
+    let value = if value_type == bool::static_type() {
+        json.as_bool().map(|v| v.to_value())
+    } else if value_type == i32::static_type() {
+        json.as_i64()
+            .and_then(|v| i32::try_from(v).ok())
+            .map(|v| v.to_value())
+    } else if value_type == u32::static_type() {
+        json.as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .map(|v| v.to_value())
+    } else if value_type == i64::static_type() {
+        json.as_i64().map(|v| v.to_value())
+    } else if value_type == u64::static_type() {
+        json.as_u64().map(|v| v.to_value())
+    } else if value_type == f32::static_type() {
+        json.as_f64().map(|v| (v as f32).to_value())
+    } else if value_type == f64::static_type() {
+        json.as_f64().map(|v| v.to_value())
+    } else if value_type == String::static_type() {
+        match json {
+            serde_json::Value::Null => Some(None::<String>.to_value()),
+            json => json.as_str().map(|v| v.to_value()),
+        }
+    } else if value_type.is_a(glib::Type::ENUM) {
+        let class = glib::EnumClass::new(value_type)
+            .ok_or_else(|| invalid(format!("{} is not an enum", value_type)))?;
+        match json {
+            serde_json::Value::String(v) => class
+                .value_by_nick(v)
+                .or_else(|| class.value_by_name(v))
+                .map(|v| v.value()),
+            json => json.as_i64().and_then(|v| i32::try_from(v).ok()),
+        }
+        .and_then(|v| class.to_value(v))
+    } else {
+        json.as_str()
+            .and_then(|v| glib::Value::deserialize(v, value_type).ok())
+    }
+    .ok_or_else(|| invalid(format!("{} is not a valid {}", json, value_type)))?;
+
+    macro_rules! check_range {
+        ($pspec_type:ty, $value_type:ty) => {
+            if let Some(pspec) = pspec.downcast_ref::<$pspec_type>() {
+                let v = value.get::<$value_type>().unwrap();
+                if v < pspec.minimum() || v > pspec.maximum() {
+                    return Err(invalid(format!(
+                        "{} is out of range {}..={}",
+                        v,
+                        pspec.minimum(),
+                        pspec.maximum()
+                    )));
+                }
+            }
+        };
+    }
+    check_range!(glib::ParamSpecInt, i32);
+    check_range!(glib::ParamSpecUInt, u32);
+    check_range!(glib::ParamSpecInt64, i64);
+    check_range!(glib::ParamSpecUInt64, u64);
+    check_range!(glib::ParamSpecFloat, f32);
+    check_range!(glib::ParamSpecDouble, f64);
+
+    Ok(value)
+}
+
+fn value_to_json(value: &glib::Value) -> serde_json::Value {
+    if let Ok(v) = value.get::<bool>() {
+        return v.into();
+    }
+    if let Ok(v) = value.get::<i32>() {
+        return v.into();
+    }
+    if let Ok(v) = value.get::<u32>() {
+        return v.into();
+    }
+    if let Ok(v) = value.get::<i64>() {
+        return v.into();
+    }
+    if let Ok(v) = value.get::<u64>() {
+        return v.into();
+    }
+    if let Ok(v) = value.get::<f32>() {
+        return (v as f64).into();
+    }
+    if let Ok(v) = value.get::<f64>() {
+        return v.into();
+    }
+    if let Ok(v) = value.get::<Option<String>>() {
+        return v.into();
+    }
+    if let Some((_, v)) = glib::EnumValue::from_value(value) {
+        return v.nick().into();
+    }
+    value
+        .serialize()
+        .map(|v| v.to_string().into())
+        .unwrap_or(serde_json::Value::Null)
+}
+
+// Check that a writable property can be changed in state, following its GST_PARAM_MUTABLE_* flags.
+// Properties without any of these flags are not restricted
+pub fn check_mutable(pspec: &glib::ParamSpec, state: gst::State) -> Result<(), ControlError> {
+    let flags = pspec.flags();
+    if flags.contains(glib::ParamFlags::CONSTRUCT_ONLY) {
+        return Err(ControlError::ConstructOnly(pspec.name().to_string()));
+    }
+    let max_state = if flags.contains(gst::PARAM_FLAG_MUTABLE_PLAYING) {
+        gst::State::Playing
+    } else if flags.contains(gst::PARAM_FLAG_MUTABLE_PAUSED) {
+        gst::State::Paused
+    } else if flags.contains(gst::PARAM_FLAG_MUTABLE_READY) {
+        gst::State::Ready
+    } else {
+        return Ok(());
+    };
+    match state > max_state {
+        true => Err(ControlError::NotMutable(pspec.name().to_string(), state)),
+        false => Ok(()),
+    }
+}
+
+// Apply a request to an element of bin, returning the property's value
+pub fn handle_request(
+    bin: &gst::Bin,
+    request: &ControlRequest,
+) -> Result<serde_json::Value, ControlError> {
+    match request {
+        ControlRequest::Get { element, property } => {
+            let (element, pspec) = find_property(bin, element, property)?;
+            if !pspec.flags().contains(glib::ParamFlags::READABLE) {
+                return Err(ControlError::NotReadable(pspec.name().to_string()));
+            }
+            Ok(value_to_json(&element.property_value(pspec.name())))
+        }
+        ControlRequest::Set {
+            element,
+            property,
+            value,
+        } => {
+            let (element, pspec) = find_property(bin, element, property)?;
+            if !pspec.flags().contains(glib::ParamFlags::WRITABLE) {
+                return Err(ControlError::NotWritable(pspec.name().to_string()));
+            }
+            // an element changing state must allow the change in both states
+            let state = element.current_state().max(element.pending_state());
+            check_mutable(&pspec, state)?;
+            let value = value_from_json(&pspec, value)?;
+            gst::info!(
+                CAT,
+                obj: element,
+                "Setting property {} to {:?}",
+                pspec.name(),
+                value
+            );
+            element.set_property_from_value(pspec.name(), &value);
+            // write-only properties like passwords reply with null
+            match pspec.flags().contains(glib::ParamFlags::READABLE) {
+                true => Ok(value_to_json(&element.property_value(pspec.name()))),
+                false => Ok(serde_json::Value::Null),
+            }
+        }
+    }
+}
+
+// Parse a json request and serialize the reply
+pub fn handle_message(bin: &gst::Bin, msg: &[u8]) -> Vec<u8> {
+    let result = serde_json::from_slice::<ControlRequest>(msg)
+        .map_err(ControlError::from)
+        .and_then(|request| handle_request(bin, &request));
+    if let Err(err) = &result {
+        gst::warning!(CAT, obj: bin, "Control request failed: {}", err);
+    }
+    serde_json::to_vec(&ControlReply::from(result)).unwrap()
+}
+
+// Connect to the NATS server at address and answer requests published to subject, until the pipeline is dropped
+// Connecting is retried with backoff, so the control plane becomes available once the server is reachable
+pub fn serve(pipeline: &gst::Bin, address: &str, subject: &str) -> thread::JoinHandle<()> {
+    let pipeline = pipeline.downgrade();
+    let address = address.to_string();
+    let subject = subject.to_string();
+    thread::spawn(move || {
+        let mut attempt = 0;
+        // the connection is owned by this thread, dropping its last handle would close the subscription
+        let (_nc, sub) = loop {
+            if pipeline.upgrade().is_none() {
+                return;
+            }
+            attempt += 1;
+            match nats::connect(address.as_str())
+                .and_then(|nc| nc.subscribe(&subject).map(|sub| (nc, sub)))
+            {
+                Ok(connected) => break connected,
+                Err(err) => {
+                    let delay = reconnect_delay(attempt, RECONNECT_DELAY, RECONNECT_DELAY_MAX);
+                    gst::warning!(
+                        CAT,
+                        "Failed to listen for control requests on {} {}, retrying in {:?}: {}",
+                        address,
+                        subject,
+                        delay,
+                        err
+                    );
+                    thread::sleep(delay);
+                }
+            }
+        };
+        gst::info!(
+            CAT,
+            "Listening for control requests on {} {}",
+            address,
+            subject
+        );
+        for msg in sub.messages() {
+            let pipeline = match pipeline.upgrade() {
+                Some(pipeline) => pipeline,
+                None => break,
+            };
+            let reply = handle_message(&pipeline, &msg.data);
+            if let Err(err) = msg.respond(reply) {
+                gst::warning!(CAT, "Failed to reply to control request: {}", err);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline() -> gst::Bin {
+        gst::init().unwrap();
+        let pipeline = gst::Pipeline::new(None);
+        let sink = gst::ElementFactory::make("fakesink")
+            .name("fakesink__test")
+            .build()
+            .unwrap();
+        pipeline.add(&sink).unwrap();
+        pipeline.upcast()
+    }
+
+    fn request(bin: &gst::Bin, json: &str) -> ControlReply {
+        serde_json::from_slice(&handle_message(bin, json.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_control_get_set() {
+        let bin = pipeline();
+
+        let reply = request(
+            &bin,
+            r#"{"op": "set", "element": "fakesink__test", "property": "num-buffers", "value": 16}"#,
+        );
+        assert_eq!(reply.value, Some(16.into()));
+        let reply = request(
+            &bin,
+            r#"{"op": "get", "element": "fakesink__test", "property": "num-buffers"}"#,
+        );
+        assert_eq!(reply.value, Some(16.into()));
+
+        let reply = request(
+            &bin,
+            r#"{"op": "set", "element": "fakesink__test", "property": "sync", "value": false}"#,
+        );
+        assert!(reply.ok);
+        assert_eq!(reply.value, Some(false.into()));
+
+        let reply = request(
+            &bin,
+            r#"{"op": "set", "element": "fakesink__test", "property": "state-error", "value": "ready-to-paused"}"#,
+        );
+        assert_eq!(reply.value, Some("ready-to-paused".into()));
+    }
+
+    #[test]
+    fn test_control_errors() {
+        let bin = pipeline();
+        let error = |json: &str| {
+            let reply = request(&bin, json);
+            assert!(!reply.ok);
+            reply.error.unwrap()
+        };
+
+        assert_eq!(
+            error(r#"{"op": "get", "element": "missing", "property": "sync"}"#),
+            "No element named missing in pipeline"
+        );
+        assert_eq!(
+            error(r#"{"op": "get", "element": "fakesink__test", "property": "missing"}"#),
+            "Element fakesink__test has no property missing"
+        );
+        assert_eq!(
+            error(
+                r#"{"op": "set", "element": "fakesink__test", "property": "last-message", "value": "x"}"#
+            ),
+            "Property last-message is not writable"
+        );
+        assert!(error(
+            r#"{"op": "set", "element": "fakesink__test", "property": "num-buffers", "value": -2}"#
+        )
+        .contains("out of range"));
+        assert!(error(
+            r#"{"op": "set", "element": "fakesink__test", "property": "sync", "value": "yes"}"#
+        )
+        .starts_with("Invalid value for property sync"));
+        assert!(error(r#"{"op": "delete"}"#).starts_with("Invalid control request"));
+    }
+
+    #[test]
+    fn test_control_construct_only() {
+        gst::init().unwrap();
+        let pspec = glib::ParamSpecString::builder("device")
+            .construct_only()
+            .build();
+        let err = check_mutable(&pspec, gst::State::Null).unwrap_err();
+        assert!(matches!(err, ControlError::ConstructOnly(_)));
+        assert_eq!(
+            err.to_string(),
+            "Property device can only be set on construction"
+        );
+    }
+
+    #[test]
+    fn test_control_not_mutable() {
+        gst::init().unwrap();
+        let pspec = glib::ParamSpecString::builder("location")
+            .mutable_ready()
+            .build();
+        assert!(check_mutable(&pspec, gst::State::Null).is_ok());
+        assert!(check_mutable(&pspec, gst::State::Ready).is_ok());
+        let err = check_mutable(&pspec, gst::State::Paused).unwrap_err();
+        assert!(matches!(
+            err,
+            ControlError::NotMutable(_, gst::State::Paused)
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Property location can not be changed in Paused state"
+        );
+
+        let pspec = glib::ParamSpecUInt::builder("window")
+            .mutable_paused()
+            .build();
+        assert!(check_mutable(&pspec, gst::State::Paused).is_ok());
+        assert!(matches!(
+            check_mutable(&pspec, gst::State::Playing),
+            Err(ControlError::NotMutable(_, gst::State::Playing))
+        ));
+
+        let pspec = glib::ParamSpecUInt::builder("window")
+            .mutable_playing()
+            .build();
+        assert!(check_mutable(&pspec, gst::State::Playing).is_ok());
+    }
+
+    #[test]
+    fn test_control_set_not_mutable_in_state() {
+        let bin = pipeline();
+        let sink = bin.by_name("fakesink__test").unwrap();
+        let pspec = sink.find_property("num-buffers").unwrap();
+        // fakesink's properties carry no mutable flags, so they can be changed in any state
+        assert!(check_mutable(&pspec, gst::State::Playing).is_ok());
+        bin.set_state(gst::State::Paused).unwrap();
+        let reply = request(
+            &bin,
+            r#"{"op": "set", "element": "fakesink__test", "property": "num-buffers", "value": 4}"#,
+        );
+        assert!(reply.ok);
+        bin.set_state(gst::State::Null).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use polars::prelude::*;
+
+use super::DataframeAnomalyMode;
+
+// Tracks an exponentially-weighted baseline (mean, variance) of a single windowed series, for example spaghetti__count
+// EWMA mode flags observations where |z-score| exceeds threshold
+// CUSUM mode accumulates standardized deviations beyond drift, and flags when either cumulative sum exceeds threshold
+#[derive(Debug, Default, Clone)]
+pub struct AnomalyDetector {
+    mean: f64,
+    var: f64,
+    observations: u64,
+    cusum_pos: f64,
+    cusum_neg: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnomalyScore {
+    pub score: f64, // z-score in EWMA mode, max(cusum_pos, cusum_neg) in CUSUM mode
+    pub anomaly: bool,
+}
+
+// number of observations used to initialize baseline before any anomalies are flagged
+const WARMUP_OBSERVATIONS: u64 = 3;
+
+impl AnomalyDetector {
+    pub fn update(
+        &mut self,
+        value: f64,
+        mode: DataframeAnomalyMode,
+        alpha: f64,
+        threshold: f64,
+        drift: f64,
+    ) -> AnomalyScore {
+        let std = self.var.sqrt();
+        let z = if std > f64::EPSILON {
+            (value - self.mean) / std
+        } else {
+            0.0
+        };
+        let warm = self.observations >= WARMUP_OBSERVATIONS;
+
+        let result = match mode {
+            DataframeAnomalyMode::Disabled => AnomalyScore {
+                score: 0.0,
+                anomaly: false,
+            },
+            DataframeAnomalyMode::Ewma => AnomalyScore {
+                score: z,
+                anomaly: warm && z.abs() > threshold,
+            },
+            DataframeAnomalyMode::Cusum => {
+                if warm {
+                    self.cusum_pos = (self.cusum_pos + z - drift).max(0.0);
+                    self.cusum_neg = (self.cusum_neg - z - drift).max(0.0);
+                }
+                let score = self.cusum_pos.max(self.cusum_neg);
+                let anomaly = score > threshold;
+                if anomaly {
+                    // restart accumulation after a change point is detected
+                    self.cusum_pos = 0.0;
+                    self.cusum_neg = 0.0;
+                }
+                AnomalyScore { score, anomaly }
+            }
+        };
+
+        // update baseline
+        if self.observations == 0 {
+            self.mean = value;
+        } else {
+            let diff = value - self.mean;
+            self.mean += alpha * diff;
+            self.var = (1.0 - alpha) * (self.var + alpha * diff * diff);
+        }
+        self.observations += 1;
+        result
+    }
+}
+
+// Collapse windowed dataframe (one row per detection class and window) into one row per window, sorted by window start
+// Columns: rt, _upper_boundary, label__count (sum over rows), label__mean (max over rows)
+pub fn window_summary(windowed_df: &DataFrame, labels: &[&str]) -> Result<DataFrame, PolarsError> {
+    let mut aggs = vec![col("_upper_boundary").max()];
+    for label in labels {
+        aggs.push(col(&format!("{}__count", label)).sum());
+        aggs.push(col(&format!("{}__mean", label)).max());
+    }
+    windowed_df
+        .clone()
+        .lazy()
+        .groupby([col("rt")])
+        .agg(aggs)
+        .sort(
+            "rt",
+            SortOptions {
+                descending: false,
+                nulls_last: false,
+            },
+        )
+        .collect()
+}
+
+// Start of windows which completed at rt_now, beginning at first and stepping every nanoseconds. Windows starting before since are skipped
+// groupby_dynamic only returns windows containing observations, so windows without detections are missing from the windowed dataframe
+pub fn completed_windows(first: i64, every: i64, period: i64, since: i64, rt_now: i64) -> Vec<i64> {
+    if every <= 0 {
+        return vec![];
+    }
+    let mut window = first + ((since - first) / every).max(0) * every;
+    let mut windows = vec![];
+    while window + period <= rt_now {
+        windows.push(window);
+        window += every;
+    }
+    windows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ewma_anomaly() {
+        let mut detector = AnomalyDetector::default();
+        let flags: Vec<bool> = [1.0, 2.0, 1.0, 2.0, 1.0, 2.0, 40.0]
+            .iter()
+            .map(|v| {
+                detector
+                    .update(*v, DataframeAnomalyMode::Ewma, 0.3, 3.0, 0.5)
+                    .anomaly
+            })
+            .collect();
+        assert_eq!(flags, vec![false, false, false, false, false, false, true]);
+    }
+
+    #[test]
+    fn test_cusum_anomaly() {
+        let mut detector = AnomalyDetector::default();
+        let flags: Vec<bool> = [1.0, 2.0, 1.0, 2.0, 1.0, 2.0, 40.0]
+            .iter()
+            .map(|v| {
+                detector
+                    .update(*v, DataframeAnomalyMode::Cusum, 0.3, 4.0, 0.5)
+                    .anomaly
+            })
+            .collect();
+        assert_eq!(flags, vec![false, false, false, false, false, false, true]);
+    }
+
+    #[test]
+    fn test_completed_windows() {
+        assert_eq!(completed_windows(0, 10, 20, 0, 50), vec![0, 10, 20, 30]);
+        // windows starting before since are skipped, keeping alignment with first
+        assert_eq!(completed_windows(5, 10, 10, 30, 60), vec![25, 35, 45]);
+        assert_eq!(completed_windows(0, 10, 20, 0, 15), Vec::<i64>::new());
+        assert_eq!(completed_windows(0, 0, 20, 0, 50), Vec::<i64>::new());
+    }
+}
@@ -0,0 +1,224 @@
+use polars::prelude::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Episode {
+    pub label: String,
+    pub start: i64, // running time of first frame above threshold
+    pub end: i64,   // running time of last frame above threshold
+    pub peak_score: f64,
+    pub score_sum: f64,
+    pub frames: u64,
+}
+
+impl Episode {
+    pub fn mean_score(&self) -> f64 {
+        self.score_sum / self.frames as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EpisodeEvent {
+    Start(Episode),
+    End(Episode),
+}
+
+impl EpisodeEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EpisodeEvent::Start(_) => "start",
+            EpisodeEvent::End(_) => "end",
+        }
+    }
+
+    pub fn episode(&self) -> &Episode {
+        match self {
+            EpisodeEvent::Start(episode) | EpisodeEvent::End(episode) => episode,
+        }
+    }
+
+    // Single-row dataframe record describing this event
+    pub fn to_dataframe(&self) -> Result<DataFrame, PolarsError> {
+        let episode = self.episode();
+        df!(
+            "event" => [self.name()],
+            "label" => [episode.label.as_str()],
+            "start" => [episode.start],
+            "end" => [episode.end],
+            "peak_score" => [episode.peak_score],
+            "mean_score" => [episode.mean_score()],
+            "frames" => [episode.frames]
+        )
+    }
+}
+
+// Session window over per-frame detection scores of a single label
+// An episode begins once min_frames frames score above threshold, and ends when no frame scores above threshold for longer than gap
+#[derive(Debug, Default)]
+pub struct EpisodeTracker {
+    open: Option<Episode>,
+    started: bool, // true once the start event was emitted for the open episode
+}
+
+impl EpisodeTracker {
+    // score is the highest detection score of the tracked label in a frame, or None if the label was not detected
+    // A frame above threshold arriving more than gap after the open episode's last frame closes the open episode first, and begins a new one
+    pub fn observe(
+        &mut self,
+        label: &str,
+        rt: i64,
+        score: Option<f64>,
+        threshold: f64,
+        gap: i64,
+        min_frames: u64,
+    ) -> Vec<EpisodeEvent> {
+        let mut events: Vec<EpisodeEvent> = self.expire(rt, gap).into_iter().collect();
+        match (score, self.open.as_mut()) {
+            (Some(score), Some(episode)) if score >= threshold => {
+                episode.end = rt;
+                episode.peak_score = episode.peak_score.max(score);
+                episode.score_sum += score;
+                episode.frames += 1;
+                if !self.started && episode.frames >= min_frames {
+                    self.started = true;
+                    events.push(EpisodeEvent::Start(episode.clone()));
+                }
+            }
+            (Some(score), None) if score >= threshold => {
+                let episode = Episode {
+                    label: label.to_string(),
+                    start: rt,
+                    end: rt,
+                    peak_score: score,
+                    score_sum: score,
+                    frames: 1,
+                };
+                self.open = Some(episode.clone());
+                if min_frames <= 1 {
+                    self.started = true;
+                    events.push(EpisodeEvent::Start(episode));
+                }
+            }
+            _ => (),
+        }
+        events
+    }
+
+    // Close the open episode if no frame scored above threshold for longer than gap, for example when no buffers arrive
+    pub fn expire(&mut self, rt: i64, gap: i64) -> Option<EpisodeEvent> {
+        match &self.open {
+            Some(episode) if rt - episode.end > gap => self.close(),
+            _ => None,
+        }
+    }
+
+    // Close the open episode. Returns an end event if a start event was emitted for the episode
+    pub fn close(&mut self) -> Option<EpisodeEvent> {
+        let started = self.started;
+        self.started = false;
+        match self.open.take() {
+            Some(episode) if started => Some(EpisodeEvent::End(episode)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_episode_start_end() {
+        let mut tracker = EpisodeTracker::default();
+        let observe = |tracker: &mut EpisodeTracker, rt, score| {
+            tracker.observe("spaghetti", rt, score, 0.5, 10, 2)
+        };
+
+        assert_eq!(observe(&mut tracker, 0, Some(0.9)), vec![]);
+        match observe(&mut tracker, 1, Some(0.7)).as_slice() {
+            [EpisodeEvent::Start(episode)] => assert_eq!(episode.start, 0),
+            other => panic!("Expected start event, got {:?}", other),
+        }
+        // gaps shorter than 10ns do not close the episode
+        assert_eq!(observe(&mut tracker, 5, None), vec![]);
+        assert_eq!(observe(&mut tracker, 8, Some(0.8)), vec![]);
+        match observe(&mut tracker, 20, Some(0.1)).as_slice() {
+            [EpisodeEvent::End(episode)] => {
+                assert_eq!(episode.start, 0);
+                assert_eq!(episode.end, 8);
+                assert_eq!(episode.frames, 3);
+                assert_eq!(episode.peak_score, 0.9);
+                assert!((episode.mean_score() - 0.8).abs() < 1e-9);
+            }
+            other => panic!("Expected end event, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_episode_below_min_frames() {
+        let mut tracker = EpisodeTracker::default();
+        assert_eq!(
+            tracker.observe("spaghetti", 0, Some(0.9), 0.5, 10, 3),
+            vec![]
+        );
+        assert_eq!(tracker.observe("spaghetti", 20, None, 0.5, 10, 3), vec![]);
+        assert_eq!(tracker.close(), None);
+    }
+
+    #[test]
+    fn test_episode_late_detection() {
+        let mut tracker = EpisodeTracker::default();
+        let observe = |tracker: &mut EpisodeTracker, rt, score| {
+            tracker.observe("spaghetti", rt, score, 0.5, 10, 2)
+        };
+
+        assert_eq!(observe(&mut tracker, 0, Some(0.9)), vec![]);
+        assert_eq!(observe(&mut tracker, 1, Some(0.9)).len(), 1);
+        // a late detection above threshold ends the open episode, and begins a new one
+        match observe(&mut tracker, 30, Some(0.8)).as_slice() {
+            [EpisodeEvent::End(episode)] => {
+                assert_eq!(episode.start, 0);
+                assert_eq!(episode.end, 1);
+                assert_eq!(episode.frames, 2);
+            }
+            other => panic!("Expected end event, got {:?}", other),
+        }
+        match observe(&mut tracker, 35, Some(0.7)).as_slice() {
+            [EpisodeEvent::Start(episode)] => {
+                assert_eq!(episode.start, 30);
+                assert_eq!(episode.frames, 2);
+            }
+            other => panic!("Expected start event, got {:?}", other),
+        }
+
+        // sparse detections further apart than gap do not add up to min_frames
+        let mut tracker = EpisodeTracker::default();
+        assert_eq!(observe(&mut tracker, 0, Some(0.9)), vec![]);
+        assert_eq!(observe(&mut tracker, 20, Some(0.9)), vec![]);
+        assert_eq!(observe(&mut tracker, 40, Some(0.9)), vec![]);
+        assert_eq!(tracker.close(), None);
+
+        // with min_frames=1, the late detection starts a new episode immediately
+        let mut tracker = EpisodeTracker::default();
+        assert_eq!(
+            tracker.observe("spaghetti", 0, Some(0.9), 0.5, 10, 1).len(),
+            1
+        );
+        let events = tracker.observe("spaghetti", 30, Some(0.9), 0.5, 10, 1);
+        assert_eq!(
+            events.iter().map(|e| e.name()).collect::<Vec<&str>>(),
+            vec!["end", "start"]
+        );
+    }
+
+    #[test]
+    fn test_episode_expire() {
+        let mut tracker = EpisodeTracker::default();
+        tracker.observe("spaghetti", 0, Some(0.9), 0.5, 10, 1);
+        assert_eq!(tracker.expire(5, 10), None);
+        match tracker.expire(20, 10) {
+            Some(EpisodeEvent::End(episode)) => assert_eq!(episode.end, 0),
+            other => panic!("Expected end event, got {:?}", other),
+        }
+        assert_eq!(tracker.close(), None);
+    }
+}
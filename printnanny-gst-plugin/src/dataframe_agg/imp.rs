@@ -1,3 +1,4 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::{Arc, Mutex};
 
 use gst::glib;
@@ -7,8 +8,20 @@ use log::debug;
 use once_cell::sync::Lazy;
 use polars::prelude::*;
 
-use super::DataframeOutputType;
-use crate::ipc::{dataframe_to_arrow_streaming_ipc_message, dataframe_to_json_bytearray};
+use super::anomaly::{completed_windows, window_summary, AnomalyDetector};
+use super::episode::{EpisodeEvent, EpisodeTracker};
+use super::score::{parse_score_weights, validate_thresholds, FailureScoreTracker, ScoreOptions};
+use super::{DataframeAnomalyMode, DataframeEmitMode, DataframeOutputType};
+use crate::caps::{arrow_stream_caps, caps_with_metadata, dataframe_sink_caps, schema_fingerprint};
+use crate::ipc::{
+    dataframe_from_bytes, dataframe_to_arrow_streaming_ipc_message, dataframe_to_json_bytearray,
+    dataframe_to_json_columns_bytearray, dataframe_to_json_framed_bytearray,
+    dataframe_to_ndjson_bytearray, with_iso8601_timestamps, ArrowStreamingIpcEncoder,
+    DataframeFormat, IpcCompression, IpcStreamMode, JsonTimestampFormat,
+};
+use crate::metadata::{
+    merge_metadata, plugin_metadata, read_labels, sha256_file, SourceMetadata, DEFAULT_LABELS,
+};
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -19,6 +32,10 @@ static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
 });
 
 const DEFAULT_OUTPUT_TYPE: DataframeOutputType = DataframeOutputType::ArrowStreamingIpc;
+const DEFAULT_EMIT_MODE: DataframeEmitMode = DataframeEmitMode::Buffer;
+const DEFAULT_IPC_COMPRESSION: IpcCompression = IpcCompression::Uncompressed;
+const DEFAULT_IPC_STREAM_MODE: IpcStreamMode = IpcStreamMode::Message;
+const DEFAULT_JSON_TIMESTAMP_FORMAT: JsonTimestampFormat = JsonTimestampFormat::Raw;
 
 const DEFAULT_MAX_SIZE_DURATION: &str = "30s";
 const DEFAULT_MAX_SIZE_BUFFERS: u64 = 900; // approx 1 minute of buffer frames @ 15fps
@@ -29,9 +46,43 @@ const DEFAULT_SCORE_THRESHOLD: f32 = 0.5;
 const DEFAULT_DDOF: u8 = 0; // delta degrees of freedom, used in std dev calculation. divisor = N - ddof, where N is the number of element in the set
 const DEFAULT_WINDOW_TRUNCATE: bool = false;
 const DEFAULT_WINDOW_INCLUDE_BOUNDARIES: bool = true;
+const DEFAULT_SCORE_WEIGHTS: &str = "adhesion=0.5,spaghetti=1.0,raft=0.25";
+const DEFAULT_SCORE_DECAY: f32 = 0.8; // weight of previous window's score, between 0 - 1
+const DEFAULT_SCORE_ALERT_THRESHOLD: f32 = 0.5;
+const DEFAULT_SCORE_CLEAR_THRESHOLD: f32 = 0.3;
+const DEFAULT_EPISODE_LABEL: &str = "spaghetti";
+const DEFAULT_EPISODE_THRESHOLD: f32 = 0.5;
+const DEFAULT_EPISODE_GAP: &str = "5s";
+const DEFAULT_EPISODE_MIN_FRAMES: u32 = 3;
+const DEFAULT_ANOMALY_MODE: DataframeAnomalyMode = DataframeAnomalyMode::Disabled;
+const DEFAULT_ANOMALY_ALPHA: f32 = 0.1; // smoothing factor of baseline mean/variance
+const DEFAULT_ANOMALY_THRESHOLD: f32 = 3.0;
+const DEFAULT_ANOMALY_DRIFT: f32 = 0.5; // CUSUM slack, in standard deviations
 
 struct State {
     dataframe: DataFrame,
+    segment_sent: bool, // true after a segment event was forwarded downstream; interval output is not pushed before that
+    eos: bool,
+    received_since_emit: bool, // true if an input buffer was received since the last interval output
+    clock_id: Option<gst::PeriodicClockId>,
+    failure_score: FailureScoreTracker, // running failure score of completed windows
+    episodes: EpisodeTracker,
+    episode_stream_started: bool,
+    anomaly_detectors: HashMap<String, AnomalyDetector>, // keyed by series name, for example spaghetti__count
+    anomaly_last_window: Option<i64>, // start of the most recent window processed by anomaly detectors
+    anomalies: BTreeMap<i64, BTreeSet<String>>, // flagged series, keyed by window start
+    ipc_encoder: ArrowStreamingIpcEncoder, // used when ipc-stream-mode=continuous
+    episode_ipc_encoder: ArrowStreamingIpcEncoder,
+    upstream_metadata: BTreeMap<String, String>, // schema metadata of the most recent input buffer
+}
+
+// Serialized output buffer
+// caps describe the buffer, including compression and schema-fingerprint for arrow output
+// In ipc-stream-mode=continuous, header holds the arrow ipc schema message whenever a new stream begins, and caps carry the schema message in the streamheader field
+struct Output {
+    caps: gst::Caps,
+    header: Option<gst::Buffer>,
+    buffer: gst::Buffer,
 }
 
 impl Default for State {
@@ -55,7 +106,22 @@ impl Default for State {
             "rt" => rt
         )
         .expect("Failed to initialize dataframe");
-        Self { dataframe }
+        Self {
+            dataframe,
+            segment_sent: false,
+            eos: false,
+            received_since_emit: false,
+            clock_id: None,
+            failure_score: FailureScoreTracker::default(),
+            episodes: EpisodeTracker::default(),
+            episode_stream_started: false,
+            anomaly_detectors: HashMap::new(),
+            anomaly_last_window: None,
+            anomalies: BTreeMap::new(),
+            ipc_encoder: ArrowStreamingIpcEncoder::new(DEFAULT_IPC_COMPRESSION),
+            episode_ipc_encoder: ArrowStreamingIpcEncoder::new(DEFAULT_IPC_COMPRESSION),
+            upstream_metadata: BTreeMap::new(),
+        }
     }
 }
 
@@ -63,6 +129,11 @@ struct Settings {
     filter_threshold: f32,
     ddof: u8,
     output_type: DataframeOutputType,
+    output_type_set: bool, // true if output-type was set explicitly, otherwise output-type is negotiated with downstream
+    emit_mode: DataframeEmitMode,
+    ipc_compression: IpcCompression,
+    ipc_stream_mode: IpcStreamMode,
+    json_timestamp_format: JsonTimestampFormat,
     max_size_duration: String,
     max_size_buffers: u64,
     window_interval: String,
@@ -70,6 +141,20 @@ struct Settings {
     window_offset: String,
     window_truncate: bool,
     window_include_boundaries: bool,
+    score_weights: Vec<(String, f64)>,
+    score_decay: f32,
+    score_alert_threshold: f32,
+    score_clear_threshold: f32,
+    episode_label: String,
+    episode_threshold: f32,
+    episode_gap: String,
+    episode_min_frames: u32,
+    anomaly_mode: DataframeAnomalyMode,
+    anomaly_alpha: f32,
+    anomaly_threshold: f32,
+    anomaly_drift: f32,
+    label_file: Option<String>,
+    source_metadata: SourceMetadata,
 }
 
 impl Default for Settings {
@@ -77,6 +162,11 @@ impl Default for Settings {
         Self {
             ddof: DEFAULT_DDOF,
             output_type: DEFAULT_OUTPUT_TYPE,
+            output_type_set: false,
+            emit_mode: DEFAULT_EMIT_MODE,
+            ipc_compression: DEFAULT_IPC_COMPRESSION,
+            ipc_stream_mode: DEFAULT_IPC_STREAM_MODE,
+            json_timestamp_format: DEFAULT_JSON_TIMESTAMP_FORMAT,
             filter_threshold: DEFAULT_SCORE_THRESHOLD,
             max_size_duration: DEFAULT_MAX_SIZE_DURATION.into(),
             max_size_buffers: DEFAULT_MAX_SIZE_BUFFERS,
@@ -85,15 +175,85 @@ impl Default for Settings {
             window_offset: DEFAULT_WINDOW_OFFSET.into(),
             window_truncate: DEFAULT_WINDOW_TRUNCATE,
             window_include_boundaries: DEFAULT_WINDOW_INCLUDE_BOUNDARIES,
+            score_weights: parse_score_weights(DEFAULT_SCORE_WEIGHTS)
+                .expect("Failed to parse DEFAULT_SCORE_WEIGHTS"),
+            score_decay: DEFAULT_SCORE_DECAY,
+            score_alert_threshold: DEFAULT_SCORE_ALERT_THRESHOLD,
+            score_clear_threshold: DEFAULT_SCORE_CLEAR_THRESHOLD,
+            episode_label: DEFAULT_EPISODE_LABEL.into(),
+            episode_threshold: DEFAULT_EPISODE_THRESHOLD,
+            episode_gap: DEFAULT_EPISODE_GAP.into(),
+            episode_min_frames: DEFAULT_EPISODE_MIN_FRAMES,
+            anomaly_mode: DEFAULT_ANOMALY_MODE,
+            anomaly_alpha: DEFAULT_ANOMALY_ALPHA,
+            anomaly_threshold: DEFAULT_ANOMALY_THRESHOLD,
+            anomaly_drift: DEFAULT_ANOMALY_DRIFT,
+            label_file: None,
+            source_metadata: SourceMetadata {
+                labels: Some(
+                    DEFAULT_LABELS
+                        .iter()
+                        .map(|label| label.to_string())
+                        .collect(),
+                ),
+                ..SourceMetadata::default()
+            },
         }
     }
 }
 
+impl Settings {
+    // Class labels indexed by detection_classes value, read from label-file. DEFAULT_LABELS are used if label-file is not set or could not be read
+    fn labels(&self) -> Vec<&str> {
+        match &self.source_metadata.labels {
+            Some(labels) => labels.iter().map(|label| label.as_str()).collect(),
+            None => DEFAULT_LABELS.to_vec(),
+        }
+    }
+
+    // Timestamp columns are rendered as ISO-8601 strings in JSON output types when json-timestamp-format=iso8601
+    fn iso8601_timestamps(&self) -> bool {
+        self.output_type != DataframeOutputType::ArrowStreamingIpc
+            && self.json_timestamp_format == JsonTimestampFormat::Iso8601
+    }
+}
+
+// Columns produced by the detection decoder (see nnstreamer.rs), cast to the decoder's column types
+fn detection_columns() -> Vec<Expr> {
+    vec![
+        col("detection_boxes_x0").cast(DataType::Float32),
+        col("detection_boxes_y0").cast(DataType::Float32),
+        col("detection_boxes_x1").cast(DataType::Float32),
+        col("detection_boxes_y1").cast(DataType::Float32),
+        col("detection_classes").cast(DataType::Int32),
+        col("detection_scores").cast(DataType::Float32),
+    ]
+}
+
+// Standard metadata of output dataframes, merged with upstream metadata. Properties set on this element take precedence
+fn output_metadata(
+    settings: &Settings,
+    upstream_metadata: &BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    merge_metadata([
+        upstream_metadata.clone(),
+        plugin_metadata(),
+        settings.source_metadata.to_map(),
+    ])
+}
+
 pub struct DataframeAgg {
     settings: Arc<Mutex<Settings>>,
     state: Arc<Mutex<State>>,
     sinkpad: gst::Pad,
     srcpad: gst::Pad,
+    episode_srcpad: Mutex<Option<gst::Pad>>,
+}
+
+// Wall-clock time in nanoseconds since epoch, for the ts column of ISO-8601 output
+// The pipeline clock is monotonic by default, so its time can't be rendered as a date
+fn realtime() -> i64 {
+    glib::real_time() * 1_000
 }
 
 impl DataframeAgg {
@@ -104,13 +264,47 @@ impl DataframeAgg {
     // Called whenever an event arrives on the sink pad. It has to be handled accordingly and in
     // most cases has to be either passed to Pad::event_default() on this pad for default handling,
     // or Pad::push_event() on all pads with the opposite direction for direct forwarding.
-    // Here we just pass through all events directly to the source pad.
+    // Here we pass through all events directly to the source pad, keeping track of segment/eos
+    // events so interval output is only pushed while the stream is running. Caps events are
+    // replaced with caps describing output-type. Caps, segment and flush-stop events begin a new
+    // arrow ipc stream when ipc-stream-mode=continuous.
     //
     // See the documentation of gst::Event and gst::EventRef to see what can be done with
     // events, and especially the gst::EventView type for inspecting events.
     fn sink_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
         gst::log!(CAT, obj: pad, "Handling event {:?}", event);
-        self.srcpad.push_event(event)
+        let event_type = event.type_();
+        let event = match event_type {
+            gst::EventType::Caps => {
+                let caps = match self.negotiate() {
+                    Ok(caps) => caps,
+                    Err(_) => return false,
+                };
+                gst::debug!(CAT, obj: pad, "Setting output caps {:?}", caps);
+                gst::event::Caps::new(&caps)
+            }
+            _ => event,
+        };
+        if event_type == gst::EventType::Eos {
+            // taking the src pad stream lock orders eos with interval output, see emit_interval()
+            let stream_lock = self.srcpad.stream_lock();
+            self.state.lock().unwrap().eos = true;
+            drop(stream_lock);
+            self.finish_episodes();
+        }
+        if matches!(
+            event_type,
+            gst::EventType::Caps | gst::EventType::Segment | gst::EventType::FlushStop
+        ) {
+            self.state.lock().unwrap().ipc_encoder.reset();
+        }
+        let res = self.srcpad.push_event(event);
+        if event_type == gst::EventType::Segment {
+            let mut state = self.state.lock().unwrap();
+            state.segment_sent = true;
+            state.eos = false;
+        }
+        res
     }
 
     // Called whenever an event arrives on the source pad. It has to be handled accordingly and in
@@ -137,7 +331,13 @@ impl DataframeAgg {
     // queries, and especially the gst::QueryView type for inspecting and modifying queries.
     fn src_query(&self, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
         gst::log!(CAT, obj: pad, "Handling query {:?}", query);
-        self.sinkpad.peer_query(query)
+        match query.view() {
+            // input and output formats are independent, so caps are answered from pad templates
+            gst::QueryView::Caps(_) | gst::QueryView::AcceptCaps(_) => {
+                pad.query_default(Some(&*self.instance()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
     }
 
     // Called whenever a query is sent to the sink pad. It has to be answered if the element can
@@ -151,44 +351,176 @@ impl DataframeAgg {
     // queries, and especially the gst::QueryView type for inspecting and modifying queries.
     fn sink_query(&self, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
         gst::log!(CAT, obj: pad, "Handling query {:?}", query);
-        self.srcpad.peer_query(query)
+        match query.view() {
+            gst::QueryView::Caps(_) | gst::QueryView::AcceptCaps(_) => {
+                pad.query_default(Some(&*self.instance()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
     }
 
-    fn sink_chain(
-        &self,
-        pad: &gst::Pad,
-        buffer: gst::Buffer,
-    ) -> Result<gst::FlowSuccess, gst::FlowError> {
-        gst::log!(CAT, obj: pad, "Handling buffer {:?}", buffer);
+    // Select output-type from caps accepted by downstream, and return caps of the selected output-type
+    // If output-type was not set, the first output type accepted by downstream is used. An explicitly set output-type is never replaced, so negotiation fails if downstream does not accept it
+    fn negotiate(&self) -> Result<gst::Caps, gst::FlowError> {
+        let element = self.instance();
+        let settings = self.settings.lock().unwrap();
+        let preferred = settings.output_type;
+        let explicit = settings.output_type_set;
+        drop(settings);
+        let peer_caps = self.srcpad.peer_query_caps(None);
+        if explicit {
+            if peer_caps.can_intersect(&preferred.caps()) {
+                return Ok(preferred.caps());
+            }
+            gst::element_error!(
+                element,
+                gst::CoreError::Negotiation,
+                [
+                    "Downstream does not accept output-type {:?}, downstream caps: {}",
+                    preferred,
+                    peer_caps
+                ]
+            );
+            return Err(gst::FlowError::NotNegotiated);
+        }
+        let output_type = std::iter::once(preferred)
+            .chain(DataframeOutputType::ALL)
+            .find(|output_type| peer_caps.can_intersect(&output_type.caps()))
+            .unwrap_or(preferred);
+        if output_type != preferred {
+            gst::info!(
+                CAT,
+                obj: element,
+                "Downstream does not accept output-type {:?}, using {:?}",
+                preferred,
+                output_type
+            );
+            self.settings.lock().unwrap().output_type = output_type;
+            element.notify("output-type");
+        }
+        Ok(output_type.caps())
+    }
 
-        let ts = match self.instance().current_clock_time() {
-            Some(clock) => clock.nseconds(),
-            None => 0,
-        } as i64;
-        let rt = match self.instance().current_running_time() {
-            Some(clock) => clock.nseconds(),
+    // Renegotiate output-type if downstream requested reconfiguration
+    fn check_reconfigure(&self) -> Result<(), gst::FlowError> {
+        if !self.srcpad.check_reconfigure() {
+            return Ok(());
+        }
+        let caps = self.negotiate().map_err(|err| {
+            self.srcpad.mark_reconfigure();
+            err
+        })?;
+        let changed = match self.srcpad.current_caps() {
+            Some(current) => !current.can_intersect(&caps),
+            None => false,
+        };
+        if changed {
+            gst::debug!(CAT, obj: &self.srcpad, "Reconfigured output caps {:?}", caps);
+            self.state.lock().unwrap().ipc_encoder.reset();
+            self.srcpad.push_event(gst::event::Caps::new(&caps));
+        }
+        Ok(())
+    }
+
+    // Hash model-file without holding the settings lock, large models take a while to read
+    fn hash_model_file(&self) {
+        let element = self.instance();
+        let model_file = self
+            .settings
+            .lock()
+            .unwrap()
+            .source_metadata
+            .model_file
+            .clone();
+        let model_sha256 = match &model_file {
+            Some(model_file) => match sha256_file(model_file) {
+                Ok(digest) => Some(digest),
+                Err(err) => {
+                    gst::error!(
+                        CAT,
+                        obj: element,
+                        "Failed to hash model-file {}: {}",
+                        model_file,
+                        err
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        let mut settings = self.settings.lock().unwrap();
+        // model-file was changed while hashing
+        if settings.source_metadata.model_file == model_file {
+            settings.source_metadata.model_sha256 = model_sha256;
+        }
+    }
+
+    // Pipeline clock time for the ts column, or wall-clock time if ts is rendered as an ISO-8601 date
+    fn clock_time(&self, settings: &Settings) -> i64 {
+        if settings.iso8601_timestamps() {
+            return realtime();
+        }
+        match self.instance().current_clock_time() {
+            Some(clock) => clock.nseconds() as i64,
             None => 0,
-        } as i64;
+        }
+    }
 
-        let mut state = self.state.lock().unwrap();
-        let settings = self.settings.lock().unwrap();
+    fn running_time(&self) -> i64 {
+        match self.instance().current_running_time() {
+            Some(clock) => clock.nseconds() as i64,
+            None => 0,
+        }
+    }
 
-        let cursor = buffer.into_cursor_readable();
+    // Deserialize an incoming dataframe buffer and merge it into the buffered dataframe
+    fn ingest(&self, settings: &Settings, buffer: gst::Buffer) -> Result<(), gst::FlowError> {
+        let ts = self.clock_time(settings);
+        let rt = self.running_time();
 
-        let reader = IpcStreamReader::new(cursor);
-        let df = reader
-            .finish()
-            .expect("Failed to deserialize Arrow IPC Stream")
+        let format = self
+            .sinkpad
+            .current_caps()
+            .and_then(|caps| DataframeFormat::from_caps(&caps));
+        let map = buffer.map_readable().map_err(|_| {
+            gst::element_error!(
+                self.instance(),
+                gst::CoreError::Failed,
+                ["Failed to map buffer"]
+            );
+            gst::FlowError::Error
+        })?;
+        let (df, upstream_metadata) = dataframe_from_bytes(&map, format).map_err(|err| {
+            gst::element_error!(
+                self.instance(),
+                gst::StreamError::Decode,
+                ["Failed to deserialize dataframe: {}", err]
+            );
+            gst::FlowError::Error
+        })?;
+        drop(map);
+        // JSON input is decoded with inferred f64/i64 columns, so detections are cast to the decoder's column types before they are merged
+        let df = df
+            .lazy()
+            .select(detection_columns())
+            .collect()
+            .map_err(|err| {
+                gst::element_error!(
+                    self.instance(),
+                    gst::StreamError::Format,
+                    ["Input dataframe does not match detection schema: {}", err]
+                );
+                gst::FlowError::Error
+            })?;
+        let episode_score = self.episode_frame_score(settings, &df);
+        let df = df
             .lazy()
             .with_columns(vec![lit(ts).alias("ts"), lit(rt).alias("rt")]);
 
+        let mut state = self.state.lock().unwrap();
         let max_duration = Duration::parse(&settings.max_size_duration);
-        state.dataframe = concat(vec![state.dataframe.clone().lazy(), df], true, false)
-            .map_err(|err| {
-                gst::error!(CAT, "Failed to merge dataframes: {}", err);
-                gst::FlowError::Error
-            })?
-            .filter(
+        let merged = concat(vec![state.dataframe.clone().lazy(), df], true, false).and_then(|lf| {
+            lf.filter(
                 col("detection_scores")
                     .gt(settings.filter_threshold)
                     .and(col("rt").gt(col("rt").max() - lit(max_duration.nanoseconds()))),
@@ -201,7 +533,140 @@ impl DataframeAgg {
                 },
             )
             .collect()
-            .expect("Failed to collect dataframes");
+        });
+        state.dataframe = merged.map_err(|err| {
+            gst::element_error!(
+                self.instance(),
+                gst::StreamError::Failed,
+                ["Failed to merge dataframes: {}", err]
+            );
+            gst::FlowError::Error
+        })?;
+        state.received_since_emit = true;
+        state.upstream_metadata = upstream_metadata;
+
+        let episode_events = match settings.episode_label.is_empty() {
+            true => vec![],
+            false => state.episodes.observe(
+                &settings.episode_label,
+                rt,
+                episode_score,
+                settings.episode_threshold as f64,
+                Duration::parse(&settings.episode_gap).nanoseconds(),
+                settings.episode_min_frames as u64,
+            ),
+        };
+        drop(state);
+
+        self.push_episodes(settings, episode_events);
+        Ok(())
+    }
+
+    // Highest detection score of episode-label in a single frame, or None if the label was not detected
+    fn episode_frame_score(&self, settings: &Settings, df: &DataFrame) -> Option<f64> {
+        let class = settings
+            .labels()
+            .iter()
+            .position(|label| *label == settings.episode_label)?;
+        df.clone()
+            .lazy()
+            .filter(col("detection_classes").eq(class as i32))
+            .select([col("detection_scores").max().cast(DataType::Float64)])
+            .collect()
+            .ok()?
+            .column("detection_scores")
+            .ok()?
+            .f64()
+            .ok()?
+            .get(0)
+    }
+
+    // Post a printnanny-episode message, and push the episode record to episode_src pad (if requested)
+    fn push_episode(
+        &self,
+        settings: &Settings,
+        event: EpisodeEvent,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let element = self.instance();
+        let episode = event.episode();
+        gst::info!(
+            CAT,
+            obj: element,
+            "Episode {}: {:?}",
+            event.name(),
+            episode
+        );
+        let msg = gst::message::Element::builder(
+            gst::Structure::builder("printnanny-episode")
+                .field("event", event.name())
+                .field("label", &episode.label)
+                .field("start", episode.start)
+                .field("end", episode.end)
+                .field("peak-score", episode.peak_score)
+                .field("mean-score", episode.mean_score())
+                .field("frames", episode.frames)
+                .build(),
+        )
+        .src(&*element)
+        .build();
+        let _ = element.post_message(msg);
+
+        let pad = match self.episode_srcpad.lock().unwrap().clone() {
+            Some(pad) => pad,
+            None => return Ok(gst::FlowSuccess::Ok),
+        };
+        let mut df = event.to_dataframe().map_err(|err| {
+            gst::error!(CAT, "Failed to create episode dataframe {}", err);
+            gst::FlowError::Error
+        })?;
+
+        let mut state = self.state.lock().unwrap();
+        let stream_started = state.episode_stream_started;
+        if !stream_started {
+            state.episode_stream_started = true;
+            state.episode_ipc_encoder.reset();
+        }
+        let metadata = output_metadata(settings, &state.upstream_metadata);
+        let output = self.serialize(settings, &mut df, &mut state.episode_ipc_encoder, metadata)?;
+        drop(state);
+
+        if !stream_started {
+            pad.push_event(gst::event::StreamStart::new("dataframe_agg-episodes"));
+            pad.push_event(gst::event::Caps::new(&settings.output_type.caps()));
+            let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+            pad.push_event(gst::event::Segment::new(&segment));
+        }
+        self.push_output(&pad, output)
+    }
+
+    // Episode records are optional output, so failing to push them does not stop the stream. episode_src is usually not linked
+    fn push_episodes(&self, settings: &Settings, events: impl IntoIterator<Item = EpisodeEvent>) {
+        for event in events {
+            match self.push_episode(settings, event) {
+                Ok(_) | Err(gst::FlowError::NotLinked) => (),
+                Err(err) => gst::warning!(
+                    CAT,
+                    obj: &*self.instance(),
+                    "Failed to push episode record: {:?}",
+                    err
+                ),
+            }
+        }
+    }
+
+    // Close any open episode at end of stream
+    fn finish_episodes(&self) {
+        let event = self.state.lock().unwrap().episodes.close();
+        let settings = self.settings.lock().unwrap();
+        self.push_episodes(&settings, event);
+        drop(settings);
+        if let Some(pad) = self.episode_srcpad.lock().unwrap().clone() {
+            pad.push_event(gst::event::Eos::new());
+        }
+    }
+
+    // Calculate windowed aggregates over buffered observations
+    fn window(&self, settings: &Settings, localdf: DataFrame) -> Result<DataFrame, gst::FlowError> {
         let group_options = DynamicGroupOptions {
             index_column: "rt".to_string(),
             every: Duration::parse(&settings.window_interval),
@@ -212,106 +677,577 @@ impl DataframeAgg {
             include_boundaries: true,
         };
 
-        let localdf = state.dataframe.clone();
-        // release state lock
-        drop(state);
-
         debug!("{:?}", &localdf);
 
-        let mut windowed_df = localdf
+        let mut aggs = vec![
+            col("rt").min().alias("rt__min"),
+            col("rt").max().alias("rt__max"),
+        ];
+        for (class, label) in settings.labels().iter().enumerate() {
+            let scores = col("detection_scores").filter(col("detection_classes").eq(class as i32));
+            aggs.push(scores.clone().count().alias(&format!("{}__count", label)));
+            aggs.push(scores.clone().mean().alias(&format!("{}__mean", label)));
+            aggs.push(scores.std(settings.ddof).alias(&format!("{}__std", label)));
+        }
+
+        let windowed_df = localdf
             .lazy()
             .groupby_dynamic([col("detection_classes")], group_options)
-            .agg([
-                col("rt").min().alias("rt__min"),
-                col("rt").max().alias("rt__max"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(0))
-                    .count()
-                    .alias("nozzle__count"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(0))
-                    .mean()
-                    .alias("nozzle__mean"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(0))
-                    .std(settings.ddof)
-                    .alias("nozzle__std"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(1))
-                    .count()
-                    .alias("adhesion__count"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(1))
-                    .mean()
-                    .alias("adhesion__mean"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(1))
-                    .std(settings.ddof)
-                    .alias("adhesion__std"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(2))
-                    .count()
-                    .alias("spaghetti__count"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(2))
-                    .mean()
-                    .alias("spaghetti__mean"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(2))
-                    .std(settings.ddof)
-                    .alias("spaghetti__std"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(3))
-                    .count()
-                    .alias("print__count"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(3))
-                    .mean()
-                    .alias("print__mean"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(3))
-                    .std(settings.ddof)
-                    .alias("print__std"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(4))
-                    .count()
-                    .alias("raft__count"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(4))
-                    .mean()
-                    .alias("raft__mean"),
-                col("detection_scores")
-                    .filter(col("detection_classes").eq(4))
-                    .std(settings.ddof)
-                    .alias("raft__std"),
-            ])
+            .agg(aggs)
             .collect()
             .map_err(|err| {
                 gst::error!(CAT, "Failed window/aggregate dataframes {}", err);
                 gst::FlowError::Error
             })?;
 
+        let windowed_df = self.score(settings, windowed_df)?;
+        self.detect_anomalies(settings, windowed_df)
+    }
+
+    // Update anomaly detectors with windows completed since the last call, then add a boolean label__count__anomaly and label__mean__anomaly column per label
+    // Windows are only fed to detectors once, after the window's upper boundary has passed. Windows without detections are fed to detectors with zero counts
+    fn detect_anomalies(
+        &self,
+        settings: &Settings,
+        windowed_df: DataFrame,
+    ) -> Result<DataFrame, gst::FlowError> {
+        if settings.anomaly_mode == DataframeAnomalyMode::Disabled {
+            return Ok(windowed_df);
+        }
+        let map_polars_err = |err: PolarsError| {
+            gst::error!(CAT, "Failed to detect anomalies {}", err);
+            gst::FlowError::Error
+        };
+
+        let labels = settings.labels();
+        let summary = window_summary(&windowed_df, &labels).map_err(map_polars_err)?;
+        let windows = summary
+            .column("rt")
+            .and_then(|s| s.cast(&DataType::Int64))
+            .map_err(map_polars_err)?;
+        let windows: Vec<i64> = windows
+            .i64()
+            .map_err(map_polars_err)?
+            .into_iter()
+            .map(|rt| rt.unwrap_or_default())
+            .collect();
+        let upper_boundaries = summary
+            .column("_upper_boundary")
+            .and_then(|s| s.cast(&DataType::Int64))
+            .map_err(map_polars_err)?;
+        let upper_boundaries = upper_boundaries.i64().map_err(map_polars_err)?;
+
+        let mut series = vec![];
+        for label in &labels {
+            for stat in ["count", "mean"] {
+                let name = format!("{}__{}", label, stat);
+                let values = summary
+                    .column(&name)
+                    .and_then(|s| s.cast(&DataType::Float64))
+                    .map_err(map_polars_err)?;
+                series.push((name, values));
+            }
+        }
+
+        let rt_now = self.running_time();
+        let every = Duration::parse(&settings.window_interval).nanoseconds();
+        let period = Duration::parse(&settings.window_period).nanoseconds();
+        let max_duration = Duration::parse(&settings.max_size_duration);
+        let rows: HashMap<i64, usize> = windows
+            .iter()
+            .enumerate()
+            .map(|(i, window)| (*window, i))
+            .collect();
+        let mut flagged = vec![];
+        let mut state = self.state.lock().unwrap();
+        let last_window = state.anomaly_last_window;
+        let mut pending: BTreeSet<i64> = windows
+            .iter()
+            .enumerate()
+            .filter(|(i, window)| {
+                let window_complete = match upper_boundaries.get(*i) {
+                    Some(upper_boundary) => upper_boundary <= rt_now,
+                    None => false,
+                };
+                window_complete && last_window.map_or(true, |last| **window > last)
+            })
+            .map(|(_, window)| *window)
+            .collect();
+        let first = match last_window {
+            Some(last) => Some(last + every),
+            None => windows.first().copied(),
+        };
+        if let Some(first) = first {
+            pending.extend(completed_windows(
+                first,
+                every,
+                period,
+                rt_now - max_duration.nanoseconds(),
+                rt_now,
+            ));
+        }
+
+        for window in pending {
+            for (name, values) in series.iter() {
+                let value = match rows.get(&window) {
+                    Some(i) => values.f64().map_err(map_polars_err)?.get(*i),
+                    None if name.ends_with("__count") => Some(0.0),
+                    None => None,
+                };
+                let value = match value {
+                    Some(value) => value,
+                    // label__mean is null when label was not detected in window
+                    None => continue,
+                };
+                let result = state
+                    .anomaly_detectors
+                    .entry(name.clone())
+                    .or_default()
+                    .update(
+                        value,
+                        settings.anomaly_mode,
+                        settings.anomaly_alpha as f64,
+                        settings.anomaly_threshold as f64,
+                        settings.anomaly_drift as f64,
+                    );
+                if result.anomaly {
+                    state
+                        .anomalies
+                        .entry(window)
+                        .or_default()
+                        .insert(name.clone());
+                    flagged.push((name.clone(), window, value, result.score));
+                }
+            }
+            state.anomaly_last_window = Some(window);
+        }
+
+        // expire flags of windows which are no longer part of the aggregate output
+        state
+            .anomalies
+            .retain(|window, _| *window > rt_now - max_duration.nanoseconds());
+
+        let mut columns = vec![Series::new("rt", &windows)];
+        for (name, _) in series.iter() {
+            let flags: Vec<bool> = windows
+                .iter()
+                .map(|window| {
+                    state
+                        .anomalies
+                        .get(window)
+                        .map_or(false, |flagged| flagged.contains(name))
+                })
+                .collect();
+            columns.push(Series::new(&format!("{}__anomaly", name), flags));
+        }
+        drop(state);
+
+        let element = self.instance();
+        for (name, window, value, score) in flagged {
+            gst::info!(
+                CAT,
+                obj: element,
+                "Anomaly detected in {} window={} value={} score={}",
+                name,
+                window,
+                value,
+                score
+            );
+            let msg = gst::message::Element::builder(
+                gst::Structure::builder("printnanny-anomaly")
+                    .field("series", &name)
+                    .field("window", window)
+                    .field("value", value)
+                    .field("score", score)
+                    .field("mode", settings.anomaly_mode)
+                    .build(),
+            )
+            .src(&*element)
+            .build();
+            let _ = element.post_message(msg);
+        }
+
+        let flags_df = DataFrame::new(columns).map_err(map_polars_err)?;
+        windowed_df
+            .lazy()
+            .left_join(flags_df.lazy(), col("rt"), col("rt"))
+            .collect()
+            .map_err(map_polars_err)
+    }
+
+    // Add failure_score/failure_alert columns, and post a printnanny-quality message when the alert state changes
+    fn score(
+        &self,
+        settings: &Settings,
+        windowed_df: DataFrame,
+    ) -> Result<DataFrame, gst::FlowError> {
+        let labels = settings.labels();
+        let options = ScoreOptions {
+            weights: &settings.score_weights,
+            labels: &labels,
+            decay: settings.score_decay as f64,
+            alert_threshold: settings.score_alert_threshold as f64,
+            clear_threshold: settings.score_clear_threshold as f64,
+            every: Duration::parse(&settings.window_interval).nanoseconds(),
+            period: Duration::parse(&settings.window_period).nanoseconds(),
+            max_duration: Duration::parse(&settings.max_size_duration).nanoseconds(),
+        };
+        let rt_now = self.running_time();
+        let mut state = self.state.lock().unwrap();
+        let (windowed_df, changed) = state
+            .failure_score
+            .with_failure_score(windowed_df, &options, rt_now)
+            .map_err(|err| {
+                gst::error!(CAT, "Failed to calculate failure score {}", err);
+                gst::FlowError::Error
+            })?;
+        drop(state);
+
+        if let Some(latest) = changed {
+            let element = self.instance();
+            gst::info!(
+                CAT,
+                obj: element,
+                "Failure score {} crossed threshold, alert={}",
+                latest.score,
+                latest.alert
+            );
+            let msg = gst::message::Element::builder(
+                gst::Structure::builder("printnanny-quality")
+                    .field("score", latest.score)
+                    .field("alert", latest.alert)
+                    .field("running-time", rt_now)
+                    .build(),
+            )
+            .src(&*element)
+            .build();
+            let _ = element.post_message(msg);
+        }
+        Ok(windowed_df)
+    }
+
+    // Arrow output carries schema metadata. JSON output types have no schema, so metadata is added to caps instead (see caps_with_metadata)
+    fn serialize(
+        &self,
+        settings: &Settings,
+        windowed_df: &mut DataFrame,
+        encoder: &mut ArrowStreamingIpcEncoder,
+        metadata: BTreeMap<String, String>,
+    ) -> Result<Output, gst::FlowError> {
+        if settings.iso8601_timestamps() {
+            with_iso8601_timestamps(windowed_df).map_err(|err| {
+                gst::error!(CAT, "Failed to format timestamp columns: {:?}", err);
+                gst::FlowError::Error
+            })?;
+        }
         let output_buffer = match settings.output_type {
-            DataframeOutputType::ArrowStreamingIpc => {
-                dataframe_to_arrow_streaming_ipc_message(&mut windowed_df, None).map_err(|err| {
+            DataframeOutputType::ArrowStreamingIpc
+                if settings.ipc_stream_mode == IpcStreamMode::Continuous =>
+            {
+                encoder.set_compression(settings.ipc_compression);
+                let parts = encoder
+                    .encode_dataframe(windowed_df, Some(metadata))
+                    .map_err(|err| {
+                        gst::error!(
+                            CAT,
+                            "Failed to serialize arrow ipc record batches: {:?}",
+                            err
+                        );
+                        gst::FlowError::Error
+                    })?;
+                let mut caps = arrow_stream_caps(
+                    settings.ipc_compression,
+                    &schema_fingerprint(&windowed_df.schema().to_arrow()),
+                );
+                let header = parts.schema.map(|schema| {
+                    let mut header = gst::Buffer::from_slice(schema);
+                    header
+                        .get_mut()
+                        .unwrap()
+                        .set_flags(gst::BufferFlags::HEADER);
+                    caps.make_mut().set(
+                        "streamheader",
+                        gst::Array::from_values([header.to_send_value()]),
+                    );
+                    header
+                });
+                return Ok(Output {
+                    caps,
+                    header,
+                    buffer: gst::Buffer::from_slice(parts.batches),
+                });
+            }
+            DataframeOutputType::ArrowStreamingIpc => dataframe_to_arrow_streaming_ipc_message(
+                windowed_df,
+                Some(metadata.clone()),
+                settings.ipc_compression,
+            )
+            .map_err(|err| {
+                gst::error!(
+                    CAT,
+                    "Failed to serialize arrow ipc streaming msg: {:?}",
+                    err
+                );
+
+                gst::FlowError::Error
+            })?,
+            DataframeOutputType::Json => {
+                dataframe_to_json_bytearray(windowed_df).map_err(|err| {
+                    gst::error!(CAT, "Failed to serialize json from dataframe: {:?}", err);
+                    gst::FlowError::Error
+                })?
+            }
+            DataframeOutputType::JsonFramed => dataframe_to_json_framed_bytearray(windowed_df)
+                .map_err(|err| {
                     gst::error!(
                         CAT,
-                        "Failed to serialize arrow ipc streaming msg: {:?}",
+                        "Failed to serialize framed json from dataframe: {:?}",
                         err
                     );
-
+                    gst::FlowError::Error
+                })?,
+            DataframeOutputType::Ndjson => {
+                dataframe_to_ndjson_bytearray(windowed_df).map_err(|err| {
+                    gst::error!(CAT, "Failed to serialize ndjson from dataframe: {:?}", err);
                     gst::FlowError::Error
                 })?
             }
-            DataframeOutputType::Json => {
-                dataframe_to_json_bytearray(&mut windowed_df).map_err(|err| {
-                    gst::error!(CAT, "Failed to serialize json from dataframe: {:?}", err);
+            DataframeOutputType::JsonColumns => dataframe_to_json_columns_bytearray(windowed_df)
+                .map_err(|err| {
+                    gst::error!(
+                        CAT,
+                        "Failed to serialize column-oriented json from dataframe: {:?}",
+                        err
+                    );
                     gst::FlowError::Error
-                })?
+                })?,
+        };
+        let caps = match settings.output_type {
+            DataframeOutputType::ArrowStreamingIpc => arrow_stream_caps(
+                settings.ipc_compression,
+                &schema_fingerprint(&windowed_df.schema().to_arrow()),
+            ),
+            output_type => caps_with_metadata(output_type.caps(), &metadata),
+        };
+        Ok(Output {
+            caps,
+            header: None,
+            buffer: gst::Buffer::from_slice(output_buffer),
+        })
+    }
+
+    // Push serialized output to pad. Caps are updated first if they changed, for example when the schema fingerprint changes
+    // When a new arrow ipc stream begins, caps carrying the schema message as streamheader are pushed first (multifdsink/tcpserversink send streamheader buffers to every new client), followed by the schema message itself
+    fn push_output(
+        &self,
+        pad: &gst::Pad,
+        output: Output,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let caps_changed = match pad.current_caps() {
+            Some(current) => !current.is_subset(&output.caps),
+            None => true,
+        };
+        if output.header.is_some() || caps_changed {
+            gst::debug!(CAT, obj: pad, "Updating output caps {:?}", output.caps);
+            pad.push_event(gst::event::Caps::new(&output.caps));
+        }
+        if let Some(header) = output.header {
+            pad.push(header)?;
+        }
+        pad.push(output.buffer)
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        gst::log!(CAT, obj: pad, "Handling buffer {:?}", buffer);
+        self.check_reconfigure()?;
+
+        let settings = self.settings.lock().unwrap();
+        self.ingest(&settings, buffer)?;
+
+        // in interval mode, output is pushed by the clock callback scheduled in start_interval_timer()
+        if settings.emit_mode == DataframeEmitMode::Interval {
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.received_since_emit = false;
+        let localdf = state.dataframe.clone();
+        // release state lock
+        drop(state);
+
+        let mut windowed_df = self.window(&settings, localdf)?;
+        let mut state = self.state.lock().unwrap();
+        let metadata = output_metadata(&settings, &state.upstream_metadata);
+        let output = self.serialize(
+            &settings,
+            &mut windowed_df,
+            &mut state.ipc_encoder,
+            metadata,
+        )?;
+        drop(state);
+        drop(settings);
+
+        self.push_output(&self.srcpad, output)
+    }
+
+    // Called from the pipeline clock every window-interval when emit-mode=interval
+    // Observations older than max-size-duration (relative to the current running time) are expired, even if no new buffers arrived.
+    // If no observations fall into the most recent window period, a placeholder observation is added so a zero-count window is emitted.
+    // The heartbeat column is true when no input buffer was received since the previous interval output.
+    fn emit_interval(&self) -> Result<gst::FlowSuccess, gst::FlowError> {
+        self.check_reconfigure()?;
+        let settings = self.settings.lock().unwrap();
+        let ts = self.clock_time(settings);
+        let rt = self.running_time();
+
+        let mut state = self.state.lock().unwrap();
+        if !state.segment_sent || state.eos {
+            return Ok(gst::FlowSuccess::Ok);
+        }
+        let heartbeat = !state.received_since_emit;
+        state.received_since_emit = false;
+        // close the open episode when no frames arrive for longer than episode-gap
+        let episode_event = state
+            .episodes
+            .expire(rt, Duration::parse(&settings.episode_gap).nanoseconds());
+
+        let max_duration = Duration::parse(&settings.max_size_duration);
+        state.dataframe = state
+            .dataframe
+            .clone()
+            .lazy()
+            .filter(col("rt").gt(lit(rt - max_duration.nanoseconds())))
+            .collect()
+            .map_err(|err| {
+                gst::error!(CAT, "Failed to expire dataframe observations: {}", err);
+                gst::FlowError::Error
+            })?;
+        let mut localdf = state.dataframe.clone();
+        // release state lock
+        drop(state);
+        self.push_episodes(&settings, episode_event);
+
+        let window_period = Duration::parse(&settings.window_period);
+        let latest_rt = localdf
+            .column("rt")
+            .ok()
+            .and_then(|rt_column| rt_column.max::<i64>());
+        let window_is_empty = match latest_rt {
+            Some(latest_rt) => latest_rt <= rt - window_period.nanoseconds(),
+            None => true,
+        };
+        if window_is_empty {
+            let placeholder = df!(
+                "detection_boxes_x0" => [None::<f32>],
+                "detection_boxes_y0" => [None::<f32>],
+                "detection_boxes_x1" => [None::<f32>],
+                "detection_boxes_y1" => [None::<f32>],
+                "detection_classes" => [None::<i32>],
+                "detection_scores" => [None::<f32>],
+                "ts" => [ts],
+                "rt" => [rt]
+            )
+            .expect("Failed to initialize dataframe");
+            localdf = concat(vec![localdf.lazy(), placeholder.lazy()], true, false)
+                .and_then(|lf| lf.collect())
+                .map_err(|err| {
+                    gst::error!(CAT, "Failed to merge dataframes: {}", err);
+                    gst::FlowError::Error
+                })?;
+        }
+
+        let mut windowed_df = self
+            .window(&settings, localdf)?
+            .lazy()
+            .with_column(lit(heartbeat).alias("heartbeat"))
+            .collect()
+            .map_err(|err| {
+                gst::error!(CAT, "Failed to add heartbeat column {}", err);
+                gst::FlowError::Error
+            })?;
+        let mut state = self.state.lock().unwrap();
+        let metadata = output_metadata(&settings, &state.upstream_metadata);
+        let output = self.serialize(
+            &settings,
+            &mut windowed_df,
+            &mut state.ipc_encoder,
+            metadata,
+        )?;
+        drop(state);
+        drop(settings);
+
+        // eos may have arrived while the window was calculated. The src pad stream lock is held until the output is pushed, so eos is either seen here or pushed after this output
+        let _stream_lock = self.srcpad.stream_lock();
+        if self.state.lock().unwrap().eos {
+            return Ok(gst::FlowSuccess::Ok);
+        }
+        self.push_output(&self.srcpad, output)
+    }
+
+    // Schedule a periodic clock callback, which pushes aggregate output every window-interval
+    fn start_interval_timer(&self) {
+        let settings = self.settings.lock().unwrap();
+        if settings.emit_mode != DataframeEmitMode::Interval {
+            return;
+        }
+        let element = self.instance();
+        let clock = match element.clock() {
+            Some(clock) => clock,
+            None => {
+                gst::warning!(
+                    CAT,
+                    obj: element,
+                    "No pipeline clock available, interval output is disabled"
+                );
+                return;
             }
         };
+        let interval = Duration::parse(&settings.window_interval);
+        drop(settings);
+
+        let interval = gst::ClockTime::from_nseconds(interval.nanoseconds() as u64);
+        let start = clock.time().unwrap_or(gst::ClockTime::ZERO) + interval;
+        let clock_id = clock.new_periodic_id(start, interval);
+
+        let element_weak = element.downgrade();
+        let res = clock_id.wait_async(move |_clock, _time, _id| {
+            let element = match element_weak.upgrade() {
+                Some(element) => element,
+                None => return,
+            };
+            match element.imp().emit_interval() {
+                Ok(_) | Err(gst::FlowError::Flushing) => (),
+                Err(err) => {
+                    gst::warning!(CAT, obj: &element, "Failed to push interval output: {:?}", err)
+                }
+            }
+        });
+        if let Err(err) = res {
+            gst::error!(
+                CAT,
+                obj: element,
+                "Failed to schedule interval clock callback: {:?}",
+                err
+            );
+            return;
+        }
+        gst::debug!(
+            CAT,
+            obj: element,
+            "Scheduled interval output every {}",
+            interval
+        );
+        self.state.lock().unwrap().clock_id = Some(clock_id);
+    }
 
-        self.srcpad.push(gst::Buffer::from_slice(output_buffer))
+    fn stop_interval_timer(&self) {
+        if let Some(clock_id) = self.state.lock().unwrap().clock_id.take() {
+            clock_id.unschedule();
+        }
     }
 }
 
@@ -370,6 +1306,7 @@ impl ObjectSubclass for DataframeAgg {
         Self {
             sinkpad,
             srcpad,
+            episode_srcpad: Mutex::new(None),
             state: Arc::new(Mutex::new(State::default())),
             settings: Arc::new(Mutex::new(Settings::default())),
         }
@@ -401,6 +1338,7 @@ impl ObjectImpl for DataframeAgg {
                     .nick("Window Interval")
                     .blurb("Interval between window occurrences")
                     .default_value(DEFAULT_WINDOW_INTERVAL)
+                    .mutable_playing()
                     .build(),
                 glib::ParamSpecString::builder("window-period")
                     .nick("Window Period")
@@ -434,7 +1372,108 @@ impl ObjectImpl for DataframeAgg {
                     .build(),
                 glib::ParamSpecEnum::builder::<DataframeOutputType>("output-type", DEFAULT_OUTPUT_TYPE)
                     .nick("Output Format Type")
-                    .blurb("Format of output buffer")
+                    .blurb("Format of output buffer. If not set, the first format accepted by downstream is used")
+                    .build(),
+                glib::ParamSpecEnum::builder::<IpcCompression>("ipc-compression", DEFAULT_IPC_COMPRESSION)
+                    .nick("IPC Compression")
+                    .blurb("Compression codec applied to record batches when output-type=arrow-streaming-ipc")
+                    .build(),
+                glib::ParamSpecEnum::builder::<JsonTimestampFormat>("json-timestamp-format", DEFAULT_JSON_TIMESTAMP_FORMAT)
+                    .nick("JSON Timestamp Format")
+                    .blurb("Render timestamp columns (ts, rt, rt__min, rt__max) as i64 nanoseconds (raw) or ISO-8601 strings (iso8601) in JSON output types. With iso8601, ts holds wall-clock time instead of pipeline clock time, so it can be rendered as a date")
+                    .build(),
+                glib::ParamSpecEnum::builder::<IpcStreamMode>("ipc-stream-mode", DEFAULT_IPC_STREAM_MODE)
+                    .nick("IPC Stream Mode")
+                    .blurb("When output-type=arrow-streaming-ipc, push a complete arrow ipc stream per buffer (message), or send the schema once per caps/segment followed by record batches (continuous)")
+                    .build(),
+                glib::ParamSpecString::builder("score-weights")
+                    .nick("Score Weights")
+                    .blurb("Comma-separated label=weight pairs used to calculate failure_score, for example: adhesion=0.5,spaghetti=1.0")
+                    .default_value(DEFAULT_SCORE_WEIGHTS)
+                    .build(),
+                glib::ParamSpecFloat::builder("score-decay")
+                    .nick("Score Decay")
+                    .blurb("Weight of the previous window's failure_score. Float between 0 - 1")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_SCORE_DECAY)
+                    .build(),
+                glib::ParamSpecFloat::builder("score-alert-threshold")
+                    .nick("Score Alert Threshold")
+                    .blurb("Raise failure_alert when failure_score is above threshold. Float between 0 - 1")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_SCORE_ALERT_THRESHOLD)
+                    .build(),
+                glib::ParamSpecFloat::builder("score-clear-threshold")
+                    .nick("Score Clear Threshold")
+                    .blurb("Clear failure_alert when failure_score is below threshold. Float between 0 - 1")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_SCORE_CLEAR_THRESHOLD)
+                    .build(),
+                glib::ParamSpecString::builder("episode-label")
+                    .nick("Episode Label")
+                    .blurb("Detect failure episodes where this label is detected above episode-threshold. Empty string disables episode detection")
+                    .default_value(DEFAULT_EPISODE_LABEL)
+                    .build(),
+                glib::ParamSpecFloat::builder("episode-threshold")
+                    .nick("Episode Threshold")
+                    .blurb("Minimum detection_score of episode-label for a frame to be part of an episode. Float between 0 - 1")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_EPISODE_THRESHOLD)
+                    .build(),
+                glib::ParamSpecString::builder("episode-gap")
+                    .nick("Episode Gap")
+                    .blurb("Episode ends when no frame is above episode-threshold for this duration")
+                    .default_value(DEFAULT_EPISODE_GAP)
+                    .build(),
+                glib::ParamSpecUInt::builder("episode-min-frames")
+                    .nick("Episode Min Frames")
+                    .blurb("Minimum number of frames above episode-threshold before an episode start record is emitted")
+                    .minimum(1)
+                    .default_value(DEFAULT_EPISODE_MIN_FRAMES)
+                    .build(),
+                glib::ParamSpecEnum::builder::<DataframeAnomalyMode>("anomaly-mode", DEFAULT_ANOMALY_MODE)
+                    .nick("Anomaly Mode")
+                    .blurb("Flag windows where label__count or label__mean changes sharply, using an EWMA z-score or CUSUM change-point detector")
+                    .build(),
+                glib::ParamSpecFloat::builder("anomaly-alpha")
+                    .nick("Anomaly Alpha")
+                    .blurb("Smoothing factor of the exponentially-weighted baseline. Float between 0 - 1")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_ANOMALY_ALPHA)
+                    .build(),
+                glib::ParamSpecFloat::builder("anomaly-threshold")
+                    .nick("Anomaly Threshold")
+                    .blurb("z-score (ewma) or cumulative sum (cusum) threshold, in standard deviations")
+                    .minimum(0.0)
+                    .default_value(DEFAULT_ANOMALY_THRESHOLD)
+                    .build(),
+                glib::ParamSpecFloat::builder("anomaly-drift")
+                    .nick("Anomaly Drift")
+                    .blurb("Deviations smaller than drift are not accumulated in cusum mode, in standard deviations")
+                    .minimum(0.0)
+                    .default_value(DEFAULT_ANOMALY_DRIFT)
+                    .build(),
+                glib::ParamSpecString::builder("model-file")
+                    .nick("Model File")
+                    .blurb("Path to the model producing detections. File name and sha256 digest are added to output schema metadata. The digest is calculated when the element starts")
+                    .build(),
+                glib::ParamSpecString::builder("label-file")
+                    .nick("Label File")
+                    .blurb("Path to labels.txt file (one label per line). Labels are added to output schema metadata")
+                    .build(),
+                glib::ParamSpecString::builder("camera-id")
+                    .nick("Camera ID")
+                    .blurb("Camera identifier added to output schema metadata")
+                    .build(),
+                glib::ParamSpecEnum::builder::<DataframeEmitMode>("emit-mode", DEFAULT_EMIT_MODE)
+                    .nick("Emit Mode")
+                    .blurb("Push output when an input buffer is received (buffer), or every window-interval using the pipeline clock (interval). May be changed while playing")
+                    .mutable_playing()
                     .build(),
             ]
         });
@@ -447,6 +1486,31 @@ impl ObjectImpl for DataframeAgg {
         match pspec.name() {
             "ddof" => settings.ddof.to_value(),
             "output-type" => settings.output_type.to_value(),
+            "emit-mode" => settings.emit_mode.to_value(),
+            "ipc-compression" => settings.ipc_compression.to_value(),
+            "ipc-stream-mode" => settings.ipc_stream_mode.to_value(),
+            "json-timestamp-format" => settings.json_timestamp_format.to_value(),
+            "score-weights" => settings
+                .score_weights
+                .iter()
+                .map(|(label, weight)| format!("{}={}", label, weight))
+                .collect::<Vec<String>>()
+                .join(",")
+                .to_value(),
+            "score-decay" => settings.score_decay.to_value(),
+            "episode-label" => settings.episode_label.to_value(),
+            "anomaly-mode" => settings.anomaly_mode.to_value(),
+            "anomaly-alpha" => settings.anomaly_alpha.to_value(),
+            "anomaly-threshold" => settings.anomaly_threshold.to_value(),
+            "anomaly-drift" => settings.anomaly_drift.to_value(),
+            "model-file" => settings.source_metadata.model_file.to_value(),
+            "label-file" => settings.label_file.to_value(),
+            "camera-id" => settings.source_metadata.camera_id.to_value(),
+            "episode-threshold" => settings.episode_threshold.to_value(),
+            "episode-gap" => settings.episode_gap.to_value(),
+            "episode-min-frames" => settings.episode_min_frames.to_value(),
+            "score-alert-threshold" => settings.score_alert_threshold.to_value(),
+            "score-clear-threshold" => settings.score_clear_threshold.to_value(),
             "filter-threshold" => settings.filter_threshold.to_value(),
             "max-size-buffers" => settings.max_size_buffers.to_value(),
             "max-size-duration" => settings.max_size_duration.to_value(),
@@ -461,6 +1525,7 @@ impl ObjectImpl for DataframeAgg {
 
     fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
         let mut settings = self.settings.lock().unwrap();
+        let restart_interval_timer = matches!(pspec.name(), "emit-mode" | "window-interval");
 
         match pspec.name() {
             "ddof" => {
@@ -470,6 +1535,120 @@ impl ObjectImpl for DataframeAgg {
                 settings.output_type = value
                     .get::<DataframeOutputType>()
                     .expect("type checked upstream");
+                settings.output_type_set = true;
+            }
+            "emit-mode" => {
+                settings.emit_mode = value
+                    .get::<DataframeEmitMode>()
+                    .expect("type checked upstream");
+            }
+            "ipc-compression" => {
+                settings.ipc_compression = value
+                    .get::<IpcCompression>()
+                    .expect("type checked upstream");
+            }
+            "json-timestamp-format" => {
+                settings.json_timestamp_format = value
+                    .get::<JsonTimestampFormat>()
+                    .expect("type checked upstream");
+            }
+            "ipc-stream-mode" => {
+                settings.ipc_stream_mode =
+                    value.get::<IpcStreamMode>().expect("type checked upstream");
+            }
+            "score-weights" => {
+                let score_weights = value.get::<String>().expect("type checked upstream");
+                match parse_score_weights(&score_weights) {
+                    Ok(weights) => settings.score_weights = weights,
+                    Err(err) => {
+                        gst::error!(CAT, "Invalid score-weights {}: {}", score_weights, err)
+                    }
+                }
+            }
+            "score-decay" => {
+                settings.score_decay = value.get::<f32>().expect("type checked upstream");
+            }
+            "anomaly-mode" => {
+                settings.anomaly_mode = value
+                    .get::<DataframeAnomalyMode>()
+                    .expect("type checked upstream");
+            }
+            "anomaly-alpha" => {
+                settings.anomaly_alpha = value.get::<f32>().expect("type checked upstream");
+            }
+            "anomaly-threshold" => {
+                settings.anomaly_threshold = value.get::<f32>().expect("type checked upstream");
+            }
+            "anomaly-drift" => {
+                settings.anomaly_drift = value.get::<f32>().expect("type checked upstream");
+            }
+            "model-file" => {
+                let model_file = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+                // sha256 digest is calculated when the element starts, see hash_model_file()
+                if settings.source_metadata.model_file != model_file {
+                    settings.source_metadata.model_sha256 = None;
+                }
+                settings.source_metadata.model_file = model_file;
+            }
+            "label-file" => {
+                let label_file = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+                settings.source_metadata.labels = match &label_file {
+                    Some(label_file) => match read_labels(label_file) {
+                        Ok(labels) => Some(labels),
+                        Err(err) => {
+                            gst::error!(CAT, "Failed to read label-file {}: {}", label_file, err);
+                            None
+                        }
+                    },
+                    None => Some(
+                        DEFAULT_LABELS
+                            .iter()
+                            .map(|label| label.to_string())
+                            .collect(),
+                    ),
+                };
+                settings.label_file = label_file;
+            }
+            "camera-id" => {
+                settings.source_metadata.camera_id = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "episode-label" => {
+                settings.episode_label = value.get::<String>().expect("type checked upstream");
+            }
+            "episode-threshold" => {
+                settings.episode_threshold = value.get::<f32>().expect("type checked upstream");
+            }
+            "episode-gap" => {
+                settings.episode_gap = value.get::<String>().expect("type checked upstream");
+            }
+            "episode-min-frames" => {
+                settings.episode_min_frames = value.get::<u32>().expect("type checked upstream");
+            }
+            "score-alert-threshold" | "score-clear-threshold" => {
+                let threshold = value.get::<f32>().expect("type checked upstream");
+                let (alert, clear) = match pspec.name() {
+                    "score-alert-threshold" => (threshold, settings.score_clear_threshold),
+                    _ => (settings.score_alert_threshold, threshold),
+                };
+                // thresholds may be set in any order before the element starts, and are validated again in change_state()
+                match validate_thresholds(alert, clear) {
+                    Err(err) if self.instance().current_state() > gst::State::Ready => {
+                        gst::error!(CAT, "Ignoring {}: {}", pspec.name(), err);
+                    }
+                    res => {
+                        if let Err(err) = res {
+                            gst::warning!(CAT, "{}", err);
+                        }
+                        settings.score_alert_threshold = alert;
+                        settings.score_clear_threshold = clear;
+                    }
+                }
             }
             "filter-threshold" => {
                 settings.filter_threshold = value.get::<f32>().expect("type checked upstream");
@@ -498,6 +1677,13 @@ impl ObjectImpl for DataframeAgg {
             }
             _ => unimplemented!(),
         }
+        drop(settings);
+
+        // reschedule the interval clock callback, so emit-mode and window-interval changes apply while playing
+        if restart_interval_timer && self.instance().current_state() == gst::State::Playing {
+            self.stop_interval_timer();
+            self.start_interval_timer();
+        }
     }
 }
 
@@ -518,7 +1704,7 @@ impl ElementImpl for DataframeAgg {
     }
     fn pad_templates() -> &'static [gst::PadTemplate] {
         static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
-            let caps = gst::Caps::new_any();
+            let caps = dataframe_sink_caps();
 
             let sink_pad_template = gst::PadTemplate::new(
                 "sink",
@@ -528,7 +1714,7 @@ impl ElementImpl for DataframeAgg {
             )
             .unwrap();
 
-            let caps = gst::Caps::new_any();
+            let caps = DataframeOutputType::template_caps();
             let src_pad_template = gst::PadTemplate::new(
                 "src",
                 gst::PadDirection::Src,
@@ -537,12 +1723,56 @@ impl ElementImpl for DataframeAgg {
             )
             .unwrap();
 
-            vec![src_pad_template, sink_pad_template]
+            let caps = DataframeOutputType::template_caps();
+            let episode_src_pad_template = gst::PadTemplate::new(
+                "episode_src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Request,
+                &caps,
+            )
+            .unwrap();
+
+            vec![
+                src_pad_template,
+                sink_pad_template,
+                episode_src_pad_template,
+            ]
         });
 
         PAD_TEMPLATES.as_ref()
     }
 
+    // episode_src is an optional pad, which outputs one record when a failure episode starts and ends
+    fn request_new_pad(
+        &self,
+        templ: &gst::PadTemplate,
+        _name: Option<&str>,
+        _caps: Option<&gst::Caps>,
+    ) -> Option<gst::Pad> {
+        let element = self.instance();
+        let mut episode_srcpad = self.episode_srcpad.lock().unwrap();
+        if episode_srcpad.is_some() {
+            gst::error!(CAT, obj: element, "episode_src pad was already requested");
+            return None;
+        }
+
+        let pad = gst::Pad::builder_with_template(templ, Some("episode_src")).build();
+        if element.current_state() > gst::State::Ready {
+            pad.set_active(true).ok()?;
+        }
+        element.add_pad(&pad).ok()?;
+        *episode_srcpad = Some(pad.clone());
+        Some(pad)
+    }
+
+    fn release_pad(&self, pad: &gst::Pad) {
+        let element = self.instance();
+        self.episode_srcpad.lock().unwrap().take();
+        self.state.lock().unwrap().episode_stream_started = false;
+        let _ = pad.set_active(false);
+        let _ = element.remove_pad(pad);
+    }
+
     // Called whenever the state of the element should be changed. This allows for
     // starting up the element, allocating/deallocating resources or shutting down
     // the element again.
@@ -553,7 +1783,30 @@ impl ElementImpl for DataframeAgg {
         let element = self.instance();
         gst::trace!(CAT, obj: element, "Changing state {:?}", transition);
 
+        if transition == gst::StateChange::NullToReady {
+            self.hash_model_file();
+        }
+        if transition == gst::StateChange::ReadyToPaused {
+            let settings = self.settings.lock().unwrap();
+            if let Err(err) = validate_thresholds(
+                settings.score_alert_threshold,
+                settings.score_clear_threshold,
+            ) {
+                gst::element_error!(element, gst::LibraryError::Settings, ["{}", err]);
+                return Err(gst::StateChangeError);
+            }
+        }
+        if transition == gst::StateChange::PlayingToPaused {
+            self.stop_interval_timer();
+        }
+
         // Call the parent class' implementation of ::change_state()
-        self.parent_change_state(transition)
+        let success = self.parent_change_state(transition)?;
+
+        if transition == gst::StateChange::PausedToPlaying {
+            self.start_interval_timer();
+        }
+
+        Ok(success)
     }
 }
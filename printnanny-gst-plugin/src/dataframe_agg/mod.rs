@@ -1,7 +1,15 @@
 use gst::glib;
 use gst::prelude::*;
 
+use crate::caps::{
+    ARROW_STREAM_MEDIA_TYPE, FRAMING_FIELD, FRAMING_LENGTH_PREFIXED, JSON_MEDIA_TYPE,
+    LAYOUT_COLUMNS, LAYOUT_FIELD, NDJSON_MEDIA_TYPE,
+};
+
+mod anomaly;
+mod episode;
 mod imp;
+mod score;
 
 // This enum may be used to control what type of output the dataframe aggregator produces
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
@@ -18,6 +26,92 @@ pub enum DataframeOutputType {
         nick = "json"
     )]
     Json = 1,
+    #[enum_value(
+        name = "JSON Framed: output the aggregate dataframe as JSON bytearray, prefixed with payload length (4 bytes, big-endian u32)",
+        nick = "json-framed"
+    )]
+    JsonFramed = 2,
+    #[enum_value(
+        name = "NDJSON: output the aggregate dataframe as newline-delimited JSON, one object per row",
+        nick = "ndjson"
+    )]
+    Ndjson = 3,
+    #[enum_value(
+        name = "JSON Columns: output the aggregate dataframe as column-oriented JSON object, for example {\"col\": [...]}",
+        nick = "json-columns"
+    )]
+    JsonColumns = 4,
+}
+
+impl DataframeOutputType {
+    pub const ALL: [DataframeOutputType; 5] = [
+        DataframeOutputType::ArrowStreamingIpc,
+        DataframeOutputType::Json,
+        DataframeOutputType::JsonFramed,
+        DataframeOutputType::Ndjson,
+        DataframeOutputType::JsonColumns,
+    ];
+
+    pub fn caps(&self) -> gst::Caps {
+        match self {
+            DataframeOutputType::ArrowStreamingIpc => {
+                gst::Caps::builder(ARROW_STREAM_MEDIA_TYPE).build()
+            }
+            DataframeOutputType::Json => gst::Caps::builder(JSON_MEDIA_TYPE).build(),
+            DataframeOutputType::JsonFramed => gst::Caps::builder(JSON_MEDIA_TYPE)
+                .field(FRAMING_FIELD, FRAMING_LENGTH_PREFIXED)
+                .build(),
+            DataframeOutputType::Ndjson => gst::Caps::builder(NDJSON_MEDIA_TYPE).build(),
+            DataframeOutputType::JsonColumns => gst::Caps::builder(JSON_MEDIA_TYPE)
+                .field(LAYOUT_FIELD, LAYOUT_COLUMNS)
+                .build(),
+        }
+    }
+
+    // Caps of all output types, used by src pad templates
+    pub fn template_caps() -> gst::Caps {
+        let mut caps = gst::Caps::new_empty();
+        for output_type in DataframeOutputType::ALL {
+            caps.make_mut().merge(output_type.caps());
+        }
+        caps
+    }
+}
+
+// This enum controls when the dataframe aggregator pushes output buffers
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDataframeAggEmitMode")]
+pub enum DataframeEmitMode {
+    #[enum_value(
+        name = "Buffer: push an aggregate dataframe every time an input buffer is received",
+        nick = "buffer"
+    )]
+    Buffer = 0,
+    #[enum_value(
+        name = "Interval: push an aggregate dataframe every window-interval, driven by the pipeline clock",
+        nick = "interval"
+    )]
+    Interval = 1,
+}
+
+// This enum selects the change-point detector used to flag anomalous windows
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDataframeAggAnomalyMode")]
+pub enum DataframeAnomalyMode {
+    #[enum_value(name = "Disabled: anomaly detection is disabled", nick = "disabled")]
+    Disabled = 0,
+    #[enum_value(
+        name = "EWMA: flag windows where the z-score against an exponentially-weighted baseline exceeds anomaly-threshold",
+        nick = "ewma"
+    )]
+    Ewma = 1,
+    #[enum_value(
+        name = "CUSUM: flag windows where the cumulative sum of deviations exceeds anomaly-threshold",
+        nick = "cusum"
+    )]
+    Cusum = 2,
 }
 
 // The public Rust wrapper type for our element
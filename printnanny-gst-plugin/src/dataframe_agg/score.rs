@@ -0,0 +1,410 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::num::ParseFloatError;
+
+use polars::prelude::*;
+use thiserror::Error;
+
+use super::anomaly::completed_windows;
+
+#[derive(Error, Debug)]
+pub enum ScoreError {
+    #[error("Expected score weight in label=weight format, but got: {weight}")]
+    InvalidWeight { weight: String },
+    #[error("Failed to parse float from score weight")]
+    ParseFloatError {
+        #[from]
+        source: ParseFloatError,
+    },
+    #[error("score-clear-threshold {clear} must be below score-alert-threshold {alert}")]
+    InvalidThresholds { alert: f32, clear: f32 },
+}
+
+// Hysteresis requires the clear threshold to be below the alert threshold
+pub fn validate_thresholds(alert: f32, clear: f32) -> Result<(), ScoreError> {
+    match clear < alert {
+        true => Ok(()),
+        false => Err(ScoreError::InvalidThresholds { alert, clear }),
+    }
+}
+
+// Parse a comma-separated String of label=weight pairs, for example "adhesion=0.5,spaghetti=1.0" -> [("adhesion", 0.5), ("spaghetti", 1.0)]
+pub fn parse_score_weights(score_weights: &str) -> Result<Vec<(String, f64)>, ScoreError> {
+    score_weights
+        .split(',')
+        .filter(|w| !w.trim().is_empty())
+        .map(|w| match w.split_once('=') {
+            Some((label, weight)) => Ok((label.trim().to_string(), weight.trim().parse::<f64>()?)),
+            None => Err(ScoreError::InvalidWeight {
+                weight: w.to_string(),
+            }),
+        })
+        .collect()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FailureScore {
+    pub score: f64,
+    pub alert: bool,
+}
+
+impl FailureScore {
+    // Smooth raw window evidence with an exponential decay, then apply hysteresis:
+    // alert is raised when score >= alert_threshold, and is only cleared once score <= clear_threshold
+    pub fn next(
+        &self,
+        evidence: f64,
+        decay: f64,
+        alert_threshold: f64,
+        clear_threshold: f64,
+    ) -> FailureScore {
+        let score = decay * self.score + (1.0 - decay) * evidence;
+        let alert = if score >= alert_threshold {
+            true
+        } else if score <= clear_threshold {
+            false
+        } else {
+            self.alert
+        };
+        FailureScore { score, alert }
+    }
+}
+
+pub struct ScoreOptions<'a> {
+    pub weights: &'a [(String, f64)],
+    pub labels: &'a [&'a str],
+    pub decay: f64,
+    pub alert_threshold: f64,
+    pub clear_threshold: f64,
+    pub every: i64,        // window-interval in nanoseconds
+    pub period: i64,       // window-period in nanoseconds
+    pub max_duration: i64, // max-size-duration in nanoseconds
+}
+
+// Running failure score, carried across aggregations so decay and hysteresis continue from the previous completed window
+#[derive(Debug, Default)]
+pub struct FailureScoreTracker {
+    running: FailureScore,    // score of the most recent completed window
+    last_window: Option<i64>, // start of the most recent completed window
+    scores: BTreeMap<i64, FailureScore>, // scores of completed windows, keyed by window start
+}
+
+impl FailureScoreTracker {
+    // Add failure_score and failure_alert columns to windowed dataframe
+    // Evidence for each window is the weighted sum of detection scores (label__count * label__mean * weight), divided by the total number of detections in the window
+    // Windows are scored once, after the window's upper boundary has passed rt_now. Windows which are not complete yet get a provisional score, which continues from the running score without updating it
+    // Completed windows without detections are missing from windowed_df, and are scored with zero evidence so the score decays after detections stop
+    // Returns the running score if its alert flag changed
+    pub fn with_failure_score(
+        &mut self,
+        windowed_df: DataFrame,
+        options: &ScoreOptions,
+        rt_now: i64,
+    ) -> Result<(DataFrame, Option<FailureScore>), PolarsError> {
+        // weights of labels missing from label-file are ignored
+        let evidence = options
+            .weights
+            .iter()
+            .filter(|(label, _)| options.labels.contains(&label.as_str()))
+            .fold(lit(0.0), |acc, (label, weight)| {
+                acc + lit(*weight)
+                    * col(&format!("{}__count", label)).cast(DataType::Float64)
+                    * col(&format!("{}__mean", label))
+                        .cast(DataType::Float64)
+                        .fill_null(lit(0.0))
+            });
+        let total = options.labels.iter().fold(lit(0.0), |acc, label| {
+            acc + col(&format!("{}__count", label)).cast(DataType::Float64)
+        });
+
+        let windows = windowed_df
+            .clone()
+            .lazy()
+            .with_columns([evidence.alias("evidence"), total.alias("total")])
+            .groupby([col("rt")])
+            .agg([
+                col("evidence").sum(),
+                col("total").sum(),
+                col("_upper_boundary")
+                    .first()
+                    .cast(DataType::Int64)
+                    .alias("_upper_boundary"),
+            ])
+            .sort(
+                "rt",
+                SortOptions {
+                    descending: false,
+                    nulls_last: false,
+                },
+            )
+            .collect()?;
+
+        let rt = windows.column("rt")?.clone();
+        let starts: Vec<i64> = rt
+            .cast(&DataType::Int64)?
+            .i64()?
+            .into_iter()
+            .map(|rt| rt.unwrap_or_default())
+            .collect();
+        let upper_boundaries = windows.column("_upper_boundary")?.i64()?.clone();
+        let evidence: Vec<f64> = windows
+            .column("evidence")?
+            .f64()?
+            .into_iter()
+            .zip(windows.column("total")?.f64()?.into_iter())
+            .map(|(evidence, total)| match (evidence, total) {
+                (Some(evidence), Some(total)) if total > 0.0 => evidence / total,
+                _ => 0.0,
+            })
+            .collect();
+
+        let alert = self.running.alert;
+        let mut pending: BTreeSet<i64> = starts
+            .iter()
+            .enumerate()
+            .filter(|(i, window)| {
+                let window_complete = match upper_boundaries.get(*i) {
+                    Some(upper_boundary) => upper_boundary <= rt_now,
+                    None => false,
+                };
+                window_complete && self.last_window.map_or(true, |last| **window > last)
+            })
+            .map(|(_, window)| *window)
+            .collect();
+        let first = match self.last_window {
+            Some(last) => Some(last + options.every),
+            None => starts.first().copied(),
+        };
+        if let Some(first) = first {
+            pending.extend(completed_windows(
+                first,
+                options.every,
+                options.period,
+                rt_now - options.max_duration,
+                rt_now,
+            ));
+        }
+        let rows: HashMap<i64, f64> = starts.iter().copied().zip(evidence).collect();
+        for window in pending {
+            let evidence = rows.get(&window).copied().unwrap_or(0.0);
+            self.running = self.running.next(
+                evidence,
+                options.decay,
+                options.alert_threshold,
+                options.clear_threshold,
+            );
+            self.last_window = Some(window);
+            self.scores.insert(window, self.running);
+        }
+
+        let scores: Vec<FailureScore> = starts
+            .iter()
+            .map(|window| match self.scores.get(window) {
+                Some(score) => *score,
+                None => self.running.next(
+                    rows[window],
+                    options.decay,
+                    options.alert_threshold,
+                    options.clear_threshold,
+                ),
+            })
+            .collect();
+        // forget windows which are no longer part of the aggregate output
+        match starts.first() {
+            Some(first) => self.scores.retain(|window, _| window >= first),
+            None => self.scores.clear(),
+        }
+
+        let scores_df = DataFrame::new(vec![
+            rt,
+            Series::new(
+                "failure_score",
+                scores.iter().map(|s| s.score).collect::<Vec<f64>>(),
+            ),
+            Series::new(
+                "failure_alert",
+                scores.iter().map(|s| s.alert).collect::<Vec<bool>>(),
+            ),
+        ])?;
+
+        let df = windowed_df
+            .lazy()
+            .left_join(scores_df.lazy(), col("rt"), col("rt"))
+            .collect()?;
+        let changed = match self.running.alert != alert {
+            true => Some(self.running),
+            false => None,
+        };
+        Ok((df, changed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Scores of a sequence of window evidence, starting from zero
+    fn failure_scores(
+        evidence: &[f64],
+        decay: f64,
+        alert_threshold: f64,
+        clear_threshold: f64,
+    ) -> Vec<FailureScore> {
+        let mut score = FailureScore::default();
+        evidence
+            .iter()
+            .map(|e| {
+                score = score.next(*e, decay, alert_threshold, clear_threshold);
+                score
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_score_weights() {
+        let weights = parse_score_weights("adhesion=0.5, spaghetti=1.0").unwrap();
+        assert_eq!(
+            weights,
+            vec![
+                ("adhesion".to_string(), 0.5),
+                ("spaghetti".to_string(), 1.0)
+            ]
+        );
+        assert!(parse_score_weights("spaghetti").is_err());
+        assert!(parse_score_weights("spaghetti=high").is_err());
+    }
+
+    #[test]
+    fn test_failure_scores_hysteresis() {
+        let evidence = [1.0, 1.0, 0.5, 0.5, 0.0, 0.0];
+        let scores = failure_scores(&evidence, 0.0, 0.8, 0.2);
+        let alerts: Vec<bool> = scores.iter().map(|s| s.alert).collect();
+        // alert is held between thresholds, and cleared below clear_threshold
+        assert_eq!(alerts, vec![true, true, true, true, false, false]);
+    }
+
+    #[test]
+    fn test_failure_scores_decay() {
+        let scores = failure_scores(&[1.0, 1.0], 0.5, 0.8, 0.2);
+        assert_eq!(scores[0].score, 0.5);
+        assert_eq!(scores[1].score, 0.75);
+        assert!(!scores[1].alert);
+    }
+
+    fn windowed_df(windows: &[(i64, u32)]) -> DataFrame {
+        df!(
+            "rt" => windows.iter().map(|(rt, _)| *rt).collect::<Vec<i64>>(),
+            "_upper_boundary" => windows.iter().map(|(rt, _)| rt + 10).collect::<Vec<i64>>(),
+            "spaghetti__count" => windows.iter().map(|(_, count)| *count).collect::<Vec<u32>>(),
+            "spaghetti__mean" => windows.iter().map(|(_, count)| match count {
+                0 => None,
+                _ => Some(1.0_f32),
+            }).collect::<Vec<Option<f32>>>()
+        )
+        .unwrap()
+    }
+
+    fn score_options(weights: &[(String, f64)]) -> ScoreOptions {
+        ScoreOptions {
+            weights,
+            labels: &["spaghetti"],
+            decay: 0.5,
+            alert_threshold: 0.6,
+            clear_threshold: 0.2,
+            every: 10,
+            period: 10,
+            max_duration: 1000,
+        }
+    }
+
+    fn scores(df: &DataFrame) -> Vec<f64> {
+        df.column("failure_score")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_iter()
+            .map(|s| s.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_failure_score_tracker() {
+        let weights = vec![("spaghetti".to_string(), 1.0)];
+        let options = score_options(&weights);
+        let mut tracker = FailureScoreTracker::default();
+
+        // window 10 is not complete at rt 15, so its score is provisional
+        let df = windowed_df(&[(0, 1), (10, 1)]);
+        let (scored, changed) = tracker
+            .with_failure_score(df.clone(), &options, 15)
+            .unwrap();
+        assert_eq!(scores(&scored), vec![0.5, 0.75]);
+        assert_eq!(changed, None);
+
+        // scores of completed windows are not recalculated, so decay continues where the previous call left off
+        let (scored, changed) = tracker.with_failure_score(df, &options, 20).unwrap();
+        assert_eq!(scores(&scored), vec![0.5, 0.75]);
+        assert_eq!(
+            changed,
+            Some(FailureScore {
+                score: 0.75,
+                alert: true
+            })
+        );
+
+        // window 0 expired, window 20 continues from window 10
+        let df = windowed_df(&[(10, 1), (20, 0)]);
+        let (scored, changed) = tracker.with_failure_score(df, &options, 30).unwrap();
+        assert_eq!(scores(&scored), vec![0.75, 0.375]);
+        assert_eq!(changed, None);
+        assert_eq!(
+            tracker.scores.keys().copied().collect::<Vec<i64>>(),
+            vec![10, 20]
+        );
+    }
+
+    #[test]
+    fn test_failure_score_tracker_detections_stop() {
+        let weights = vec![("spaghetti".to_string(), 1.0)];
+        let options = score_options(&weights);
+        let mut tracker = FailureScoreTracker::default();
+
+        let (_, changed) = tracker
+            .with_failure_score(windowed_df(&[(0, 1), (10, 1)]), &options, 20)
+            .unwrap();
+        assert_eq!(
+            changed,
+            Some(FailureScore {
+                score: 0.75,
+                alert: true
+            })
+        );
+
+        // completed windows without detections are scored with zero evidence, and the alert is held above clear_threshold
+        let (scored, changed) = tracker
+            .with_failure_score(windowed_df(&[]), &options, 30)
+            .unwrap();
+        assert_eq!(scored.height(), 0);
+        assert_eq!(changed, None);
+        assert_eq!(tracker.running.score, 0.375);
+
+        let (_, changed) = tracker
+            .with_failure_score(windowed_df(&[]), &options, 40)
+            .unwrap();
+        assert_eq!(
+            changed,
+            Some(FailureScore {
+                score: 0.1875,
+                alert: false
+            })
+        );
+        assert_eq!(tracker.last_window, Some(30));
+        assert!(tracker.scores.is_empty());
+    }
+
+    #[test]
+    fn test_validate_thresholds() {
+        assert!(validate_thresholds(0.5, 0.3).is_ok());
+        assert!(validate_thresholds(0.5, 0.5).is_err());
+        assert!(validate_thresholds(0.3, 0.5).is_err());
+    }
+}
@@ -8,11 +8,19 @@ use gst::subclass::prelude::*;
 use once_cell::sync::Lazy;
 use std::sync::{Arc, Mutex};
 
+use crate::caps::dataframe_sink_caps;
+use crate::ipc::{
+    arrow_streaming_ipc_buffer_with_compression, is_arrow_streaming_ipc_message,
+    ArrowStreamingIpcEncoder, IpcCompression, IpcStreamMode,
+};
+
 const DEFAULT_LOCATION: &str = "dataframe%05d.ipc";
 const DEFAULT_MAX_FILE_DURATION: u64 = 18446744073709551615;
 const DEFAULT_MAX_FILE_SIZE: u64 = 2147483648;
 const DEFAULT_MAX_FILES: u32 = 0;
 const DEFAULT_POST_FILE_MESSAGES: bool = false;
+const DEFAULT_IPC_COMPRESSION: IpcCompression = IpcCompression::Uncompressed;
+const DEFAULT_IPC_STREAM_MODE: IpcStreamMode = IpcStreamMode::Message;
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -28,6 +36,8 @@ struct Settings {
     max_file_size: u64,     // Maximum file size before starting a new file in max-size mode.
     max_files: u32, // Maximum number of files to keep on disk. Once the maximum is reached, old files start to be deleted to make room for new ones.
     post_file_messages: bool, // Post a message on the GstBus for each file.
+    ipc_compression: IpcCompression, // Re-encode arrow ipc messages with this compression codec before writing to disk.
+    ipc_stream_mode: IpcStreamMode, // In continuous mode, each file is a single arrow ipc stream
 }
 
 impl Default for Settings {
@@ -38,7 +48,148 @@ impl Default for Settings {
             max_file_size: DEFAULT_MAX_FILE_SIZE,
             max_files: DEFAULT_MAX_FILES,
             post_file_messages: DEFAULT_POST_FILE_MESSAGES,
+            ipc_compression: DEFAULT_IPC_COMPRESSION,
+            ipc_stream_mode: DEFAULT_IPC_STREAM_MODE,
+        }
+    }
+}
+
+// Tracks the arrow ipc stream written to the current file when ipc-stream-mode=continuous
+struct ContinuousState {
+    encoder: ArrowStreamingIpcEncoder,
+    schema: Option<Vec<u8>>, // most recent schema message, written at the start of every file
+    schema_changed: bool,
+    upstream_continuous: bool, // true if upstream caps carry a streamheader, meaning buffers contain record batches only
+    file_size: u64,
+    file_start: Option<gst::ClockTime>,
+}
+
+impl Default for ContinuousState {
+    fn default() -> Self {
+        Self {
+            encoder: ArrowStreamingIpcEncoder::new(DEFAULT_IPC_COMPRESSION),
+            schema: None,
+            schema_changed: false,
+            upstream_continuous: false,
+            file_size: 0,
+            file_start: None,
+        }
+    }
+}
+
+// Rewrite buffers into a continuous arrow ipc stream. Every file starts with the schema message, followed by record batches.
+// multifilesink runs in next-file=key-frame mode, so buffers starting a new file are pushed without the DELTA_UNIT flag.
+// A new file is started when max-file-size or max-file-duration would be exceeded, or the schema changes.
+fn continuous_probe(
+    settings: &Settings,
+    state: &mut ContinuousState,
+    pad: &gst::Pad,
+    info: &mut gst::PadProbeInfo,
+) -> gst::PadProbeReturn {
+    match info.data {
+        Some(gst::PadProbeData::Event(ref mut event)) => {
+            if let gst::EventView::Caps(caps_event) = event.view() {
+                let mut caps = caps_event.caps_owned();
+                state.upstream_continuous = caps
+                    .structure(0)
+                    .map(|s| s.has_field("streamheader"))
+                    .unwrap_or(false);
+                // the schema is written in-band at the start of each file, instead of by multifilesink
+                if state.upstream_continuous {
+                    if let Some(s) = caps.make_mut().structure_mut(0) {
+                        s.remove_field("streamheader");
+                    }
+                    *event = gst::event::Caps::new(&caps);
+                }
+            }
+            if matches!(
+                event.type_(),
+                gst::EventType::Caps | gst::EventType::Segment | gst::EventType::FlushStop
+            ) {
+                state.encoder.reset();
+            }
+            gst::PadProbeReturn::Ok
+        }
+        Some(gst::PadProbeData::Buffer(ref mut buffer)) => {
+            let map = match buffer.map_readable() {
+                Ok(map) => map,
+                Err(_) => return gst::PadProbeReturn::Ok,
+            };
+            let batches = if state.upstream_continuous {
+                if buffer.flags().contains(gst::BufferFlags::HEADER) {
+                    state.schema = Some(map.to_vec());
+                    state.schema_changed = true;
+                    return gst::PadProbeReturn::Drop;
+                }
+                map.to_vec()
+            } else {
+                if !is_arrow_streaming_ipc_message(&map) {
+                    return gst::PadProbeReturn::Ok;
+                }
+                state.encoder.set_compression(settings.ipc_compression);
+                match state.encoder.encode_arrow_streaming_ipc_message(&map) {
+                    Ok(parts) => {
+                        if let Some(schema) = parts.schema {
+                            state.schema = Some(schema);
+                            state.schema_changed = true;
+                        }
+                        parts.batches
+                    }
+                    Err(err) => {
+                        gst::warning!(CAT, obj: pad, "Failed to split arrow ipc message: {:?}", err);
+                        return gst::PadProbeReturn::Ok;
+                    }
+                }
+            };
+            drop(map);
+
+            let schema = match &state.schema {
+                Some(schema) => schema,
+                None => {
+                    gst::warning!(CAT, obj: pad, "Dropping record batches received before arrow ipc schema");
+                    return gst::PadProbeReturn::Drop;
+                }
+            };
+            let pts = buffer.pts();
+            let exceeds_size = state.file_size + batches.len() as u64 > settings.max_file_size;
+            let exceeds_duration = match (state.file_start, pts) {
+                (Some(start), Some(pts)) => {
+                    pts.saturating_sub(start).nseconds() >= settings.max_file_duration
+                }
+                _ => false,
+            };
+            let new_file = state.file_size == 0 || state.schema_changed || exceeds_size || exceeds_duration;
+
+            let mut data = vec![];
+            if new_file {
+                data.extend(schema);
+                state.file_size = 0;
+                state.file_start = pts;
+                state.schema_changed = false;
+            }
+            data.extend(batches);
+            state.file_size += data.len() as u64;
+
+            let mut output = gst::Buffer::from_slice(data);
+            {
+                let output = output.get_mut().unwrap();
+                let _ = buffer.copy_into(
+                    output,
+                    gst::BufferCopyFlags::FLAGS
+                        | gst::BufferCopyFlags::TIMESTAMPS
+                        | gst::BufferCopyFlags::META,
+                    0,
+                    None,
+                );
+                match new_file {
+                    true => output.unset_flags(gst::BufferFlags::DELTA_UNIT),
+                    false => output.set_flags(gst::BufferFlags::DELTA_UNIT),
+                }
+            }
+            *buffer = output;
+            gst::PadProbeReturn::Ok
         }
+        _ => gst::PadProbeReturn::Ok,
     }
 }
 
@@ -114,6 +265,14 @@ impl ObjectImpl for DataframeFileSink {
                     .blurb("Maximum number of files to keep on disk. Once the maximum is reached, old files start to be deleted to make room for new ones.")
                     .default_value(DEFAULT_POST_FILE_MESSAGES)
                     .build(),   
+                glib::ParamSpecEnum::builder::<IpcCompression>("ipc-compression", DEFAULT_IPC_COMPRESSION)
+                    .nick("IPC Compression")
+                    .blurb("Re-encode arrow ipc messages with this compression codec before writing to disk. Buffers are written unchanged when set to none.")
+                    .build(),
+                glib::ParamSpecEnum::builder::<IpcStreamMode>("ipc-stream-mode", DEFAULT_IPC_STREAM_MODE)
+                    .nick("IPC Stream Mode")
+                    .blurb("Write every buffer as a complete arrow ipc stream (message), or write each file as a single arrow ipc stream with one schema message (continuous). Continuous mode starts new files on max-file-size/max-file-duration.")
+                    .build(),
             ]
         });
 
@@ -167,6 +326,21 @@ impl ObjectImpl for DataframeFileSink {
                     .multifilesink
                     .set_property("post-messages", &settings.post_file_messages);            
             }
+            "ipc-compression" => {
+                settings.ipc_compression = value
+                    .get::<IpcCompression>()
+                    .expect("type checked upstream");
+            }
+            "ipc-stream-mode" => {
+                settings.ipc_stream_mode = value
+                    .get::<IpcStreamMode>()
+                    .expect("type checked upstream");
+                let next_file = match settings.ipc_stream_mode {
+                    IpcStreamMode::Message => "buffer",
+                    IpcStreamMode::Continuous => "key-frame",
+                };
+                self.multifilesink.set_property_from_str("next-file", next_file);
+            }
             _ => unimplemented!("Property is not implemented {:?}", value),
         };
     }
@@ -179,6 +353,8 @@ impl ObjectImpl for DataframeFileSink {
             "max-file-size" => settings.max_file_size.to_value(),
             "max-files" => settings.max_files.to_value(),
             "post-messages" => settings.post_file_messages.to_value(),
+            "ipc-compression" => settings.ipc_compression.to_value(),
+            "ipc-stream-mode" => settings.ipc_stream_mode.to_value(),
             _ => unimplemented!(),
         }
     }
@@ -193,6 +369,31 @@ impl ObjectImpl for DataframeFileSink {
             .set_target(Some(&self.multifilesink.static_pad("sink").unwrap()))
             .unwrap();
 
+        // re-encode arrow ipc messages with ipc-compression and ipc-stream-mode before they are written by multifilesink
+        let settings = self.settings.clone();
+        let continuous_state = Mutex::new(ContinuousState::default());
+        self.multifilesink
+            .static_pad("sink")
+            .unwrap()
+            .add_probe(gst::PadProbeType::BUFFER | gst::PadProbeType::EVENT_DOWNSTREAM, move |pad, info| {
+                let settings = settings.lock().unwrap();
+                if settings.ipc_stream_mode == IpcStreamMode::Continuous {
+                    let mut state = continuous_state.lock().unwrap();
+                    return continuous_probe(&settings, &mut state, pad, info);
+                }
+                let compression = settings.ipc_compression;
+                if compression == IpcCompression::Uncompressed {
+                    return gst::PadProbeReturn::Ok;
+                }
+                if let Some(gst::PadProbeData::Buffer(ref mut buffer)) = info.data {
+                    match arrow_streaming_ipc_buffer_with_compression(buffer, compression) {
+                        Ok(output) => *buffer = output,
+                        Err(err) => gst::warning!(CAT, obj: pad, "Failed to compress arrow ipc message: {:?}", err),
+                    }
+                }
+                gst::PadProbeReturn::Ok
+            });
+
         gst::log!(CAT, obj: &self.sinkpad, "Initializing DataframeFileSink");
 
         // And finally add the ghostpads to the bin.
@@ -218,8 +419,8 @@ impl ElementImpl for DataframeFileSink {
 
     fn pad_templates() -> &'static [gst::PadTemplate] {
         static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
-            // Our element can accept any possible caps
-            let caps = gst::Caps::new_any();
+            // Our element accepts any serialized dataframe format
+            let caps = dataframe_sink_caps();
             let sink_pad_template = gst::PadTemplate::new(
                 "sink",
                 gst::PadDirection::Sink,
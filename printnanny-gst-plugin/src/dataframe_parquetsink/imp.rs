@@ -0,0 +1,553 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst_base::subclass::prelude::*;
+use once_cell::sync::Lazy;
+use polars::prelude::*;
+use std::sync::Mutex;
+
+use crate::caps::dataframe_sink_caps;
+use crate::ipc::{dataframe_from_bytes, DataframeFormat, ParquetCodec, ParquetFileWriter};
+
+const DEFAULT_LOCATION: &str = "dataframe%05d.parquet";
+const DEFAULT_COMPRESSION: ParquetCodec = ParquetCodec::Snappy;
+const DEFAULT_ROW_GROUP_SIZE: u32 = 10000;
+const DEFAULT_ROW_GROUP_BYTES: u64 = 134217728;
+const DEFAULT_MAX_FILE_DURATION: u64 = 18446744073709551615;
+const DEFAULT_MAX_FILE_SIZE: u64 = 2147483648;
+const DEFAULT_MAX_FILE_ROWS: u64 = 18446744073709551615;
+const DEFAULT_POST_FILE_MESSAGES: bool = false;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "dataframe_parquetsink",
+        gst::DebugColorFlags::empty(),
+        Some("PrintNanny Dataframe parquet sink"),
+    )
+});
+
+#[derive(Debug, Clone)]
+struct Settings {
+    location: String, // Location of files to write, formatted with file index (printf-style %d or %05d)
+    compression: ParquetCodec,
+    row_group_size: u32,      // Maximum number of rows per row group
+    row_group_bytes: u64,     // Maximum (estimated, uncompressed) size of a row group, in bytes
+    max_file_duration: u64, // Maximum duration (buffer pts) of dataframes in a file, in nanoseconds
+    max_file_size: u64, // Maximum (estimated, uncompressed) size of dataframes in a file, in bytes
+    max_file_rows: u64, // Maximum number of rows in a file
+    post_file_messages: bool, // Post a message on the GstBus for each file.
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            location: DEFAULT_LOCATION.into(),
+            compression: DEFAULT_COMPRESSION,
+            row_group_size: DEFAULT_ROW_GROUP_SIZE,
+            row_group_bytes: DEFAULT_ROW_GROUP_BYTES,
+            max_file_duration: DEFAULT_MAX_FILE_DURATION,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_file_rows: DEFAULT_MAX_FILE_ROWS,
+            post_file_messages: DEFAULT_POST_FILE_MESSAGES,
+        }
+    }
+}
+
+// Parquet file which is currently written. Dataframes are buffered until they fill a row group
+struct OpenFile {
+    location: String,
+    writer: ParquetFileWriter<BufWriter<File>>,
+    row_group: Vec<DataFrame>,
+    row_group_rows: u64,
+    row_group_size: u64,
+}
+
+#[derive(Default)]
+struct PendingFile {
+    file: Option<OpenFile>,
+    rows: u64,
+    size: u64,
+    start: Option<gst::ClockTime>,
+}
+
+enum State {
+    Stopped,
+    Started { index: u32, pending: PendingFile },
+}
+
+impl Default for State {
+    fn default() -> State {
+        State::Stopped
+    }
+}
+
+#[derive(Default)]
+pub struct DataframeParquetSink {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+// Format location with file index, for example dataframe%05d.parquet -> dataframe00001.parquet
+fn file_location(location: &str, index: u32) -> String {
+    let start = match location.find('%') {
+        Some(start) => start,
+        None => return location.to_string(),
+    };
+    let spec = &location[start + 1..];
+    let end = match spec.find('d') {
+        Some(end) if spec[..end].chars().all(|c| c.is_ascii_digit()) => end,
+        _ => return location.to_string(),
+    };
+    let width = spec[..end].parse::<usize>().unwrap_or(0);
+    format!(
+        "{}{:0width$}{}",
+        &location[..start],
+        index,
+        &spec[end + 1..],
+        width = width
+    )
+}
+
+impl DataframeParquetSink {
+    // Create the file at the next location. The parquet schema is taken from the first dataframe
+    fn open_file(
+        &self,
+        settings: &Settings,
+        index: u32,
+        schema: &Schema,
+    ) -> Result<OpenFile, gst::ErrorMessage> {
+        let location = file_location(&settings.location, index);
+        let file = File::create(&location).map_err(|err| {
+            gst::error_msg!(
+                gst::ResourceError::OpenWrite,
+                ["Failed to create file {}: {}", location, err]
+            )
+        })?;
+        let writer = ParquetFileWriter::try_new(BufWriter::new(file), schema, settings.compression)
+            .map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::OpenWrite,
+                    ["Failed to create parquet writer {}: {:?}", location, err]
+                )
+            })?;
+        gst::debug!(CAT, obj: self.instance(), "Opened {}", location);
+        Ok(OpenFile {
+            location,
+            writer,
+            row_group: vec![],
+            row_group_rows: 0,
+            row_group_size: 0,
+        })
+    }
+
+    // Write buffered dataframes as row groups of at most row-group-size rows
+    fn write_row_group(
+        &self,
+        settings: &Settings,
+        file: &mut OpenFile,
+    ) -> Result<(), gst::ErrorMessage> {
+        let mut dataframes = std::mem::take(&mut file.row_group).into_iter();
+        file.row_group_rows = 0;
+        file.row_group_size = 0;
+        let mut df = match dataframes.next() {
+            Some(df) => df,
+            None => return Ok(()),
+        };
+        let location = file.location.clone();
+        let map_err = |err: String| {
+            gst::error_msg!(
+                gst::ResourceError::Write,
+                ["Failed to write parquet row group {}: {}", location, err]
+            )
+        };
+        for other in dataframes {
+            df.vstack_mut(&other)
+                .map_err(|err| map_err(err.to_string()))?;
+        }
+        df.rechunk();
+        let rows = match settings.row_group_size {
+            0 => df.height(),
+            size => size as usize,
+        };
+        let mut offset = 0;
+        while offset < df.height() {
+            file.writer
+                .write_row_group(&df.slice(offset as i64, rows))
+                .map_err(|err| map_err(format!("{:?}", err)))?;
+            offset += rows;
+        }
+        Ok(())
+    }
+
+    // Write remaining row groups and the parquet footer of the current file, if a file is open
+    fn finish_file(
+        &self,
+        settings: &Settings,
+        index: &mut u32,
+        pending: &mut PendingFile,
+    ) -> Result<(), gst::ErrorMessage> {
+        let mut pending = std::mem::take(pending);
+        let mut file = match pending.file.take() {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        let element = self.instance();
+        self.write_row_group(settings, &mut file)?;
+        let location = file.location;
+        file.writer
+            .finish()
+            .map_err(|err| format!("{:?}", err))
+            .and_then(|mut writer| writer.flush().map_err(|err| err.to_string()))
+            .map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::Write,
+                    ["Failed to write parquet file {}: {}", location, err]
+                )
+            })?;
+        gst::info!(
+            CAT,
+            obj: element,
+            "Wrote {} rows to {}",
+            pending.rows,
+            location
+        );
+
+        if settings.post_file_messages {
+            let msg = gst::message::Element::builder(
+                gst::Structure::builder("printnanny-parquet-file")
+                    .field("filename", &location)
+                    .field("index", *index)
+                    .field("rows", pending.rows)
+                    .build(),
+            )
+            .src(&*element)
+            .build();
+            let _ = element.post_message(msg);
+        }
+        *index += 1;
+        Ok(())
+    }
+
+    // Finish the current file, if a file is open
+    fn finish(&self) -> Result<(), gst::ErrorMessage> {
+        let mut state = self.state.lock().unwrap();
+        let settings = self.settings.lock().unwrap().clone();
+        match *state {
+            State::Started {
+                ref mut index,
+                ref mut pending,
+            } => self.finish_file(&settings, index, pending),
+            State::Stopped => Ok(()),
+        }
+    }
+
+    // Add dataframe to the current file, opening a new file if needed. Row groups are written once row-group-size or row-group-bytes is reached
+    fn write_dataframe(
+        &self,
+        settings: &Settings,
+        index: &mut u32,
+        pending: &mut PendingFile,
+        df: DataFrame,
+    ) -> Result<(), gst::ErrorMessage> {
+        let schema = df.schema();
+        let schema_changed = match &pending.file {
+            Some(file) => !file.writer.accepts(&schema),
+            None => false,
+        };
+        if schema_changed {
+            gst::info!(
+                CAT,
+                obj: self.instance(),
+                "Dataframe schema changed, starting a new file"
+            );
+            self.finish_file(settings, index, pending)?;
+        }
+        if pending.file.is_none() {
+            pending.file = Some(self.open_file(settings, *index, &schema)?);
+        }
+        let file = pending.file.as_mut().unwrap();
+        file.row_group_rows += df.height() as u64;
+        file.row_group_size += df.estimated_size() as u64;
+        file.row_group.push(df);
+        let full = (settings.row_group_size > 0
+            && file.row_group_rows >= settings.row_group_size as u64)
+            || file.row_group_size >= settings.row_group_bytes;
+        if full {
+            self.write_row_group(settings, file)?;
+        }
+        Ok(())
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for DataframeParquetSink {
+    const NAME: &'static str = "DataframeParquetSink";
+    type Type = super::DataframeParquetSink;
+    type ParentType = gst_base::BaseSink;
+}
+
+impl ObjectImpl for DataframeParquetSink {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::builder("location")
+                    .nick("File Location")
+                    .blurb("Location of the files to write, formatted with file index (for example: dataframe%05d.parquet)")
+                    .default_value(Some(DEFAULT_LOCATION))
+                    .build(),
+                glib::ParamSpecEnum::builder::<ParquetCodec>("compression", DEFAULT_COMPRESSION)
+                    .nick("Compression")
+                    .blurb("Compression codec applied to parquet column chunks")
+                    .build(),
+                glib::ParamSpecUInt::builder("row-group-size")
+                    .nick("Row Group Size")
+                    .blurb("Number of rows per row group. Buffered rows are written to the file as a row group once this many rows are received. Set to 0 to only limit row groups by row-group-bytes")
+                    .default_value(DEFAULT_ROW_GROUP_SIZE)
+                    .build(),
+                glib::ParamSpecUInt64::builder("row-group-bytes")
+                    .nick("Row Group Bytes")
+                    .blurb("Maximum size of buffered rows before they are written to the file as a row group, in bytes. Size is estimated from uncompressed in-memory dataframes")
+                    .default_value(DEFAULT_ROW_GROUP_BYTES)
+                    .build(),
+                glib::ParamSpecUInt64::builder("max-file-duration")
+                    .nick("Max File Duration")
+                    .blurb("Maximum duration (by buffer timestamps) of dataframes in a file before starting a new file, in nanoseconds")
+                    .default_value(DEFAULT_MAX_FILE_DURATION)
+                    .build(),
+                glib::ParamSpecUInt64::builder("max-file-size")
+                    .nick("Max File Size")
+                    .blurb("Maximum size of dataframes in a file before starting a new file, in bytes. Size is estimated from uncompressed in-memory dataframes, so files on disk are typically smaller")
+                    .default_value(DEFAULT_MAX_FILE_SIZE)
+                    .build(),
+                glib::ParamSpecUInt64::builder("max-file-rows")
+                    .nick("Max File Rows")
+                    .blurb("Maximum number of rows in a file before starting a new file")
+                    .default_value(DEFAULT_MAX_FILE_ROWS)
+                    .build(),
+                glib::ParamSpecBoolean::builder("post-messages")
+                    .nick("Post Messages")
+                    .blurb("Post a printnanny-parquet-file message on the GstBus for each file written")
+                    .default_value(DEFAULT_POST_FILE_MESSAGES)
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+
+        match pspec.name() {
+            "location" => {
+                settings.location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_LOCATION.into());
+            }
+            "compression" => {
+                settings.compression = value.get::<ParquetCodec>().expect("type checked upstream");
+            }
+            "row-group-size" => {
+                settings.row_group_size = value.get::<u32>().expect("type checked upstream");
+            }
+            "row-group-bytes" => {
+                settings.row_group_bytes = value.get::<u64>().expect("type checked upstream");
+            }
+            "max-file-duration" => {
+                settings.max_file_duration = value.get::<u64>().expect("type checked upstream");
+            }
+            "max-file-size" => {
+                settings.max_file_size = value.get::<u64>().expect("type checked upstream");
+            }
+            "max-file-rows" => {
+                settings.max_file_rows = value.get::<u64>().expect("type checked upstream");
+            }
+            "post-messages" => {
+                settings.post_file_messages = value.get::<bool>().expect("type checked upstream");
+            }
+            _ => unimplemented!(
+                "dataframe_parquetsink does not implement property: {}",
+                pspec.name()
+            ),
+        };
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+
+        match pspec.name() {
+            "location" => settings.location.to_value(),
+            "compression" => settings.compression.to_value(),
+            "row-group-size" => settings.row_group_size.to_value(),
+            "row-group-bytes" => settings.row_group_bytes.to_value(),
+            "max-file-duration" => settings.max_file_duration.to_value(),
+            "max-file-size" => settings.max_file_size.to_value(),
+            "max-file-rows" => settings.max_file_rows.to_value(),
+            "post-messages" => settings.post_file_messages.to_value(),
+            _ => unimplemented!(
+                "dataframe_parquetsink does not implement property: {}",
+                pspec.name()
+            ),
+        }
+    }
+}
+
+impl GstObjectImpl for DataframeParquetSink {}
+
+impl ElementImpl for DataframeParquetSink {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "Dataframe parquet file sink",
+                "Sink/Files",
+                "Write dataframes to rolling parquet files",
+                "Leigh Johnson <leigh@printnanny.ai>",
+            )
+        });
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = dataframe_sink_caps();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl BaseSinkImpl for DataframeParquetSink {
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        let mut state = self.state.lock().unwrap();
+        if let State::Started { .. } = *state {
+            unreachable!("DataframeParquetSink already started");
+        }
+        *state = State::Started {
+            index: 0,
+            pending: PendingFile::default(),
+        };
+        gst::info!(CAT, obj: self.instance(), "Started");
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        self.finish()?;
+        *self.state.lock().unwrap() = State::Stopped;
+        gst::info!(CAT, obj: self.instance(), "Stopped");
+        Ok(())
+    }
+
+    fn event(&self, event: gst::Event) -> bool {
+        if let gst::EventView::Eos(_) = event.view() {
+            if let Err(err) = self.finish() {
+                self.post_error_message(err);
+                return false;
+            }
+        }
+        self.parent_event(event)
+    }
+
+    fn render(&self, buffer: &gst::Buffer) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let element = self.instance();
+        let mut state = self.state.lock().unwrap();
+        let settings = self.settings.lock().unwrap().clone();
+
+        let (index, pending) = match *state {
+            State::Started {
+                ref mut index,
+                ref mut pending,
+            } => (index, pending),
+            State::Stopped => {
+                gst::element_error!(element, gst::CoreError::Failed, ["Not started yet"]);
+                return Err(gst::FlowError::Error);
+            }
+        };
+
+        gst::trace!(CAT, obj: element, "Rendering {:?}", buffer);
+        let format = element
+            .static_pad("sink")
+            .and_then(|pad| pad.current_caps())
+            .and_then(|caps| DataframeFormat::from_caps(&caps));
+        let map = buffer.map_readable().map_err(|_| {
+            gst::element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+            gst::FlowError::Error
+        })?;
+        let (df, _metadata) = dataframe_from_bytes(&map, format).map_err(|err| {
+            gst::element_error!(
+                element,
+                gst::StreamError::Decode,
+                ["Failed to deserialize dataframe: {}", err]
+            );
+            gst::FlowError::Error
+        })?;
+        drop(map);
+        let rows = df.height() as u64;
+        let size = df.estimated_size() as u64;
+        let pts = buffer.pts();
+
+        // roll over to a new file before this dataframe would exceed max-file-size or max-file-duration
+        let exceeds_size = pending.size + size > settings.max_file_size;
+        let exceeds_duration = match (pending.start, pts) {
+            (Some(start), Some(pts)) => {
+                pts.saturating_sub(start).nseconds() >= settings.max_file_duration
+            }
+            _ => false,
+        };
+        if pending.rows > 0 && (exceeds_size || exceeds_duration) {
+            self.finish_file(&settings, index, pending).map_err(|err| {
+                self.post_error_message(err);
+                gst::FlowError::Error
+            })?;
+        }
+
+        if pending.start.is_none() {
+            pending.start = pts;
+        }
+        self.write_dataframe(&settings, index, pending, df)
+            .map_err(|err| {
+                self.post_error_message(err);
+                gst::FlowError::Error
+            })?;
+        pending.rows += rows;
+        pending.size += size;
+
+        if pending.rows >= settings.max_file_rows {
+            self.finish_file(&settings, index, pending).map_err(|err| {
+                self.post_error_message(err);
+                gst::FlowError::Error
+            })?;
+        }
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_location() {
+        assert_eq!(
+            file_location("dataframe%05d.parquet", 12),
+            "dataframe00012.parquet"
+        );
+        assert_eq!(
+            file_location("dataframe%d.parquet", 12),
+            "dataframe12.parquet"
+        );
+        assert_eq!(file_location("dataframe.parquet", 12), "dataframe.parquet");
+    }
+}
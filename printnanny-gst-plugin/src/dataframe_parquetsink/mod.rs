@@ -0,0 +1,17 @@
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+
+glib::wrapper! {
+    pub struct DataframeParquetSink(ObjectSubclass<imp::DataframeParquetSink>) @extends gst_base::BaseSink, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "dataframe_parquetsink",
+        gst::Rank::None,
+        DataframeParquetSink::static_type(),
+    )
+}
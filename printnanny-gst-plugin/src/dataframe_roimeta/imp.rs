@@ -0,0 +1,587 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use once_cell::sync::Lazy;
+use polars::prelude::*;
+
+use crate::caps::dataframe_sink_caps;
+use crate::ipc::{
+    dataframe_from_bytes, dataframe_to_arrow_streaming_ipc_message, DataframeFormat, IpcCompression,
+};
+use crate::metadata::{labels_from_metadata, DEFAULT_LABELS};
+use crate::roi::{add_dataframe_meta, add_roi_meta, class_label, detections_from_dataframe};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "dataframe_roimeta",
+        gst::DebugColorFlags::empty(),
+        Some("PrintNanny Dataframe region of interest meta"),
+    )
+});
+
+const DEFAULT_MATCH_TOLERANCE: u64 = 0;
+const DEFAULT_MAX_LATENCY: u64 = 1_000_000_000; // 1s
+const DEFAULT_FILTER_THRESHOLD: f32 = 0.5;
+const DEFAULT_ATTACH_DATAFRAME_META: bool = true;
+
+// dataframes which did not match any video buffer are dropped (oldest first) above this limit
+const MAX_QUEUED_DATAFRAMES: usize = 64;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    match_tolerance: u64, // Max difference between video buffer and dataframe pts, in nanoseconds
+    max_latency: u64, // Max time a video buffer is held while waiting for dataframes, in nanoseconds
+    filter_threshold: f32, // Detections below this score are not attached as region of interest meta
+    attach_dataframe_meta: bool, // Attach the matching dataframe as PrintNannyDataframeMeta
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            match_tolerance: DEFAULT_MATCH_TOLERANCE,
+            max_latency: DEFAULT_MAX_LATENCY,
+            filter_threshold: DEFAULT_FILTER_THRESHOLD,
+            attach_dataframe_meta: DEFAULT_ATTACH_DATAFRAME_META,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct QueuedDataframe {
+    pts: u64,
+    dataframe: DataFrame,
+    metadata: BTreeMap<String, String>,
+}
+
+#[derive(Default)]
+struct State {
+    dataframes: VecDeque<QueuedDataframe>, // ordered by pts
+    latest_pts: Option<u64>,               // pts of the most recent dataframe
+    dataframe_eos: bool,
+    flushing: bool,
+    video_info: Option<gst_video::VideoInfo>,
+}
+
+pub struct DataframeRoiMeta {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    dataframe_received: Condvar, // notified when a dataframe is queued, or dataframe_eos/flushing changes
+    video_sinkpad: gst::Pad,
+    dataframe_sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+}
+
+impl DataframeRoiMeta {
+    // Video events are passed through to the source pad. Flush events unblock video buffers waiting for dataframes
+    fn video_sink_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        gst::log!(CAT, obj: pad, "Handling event {:?}", event);
+        match event.view() {
+            gst::EventView::Caps(caps) => {
+                let video_info = gst_video::VideoInfo::from_caps(caps.caps()).ok();
+                if video_info.is_none() {
+                    gst::error!(CAT, obj: pad, "Invalid video caps {:?}", caps.caps());
+                    return false;
+                }
+                self.state.lock().unwrap().video_info = video_info;
+            }
+            gst::EventView::FlushStart(_) => self.set_flushing(true),
+            gst::EventView::FlushStop(_) => self.set_flushing(false),
+            _ => (),
+        }
+        self.srcpad.push_event(event)
+    }
+
+    // Dataframe events are not forwarded, only the video stream is output
+    fn dataframe_sink_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        gst::log!(CAT, obj: pad, "Handling event {:?}", event);
+        match event.view() {
+            gst::EventView::FlushStart(_) => self.set_flushing(true),
+            gst::EventView::FlushStop(_) => self.set_flushing(false),
+            gst::EventView::StreamStart(_) => {
+                self.state.lock().unwrap().dataframe_eos = false;
+            }
+            gst::EventView::Eos(_) => {
+                let mut state = self.state.lock().unwrap();
+                state.dataframe_eos = true;
+                self.dataframe_received.notify_all();
+            }
+            _ => (),
+        }
+        true
+    }
+
+    // Flushing releases video buffers waiting for dataframes. Queued dataframes are discarded when flushing stops
+    fn set_flushing(&self, flushing: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.flushing = flushing;
+        if !flushing {
+            state.dataframes.clear();
+            state.latest_pts = None;
+            state.dataframe_eos = false;
+        }
+        self.dataframe_received.notify_all();
+    }
+
+    fn src_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        gst::log!(CAT, obj: pad, "Handling event {:?}", event);
+        self.video_sinkpad.push_event(event)
+    }
+
+    // Queries are forwarded to the video sink pad's peer. Video buffers may be held for up to max-latency, which is added to the reported latency
+    fn src_query(&self, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
+        gst::log!(CAT, obj: pad, "Handling query {:?}", query);
+        let res = self.video_sinkpad.peer_query(query);
+        if let (true, gst::QueryViewMut::Latency(q)) = (res, query.view_mut()) {
+            let max_latency =
+                gst::ClockTime::from_nseconds(self.settings.lock().unwrap().max_latency);
+            let (live, min, max) = q.result();
+            q.set(live, min + max_latency, max.map(|max| max + max_latency));
+        }
+        res
+    }
+
+    fn video_sink_query(&self, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
+        gst::log!(CAT, obj: pad, "Handling query {:?}", query);
+        self.srcpad.peer_query(query)
+    }
+
+    fn dataframe_sink_query(&self, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
+        gst::log!(CAT, obj: pad, "Handling query {:?}", query);
+        pad.query_default(Some(&*self.instance()), query)
+    }
+
+    fn dataframe_sink_chain(
+        &self,
+        pad: &gst::Pad,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        gst::log!(CAT, obj: pad, "Handling buffer {:?}", buffer);
+        let pts = match buffer.pts() {
+            Some(pts) => pts.nseconds(),
+            None => {
+                gst::warning!(CAT, obj: pad, "Dropping dataframe buffer without pts");
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        };
+        let format = pad
+            .current_caps()
+            .and_then(|caps| DataframeFormat::from_caps(&caps));
+        let map = buffer.map_readable().map_err(|_| {
+            gst::element_error!(
+                self.instance(),
+                gst::CoreError::Failed,
+                ["Failed to map buffer"]
+            );
+            gst::FlowError::Error
+        })?;
+        let (dataframe, metadata) = dataframe_from_bytes(&map, format).map_err(|err| {
+            gst::element_error!(
+                self.instance(),
+                gst::StreamError::Decode,
+                ["Failed to deserialize dataframe: {}", err]
+            );
+            gst::FlowError::Error
+        })?;
+
+        let mut state = self.state.lock().unwrap();
+        if state.flushing {
+            return Err(gst::FlowError::Flushing);
+        }
+        if state.dataframes.len() >= MAX_QUEUED_DATAFRAMES {
+            gst::debug!(CAT, obj: pad, "Dropping unmatched dataframe");
+            state.dataframes.pop_front();
+        }
+        state.dataframes.push_back(QueuedDataframe {
+            pts,
+            dataframe,
+            metadata,
+        });
+        state.latest_pts = Some(state.latest_pts.map_or(pts, |latest| latest.max(pts)));
+        self.dataframe_received.notify_all();
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    // Block until a dataframe at or after pts + match-tolerance was received (so no later dataframe can match better), the dataframe stream ended, or max-latency elapsed
+    // Returns the dataframe closest to pts within match-tolerance, if any
+    fn wait_for_dataframe(
+        &self,
+        settings: &Settings,
+        pts: u64,
+    ) -> Result<(Option<QueuedDataframe>, Option<gst_video::VideoInfo>), gst::FlowError> {
+        let deadline = Instant::now() + Duration::from_nanos(settings.max_latency);
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.flushing {
+                return Err(gst::FlowError::Flushing);
+            }
+            let received = state.latest_pts.map_or(false, |latest| {
+                latest >= pts.saturating_add(settings.match_tolerance)
+            });
+            if received || state.dataframe_eos {
+                break;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                gst::debug!(
+                    CAT,
+                    obj: &self.video_sinkpad,
+                    "Timed out waiting for dataframe with pts {}",
+                    pts
+                );
+                break;
+            }
+            state = self
+                .dataframe_received
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+
+        // dataframes before pts - match-tolerance can no longer match this or any later video buffer
+        while let Some(queued) = state.dataframes.front() {
+            if queued.pts.saturating_add(settings.match_tolerance) >= pts {
+                break;
+            }
+            state.dataframes.pop_front();
+        }
+        let matched = state
+            .dataframes
+            .iter()
+            .filter(|queued| queued.pts.abs_diff(pts) <= settings.match_tolerance)
+            .min_by_key(|queued| queued.pts.abs_diff(pts))
+            .cloned();
+        Ok((matched, state.video_info.clone()))
+    }
+
+    // Attach a region of interest meta per detection above filter-threshold, and the dataframe as arrow streaming ipc message
+    fn attach(
+        &self,
+        settings: &Settings,
+        buffer: &mut gst::BufferRef,
+        queued: QueuedDataframe,
+        video_info: &gst_video::VideoInfo,
+    ) -> Result<(), gst::FlowError> {
+        let QueuedDataframe {
+            mut dataframe,
+            metadata,
+            ..
+        } = queued;
+        let detections = detections_from_dataframe(&dataframe).map_err(|err| {
+            gst::element_error!(
+                self.instance(),
+                gst::StreamError::Format,
+                ["Dataframe is not a detection dataframe: {}", err]
+            );
+            gst::FlowError::Error
+        })?;
+        let labels = labels_from_metadata(&metadata).unwrap_or_else(|| {
+            DEFAULT_LABELS
+                .iter()
+                .map(|label| label.to_string())
+                .collect()
+        });
+        for detection in detections
+            .iter()
+            .filter(|detection| detection.score >= settings.filter_threshold)
+        {
+            let label = class_label(&labels, detection.class);
+            add_roi_meta(
+                buffer,
+                detection,
+                &label,
+                video_info.width(),
+                video_info.height(),
+            );
+        }
+
+        if settings.attach_dataframe_meta {
+            let msg = dataframe_to_arrow_streaming_ipc_message(
+                &mut dataframe,
+                Some(metadata),
+                IpcCompression::Uncompressed,
+            )
+            .map_err(|err| {
+                gst::error!(
+                    CAT,
+                    "Failed to serialize arrow ipc streaming msg: {:?}",
+                    err
+                );
+                gst::FlowError::Error
+            })?;
+            add_dataframe_meta(buffer, msg).map_err(|err| {
+                gst::error!(CAT, "Failed to add dataframe meta: {}", err);
+                gst::FlowError::Error
+            })?;
+        }
+        Ok(())
+    }
+
+    fn video_sink_chain(
+        &self,
+        pad: &gst::Pad,
+        mut buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        gst::log!(CAT, obj: pad, "Handling buffer {:?}", buffer);
+        let pts = match buffer.pts() {
+            Some(pts) => pts.nseconds(),
+            None => return self.srcpad.push(buffer),
+        };
+        let settings = self.settings.lock().unwrap().clone();
+        let (matched, video_info) = self.wait_for_dataframe(&settings, pts)?;
+        match (matched, video_info) {
+            (Some(queued), Some(video_info)) => {
+                self.attach(&settings, buffer.make_mut(), queued, &video_info)?;
+            }
+            (None, _) => {
+                gst::trace!(CAT, obj: pad, "No dataframe matches pts {}", pts);
+            }
+            (Some(_), None) => {
+                gst::element_error!(
+                    self.instance(),
+                    gst::CoreError::Negotiation,
+                    ["Received video buffer before caps"]
+                );
+                return Err(gst::FlowError::NotNegotiated);
+            }
+        }
+        self.srcpad.push(buffer)
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for DataframeRoiMeta {
+    const NAME: &'static str = "DataframeRoiMeta";
+    type Type = super::DataframeRoiMeta;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let templ = klass.pad_template("src").unwrap();
+        let srcpad = gst::Pad::builder_with_template(&templ, Some("src"))
+            .event_function(|pad, parent, event| {
+                DataframeRoiMeta::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |element| element.src_event(pad, event),
+                )
+            })
+            .query_function(|pad, parent, query| {
+                DataframeRoiMeta::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |element| element.src_query(pad, query),
+                )
+            })
+            .build();
+
+        let templ = klass.pad_template("video_sink").unwrap();
+        let video_sinkpad = gst::Pad::builder_with_template(&templ, Some("video_sink"))
+            .chain_function(|pad, parent, buffer| {
+                DataframeRoiMeta::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |element| element.video_sink_chain(pad, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                DataframeRoiMeta::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |element| element.video_sink_event(pad, event),
+                )
+            })
+            .query_function(|pad, parent, query| {
+                DataframeRoiMeta::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |element| element.video_sink_query(pad, query),
+                )
+            })
+            .flags(gst::PadFlags::PROXY_CAPS | gst::PadFlags::PROXY_ALLOCATION)
+            .build();
+
+        let templ = klass.pad_template("dataframe_sink").unwrap();
+        let dataframe_sinkpad = gst::Pad::builder_with_template(&templ, Some("dataframe_sink"))
+            .chain_function(|pad, parent, buffer| {
+                DataframeRoiMeta::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |element| element.dataframe_sink_chain(pad, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                DataframeRoiMeta::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |element| element.dataframe_sink_event(pad, event),
+                )
+            })
+            .query_function(|pad, parent, query| {
+                DataframeRoiMeta::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |element| element.dataframe_sink_query(pad, query),
+                )
+            })
+            .build();
+
+        Self {
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+            dataframe_received: Condvar::new(),
+            video_sinkpad,
+            dataframe_sinkpad,
+            srcpad,
+        }
+    }
+}
+
+impl ObjectImpl for DataframeRoiMeta {
+    fn constructed(&self) {
+        self.parent_constructed();
+        let obj = self.instance();
+
+        obj.add_pad(&self.video_sinkpad).unwrap();
+        obj.add_pad(&self.dataframe_sinkpad).unwrap();
+        obj.add_pad(&self.srcpad).unwrap();
+    }
+
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecUInt64::builder("match-tolerance")
+                    .nick("Match Tolerance")
+                    .blurb("Max difference between video buffer and dataframe pts, in nanoseconds. 0 requires an exact match")
+                    .default_value(DEFAULT_MATCH_TOLERANCE)
+                    .build(),
+                glib::ParamSpecUInt64::builder("max-latency")
+                    .nick("Max Latency")
+                    .blurb("Max time a video buffer is held while waiting for the matching dataframe, in nanoseconds")
+                    .default_value(DEFAULT_MAX_LATENCY)
+                    .build(),
+                glib::ParamSpecFloat::builder("filter-threshold")
+                    .nick("Filter Threshold")
+                    .blurb("Detections where detection_score is below threshold are not attached. Float between 0 - 1")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_FILTER_THRESHOLD)
+                    .build(),
+                glib::ParamSpecBoolean::builder("attach-dataframe-meta")
+                    .nick("Attach Dataframe Meta")
+                    .blurb("Attach the matching dataframe as PrintNannyDataframeMeta, an arrow streaming ipc message")
+                    .default_value(DEFAULT_ATTACH_DATAFRAME_META)
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "match-tolerance" => settings.match_tolerance.to_value(),
+            "max-latency" => settings.max_latency.to_value(),
+            "filter-threshold" => settings.filter_threshold.to_value(),
+            "attach-dataframe-meta" => settings.attach_dataframe_meta.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "match-tolerance" => {
+                settings.match_tolerance = value.get::<u64>().expect("type checked upstream");
+            }
+            "max-latency" => {
+                settings.max_latency = value.get::<u64>().expect("type checked upstream");
+            }
+            "filter-threshold" => {
+                settings.filter_threshold = value.get::<f32>().expect("type checked upstream");
+            }
+            "attach-dataframe-meta" => {
+                settings.attach_dataframe_meta =
+                    value.get::<bool>().expect("type checked upstream");
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for DataframeRoiMeta {}
+
+impl ElementImpl for DataframeRoiMeta {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "PrintNanny Dataframe region of interest meta",
+                "Filter/Video",
+                "Attach detection dataframes to matching video buffers as region of interest meta",
+                "Leigh Johnson <leigh@printnanny.ai>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("video/x-raw").build();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            let video_sink_pad_template = gst::PadTemplate::new(
+                "video_sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            let caps = dataframe_sink_caps();
+            let dataframe_sink_pad_template = gst::PadTemplate::new(
+                "dataframe_sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![
+                src_pad_template,
+                video_sink_pad_template,
+                dataframe_sink_pad_template,
+            ]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    // Release video buffers waiting for dataframes before pads are deactivated
+    fn change_state(
+        &self,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        let element = self.instance();
+        gst::trace!(CAT, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                *self.state.lock().unwrap() = State::default();
+            }
+            gst::StateChange::PausedToReady => self.set_flushing(true),
+            _ => (),
+        }
+
+        self.parent_change_state(transition)
+    }
+}
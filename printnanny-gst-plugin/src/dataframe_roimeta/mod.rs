@@ -0,0 +1,18 @@
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+
+// Attaches detection dataframes to the video buffer with matching pts, as GstVideoRegionOfInterestMeta (one per detection) and PrintNannyDataframeMeta (arrow streaming ipc message)
+glib::wrapper! {
+    pub struct DataframeRoiMeta(ObjectSubclass<imp::DataframeRoiMeta>) @extends gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "dataframe_roimeta",
+        gst::Rank::None,
+        DataframeRoiMeta::static_type(),
+    )
+}
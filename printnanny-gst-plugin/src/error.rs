@@ -24,4 +24,71 @@ pub enum SerializationError {
     },
     #[error("Failed to unwrap BufWriter inner contents")]
     BufferError,
+    #[error("Failed to detect dataframe format from buffer contents")]
+    UnknownFormat,
+    #[error("Expected framed payload of {expected} bytes, but got {actual} bytes")]
+    FramingError { expected: usize, actual: usize },
+    #[error("Invalid chunked message: {0}")]
+    InvalidChunk(String),
+    #[error("Invalid batched message: {0}")]
+    InvalidBatch(String),
+}
+
+#[derive(Error, Debug)]
+pub enum NatsConfigError {
+    #[error("Only one of user/password, token, nkey-seed or creds-file may be set, but got {0}")]
+    ConflictingAuth(String),
+    #[error("{0} is set, but {1} is missing")]
+    MissingProperty(&'static str, &'static str),
+    #[error("Invalid nkey-seed: {0}")]
+    InvalidNkeySeed(String),
+    #[error("Invalid subject template {0}")]
+    InvalidSubjectTemplate(String),
+}
+
+#[derive(Error, Debug)]
+pub enum PublishError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Message of {size} bytes exceeds NATS server max_payload of {max_payload} bytes")]
+    Oversize { size: usize, max_payload: usize },
+    #[error("JetStream did not acknowledge message after {attempts} attempts: {source}")]
+    Unacknowledged {
+        attempts: u32,
+        source: std::io::Error,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum MqttConfigError {
+    #[error("Invalid mqtt-address {0}, expected host:port")]
+    InvalidAddress(String),
+    #[error("Invalid client-id {0:?}, client ids may not be empty or start with a space")]
+    InvalidClientId(String),
+    #[error("Invalid {0} {1:?}, topics may not be empty or contain + and # wildcards")]
+    InvalidTopic(&'static str, String),
+    #[error("{0} is set, but {1} is missing")]
+    MissingProperty(&'static str, &'static str),
+    #[error("Failed to read {0}: {1}")]
+    ReadFile(String, std::io::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum ControlError {
+    #[error("Invalid control request: {0}")]
+    InvalidRequest(#[from] serde_json::Error),
+    #[error("No element named {0} in pipeline")]
+    UnknownElement(String),
+    #[error("Element {0} has no property {1}")]
+    UnknownProperty(String, String),
+    #[error("Property {0} is not readable")]
+    NotReadable(String),
+    #[error("Property {0} is not writable")]
+    NotWritable(String),
+    #[error("Property {0} can only be set on construction")]
+    ConstructOnly(String),
+    #[error("Property {0} can not be changed in {1:?} state")]
+    NotMutable(String, gst::State),
+    #[error("Invalid value for property {0}: {1}")]
+    InvalidValue(String, String),
 }
@@ -1,11 +1,208 @@
 use std::collections::BTreeMap;
+use std::io::Write;
 
+use gst::glib;
+use polars::export::arrow::array::Array;
+use polars::export::arrow::chunk::Chunk;
+use polars::export::arrow::datatypes::{PhysicalType, Schema as ArrowSchema};
 use polars::export::arrow::io::ipc;
-use polars::io::json::{JsonFormat, JsonWriter};
+use polars::export::arrow::io::parquet;
+use polars::export::chrono::NaiveDateTime;
+use polars::io::json::{JsonFormat, JsonReader, JsonWriter};
 use polars::prelude::*;
 
+use crate::caps::{
+    ARROW_FILE_MEDIA_TYPE, ARROW_STREAM_MEDIA_TYPE, FRAMING_FIELD, FRAMING_LENGTH_PREFIXED,
+    JSON_MEDIA_TYPE, LAYOUT_COLUMNS, LAYOUT_FIELD, NDJSON_MEDIA_TYPE, PARQUET_MEDIA_TYPE,
+};
 use crate::error::SerializationError;
 
+// Arrow IPC continuation indicator, every message in an arrow streaming ipc message starts with these bytes
+const ARROW_CONTINUATION_MARKER: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+// Arrow IPC file format begins with these magic bytes
+const ARROW_FILE_MAGIC: &[u8] = b"ARROW1";
+// Parquet files begin with these magic bytes
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+
+// Serialization formats understood by dataframe_from_bytes
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum DataframeFormat {
+    ArrowStreamingIpc,
+    ArrowFile,
+    Parquet,
+    Json,
+    JsonFramed,
+    Ndjson,
+}
+
+impl DataframeFormat {
+    // Detect format from the leading bytes of a serialized dataframe
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&ARROW_CONTINUATION_MARKER) {
+            return Some(DataframeFormat::ArrowStreamingIpc);
+        }
+        if bytes.starts_with(ARROW_FILE_MAGIC) {
+            return Some(DataframeFormat::ArrowFile);
+        }
+        if bytes.starts_with(PARQUET_MAGIC) {
+            return Some(DataframeFormat::Parquet);
+        }
+        if bytes.len() > 4 {
+            let (length, payload) = bytes.split_at(4);
+            let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+            if length == payload.len() && matches!(payload.first(), Some(b'[')) {
+                return Some(DataframeFormat::JsonFramed);
+            }
+        }
+        match bytes.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'[') => Some(DataframeFormat::Json),
+            Some(b'{') => Some(DataframeFormat::Ndjson),
+            _ => None,
+        }
+    }
+
+    // Format described by caps media type, for example application/vnd.apache.arrow.stream
+    pub fn from_caps(caps: &gst::CapsRef) -> Option<Self> {
+        let s = caps.structure(0)?;
+        match s.name() {
+            ARROW_STREAM_MEDIA_TYPE => Some(DataframeFormat::ArrowStreamingIpc),
+            ARROW_FILE_MEDIA_TYPE => Some(DataframeFormat::ArrowFile),
+            PARQUET_MEDIA_TYPE => Some(DataframeFormat::Parquet),
+            NDJSON_MEDIA_TYPE => Some(DataframeFormat::Ndjson),
+            JSON_MEDIA_TYPE => match (s.get::<&str>(FRAMING_FIELD), s.get::<&str>(LAYOUT_FIELD)) {
+                // column-oriented json is an output-only format
+                (_, Ok(LAYOUT_COLUMNS)) => None,
+                (Ok(FRAMING_LENGTH_PREFIXED), _) => Some(DataframeFormat::JsonFramed),
+                _ => Some(DataframeFormat::Json),
+            },
+            _ => None,
+        }
+    }
+
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            DataframeFormat::ArrowStreamingIpc => ARROW_STREAM_MEDIA_TYPE,
+            DataframeFormat::ArrowFile => ARROW_FILE_MEDIA_TYPE,
+            DataframeFormat::Parquet => PARQUET_MEDIA_TYPE,
+            DataframeFormat::Json | DataframeFormat::JsonFramed => JSON_MEDIA_TYPE,
+            DataframeFormat::Ndjson => NDJSON_MEDIA_TYPE,
+        }
+    }
+}
+
+// Body compression applied to record batches in arrow ipc messages
+// Arrow readers decompress record batches transparently
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDataframeIpcCompression")]
+pub enum IpcCompression {
+    #[default]
+    #[enum_value(name = "Uncompressed", nick = "none")]
+    Uncompressed = 0,
+    #[enum_value(name = "LZ4 frame compression", nick = "lz4")]
+    Lz4 = 1,
+    #[enum_value(name = "ZSTD compression", nick = "zstd")]
+    Zstd = 2,
+}
+
+impl IpcCompression {
+    fn write_options(&self) -> ipc::write::WriteOptions {
+        let compression = match self {
+            IpcCompression::Uncompressed => None,
+            IpcCompression::Lz4 => Some(ipc::write::Compression::LZ4),
+            IpcCompression::Zstd => Some(ipc::write::Compression::ZSTD),
+        };
+        ipc::write::WriteOptions { compression }
+    }
+
+    // Same as enum_value nick, used in caps compression field
+    pub fn nick(&self) -> &'static str {
+        match self {
+            IpcCompression::Uncompressed => "none",
+            IpcCompression::Lz4 => "lz4",
+            IpcCompression::Zstd => "zstd",
+        }
+    }
+}
+
+// Controls how dataframes are framed into arrow ipc streams
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDataframeIpcStreamMode")]
+pub enum IpcStreamMode {
+    #[default]
+    #[enum_value(
+        name = "Message: every buffer is a complete arrow ipc stream (schema, record batches, end-of-stream marker)",
+        nick = "message"
+    )]
+    Message = 0,
+    #[enum_value(
+        name = "Continuous: schema is sent once per stream, followed by record batches. Concatenated buffers are a single arrow ipc stream",
+        nick = "continuous"
+    )]
+    Continuous = 1,
+}
+
+// Compression codec applied to parquet column chunks
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDataframeParquetCompression")]
+pub enum ParquetCodec {
+    #[enum_value(name = "Uncompressed", nick = "none")]
+    Uncompressed = 0,
+    #[default]
+    #[enum_value(name = "Snappy compression", nick = "snappy")]
+    Snappy = 1,
+    #[enum_value(name = "GZIP compression", nick = "gzip")]
+    Gzip = 2,
+    #[enum_value(name = "LZ4 (raw) compression", nick = "lz4")]
+    Lz4 = 3,
+    #[enum_value(name = "ZSTD compression", nick = "zstd")]
+    Zstd = 4,
+}
+
+impl ParquetCodec {
+    fn compression(&self) -> ParquetCompression {
+        match self {
+            ParquetCodec::Uncompressed => ParquetCompression::Uncompressed,
+            ParquetCodec::Snappy => ParquetCompression::Snappy,
+            ParquetCodec::Gzip => ParquetCompression::Gzip(None),
+            ParquetCodec::Lz4 => ParquetCompression::Lz4Raw,
+            ParquetCodec::Zstd => ParquetCompression::Zstd(None),
+        }
+    }
+}
+
+// Controls how timestamp columns are rendered in JSON output
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDataframeJsonTimestampFormat")]
+pub enum JsonTimestampFormat {
+    #[default]
+    #[enum_value(name = "Raw: i64 nanoseconds", nick = "raw")]
+    Raw = 0,
+    #[enum_value(
+        name = "ISO-8601: clock time columns as date-time strings, running time columns as duration strings",
+        nick = "iso8601"
+    )]
+    Iso8601 = 1,
+}
+
+// Clock time columns (nanoseconds since epoch), rendered as ISO-8601 date-time: 2022-10-01T12:00:00.000000000Z
+pub const CLOCK_TIME_COLUMNS: [&str; 1] = ["ts"];
+// Running time columns (nanoseconds since pipeline start), rendered as ISO-8601 duration: PT12.500000000S
+pub const RUNNING_TIME_COLUMNS: [&str; 5] = [
+    "rt",
+    "rt__min",
+    "rt__max",
+    "_lower_boundary",
+    "_upper_boundary",
+];
+
+pub fn is_arrow_streaming_ipc_message(msg: &[u8]) -> bool {
+    msg.starts_with(&ARROW_CONTINUATION_MARKER)
+}
+
 // dataframe will be re-chunked in-place, then serialized to arrow streaming ipc message
 // NOTE: streaming ipc and file-based ipcs use different memory layouts!
 // The streaming IPC layout encapsulaes an unbounded sequence of messages:
@@ -16,6 +213,7 @@ use crate::error::SerializationError;
 pub fn dataframe_to_arrow_streaming_ipc_message(
     df: &mut DataFrame,
     metadata: Option<BTreeMap<String, String>>,
+    compression: IpcCompression,
 ) -> Result<Vec<u8>, SerializationError> {
     let arrow_schema = match metadata {
         Some(m) => {
@@ -28,10 +226,7 @@ pub fn dataframe_to_arrow_streaming_ipc_message(
     // create a buffed memory writer
     let mut bufwriter = std::io::BufWriter::new(Vec::new());
     // initialize ipc stream writer
-    let mut ipcwriter = ipc::write::StreamWriter::new(
-        &mut bufwriter,
-        ipc::write::WriteOptions { compression: None },
-    );
+    let mut ipcwriter = ipc::write::StreamWriter::new(&mut bufwriter, compression.write_options());
     ipcwriter.start(&arrow_schema, None)?;
     df.rechunk();
     for batch in df.iter_chunks() {
@@ -45,6 +240,162 @@ pub fn dataframe_to_arrow_streaming_ipc_message(
     Ok(arrow_msg)
 }
 
+// Re-encode an arrow streaming ipc message with a different body compression, preserving schema metadata
+pub fn arrow_streaming_ipc_message_with_compression(
+    msg: &[u8],
+    compression: IpcCompression,
+) -> Result<Vec<u8>, SerializationError> {
+    let mut reader = std::io::Cursor::new(msg);
+    let metadata = ipc::read::read_stream_metadata(&mut reader)?;
+    let arrow_schema = metadata.schema.clone();
+    let stream = ipc::read::StreamReader::new(reader, metadata, None);
+
+    let mut bufwriter = std::io::BufWriter::new(Vec::new());
+    let mut ipcwriter = ipc::write::StreamWriter::new(&mut bufwriter, compression.write_options());
+    ipcwriter.start(&arrow_schema, None)?;
+    for state in stream {
+        match state? {
+            ipc::read::StreamState::Some(batch) => ipcwriter.write(&batch, None)?,
+            ipc::read::StreamState::Waiting => break,
+        }
+    }
+    ipcwriter.finish()?;
+
+    let arrow_msg = bufwriter
+        .into_inner()
+        .map_err(|_| SerializationError::BufferError)?;
+    Ok(arrow_msg)
+}
+
+// Re-encode a gstreamer buffer containing an arrow streaming ipc message, copying flags, timestamps and meta from the input buffer
+// Buffers which do not contain an arrow streaming ipc message are returned unchanged
+pub fn arrow_streaming_ipc_buffer_with_compression(
+    buffer: &gst::Buffer,
+    compression: IpcCompression,
+) -> Result<gst::Buffer, SerializationError> {
+    let map = buffer
+        .map_readable()
+        .map_err(|_| SerializationError::BufferError)?;
+    if !is_arrow_streaming_ipc_message(map.as_slice()) {
+        return Ok(buffer.clone());
+    }
+    let arrow_msg = arrow_streaming_ipc_message_with_compression(map.as_slice(), compression)?;
+    drop(map);
+
+    let mut output = gst::Buffer::from_slice(arrow_msg);
+    buffer
+        .copy_into(
+            output.get_mut().unwrap(),
+            gst::BufferCopyFlags::FLAGS
+                | gst::BufferCopyFlags::TIMESTAMPS
+                | gst::BufferCopyFlags::META,
+            0,
+            None,
+        )
+        .map_err(|_| SerializationError::BufferError)?;
+    Ok(output)
+}
+
+// Output of ArrowStreamingIpcEncoder
+// schema is Some(schema message) for the first output of a stream, or when the schema changes
+pub struct ArrowStreamingIpcParts {
+    pub schema: Option<Vec<u8>>,
+    pub batches: Vec<u8>,
+}
+
+// Encodes a continuous arrow ipc stream: the schema message is written once, followed by record batch messages
+// Writing schema + batches of each output in order yields a single valid arrow ipc stream, which may be terminated with ARROW_EOS_MARKER
+// Readers also treat end-of-file as end-of-stream, so the end-of-stream marker is optional
+pub struct ArrowStreamingIpcEncoder {
+    compression: IpcCompression,
+    schema: Option<ArrowSchema>,
+}
+
+// Arrow ipc end-of-stream marker: continuation indicator followed by 0 metadata length
+pub const ARROW_EOS_MARKER: [u8; 8] = [0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00];
+
+impl ArrowStreamingIpcEncoder {
+    pub fn new(compression: IpcCompression) -> Self {
+        Self {
+            compression,
+            schema: None,
+        }
+    }
+
+    // The next output will start a new stream with a schema message
+    pub fn reset(&mut self) {
+        self.schema = None;
+    }
+
+    pub fn set_compression(&mut self, compression: IpcCompression) {
+        self.compression = compression;
+    }
+
+    pub fn encode_dataframe(
+        &mut self,
+        df: &mut DataFrame,
+        metadata: Option<BTreeMap<String, String>>,
+    ) -> Result<ArrowStreamingIpcParts, SerializationError> {
+        let arrow_schema = match metadata {
+            Some(m) => df.schema().to_arrow().with_metadata(m),
+            None => df.schema().to_arrow(),
+        };
+        df.rechunk();
+        let chunks: Vec<Chunk<Box<dyn Array>>> = df.iter_chunks().collect();
+        self.encode_chunks(arrow_schema, &chunks)
+    }
+
+    // Split a complete arrow streaming ipc message (schema, record batches, end-of-stream marker) into parts of a continuous stream
+    pub fn encode_arrow_streaming_ipc_message(
+        &mut self,
+        msg: &[u8],
+    ) -> Result<ArrowStreamingIpcParts, SerializationError> {
+        let mut reader = std::io::Cursor::new(msg);
+        let metadata = ipc::read::read_stream_metadata(&mut reader)?;
+        let arrow_schema = metadata.schema.clone();
+        let mut chunks = vec![];
+        for state in ipc::read::StreamReader::new(reader, metadata, None) {
+            match state? {
+                ipc::read::StreamState::Some(chunk) => chunks.push(chunk),
+                ipc::read::StreamState::Waiting => break,
+            }
+        }
+        self.encode_chunks(arrow_schema, &chunks)
+    }
+
+    fn encode_chunks(
+        &mut self,
+        arrow_schema: ArrowSchema,
+        chunks: &[Chunk<Box<dyn Array>>],
+    ) -> Result<ArrowStreamingIpcParts, SerializationError> {
+        let options = self.compression.write_options();
+
+        // the stream writer always begins with a schema message, which is measured and stripped from batches
+        let mut schema_msg = vec![];
+        let mut ipcwriter = ipc::write::StreamWriter::new(&mut schema_msg, options);
+        ipcwriter.start(&arrow_schema, None)?;
+        drop(ipcwriter);
+
+        let mut msg = vec![];
+        let mut ipcwriter = ipc::write::StreamWriter::new(&mut msg, options);
+        ipcwriter.start(&arrow_schema, None)?;
+        for chunk in chunks {
+            ipcwriter.write(chunk, None)?;
+        }
+        drop(ipcwriter);
+        let batches = msg.split_off(schema_msg.len());
+
+        let schema = match self.schema.as_ref() == Some(&arrow_schema) {
+            true => None,
+            false => {
+                self.schema = Some(arrow_schema);
+                Some(schema_msg)
+            }
+        };
+        Ok(ArrowStreamingIpcParts { schema, batches })
+    }
+}
+
 pub fn dataframe_to_json_bytearray(df: &mut DataFrame) -> Result<Vec<u8>, SerializationError> {
     let mut bufwriter = std::io::BufWriter::new(Vec::new());
     let mut jsonwriter = JsonWriter::new(&mut bufwriter).with_json_format(JsonFormat::Json);
@@ -55,6 +406,293 @@ pub fn dataframe_to_json_bytearray(df: &mut DataFrame) -> Result<Vec<u8>, Serial
     Ok(output)
 }
 
+// Newline-delimited JSON: one object per row, separated by newlines
+pub fn dataframe_to_ndjson_bytearray(df: &mut DataFrame) -> Result<Vec<u8>, SerializationError> {
+    let mut bufwriter = std::io::BufWriter::new(Vec::new());
+    let mut jsonwriter = JsonWriter::new(&mut bufwriter).with_json_format(JsonFormat::JsonLines);
+    jsonwriter.finish(df)?;
+    let output = bufwriter
+        .into_inner()
+        .map_err(|_| SerializationError::BufferError)?;
+    Ok(output)
+}
+
+// Column-oriented JSON: one object with a key per column, in dataframe column order
+// {"x0":[0,0],"x1":[1,1]}
+pub fn dataframe_to_json_columns_bytearray(
+    df: &mut DataFrame,
+) -> Result<Vec<u8>, SerializationError> {
+    let mut output = vec![b'{'];
+    for (i, series) in df.get_columns().iter().enumerate() {
+        if i > 0 {
+            output.push(b',');
+        }
+        serde_json::to_writer(&mut output, series.name())?;
+        output.push(b':');
+        let series = series.rechunk();
+        let values: Vec<serde_json::Value> = series.iter().map(json_value).collect();
+        serde_json::to_writer(&mut output, &values)?;
+    }
+    output.push(b'}');
+    Ok(output)
+}
+
+// Numbers, strings, booleans and lists map to their JSON equivalent. NaN and infinite floats are null, other types are rendered as strings
+fn json_value(value: AnyValue) -> serde_json::Value {
+    match value {
+        AnyValue::Null => serde_json::Value::Null,
+        AnyValue::Boolean(v) => v.into(),
+        AnyValue::Utf8(v) => v.into(),
+        AnyValue::Utf8Owned(v) => v.into(),
+        AnyValue::UInt8(v) => v.into(),
+        AnyValue::UInt16(v) => v.into(),
+        AnyValue::UInt32(v) => v.into(),
+        AnyValue::UInt64(v) => v.into(),
+        AnyValue::Int8(v) => v.into(),
+        AnyValue::Int16(v) => v.into(),
+        AnyValue::Int32(v) => v.into(),
+        AnyValue::Int64(v) => v.into(),
+        AnyValue::Float32(v) => v.into(),
+        AnyValue::Float64(v) => v.into(),
+        AnyValue::List(series) => series.rechunk().iter().map(json_value).collect(),
+        other => other.to_string().into(),
+    }
+}
+
+fn iso8601_datetime(ns: i64) -> Option<String> {
+    let dt = NaiveDateTime::from_timestamp_opt(
+        ns.div_euclid(1_000_000_000),
+        ns.rem_euclid(1_000_000_000) as u32,
+    )?;
+    Some(dt.format("%Y-%m-%dT%H:%M:%S%.9fZ").to_string())
+}
+
+fn iso8601_duration(ns: i64) -> String {
+    let sign = if ns < 0 { "-" } else { "" };
+    let ns = ns.unsigned_abs();
+    format!(
+        "{}PT{}.{:09}S",
+        sign,
+        ns / 1_000_000_000,
+        ns % 1_000_000_000
+    )
+}
+
+// Replace i64 nanosecond columns in CLOCK_TIME_COLUMNS and RUNNING_TIME_COLUMNS with ISO-8601 strings
+// Columns that are missing, or not i64, are left unchanged
+pub fn with_iso8601_timestamps(df: &mut DataFrame) -> Result<(), SerializationError> {
+    let columns = CLOCK_TIME_COLUMNS
+        .iter()
+        .map(|name| (name, true))
+        .chain(RUNNING_TIME_COLUMNS.iter().map(|name| (name, false)));
+    for (name, is_clock_time) in columns {
+        let values = match df.column(name).ok().and_then(|s| s.i64().ok()) {
+            Some(values) => values,
+            None => continue,
+        };
+        let mut rendered: Utf8Chunked = values
+            .into_iter()
+            .map(|v| {
+                v.and_then(|ns| match is_clock_time {
+                    true => iso8601_datetime(ns),
+                    false => Some(iso8601_duration(ns)),
+                })
+            })
+            .collect();
+        rendered.rename(name);
+        df.replace(name, rendered.into_series())?;
+    }
+    Ok(())
+}
+
+// json-framed messages are prefixed with payload length (4 bytes, big-endian u32), so consumers of a byte stream (tcp, unix socket) can split messages
+// | length (u32 BE) | JSON bytearray (length bytes) |
+pub fn dataframe_to_json_framed_bytearray(
+    df: &mut DataFrame,
+) -> Result<Vec<u8>, SerializationError> {
+    let payload = dataframe_to_json_bytearray(df)?;
+    let length = u32::try_from(payload.len()).map_err(|_| SerializationError::BufferError)?;
+    let mut output = Vec::with_capacity(payload.len() + 4);
+    output.extend_from_slice(&length.to_be_bytes());
+    output.extend_from_slice(&payload);
+    Ok(output)
+}
+
+// Write dataframe to a parquet file, including footer
+// row_group_size is the maximum number of rows per row group, or None to write the dataframe's chunks as row groups
+pub fn dataframe_to_parquet<W: Write>(
+    df: &mut DataFrame,
+    writer: W,
+    codec: ParquetCodec,
+    row_group_size: Option<usize>,
+) -> Result<(), SerializationError> {
+    ParquetWriter::new(writer)
+        .with_compression(codec.compression())
+        .with_statistics(true)
+        .with_row_group_size(row_group_size)
+        .finish(df)?;
+    Ok(())
+}
+
+pub fn dataframe_to_parquet_bytearray(
+    df: &mut DataFrame,
+    codec: ParquetCodec,
+    row_group_size: Option<usize>,
+) -> Result<Vec<u8>, SerializationError> {
+    let mut output = Vec::new();
+    dataframe_to_parquet(df, &mut output, codec, row_group_size)?;
+    Ok(output)
+}
+
+// Parquet file written incrementally, one row group at a time. The file's schema is taken from the first dataframe, and the footer is written by finish()
+pub struct ParquetFileWriter<W: Write> {
+    writer: parquet::write::FileWriter<W>,
+    encodings: Vec<Vec<parquet::write::Encoding>>,
+}
+
+impl<W: Write> ParquetFileWriter<W> {
+    pub fn try_new(
+        writer: W,
+        schema: &Schema,
+        codec: ParquetCodec,
+    ) -> Result<Self, SerializationError> {
+        let schema = schema.to_arrow();
+        let options = parquet::write::WriteOptions {
+            write_statistics: true,
+            compression: codec.compression(),
+            version: parquet::write::Version::V2,
+        };
+        let encodings = schema
+            .fields
+            .iter()
+            .map(|field| {
+                parquet::write::transverse(&field.data_type, |data_type| {
+                    match data_type.to_physical_type() {
+                        PhysicalType::Dictionary(_) => parquet::write::Encoding::RleDictionary,
+                        _ => parquet::write::Encoding::Plain,
+                    }
+                })
+            })
+            .collect();
+        let writer = parquet::write::FileWriter::try_new(writer, schema, options)?;
+        Ok(Self { writer, encodings })
+    }
+
+    // Dataframes with a different schema can't be added to this file
+    pub fn accepts(&self, schema: &Schema) -> bool {
+        self.writer.schema().fields == schema.to_arrow().fields
+    }
+
+    // Append dataframe to the file as a row group (or one row group per chunk, if the dataframe is not contiguous)
+    pub fn write_row_group(&mut self, df: &DataFrame) -> Result<(), SerializationError> {
+        let options = self.writer.options();
+        let fields = self.writer.parquet_schema().fields().to_vec();
+        for chunk in df.iter_chunks() {
+            if chunk.is_empty() {
+                continue;
+            }
+            let row_group = parquet::write::row_group_iter(
+                chunk,
+                self.encodings.clone(),
+                fields.clone(),
+                options,
+            );
+            self.writer.write(row_group)?;
+        }
+        Ok(())
+    }
+
+    // Write the parquet footer, and return the inner writer
+    pub fn finish(mut self) -> Result<W, SerializationError> {
+        self.writer.end(None)?;
+        Ok(self.writer.into_inner())
+    }
+}
+
+// Schema metadata of arrow and parquet bytes, without reading record batches
+// Empty for JSON formats, which have no schema, and for bytes without a schema (for example arrow ipc record batch messages)
+pub fn metadata_from_bytes(bytes: &[u8]) -> BTreeMap<String, String> {
+    let mut cursor = std::io::Cursor::new(bytes);
+    let metadata = match DataframeFormat::detect(bytes) {
+        Some(DataframeFormat::ArrowStreamingIpc) => ipc::read::read_stream_metadata(&mut cursor)
+            .map(|metadata| metadata.schema.metadata)
+            .ok(),
+        Some(DataframeFormat::ArrowFile) => ipc::read::read_file_metadata(&mut cursor)
+            .map(|metadata| metadata.schema.metadata)
+            .ok(),
+        Some(DataframeFormat::Parquet) => parquet::read::read_metadata(&mut cursor)
+            .ok()
+            .and_then(|metadata| parquet::read::infer_schema(&metadata).ok())
+            .map(|schema| schema.metadata),
+        _ => None,
+    };
+    metadata.unwrap_or_default()
+}
+
+// Deserialize a dataframe and its schema metadata
+// format is detected from magic bytes when None. JSON formats do not carry schema metadata, so an empty map is returned
+pub fn dataframe_from_bytes(
+    bytes: &[u8],
+    format: Option<DataframeFormat>,
+) -> Result<(DataFrame, BTreeMap<String, String>), SerializationError> {
+    let format = match format {
+        Some(format) => format,
+        None => DataframeFormat::detect(bytes).ok_or(SerializationError::UnknownFormat)?,
+    };
+    let cursor = std::io::Cursor::new(bytes);
+    match format {
+        DataframeFormat::ArrowStreamingIpc => {
+            let metadata = ipc::read::read_stream_metadata(&mut cursor.clone())?
+                .schema
+                .metadata;
+            let df = IpcStreamReader::new(cursor).finish()?;
+            Ok((df, metadata))
+        }
+        DataframeFormat::ArrowFile => {
+            let metadata = ipc::read::read_file_metadata(&mut cursor.clone())?
+                .schema
+                .metadata;
+            let df = IpcReader::new(cursor).finish()?;
+            Ok((df, metadata))
+        }
+        DataframeFormat::Parquet => {
+            let file_metadata = parquet::read::read_metadata(&mut cursor.clone())?;
+            let metadata = parquet::read::infer_schema(&file_metadata)?.metadata;
+            let df = ParquetReader::new(cursor).finish()?;
+            Ok((df, metadata))
+        }
+        DataframeFormat::Json => {
+            let df = JsonReader::new(cursor)
+                .with_json_format(JsonFormat::Json)
+                .finish()?;
+            Ok((df, BTreeMap::new()))
+        }
+        DataframeFormat::Ndjson => {
+            let df = JsonReader::new(cursor)
+                .with_json_format(JsonFormat::JsonLines)
+                .finish()?;
+            Ok((df, BTreeMap::new()))
+        }
+        DataframeFormat::JsonFramed => {
+            if bytes.len() < 4 {
+                return Err(SerializationError::FramingError {
+                    expected: 4,
+                    actual: bytes.len(),
+                });
+            }
+            let (length, payload) = bytes.split_at(4);
+            let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+            if length != payload.len() {
+                return Err(SerializationError::FramingError {
+                    expected: length,
+                    actual: payload.len(),
+                });
+            }
+            dataframe_from_bytes(payload, Some(DataframeFormat::Json))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -86,6 +724,67 @@ mod tests {
         assert_eq!(String::from_utf8(b).unwrap(), expected_json);
     }
 
+    #[test]
+    fn test_dataframe_to_ndjson_and_json_columns() {
+        let mut dataframe = df!(
+            "x0" => vec![0; 2],
+            "x1" => vec![1; 2]
+        )
+        .unwrap();
+
+        let ndjson = dataframe_to_ndjson_bytearray(&mut dataframe).unwrap();
+        let lines: Vec<&str> = std::str::from_utf8(&ndjson).unwrap().lines().collect();
+        assert_eq!(lines, vec![r#"{"x0":0,"x1":1}"#, r#"{"x0":0,"x1":1}"#]);
+
+        let columns = dataframe_to_json_columns_bytearray(&mut dataframe).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&columns).unwrap(),
+            r#"{"x0":[0,0],"x1":[1,1]}"#
+        );
+
+        let mut dataframe = df!(
+            "score" => [Some(0.5f32), None],
+            "label" => ["nozzle", "adhesion"],
+            "alert" => [true, false]
+        )
+        .unwrap();
+        let columns = dataframe_to_json_columns_bytearray(&mut dataframe).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&columns).unwrap(),
+            r#"{"score":[0.5,null],"label":["nozzle","adhesion"],"alert":[true,false]}"#
+        );
+    }
+
+    #[test]
+    fn test_with_iso8601_timestamps() {
+        let mut dataframe = df!(
+            "ts" => [1_664_625_600_500_000_000i64],
+            "rt" => [12_500_000_000i64],
+            "x0" => [0i64]
+        )
+        .unwrap();
+        with_iso8601_timestamps(&mut dataframe).unwrap();
+        let row = dataframe.get(0).unwrap();
+        assert_eq!(row[0], AnyValue::Utf8("2022-10-01T12:00:00.500000000Z"));
+        assert_eq!(row[1], AnyValue::Utf8("PT12.500000000S"));
+        assert_eq!(row[2], AnyValue::Int64(0));
+    }
+
+    #[test]
+    fn test_dataframe_to_json_framed() {
+        let mut dataframe = df!(
+            "x0" => vec![0; 2],
+            "x1" => vec![1; 2]
+        )
+        .unwrap();
+
+        let expected_json = r#"[{"x0":0,"x1":1},{"x0":0,"x1":1}]"#;
+        let b = dataframe_to_json_framed_bytearray(&mut dataframe).unwrap();
+
+        assert_eq!(b[0..4], (expected_json.len() as u32).to_be_bytes());
+        assert_eq!(String::from_utf8(b[4..].to_vec()).unwrap(), expected_json);
+    }
+
     #[test]
     fn test_dataframe_to_arrow_streaming_ipc_message() {
         let mut dataframe = df!(
@@ -118,7 +817,12 @@ mod tests {
         ]
         .map(|v| v as u8);
 
-        let b = dataframe_to_arrow_streaming_ipc_message(&mut dataframe, None).unwrap();
+        let b = dataframe_to_arrow_streaming_ipc_message(
+            &mut dataframe,
+            None,
+            IpcCompression::Uncompressed,
+        )
+        .unwrap();
         assert_eq!(b, expected);
     }
 
@@ -164,7 +868,200 @@ mod tests {
             ("frame_rate_d".to_string(), "15".to_string()),
         ]);
 
-        let b = dataframe_to_arrow_streaming_ipc_message(&mut dataframe, Some(metadata)).unwrap();
+        let b = dataframe_to_arrow_streaming_ipc_message(
+            &mut dataframe,
+            Some(metadata),
+            IpcCompression::Uncompressed,
+        )
+        .unwrap();
         assert_eq!(b, expected);
     }
+
+    #[test]
+    fn test_dataframe_to_parquet() {
+        let mut dataframe = df!(
+            "x0" => vec![0; 10],
+            "x1" => vec![1; 10]
+        )
+        .unwrap();
+
+        for codec in [
+            ParquetCodec::Uncompressed,
+            ParquetCodec::Snappy,
+            ParquetCodec::Zstd,
+        ] {
+            let bytes = dataframe_to_parquet_bytearray(&mut dataframe, codec, Some(4)).unwrap();
+            // parquet files begin and end with magic bytes
+            assert!(bytes.starts_with(b"PAR1"));
+            assert!(bytes.ends_with(b"PAR1"));
+            let df = ParquetReader::new(std::io::Cursor::new(bytes))
+                .finish()
+                .unwrap();
+            assert!(df.frame_equal(&dataframe));
+        }
+    }
+
+    #[test]
+    fn test_parquet_file_writer() {
+        let first = df!("x0" => vec![0; 10], "x1" => vec![1; 10]).unwrap();
+        let second = df!("x0" => vec![2; 5], "x1" => vec![3; 5]).unwrap();
+        let other = df!("y0" => vec![0; 5]).unwrap();
+
+        let mut writer =
+            ParquetFileWriter::try_new(Vec::new(), &first.schema(), ParquetCodec::Snappy).unwrap();
+        assert!(writer.accepts(&second.schema()));
+        assert!(!writer.accepts(&other.schema()));
+        writer.write_row_group(&first).unwrap();
+        writer.write_row_group(&second).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let file_metadata =
+            parquet::read::read_metadata(&mut std::io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(file_metadata.row_groups.len(), 2);
+        let df = ParquetReader::new(std::io::Cursor::new(bytes))
+            .finish()
+            .unwrap();
+        let mut expected = first.clone();
+        expected.vstack_mut(&second).unwrap();
+        assert!(df.frame_equal(&expected));
+    }
+
+    #[test]
+    fn test_dataframe_from_bytes() {
+        let mut dataframe = df!(
+            "x0" => vec![0; 10],
+            "x1" => vec![1; 10]
+        )
+        .unwrap();
+        let metadata = BTreeMap::from([("model".to_string(), "test".to_string())]);
+
+        let arrow_stream = dataframe_to_arrow_streaming_ipc_message(
+            &mut dataframe,
+            Some(metadata.clone()),
+            IpcCompression::Uncompressed,
+        )
+        .unwrap();
+        let mut arrow_file = vec![];
+        IpcWriter::new(&mut arrow_file)
+            .finish(&mut dataframe)
+            .unwrap();
+        let parquet =
+            dataframe_to_parquet_bytearray(&mut dataframe, ParquetCodec::Snappy, None).unwrap();
+        let json = dataframe_to_json_bytearray(&mut dataframe).unwrap();
+        let json_framed = dataframe_to_json_framed_bytearray(&mut dataframe).unwrap();
+        let mut ndjson = vec![];
+        JsonWriter::new(&mut ndjson)
+            .with_json_format(JsonFormat::JsonLines)
+            .finish(&mut dataframe)
+            .unwrap();
+
+        for (bytes, format) in [
+            (&arrow_stream, DataframeFormat::ArrowStreamingIpc),
+            (&arrow_file, DataframeFormat::ArrowFile),
+            (&parquet, DataframeFormat::Parquet),
+            (&json, DataframeFormat::Json),
+            (&json_framed, DataframeFormat::JsonFramed),
+            (&ndjson, DataframeFormat::Ndjson),
+        ] {
+            assert_eq!(DataframeFormat::detect(bytes), Some(format));
+            let (df, _) = dataframe_from_bytes(bytes, None).unwrap();
+            assert_eq!(df.shape(), dataframe.shape(), "{:?}", format);
+        }
+
+        let (_, decoded_metadata) = dataframe_from_bytes(&arrow_stream, None).unwrap();
+        assert_eq!(decoded_metadata, metadata);
+        assert_eq!(metadata_from_bytes(&arrow_stream), metadata);
+        assert!(metadata_from_bytes(&json).is_empty());
+        assert!(metadata_from_bytes(b"not a dataframe").is_empty());
+
+        assert!(matches!(
+            dataframe_from_bytes(b"not a dataframe", None),
+            Err(SerializationError::UnknownFormat)
+        ));
+        assert!(matches!(
+            dataframe_from_bytes(
+                &json_framed[..json_framed.len() - 1],
+                Some(DataframeFormat::JsonFramed)
+            ),
+            Err(SerializationError::FramingError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_arrow_streaming_ipc_encoder() {
+        let mut dataframe = df!(
+            "x0" => vec![0; 10],
+            "x1" => vec![1; 10]
+        )
+        .unwrap();
+
+        let mut encoder = ArrowStreamingIpcEncoder::new(IpcCompression::Uncompressed);
+        let first = encoder.encode_dataframe(&mut dataframe, None).unwrap();
+        let second = encoder.encode_dataframe(&mut dataframe, None).unwrap();
+        assert!(first.schema.is_some());
+        assert!(second.schema.is_none());
+
+        // schema + batches + batches is a single arrow ipc stream
+        let mut stream = first.schema.unwrap();
+        stream.extend(first.batches);
+        stream.extend(second.batches);
+        stream.extend(ARROW_EOS_MARKER);
+        let df = IpcStreamReader::new(std::io::Cursor::new(&stream))
+            .finish()
+            .unwrap();
+        assert_eq!(df.shape(), (20, 2));
+
+        // complete stream messages are split into the same parts
+        let msg = dataframe_to_arrow_streaming_ipc_message(
+            &mut dataframe,
+            None,
+            IpcCompression::Uncompressed,
+        )
+        .unwrap();
+        let parts = encoder.encode_arrow_streaming_ipc_message(&msg).unwrap();
+        assert!(parts.schema.is_none());
+        encoder.reset();
+        let parts = encoder.encode_arrow_streaming_ipc_message(&msg).unwrap();
+        let mut stream = parts.schema.unwrap();
+        stream.extend(parts.batches);
+        stream.extend(ARROW_EOS_MARKER);
+        assert_eq!(stream, msg);
+    }
+
+    #[test]
+    fn test_arrow_streaming_ipc_message_compression() {
+        let mut dataframe = df!(
+            "x0" => vec![0; 1000],
+            "x1" => vec![1; 1000]
+        )
+        .unwrap();
+
+        let uncompressed = dataframe_to_arrow_streaming_ipc_message(
+            &mut dataframe,
+            None,
+            IpcCompression::Uncompressed,
+        )
+        .unwrap();
+
+        for compression in [IpcCompression::Lz4, IpcCompression::Zstd] {
+            let compressed =
+                dataframe_to_arrow_streaming_ipc_message(&mut dataframe, None, compression)
+                    .unwrap();
+            assert!(is_arrow_streaming_ipc_message(&compressed));
+            assert!(compressed.len() < uncompressed.len());
+
+            // readers decompress transparently
+            let df = IpcStreamReader::new(std::io::Cursor::new(&compressed))
+                .finish()
+                .unwrap();
+            assert!(df.frame_equal(&dataframe));
+
+            let recompressed = arrow_streaming_ipc_message_with_compression(
+                &compressed,
+                IpcCompression::Uncompressed,
+            )
+            .unwrap();
+            assert_eq!(recompressed, uncompressed);
+        }
+    }
 }
@@ -1,17 +1,33 @@
 use gst::glib;
 mod dataframe_agg;
 mod dataframe_filesink;
+mod dataframe_parquetsink;
+mod dataframe_roimeta;
+mod mqtt_sink;
 mod nats_sink;
+mod nats_src;
+mod roimeta_dataframe;
 
+pub mod caps;
+pub mod control;
 pub mod error;
 pub mod ipc;
+pub mod metadata;
+pub mod nats_common;
 pub mod nnstreamer;
+pub mod roi;
 pub mod tensor;
 
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     dataframe_filesink::register(plugin)?;
     dataframe_agg::register(plugin)?;
+    dataframe_parquetsink::register(plugin)?;
+    roi::register_dataframe_meta();
+    dataframe_roimeta::register(plugin)?;
+    roimeta_dataframe::register(plugin)?;
     nats_sink::register(plugin)?;
+    nats_src::register(plugin)?;
+    mqtt_sink::register(plugin)?;
     nnstreamer::register_nnstreamer_callbacks();
     Ok(())
 }
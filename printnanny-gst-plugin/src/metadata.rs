@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+// Version of the dataframe schemas produced by this plugin. Increment when columns are added, removed or renamed
+pub const SCHEMA_VERSION: &str = "1";
+
+// Standard schema metadata keys
+pub const SCHEMA_VERSION_KEY: &str = "printnanny:schema_version";
+pub const PLUGIN_VERSION_KEY: &str = "printnanny:plugin_version";
+pub const PLUGIN_COMMIT_KEY: &str = "printnanny:plugin_commit";
+pub const MODEL_FILE_KEY: &str = "printnanny:model_file";
+pub const MODEL_SHA256_KEY: &str = "printnanny:model_sha256";
+pub const LABELS_KEY: &str = "printnanny:labels"; // comma-separated, indexed by detection_classes value
+pub const CAMERA_ID_KEY: &str = "printnanny:camera_id";
+
+// class labels of the default PrintNanny model, indexed by detection_classes value
+pub const DEFAULT_LABELS: [&str; 5] = ["nozzle", "adhesion", "spaghetti", "print", "raft"];
+
+// Schema version and plugin build, added by every element producing dataframes
+pub fn plugin_metadata() -> BTreeMap<String, String> {
+    BTreeMap::from([
+        (SCHEMA_VERSION_KEY.to_string(), SCHEMA_VERSION.to_string()),
+        (
+            PLUGIN_VERSION_KEY.to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        (PLUGIN_COMMIT_KEY.to_string(), env!("COMMIT_ID").to_string()),
+    ])
+}
+
+// Describes the model and camera producing detections
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SourceMetadata {
+    pub model_file: Option<String>,
+    pub model_sha256: Option<String>,
+    pub labels: Option<Vec<String>>,
+    pub camera_id: Option<String>,
+}
+
+impl SourceMetadata {
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        let mut metadata = BTreeMap::new();
+        if let Some(model_file) = &self.model_file {
+            metadata.insert(MODEL_FILE_KEY.to_string(), model_file.clone());
+        }
+        if let Some(model_sha256) = &self.model_sha256 {
+            metadata.insert(MODEL_SHA256_KEY.to_string(), model_sha256.clone());
+        }
+        if let Some(labels) = &self.labels {
+            metadata.insert(LABELS_KEY.to_string(), labels.join(","));
+        }
+        if let Some(camera_id) = &self.camera_id {
+            metadata.insert(CAMERA_ID_KEY.to_string(), camera_id.clone());
+        }
+        metadata
+    }
+}
+
+// Hex-encoded sha256 digest of file contents
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Labels from LABELS_KEY schema metadata, if present
+pub fn labels_from_metadata(metadata: &BTreeMap<String, String>) -> Option<Vec<String>> {
+    metadata
+        .get(LABELS_KEY)
+        .map(|labels| labels.split(',').map(|label| label.to_string()).collect())
+}
+
+// Read labels.txt file, one label per line
+pub fn read_labels<P: AsRef<Path>>(path: P) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect())
+}
+
+// Merge metadata maps in order, keys in later maps take precedence
+pub fn merge_metadata<I>(maps: I) -> BTreeMap<String, String>
+where
+    I: IntoIterator<Item = BTreeMap<String, String>>,
+{
+    maps.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_source_metadata() {
+        let base_path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let labels = read_labels(base_path.join("fixtures/labels.txt")).unwrap();
+        assert_eq!(labels[0], "nozzle");
+
+        let model_sha256 = sha256_file(base_path.join("fixtures/model.tflite")).unwrap();
+        assert_eq!(model_sha256.len(), 64);
+
+        let source = SourceMetadata {
+            model_file: None,
+            model_sha256: Some(model_sha256.clone()),
+            labels: Some(labels),
+            camera_id: Some("camera0".into()),
+        };
+        let metadata = merge_metadata([
+            BTreeMap::from([
+                ("frame_rate_n".to_string(), "15".to_string()),
+                (CAMERA_ID_KEY.to_string(), "upstream".to_string()),
+            ]),
+            plugin_metadata(),
+            source.to_map(),
+        ]);
+        assert_eq!(metadata["frame_rate_n"], "15");
+        assert_eq!(metadata[CAMERA_ID_KEY], "camera0");
+        assert_eq!(metadata[MODEL_SHA256_KEY], model_sha256);
+        assert_eq!(metadata[SCHEMA_VERSION_KEY], SCHEMA_VERSION);
+        assert!(!metadata.contains_key(MODEL_FILE_KEY));
+        assert_eq!(labels_from_metadata(&metadata).unwrap()[2], "spaghetti");
+    }
+}
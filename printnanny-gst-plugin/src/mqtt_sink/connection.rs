@@ -0,0 +1,488 @@
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use gst::glib;
+use gst::prelude::*;
+use rumqttc::{
+    Client, Connection, Event, Key, LastWill, MqttOptions, Outgoing, Packet, QoS, TlsConfiguration,
+    Transport,
+};
+
+use super::homeassistant::Availability;
+use super::imp::CAT;
+use super::MqttQos;
+use crate::error::MqttConfigError;
+
+// Name of the bus message posted when the MQTT connection state changes
+pub const CONNECTION_MESSAGE_NAME: &str = "printnanny-mqtt-connection";
+
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5); // max time to publish queued messages when stopping
+const MAX_PACKET_SIZE: usize = 268_435_455; // max remaining length of an MQTT packet, dataframes are often larger than rumqttc's 10KiB default
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+    Closed, // the element was stopped
+}
+
+impl ConnectionState {
+    pub fn nick(&self) -> &'static str {
+        match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Disconnected => "disconnected",
+            ConnectionState::Closed => "closed",
+        }
+    }
+}
+
+// Message published with the element's qos
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+pub fn qos(qos: MqttQos) -> QoS {
+    match qos {
+        MqttQos::AtMostOnce => QoS::AtMostOnce,
+        MqttQos::AtLeastOnce => QoS::AtLeastOnce,
+        MqttQos::ExactlyOnce => QoS::ExactlyOnce,
+    }
+}
+
+// Split host:port, host may be a bracketed IPv6 address
+pub fn parse_address(address: &str) -> Result<(String, u16), MqttConfigError> {
+    let invalid = || MqttConfigError::InvalidAddress(address.to_string());
+    let (host, port) = address.rsplit_once(':').ok_or_else(invalid)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = port.parse::<u16>().map_err(|_| invalid())?;
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok((host.to_string(), port))
+}
+
+// Topics of published messages may not contain wildcards
+pub fn validate_topic(property: &'static str, topic: &str) -> Result<(), MqttConfigError> {
+    if topic.is_empty() || topic.contains(['+', '#']) {
+        return Err(MqttConfigError::InvalidTopic(property, topic.to_string()));
+    }
+    Ok(())
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, MqttConfigError> {
+    fs::read(path).map_err(|err| MqttConfigError::ReadFile(path.to_string(), err))
+}
+
+// rustls needs the key's algorithm, which is named in the PEM header
+fn client_key(pem: Vec<u8>) -> Key {
+    if String::from_utf8_lossy(&pem).contains("BEGIN EC PRIVATE KEY") {
+        Key::ECC(pem)
+    } else {
+        Key::RSA(pem)
+    }
+}
+
+#[derive(Clone)]
+pub struct ConnectionOptions {
+    pub address: String,
+    pub client_id: String,
+    pub qos: MqttQos,
+    pub keep_alive: Duration,
+    pub reconnect_delay: Duration,
+    pub max_queue_size: usize,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls_ca_file: Option<String>,
+    pub tls_cert_file: Option<String>,
+    pub tls_key_file: Option<String>,
+    pub will: Option<Availability>, // the broker publishes the offline payload if the connection is lost, the online payload is published after connecting
+    pub announce: Vec<MqttMessage>, // published after every connect, for example Home Assistant discovery configs
+}
+
+impl ConnectionOptions {
+    pub fn mqtt_options(&self) -> Result<MqttOptions, MqttConfigError> {
+        let (host, port) = parse_address(&self.address)?;
+        if self.client_id.is_empty() || self.client_id.starts_with(' ') {
+            return Err(MqttConfigError::InvalidClientId(self.client_id.clone()));
+        }
+        let mut options = MqttOptions::new(self.client_id.clone(), host, port);
+        options.set_keep_alive(self.keep_alive);
+        options.set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+
+        match (&self.username, &self.password) {
+            (Some(username), password) => {
+                options.set_credentials(username, password.as_deref().unwrap_or_default());
+            }
+            (None, Some(_)) => {
+                return Err(MqttConfigError::MissingProperty("password", "username"))
+            }
+            (None, None) => (),
+        }
+
+        if let Some(will) = &self.will {
+            options.set_last_will(LastWill::new(
+                will.topic.clone(),
+                will.offline_payload.clone(),
+                qos(self.qos),
+                true,
+            ));
+        }
+
+        match &self.tls_ca_file {
+            Some(ca_file) => {
+                let client_auth = match (&self.tls_cert_file, &self.tls_key_file) {
+                    (Some(cert_file), Some(key_file)) => {
+                        Some((read_file(cert_file)?, client_key(read_file(key_file)?)))
+                    }
+                    (Some(_), None) => {
+                        return Err(MqttConfigError::MissingProperty(
+                            "tls-cert-file",
+                            "tls-key-file",
+                        ))
+                    }
+                    (None, Some(_)) => {
+                        return Err(MqttConfigError::MissingProperty(
+                            "tls-key-file",
+                            "tls-cert-file",
+                        ))
+                    }
+                    (None, None) => None,
+                };
+                options.set_transport(Transport::tls_with_config(TlsConfiguration::Simple {
+                    ca: read_file(ca_file)?,
+                    alpn: None,
+                    client_auth,
+                }));
+            }
+            None if self.tls_cert_file.is_some() => {
+                return Err(MqttConfigError::MissingProperty(
+                    "tls-cert-file",
+                    "tls-ca-file",
+                ))
+            }
+            None if self.tls_key_file.is_some() => {
+                return Err(MqttConfigError::MissingProperty(
+                    "tls-key-file",
+                    "tls-ca-file",
+                ))
+            }
+            None => (),
+        }
+        Ok(options)
+    }
+}
+
+// Message counters, read without locking by the element's read-only properties
+#[derive(Debug, Default)]
+pub struct PublishStats {
+    pub published: AtomicU64,
+    pub dropped: AtomicU64,
+}
+
+impl PublishStats {
+    pub fn reset(&self) {
+        self.published.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+    }
+}
+
+struct Inner {
+    client: Client,
+    state: ConnectionState,
+    stopping: bool,
+    disconnecting: bool, // a disconnect request was queued behind the messages waiting to be published
+}
+
+impl Inner {
+    // Fails while the queue is full, and is retried by the event loop thread
+    fn disconnect(&mut self) {
+        if !self.disconnecting {
+            self.disconnecting = self.client.try_disconnect().is_ok();
+        }
+    }
+}
+
+// MQTT connection driven by an event loop thread, which reconnects after reconnect-delay when the connection is lost
+// Messages are queued in rumqttc's request queue, so a slow or unreachable broker never blocks the streaming thread
+pub struct MqttConnection {
+    options: ConnectionOptions,
+    element: glib::WeakRef<super::MqttSink>,
+    inner: Mutex<Inner>,
+    changed: Condvar, // notified on state and stopping changes
+    stats: Arc<PublishStats>,
+}
+
+impl MqttConnection {
+    // Returns immediately, the connection is established by a background thread
+    pub fn start(
+        element: &super::MqttSink,
+        options: ConnectionOptions,
+        stats: Arc<PublishStats>,
+    ) -> Result<Arc<Self>, MqttConfigError> {
+        let (client, connection) = Client::new(options.mqtt_options()?, options.max_queue_size);
+        let mqtt_connection = Arc::new(Self {
+            inner: Mutex::new(Inner {
+                client,
+                state: ConnectionState::Connecting,
+                stopping: false,
+                disconnecting: false,
+            }),
+            options,
+            element: element.downgrade(),
+            changed: Condvar::new(),
+            stats,
+        });
+        // queued before the first buffer, so they are the first messages published after connecting
+        mqtt_connection.announce();
+        let thread_connection = mqtt_connection.clone();
+        thread::spawn(move || thread_connection.event_loop(connection));
+        Ok(mqtt_connection)
+    }
+
+    // Publish the will topic's online payload and announce messages
+    fn announce(&self) {
+        let online = self.options.will.as_ref().map(|will| MqttMessage {
+            topic: will.topic.clone(),
+            payload: will.online_payload.clone().into_bytes(),
+            retain: true,
+        });
+        for msg in online
+            .into_iter()
+            .chain(self.options.announce.iter().cloned())
+        {
+            let topic = msg.topic.clone();
+            if !self.publish(msg) {
+                gst::warning!(CAT, "Failed to queue message to {}, queue is full", topic);
+            }
+        }
+    }
+
+    fn event_loop(self: Arc<Self>, mut connection: Connection) {
+        self.set_state(ConnectionState::Connecting, None);
+        let mut connected = false;
+        for event in connection.iter() {
+            let mut inner = self.inner.lock().unwrap();
+            let stopping = inner.stopping;
+            if stopping {
+                inner.disconnect();
+            }
+            drop(inner);
+
+            match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    self.set_state(ConnectionState::Connected, None);
+                    // announced again after reconnecting, because the broker published the will's offline payload when the connection was lost
+                    if connected {
+                        self.announce();
+                    }
+                    connected = true;
+                }
+                Ok(Event::Outgoing(Outgoing::Publish(_))) => {
+                    self.stats.published.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                Ok(_) => (),
+                Err(_) if stopping => break,
+                Err(err) => {
+                    self.set_state(ConnectionState::Disconnected, Some(err.to_string()));
+                    // rumqttc reconnects on the next iteration, so wait for reconnect-delay or until the element stops
+                    let inner = self.inner.lock().unwrap();
+                    let (inner, _) = self
+                        .changed
+                        .wait_timeout_while(inner, self.options.reconnect_delay, |inner| {
+                            !inner.stopping
+                        })
+                        .unwrap();
+                    if inner.stopping {
+                        break;
+                    }
+                }
+            }
+        }
+        self.set_state(ConnectionState::Closed, None);
+    }
+
+    // Update state, and post a printnanny-mqtt-connection message
+    fn set_state(&self, state: ConnectionState, error: Option<String>) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.stopping && state != ConnectionState::Closed {
+            return;
+        }
+        inner.state = state;
+        self.changed.notify_all();
+        drop(inner);
+
+        let element = match self.element.upgrade() {
+            Some(element) => element,
+            None => return,
+        };
+        match &error {
+            Some(error) => gst::warning!(
+                CAT,
+                obj: &element,
+                "MQTT connection to {} {}: {}",
+                self.options.address,
+                state.nick(),
+                error
+            ),
+            None => gst::info!(
+                CAT,
+                obj: &element,
+                "MQTT connection to {} {}",
+                self.options.address,
+                state.nick()
+            ),
+        }
+        let mut structure = gst::Structure::builder(CONNECTION_MESSAGE_NAME)
+            .field("state", state.nick())
+            .field("address", &self.options.address)
+            .build();
+        if let Some(error) = error {
+            structure.set("error", error);
+        }
+        let msg = gst::message::Element::builder(structure)
+            .src(&element)
+            .build();
+        let _ = element.post_message(msg);
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.inner.lock().unwrap().state
+    }
+
+    // Returns false if the message was dropped, because the queue is full or the element is stopping
+    pub fn publish(&self, msg: MqttMessage) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let queued = !inner.stopping
+            && inner
+                .client
+                .try_publish(msg.topic, qos(self.options.qos), msg.retain, msg.payload)
+                .is_ok();
+        if !queued {
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        queued
+    }
+
+    // Publish queued messages while connected, for at most DRAIN_TIMEOUT, then disconnect
+    // The broker does not publish the will's offline payload after a clean disconnect, so it is published before disconnecting
+    pub fn close(&self) {
+        if let Some(will) = &self.options.will {
+            self.publish(MqttMessage {
+                topic: will.topic.clone(),
+                payload: will.offline_payload.clone().into_bytes(),
+                retain: true,
+            });
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.stopping = true;
+        inner.disconnect();
+        self.changed.notify_all();
+        let (_inner, _) = self
+            .changed
+            .wait_timeout_while(inner, DRAIN_TIMEOUT, |inner| {
+                inner.state != ConnectionState::Closed
+            })
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> ConnectionOptions {
+        ConnectionOptions {
+            address: "127.0.0.1:1883".into(),
+            client_id: "printnanny-test".into(),
+            qos: MqttQos::AtLeastOnce,
+            keep_alive: Duration::from_secs(60),
+            reconnect_delay: Duration::from_secs(1),
+            max_queue_size: 10,
+            username: None,
+            password: None,
+            tls_ca_file: None,
+            tls_cert_file: None,
+            tls_key_file: None,
+            will: None,
+            announce: vec![],
+        }
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_address("127.0.0.1:1883").unwrap(),
+            ("127.0.0.1".to_string(), 1883)
+        );
+        assert_eq!(
+            parse_address("[::1]:8883").unwrap(),
+            ("::1".to_string(), 8883)
+        );
+        assert!(parse_address("homeassistant.local").is_err());
+        assert!(parse_address("homeassistant.local:mqtt").is_err());
+        assert!(parse_address(":1883").is_err());
+    }
+
+    #[test]
+    fn test_validate_topic() {
+        assert!(validate_topic("topic", "printnanny/qc/df").is_ok());
+        assert!(validate_topic("topic", "printnanny/+/df").is_err());
+        assert!(validate_topic("topic", "printnanny/#").is_err());
+        assert!(validate_topic("topic", "").is_err());
+    }
+
+    #[test]
+    fn test_mqtt_options() {
+        let mqtt_options = options().mqtt_options().unwrap();
+        assert_eq!(
+            mqtt_options.broker_address(),
+            ("127.0.0.1".to_string(), 1883)
+        );
+        assert_eq!(mqtt_options.keep_alive(), Duration::from_secs(60));
+
+        let err = ConnectionOptions {
+            password: Some("secret".into()),
+            ..options()
+        }
+        .mqtt_options()
+        .unwrap_err();
+        assert_eq!(err.to_string(), "password is set, but username is missing");
+
+        let err = ConnectionOptions {
+            tls_cert_file: Some("client.crt".into()),
+            ..options()
+        }
+        .mqtt_options()
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "tls-cert-file is set, but tls-ca-file is missing"
+        );
+
+        let err = ConnectionOptions {
+            tls_ca_file: Some("/nonexistent/ca.crt".into()),
+            ..options()
+        }
+        .mqtt_options()
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Failed to read /nonexistent/ca.crt"));
+
+        assert!(ConnectionOptions {
+            client_id: "".into(),
+            ..options()
+        }
+        .mqtt_options()
+        .is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use polars::prelude::*;
+use serde_json::json;
+
+use super::connection::MqttMessage;
+
+// Columns added by dataframe_agg, see dataframe_agg::score::with_failure_score
+pub const FAILURE_SCORE_COLUMN: &str = "failure_score";
+pub const FAILURE_ALERT_COLUMN: &str = "failure_alert";
+
+// Availability topic and payloads, shared with the MQTT Last-Will
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Availability {
+    pub topic: String,
+    pub online_payload: String,
+    pub offline_payload: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveryOptions {
+    pub prefix: String,
+    pub device_id: String,
+    pub device_name: String,
+    pub state_topic: String,
+    pub availability: Option<Availability>,
+}
+
+// Node and object ids of discovery topics may only contain letters, digits, _ and -
+pub fn node_id(device_id: &str) -> String {
+    device_id
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c,
+            '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+// Retained config messages announcing the failure score sensor and failure alert binary sensor to Home Assistant MQTT discovery
+// Both read the JSON object published to state_topic by state_message
+pub fn discovery_messages(options: &DiscoveryOptions) -> Vec<MqttMessage> {
+    let node_id = node_id(&options.device_id);
+    let device = json!({
+        "identifiers": [options.device_id],
+        "name": options.device_name,
+        "manufacturer": "PrintNanny",
+    });
+    let entities = [
+        (
+            "sensor",
+            FAILURE_SCORE_COLUMN,
+            json!({
+                "name": format!("{} Failure Score", options.device_name),
+                "value_template": format!("{{{{ value_json.{} }}}}", FAILURE_SCORE_COLUMN),
+                "state_class": "measurement",
+                "icon": "mdi:printer-3d-nozzle-alert",
+            }),
+        ),
+        (
+            "binary_sensor",
+            FAILURE_ALERT_COLUMN,
+            json!({
+                "name": format!("{} Failure Alert", options.device_name),
+                "value_template": format!(
+                    "{{{{ 'ON' if value_json.{} else 'OFF' }}}}",
+                    FAILURE_ALERT_COLUMN
+                ),
+                "device_class": "problem",
+            }),
+        ),
+    ];
+    entities
+        .into_iter()
+        .map(|(component, object_id, mut config)| {
+            config["unique_id"] = json!(format!("{}_{}", node_id, object_id));
+            config["state_topic"] = json!(options.state_topic);
+            config["device"] = device.clone();
+            if let Some(availability) = &options.availability {
+                config["availability_topic"] = json!(availability.topic);
+                config["payload_available"] = json!(availability.online_payload);
+                config["payload_not_available"] = json!(availability.offline_payload);
+            }
+            MqttMessage {
+                topic: format!(
+                    "{}/{}/{}/{}/config",
+                    options.prefix, component, node_id, object_id
+                ),
+                payload: serde_json::to_vec(&config).unwrap(),
+                retain: true,
+            }
+        })
+        .collect()
+}
+
+// JSON object with the failure score and alert of the last row with a score, or None if the dataframe has no failure_score column
+pub fn state_message(
+    df: &DataFrame,
+    state_topic: &str,
+) -> Result<Option<MqttMessage>, PolarsError> {
+    let scores: Vec<Option<f64>> = match df.column(FAILURE_SCORE_COLUMN) {
+        Ok(scores) => scores
+            .cast(&DataType::Float64)?
+            .f64()?
+            .into_iter()
+            .collect(),
+        Err(_) => return Ok(None),
+    };
+    let (row, score) = match scores
+        .iter()
+        .enumerate()
+        .rev()
+        .find_map(|(row, score)| score.map(|score| (row, score)))
+    {
+        Some(last) => last,
+        None => return Ok(None),
+    };
+    let alert = match df.column(FAILURE_ALERT_COLUMN) {
+        Ok(alerts) => alerts.bool()?.get(row),
+        Err(_) => None,
+    };
+    let state = json!({
+        FAILURE_SCORE_COLUMN: score,
+        FAILURE_ALERT_COLUMN: alert.unwrap_or(false),
+    });
+    Ok(Some(MqttMessage {
+        topic: state_topic.to_string(),
+        payload: serde_json::to_vec(&state).unwrap(),
+        retain: true,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discovery_messages() {
+        let options = DiscoveryOptions {
+            prefix: "homeassistant".into(),
+            device_id: "octoprint.local".into(),
+            device_name: "Voron".into(),
+            state_topic: "printnanny/qc/df/state".into(),
+            availability: Some(Availability {
+                topic: "printnanny/status".into(),
+                online_payload: "online".into(),
+                offline_payload: "offline".into(),
+            }),
+        };
+        let msgs = discovery_messages(&options);
+        let topics: Vec<&str> = msgs.iter().map(|msg| msg.topic.as_str()).collect();
+        assert_eq!(
+            topics,
+            vec![
+                "homeassistant/sensor/octoprint_local/failure_score/config",
+                "homeassistant/binary_sensor/octoprint_local/failure_alert/config"
+            ]
+        );
+        assert!(msgs.iter().all(|msg| msg.retain));
+
+        let config: serde_json::Value = serde_json::from_slice(&msgs[0].payload).unwrap();
+        assert_eq!(config["name"], "Voron Failure Score");
+        assert_eq!(config["unique_id"], "octoprint_local_failure_score");
+        assert_eq!(config["state_topic"], "printnanny/qc/df/state");
+        assert_eq!(config["value_template"], "{{ value_json.failure_score }}");
+        assert_eq!(config["availability_topic"], "printnanny/status");
+        assert_eq!(config["device"]["identifiers"][0], "octoprint.local");
+
+        let config: serde_json::Value = serde_json::from_slice(&msgs[1].payload).unwrap();
+        assert_eq!(config["device_class"], "problem");
+        assert_eq!(
+            config["value_template"],
+            "{{ 'ON' if value_json.failure_alert else 'OFF' }}"
+        );
+
+        let msgs = discovery_messages(&DiscoveryOptions {
+            availability: None,
+            ..options
+        });
+        let config: serde_json::Value = serde_json::from_slice(&msgs[0].payload).unwrap();
+        assert!(config.get("availability_topic").is_none());
+    }
+
+    #[test]
+    fn test_state_message() {
+        let df = df!(
+            "rt" => [1_i64, 2, 3],
+            FAILURE_SCORE_COLUMN => [Some(0.2), Some(0.7), None],
+            FAILURE_ALERT_COLUMN => [Some(false), Some(true), None]
+        )
+        .unwrap();
+        let msg = state_message(&df, "printnanny/qc/df/state")
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.topic, "printnanny/qc/df/state");
+        assert!(msg.retain);
+        let state: serde_json::Value = serde_json::from_slice(&msg.payload).unwrap();
+        assert_eq!(
+            state,
+            json!({ "failure_score": 0.7, "failure_alert": true })
+        );
+
+        let df = df!("rt" => [1_i64]).unwrap();
+        assert!(state_message(&df, "printnanny/qc/df/state")
+            .unwrap()
+            .is_none());
+    }
+}
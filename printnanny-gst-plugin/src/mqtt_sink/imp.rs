@@ -0,0 +1,585 @@
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst_base::subclass::prelude::*;
+use once_cell::sync::Lazy;
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::connection::{
+    validate_topic, ConnectionOptions, MqttConnection, MqttMessage, PublishStats,
+};
+use super::homeassistant::{discovery_messages, state_message, Availability, DiscoveryOptions};
+use super::MqttQos;
+use crate::caps::dataframe_sink_caps;
+use crate::error::MqttConfigError;
+use crate::ipc::dataframe_from_bytes;
+
+const DEFAULT_MQTT_ADDRESS: &str = "127.0.0.1:1883";
+const DEFAULT_TOPIC: &str = "printnanny/qc/df";
+const DEFAULT_QOS: MqttQos = MqttQos::AtMostOnce;
+const DEFAULT_RETAIN: bool = false;
+const DEFAULT_KEEP_ALIVE: u64 = 60_000_000_000; // 60s
+const MIN_KEEP_ALIVE: u64 = 5_000_000_000; // 5s, rumqttc rejects shorter keep alive intervals
+const DEFAULT_RECONNECT_DELAY: u64 = 1_000_000_000; // 1s
+const DEFAULT_MAX_QUEUE_SIZE: u32 = 1000;
+const DEFAULT_WILL_PAYLOAD: &str = "offline";
+const DEFAULT_ONLINE_PAYLOAD: &str = "online";
+const DEFAULT_HOMEASSISTANT_DISCOVERY: bool = false;
+const DEFAULT_HOMEASSISTANT_PREFIX: &str = "homeassistant";
+const DEFAULT_DEVICE_ID: &str = "printnanny";
+const DEFAULT_DEVICE_NAME: &str = "PrintNanny";
+
+#[derive(Clone)]
+struct Settings {
+    mqtt_address: String,
+    topic: String,
+    client_id: Option<String>, // None generates a client id when the element starts
+    qos: MqttQos,
+    retain: bool,
+    username: Option<String>,
+    password: Option<String>,
+    tls_ca_file: Option<String>,
+    tls_cert_file: Option<String>,
+    tls_key_file: Option<String>,
+    keep_alive: u64,      // in nanoseconds
+    reconnect_delay: u64, // in nanoseconds
+    max_queue_size: u32,  // Max number of messages waiting to be published
+    will_topic: Option<String>,
+    will_payload: String,
+    online_payload: String,
+    homeassistant_discovery: bool,
+    homeassistant_prefix: String,
+    device_id: String,
+    device_name: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            mqtt_address: DEFAULT_MQTT_ADDRESS.into(),
+            topic: DEFAULT_TOPIC.into(),
+            client_id: None,
+            qos: DEFAULT_QOS,
+            retain: DEFAULT_RETAIN,
+            username: None,
+            password: None,
+            tls_ca_file: None,
+            tls_cert_file: None,
+            tls_key_file: None,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            reconnect_delay: DEFAULT_RECONNECT_DELAY,
+            max_queue_size: DEFAULT_MAX_QUEUE_SIZE,
+            will_topic: None,
+            will_payload: DEFAULT_WILL_PAYLOAD.into(),
+            online_payload: DEFAULT_ONLINE_PAYLOAD.into(),
+            homeassistant_discovery: DEFAULT_HOMEASSISTANT_DISCOVERY,
+            homeassistant_prefix: DEFAULT_HOMEASSISTANT_PREFIX.into(),
+            device_id: DEFAULT_DEVICE_ID.into(),
+            device_name: DEFAULT_DEVICE_NAME.into(),
+        }
+    }
+}
+
+// The password is redacted, so settings may be logged
+impl fmt::Debug for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Settings")
+            .field("mqtt_address", &self.mqtt_address)
+            .field("topic", &self.topic)
+            .field("client_id", &self.client_id)
+            .field("qos", &self.qos)
+            .field("retain", &self.retain)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("tls_ca_file", &self.tls_ca_file)
+            .field("tls_cert_file", &self.tls_cert_file)
+            .field("tls_key_file", &self.tls_key_file)
+            .field("keep_alive", &self.keep_alive)
+            .field("reconnect_delay", &self.reconnect_delay)
+            .field("max_queue_size", &self.max_queue_size)
+            .field("will_topic", &self.will_topic)
+            .field("homeassistant_discovery", &self.homeassistant_discovery)
+            .field("homeassistant_prefix", &self.homeassistant_prefix)
+            .field("device_id", &self.device_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Settings {
+    // Home Assistant reads the failure score and alert from this topic
+    fn state_topic(&self) -> String {
+        format!("{}/state", self.topic)
+    }
+
+    fn availability(&self) -> Option<Availability> {
+        self.will_topic.clone().map(|topic| Availability {
+            topic,
+            online_payload: self.online_payload.clone(),
+            offline_payload: self.will_payload.clone(),
+        })
+    }
+
+    fn connection_options(&self) -> Result<ConnectionOptions, MqttConfigError> {
+        validate_topic("topic", &self.topic)?;
+        if let Some(will_topic) = &self.will_topic {
+            validate_topic("will-topic", will_topic)?;
+        }
+        let announce = match self.homeassistant_discovery {
+            true => discovery_messages(&DiscoveryOptions {
+                prefix: self.homeassistant_prefix.clone(),
+                device_id: self.device_id.clone(),
+                device_name: self.device_name.clone(),
+                state_topic: self.state_topic(),
+                availability: self.availability(),
+            }),
+            false => vec![],
+        };
+        Ok(ConnectionOptions {
+            address: self.mqtt_address.clone(),
+            // client ids are unique per broker, the broker disconnects the previous client with the same id
+            client_id: self
+                .client_id
+                .clone()
+                .unwrap_or_else(|| format!("printnanny-{:016x}", rand::random::<u64>())),
+            qos: self.qos,
+            keep_alive: Duration::from_nanos(self.keep_alive),
+            reconnect_delay: Duration::from_nanos(self.reconnect_delay),
+            max_queue_size: self.max_queue_size as usize,
+            username: self.username.clone(),
+            password: self.password.clone(),
+            tls_ca_file: self.tls_ca_file.clone(),
+            tls_cert_file: self.tls_cert_file.clone(),
+            tls_key_file: self.tls_key_file.clone(),
+            will: self.availability(),
+            announce,
+        })
+    }
+}
+
+enum State {
+    Stopped,
+    Started { connection: Arc<MqttConnection> },
+}
+
+impl Default for State {
+    fn default() -> State {
+        State::Stopped
+    }
+}
+
+#[derive(Default)]
+pub struct MqttSink {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    stats: Arc<PublishStats>,
+}
+
+pub(super) static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "mqtt_sink",
+        gst::DebugColorFlags::empty(),
+        Some("MQTT Sink"),
+    )
+});
+
+#[glib::object_subclass]
+impl ObjectSubclass for MqttSink {
+    const NAME: &'static str = "MqttSink";
+    type Type = super::MqttSink;
+    type ParentType = gst_base::BaseSink;
+}
+
+impl ObjectImpl for MqttSink {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::builder("mqtt-address")
+                    .nick("MQTT Address")
+                    .default_value(DEFAULT_MQTT_ADDRESS)
+                    .blurb("MQTT broker address, as host:port")
+                    .build(),
+                glib::ParamSpecString::builder("topic")
+                    .nick("Topic")
+                    .default_value(DEFAULT_TOPIC)
+                    .blurb("MQTT topic which receives every buffer unchanged, for example the JSON or arrow ipc dataframes emitted by dataframe_agg")
+                    .build(),
+                glib::ParamSpecString::builder("client-id")
+                    .nick("Client ID")
+                    .blurb("MQTT client id, defaults to printnanny- followed by a random suffix")
+                    .build(),
+                glib::ParamSpecEnum::builder::<MqttQos>("qos", DEFAULT_QOS)
+                    .nick("QoS")
+                    .blurb("Quality of service level of every published message")
+                    .build(),
+                glib::ParamSpecBoolean::builder("retain")
+                    .nick("Retain")
+                    .blurb("Publish buffers as retained messages, so subscribers receive the latest buffer when subscribing")
+                    .default_value(DEFAULT_RETAIN)
+                    .build(),
+                glib::ParamSpecString::builder("username")
+                    .nick("Username")
+                    .blurb("Authenticate with username and password")
+                    .build(),
+                glib::ParamSpecString::builder("password")
+                    .nick("Password")
+                    .blurb("Authenticate with username and password")
+                    .write_only()
+                    .build(),
+                glib::ParamSpecString::builder("tls-ca-file")
+                    .nick("TLS CA File")
+                    .blurb("PEM-encoded root certificate used to verify the broker's certificate. Connects with TLS if set")
+                    .build(),
+                glib::ParamSpecString::builder("tls-cert-file")
+                    .nick("TLS Certificate File")
+                    .blurb("PEM-encoded client certificate, requires tls-ca-file and tls-key-file")
+                    .build(),
+                glib::ParamSpecString::builder("tls-key-file")
+                    .nick("TLS Key File")
+                    .blurb("PEM-encoded RSA or EC client private key, requires tls-ca-file and tls-cert-file")
+                    .build(),
+                glib::ParamSpecUInt64::builder("keep-alive")
+                    .nick("Keep Alive")
+                    .blurb("Interval between pings while no messages are published, in nanoseconds")
+                    .minimum(MIN_KEEP_ALIVE)
+                    .default_value(DEFAULT_KEEP_ALIVE)
+                    .build(),
+                glib::ParamSpecUInt64::builder("reconnect-delay")
+                    .nick("Reconnect Delay")
+                    .blurb("Delay between reconnect attempts, in nanoseconds")
+                    .default_value(DEFAULT_RECONNECT_DELAY)
+                    .build(),
+                glib::ParamSpecUInt::builder("max-queue-size")
+                    .nick("Max Queue Size")
+                    .blurb("Max number of messages waiting to be published, including messages queued while disconnected. New messages are dropped when the queue is full")
+                    .minimum(1)
+                    .default_value(DEFAULT_MAX_QUEUE_SIZE)
+                    .build(),
+                glib::ParamSpecString::builder("will-topic")
+                    .nick("Will Topic")
+                    .blurb("Topic of the Last-Will message, which the broker publishes with will-payload if the connection is lost. online-payload is published after connecting, and will-payload before stopping. Both are retained")
+                    .build(),
+                glib::ParamSpecString::builder("will-payload")
+                    .nick("Will Payload")
+                    .default_value(DEFAULT_WILL_PAYLOAD)
+                    .blurb("Payload published to will-topic when the element is offline")
+                    .build(),
+                glib::ParamSpecString::builder("online-payload")
+                    .nick("Online Payload")
+                    .default_value(DEFAULT_ONLINE_PAYLOAD)
+                    .blurb("Payload published to will-topic when the element is online")
+                    .build(),
+                glib::ParamSpecBoolean::builder("homeassistant-discovery")
+                    .nick("Home Assistant Discovery")
+                    .blurb("Publish Home Assistant MQTT discovery configs for failure score and failure alert sensors, and publish the failure_score and failure_alert of every dataframe to <topic>/state. Sensors are available while will-topic is online, if will-topic is set")
+                    .default_value(DEFAULT_HOMEASSISTANT_DISCOVERY)
+                    .build(),
+                glib::ParamSpecString::builder("homeassistant-prefix")
+                    .nick("Home Assistant Prefix")
+                    .default_value(DEFAULT_HOMEASSISTANT_PREFIX)
+                    .blurb("Home Assistant MQTT discovery prefix")
+                    .build(),
+                glib::ParamSpecString::builder("device-id")
+                    .nick("Device ID")
+                    .default_value(DEFAULT_DEVICE_ID)
+                    .blurb("Device identifier, used as Home Assistant node id and in sensor unique ids")
+                    .build(),
+                glib::ParamSpecString::builder("device-name")
+                    .nick("Device Name")
+                    .default_value(DEFAULT_DEVICE_NAME)
+                    .blurb("Device name shown by Home Assistant, and prefix of sensor names")
+                    .build(),
+                glib::ParamSpecUInt64::builder("published-messages")
+                    .nick("Published Messages")
+                    .blurb("Number of messages published since the element started, including will-topic and Home Assistant messages")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecUInt64::builder("dropped-messages")
+                    .nick("Dropped Messages")
+                    .blurb("Number of messages dropped since the element started, because the queue was full")
+                    .read_only()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+
+        match pspec.name() {
+            "mqtt-address" => {
+                settings.mqtt_address = value.get::<String>().expect("type checked upstream");
+            }
+            "topic" => {
+                settings.topic = value.get::<String>().expect("type checked upstream");
+            }
+            "client-id" => {
+                settings.client_id = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "qos" => {
+                settings.qos = value.get::<MqttQos>().expect("type checked upstream");
+            }
+            "retain" => {
+                settings.retain = value.get::<bool>().expect("type checked upstream");
+            }
+            "username" => {
+                settings.username = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "password" => {
+                settings.password = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "tls-ca-file" => {
+                settings.tls_ca_file = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "tls-cert-file" => {
+                settings.tls_cert_file = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "tls-key-file" => {
+                settings.tls_key_file = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "keep-alive" => {
+                settings.keep_alive = value.get::<u64>().expect("type checked upstream");
+            }
+            "reconnect-delay" => {
+                settings.reconnect_delay = value.get::<u64>().expect("type checked upstream");
+            }
+            "max-queue-size" => {
+                settings.max_queue_size = value.get::<u32>().expect("type checked upstream");
+            }
+            "will-topic" => {
+                settings.will_topic = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "will-payload" => {
+                settings.will_payload = value.get::<String>().expect("type checked upstream");
+            }
+            "online-payload" => {
+                settings.online_payload = value.get::<String>().expect("type checked upstream");
+            }
+            "homeassistant-discovery" => {
+                settings.homeassistant_discovery =
+                    value.get::<bool>().expect("type checked upstream");
+            }
+            "homeassistant-prefix" => {
+                settings.homeassistant_prefix =
+                    value.get::<String>().expect("type checked upstream");
+            }
+            "device-id" => {
+                settings.device_id = value.get::<String>().expect("type checked upstream");
+            }
+            "device-name" => {
+                settings.device_name = value.get::<String>().expect("type checked upstream");
+            }
+            _ => unimplemented!("mqtt_sink does not implement property: {}", pspec.name()),
+        };
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+
+        match pspec.name() {
+            "mqtt-address" => settings.mqtt_address.to_value(),
+            "topic" => settings.topic.to_value(),
+            "client-id" => settings.client_id.to_value(),
+            "qos" => settings.qos.to_value(),
+            "retain" => settings.retain.to_value(),
+            "username" => settings.username.to_value(),
+            "tls-ca-file" => settings.tls_ca_file.to_value(),
+            "tls-cert-file" => settings.tls_cert_file.to_value(),
+            "tls-key-file" => settings.tls_key_file.to_value(),
+            "keep-alive" => settings.keep_alive.to_value(),
+            "reconnect-delay" => settings.reconnect_delay.to_value(),
+            "max-queue-size" => settings.max_queue_size.to_value(),
+            "will-topic" => settings.will_topic.to_value(),
+            "will-payload" => settings.will_payload.to_value(),
+            "online-payload" => settings.online_payload.to_value(),
+            "homeassistant-discovery" => settings.homeassistant_discovery.to_value(),
+            "homeassistant-prefix" => settings.homeassistant_prefix.to_value(),
+            "device-id" => settings.device_id.to_value(),
+            "device-name" => settings.device_name.to_value(),
+            "published-messages" => self.stats.published.load(Ordering::Relaxed).to_value(),
+            "dropped-messages" => self.stats.dropped.load(Ordering::Relaxed).to_value(),
+            _ => unimplemented!("mqtt_sink does not implement property: {}", pspec.name()),
+        }
+    }
+}
+
+impl GstObjectImpl for MqttSink {}
+
+impl ElementImpl for MqttSink {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "MQTT Sink",
+                "Sink/MQTT",
+                "Write stream to an MQTT topic, with optional Home Assistant MQTT discovery",
+                "Leigh Johnson <leigh@printnanny.ai>",
+            )
+        });
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = dataframe_sink_caps();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl BaseSinkImpl for MqttSink {
+    // The connection is established in the background, so the pipeline starts even if the MQTT broker is unreachable
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        let mut state = self.state.lock().unwrap();
+        if let State::Started { .. } = *state {
+            unreachable!("MqttSink already started");
+        }
+
+        let element = self.instance();
+        let settings = self.settings.lock().unwrap().clone();
+        gst::debug!(CAT, obj: element, "Starting with settings {:?}", settings);
+        self.stats.reset();
+        // invalid addresses, topics and credentials are configuration errors, which reconnecting would not fix
+        let connection = settings
+            .connection_options()
+            .and_then(|options| MqttConnection::start(&element, options, self.stats.clone()))
+            .map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Invalid MQTT settings: {}", err]
+                )
+            })?;
+
+        *state = State::Started { connection };
+        gst::info!(CAT, obj: element, "Started");
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        let mut state = self.state.lock().unwrap();
+
+        let element = self.instance();
+
+        let connection = match *state {
+            State::Started { ref connection } => connection.clone(),
+            State::Stopped => {
+                gst::element_error!(element, gst::CoreError::Failed, ["Not started yet"]);
+                return Err(gst::error_msg!(
+                    gst::ResourceError::Settings,
+                    ["MqttSink not started"]
+                ));
+            }
+        };
+        *state = State::Stopped;
+        drop(state);
+
+        connection.close();
+        gst::info!(CAT, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn render(&self, buffer: &gst::Buffer) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let state = self.state.lock().unwrap();
+        let settings = self.settings.lock().unwrap().clone();
+
+        let element = self.instance();
+
+        let connection = match *state {
+            State::Started { ref connection } => connection.clone(),
+            State::Stopped => {
+                gst::element_error!(element, gst::CoreError::Failed, ["Not started yet"]);
+                return Err(gst::FlowError::Error);
+            }
+        };
+        drop(state);
+
+        gst::trace!(CAT, obj: element, "Rendering {:?}", buffer);
+        let map = buffer.map_readable().map_err(|_| {
+            gst::element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+            gst::FlowError::Error
+        })?;
+
+        let mut msgs = vec![MqttMessage {
+            topic: settings.topic.clone(),
+            payload: map.as_slice().to_vec(),
+            retain: settings.retain,
+        }];
+        if settings.homeassistant_discovery {
+            let (df, _) = dataframe_from_bytes(map.as_slice(), None).map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::CoreError::Failed,
+                    ["Failed to read dataframe: {:?}", err]
+                );
+                gst::FlowError::Error
+            })?;
+            match state_message(&df, &settings.state_topic()) {
+                Ok(Some(msg)) => msgs.push(msg),
+                Ok(None) => gst::trace!(
+                    CAT,
+                    obj: element,
+                    "Dataframe has no failure_score, not publishing state"
+                ),
+                Err(err) => gst::warning!(
+                    CAT,
+                    obj: element,
+                    "Failed to read failure_score: {:?}",
+                    err
+                ),
+            }
+        }
+
+        // messages are published by the connection's event loop thread, publish errors are not fatal
+        for msg in msgs {
+            let topic = msg.topic.clone();
+            if connection.publish(msg) {
+                gst::trace!(
+                    CAT,
+                    obj: element,
+                    "Queued message to {}, MQTT connection is {}",
+                    topic,
+                    connection.state().nick()
+                );
+            } else {
+                gst::warning!(
+                    CAT,
+                    obj: element,
+                    "Dropped message to {}, MQTT connection is {} and queue is full",
+                    topic,
+                    connection.state().nick()
+                );
+            }
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
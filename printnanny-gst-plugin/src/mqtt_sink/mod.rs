@@ -0,0 +1,41 @@
+use gst::glib;
+use gst::prelude::*;
+
+mod connection;
+mod homeassistant;
+mod imp;
+
+// MQTT quality of service level of published messages
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstMqttSinkQos")]
+pub enum MqttQos {
+    #[enum_value(
+        name = "At most once: QoS 0, messages are sent once without acknowledgement",
+        nick = "at-most-once"
+    )]
+    AtMostOnce = 0,
+    #[enum_value(
+        name = "At least once: QoS 1, messages are sent until acknowledged, and may be duplicated",
+        nick = "at-least-once"
+    )]
+    AtLeastOnce = 1,
+    #[enum_value(
+        name = "Exactly once: QoS 2, messages are delivered once with a four-step handshake",
+        nick = "exactly-once"
+    )]
+    ExactlyOnce = 2,
+}
+
+glib::wrapper! {
+    pub struct MqttSink(ObjectSubclass<imp::MqttSink>) @extends gst_base::BaseSink, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "mqtt_sink",
+        gst::Rank::None,
+        MqttSink::static_type(),
+    )
+}
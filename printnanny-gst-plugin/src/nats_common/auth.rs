@@ -0,0 +1,257 @@
+use std::fmt;
+
+use gst::glib;
+use gst::prelude::*;
+
+use crate::error::NatsConfigError;
+
+// Properties of elements connecting to NATS, handled by NatsAuth::set_property and NatsAuth::property
+pub fn auth_properties() -> Vec<glib::ParamSpec> {
+    vec![
+        glib::ParamSpecString::builder("user")
+            .nick("User")
+            .blurb("Authenticate with user and password")
+            .build(),
+        glib::ParamSpecString::builder("password")
+            .nick("Password")
+            .blurb("Authenticate with user and password")
+            .write_only()
+            .build(),
+        glib::ParamSpecString::builder("token")
+            .nick("Token")
+            .blurb("Authenticate with token")
+            .write_only()
+            .build(),
+        glib::ParamSpecString::builder("nkey-seed")
+            .nick("NKey Seed")
+            .blurb(
+                "Authenticate with nkey, by signing the server's challenge with this seed (SU...)",
+            )
+            .write_only()
+            .build(),
+        glib::ParamSpecString::builder("creds-file")
+            .nick("Credentials File")
+            .blurb("Authenticate with a .creds file, containing a user JWT and nkey seed")
+            .build(),
+        glib::ParamSpecString::builder("tls-ca-file")
+            .nick("TLS CA File")
+            .blurb("PEM-encoded root certificate used to verify the server's certificate")
+            .build(),
+        glib::ParamSpecString::builder("tls-cert-file")
+            .nick("TLS Certificate File")
+            .blurb("PEM-encoded client certificate, requires tls-key-file")
+            .build(),
+        glib::ParamSpecString::builder("tls-key-file")
+            .nick("TLS Key File")
+            .blurb("PEM-encoded client private key, requires tls-cert-file")
+            .build(),
+        glib::ParamSpecBoolean::builder("tls-required")
+            .nick("TLS Required")
+            .blurb("Refuse to connect if the server does not support TLS")
+            .default_value(false)
+            .build(),
+    ]
+}
+
+// Authentication and TLS material of a NATS connection
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct NatsAuth {
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub token: Option<String>,
+    pub nkey_seed: Option<String>,
+    pub creds_file: Option<String>, // .creds file, containing a user JWT and nkey seed
+    pub tls_ca_file: Option<String>,
+    pub tls_cert_file: Option<String>,
+    pub tls_key_file: Option<String>,
+    pub tls_required: bool,
+}
+
+// Secrets are redacted, so settings may be logged
+impl fmt::Debug for NatsAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redacted = |secret: &Option<String>| secret.as_ref().map(|_| "<redacted>");
+        f.debug_struct("NatsAuth")
+            .field("user", &self.user)
+            .field("password", &redacted(&self.password))
+            .field("token", &redacted(&self.token))
+            .field("nkey_seed", &redacted(&self.nkey_seed))
+            .field("creds_file", &self.creds_file)
+            .field("tls_ca_file", &self.tls_ca_file)
+            .field("tls_cert_file", &self.tls_cert_file)
+            .field("tls_key_file", &self.tls_key_file)
+            .field("tls_required", &self.tls_required)
+            .finish()
+    }
+}
+
+impl NatsAuth {
+    // Returns false if name is not one of auth_properties()
+    pub fn set_property(&mut self, name: &str, value: &glib::Value) -> bool {
+        let string = || {
+            value
+                .get::<Option<String>>()
+                .expect("type checked upstream")
+        };
+        match name {
+            "user" => self.user = string(),
+            "password" => self.password = string(),
+            "token" => self.token = string(),
+            "nkey-seed" => self.nkey_seed = string(),
+            "creds-file" => self.creds_file = string(),
+            "tls-ca-file" => self.tls_ca_file = string(),
+            "tls-cert-file" => self.tls_cert_file = string(),
+            "tls-key-file" => self.tls_key_file = string(),
+            "tls-required" => {
+                self.tls_required = value.get::<bool>().expect("type checked upstream")
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Secrets are write-only
+    pub fn property(&self, name: &str) -> Option<glib::Value> {
+        match name {
+            "user" => Some(self.user.to_value()),
+            "creds-file" => Some(self.creds_file.to_value()),
+            "tls-ca-file" => Some(self.tls_ca_file.to_value()),
+            "tls-cert-file" => Some(self.tls_cert_file.to_value()),
+            "tls-key-file" => Some(self.tls_key_file.to_value()),
+            "tls-required" => Some(self.tls_required.to_value()),
+            _ => None,
+        }
+    }
+
+    // Check for conflicting or incomplete options, so misconfiguration fails at startup instead of on every reconnect attempt
+    pub fn validate(&self) -> Result<(), NatsConfigError> {
+        let methods: Vec<&str> = [
+            ("user", self.user.is_some() || self.password.is_some()),
+            ("token", self.token.is_some()),
+            ("nkey-seed", self.nkey_seed.is_some()),
+            ("creds-file", self.creds_file.is_some()),
+        ]
+        .iter()
+        .filter(|(_, is_set)| *is_set)
+        .map(|(method, _)| *method)
+        .collect();
+        if methods.len() > 1 {
+            return Err(NatsConfigError::ConflictingAuth(methods.join(", ")));
+        }
+        match (&self.user, &self.password) {
+            (Some(_), None) => return Err(NatsConfigError::MissingProperty("user", "password")),
+            (None, Some(_)) => return Err(NatsConfigError::MissingProperty("password", "user")),
+            _ => (),
+        }
+        match (&self.tls_cert_file, &self.tls_key_file) {
+            (Some(_), None) => {
+                return Err(NatsConfigError::MissingProperty(
+                    "tls-cert-file",
+                    "tls-key-file",
+                ))
+            }
+            (None, Some(_)) => {
+                return Err(NatsConfigError::MissingProperty(
+                    "tls-key-file",
+                    "tls-cert-file",
+                ))
+            }
+            _ => (),
+        }
+        if let Some(seed) = &self.nkey_seed {
+            nkeys::KeyPair::from_seed(seed)
+                .map_err(|err| NatsConfigError::InvalidNkeySeed(err.to_string()))?;
+        }
+        Ok(())
+    }
+
+    // Build nats::Options with credentials and TLS settings applied. Call validate() first
+    pub fn options(&self) -> Result<nats::Options, NatsConfigError> {
+        let options = match (
+            &self.user,
+            &self.password,
+            &self.token,
+            &self.nkey_seed,
+            &self.creds_file,
+        ) {
+            (Some(user), Some(password), _, _, _) => nats::Options::with_user_pass(user, password),
+            (_, _, Some(token), _, _) => nats::Options::with_token(token),
+            (_, _, _, Some(seed), _) => {
+                let key_pair = nkeys::KeyPair::from_seed(seed)
+                    .map_err(|err| NatsConfigError::InvalidNkeySeed(err.to_string()))?;
+                let public_key = key_pair.public_key();
+                nats::Options::with_nkey(&public_key, move |nonce| {
+                    key_pair
+                        .sign(nonce)
+                        .expect("Failed to sign nonce with nkey")
+                })
+            }
+            (_, _, _, _, Some(creds_file)) => nats::Options::with_credentials(creds_file),
+            _ => nats::Options::new(),
+        };
+
+        let mut options = options.tls_required(self.tls_required);
+        if let Some(ca_file) = &self.tls_ca_file {
+            options = options.add_root_certificate(ca_file);
+        }
+        if let (Some(cert_file), Some(key_file)) = (&self.tls_cert_file, &self.tls_key_file) {
+            options = options.client_cert(cert_file, key_file);
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_auth() {
+        assert!(NatsAuth::default().validate().is_ok());
+
+        let auth = NatsAuth {
+            user: Some("printnanny".into()),
+            password: Some("secret".into()),
+            ..NatsAuth::default()
+        };
+        assert!(auth.validate().is_ok());
+        assert!(!format!("{:?}", auth).contains("secret"));
+
+        let auth = NatsAuth {
+            token: Some("secret".into()),
+            creds_file: Some("/etc/printnanny/nats.creds".into()),
+            ..NatsAuth::default()
+        };
+        assert!(matches!(
+            auth.validate(),
+            Err(NatsConfigError::ConflictingAuth(_))
+        ));
+
+        let auth = NatsAuth {
+            user: Some("printnanny".into()),
+            ..NatsAuth::default()
+        };
+        assert!(matches!(
+            auth.validate(),
+            Err(NatsConfigError::MissingProperty("user", "password"))
+        ));
+
+        let auth = NatsAuth {
+            nkey_seed: Some("not-a-seed".into()),
+            ..NatsAuth::default()
+        };
+        assert!(matches!(
+            auth.validate(),
+            Err(NatsConfigError::InvalidNkeySeed(_))
+        ));
+
+        let seed = nkeys::KeyPair::new_user().seed().unwrap();
+        let auth = NatsAuth {
+            nkey_seed: Some(seed),
+            tls_required: true,
+            ..NatsAuth::default()
+        };
+        assert!(auth.validate().is_ok());
+        assert!(auth.options().is_ok());
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::VecDeque;
+
+use super::headers::{
+    header, headers_size, without_headers, Headers, BATCH_COUNT_HEADER, BATCH_PTS_HEADER,
+    CHUNK_COUNT_HEADER, CHUNK_ID_HEADER, CHUNK_INDEX_HEADER, DTS_HEADER, DURATION_HEADER,
+    MSG_ID_HEADER, PTS_HEADER,
+};
+use super::NatsMessage;
+use crate::error::SerializationError;
+
+// Max number of partially received chunked messages kept by Reassembler, the oldest is discarded when a new one starts
+const MAX_PENDING_CHUNKED: usize = 16;
+// Length of the longest decimal u64, used to size chunk index, chunk count and batched timestamp header values
+const MAX_DECIMAL_LEN: usize = 20;
+
+// Size of a message on the wire, counted towards the server's max_payload
+pub fn message_size(msg: &NatsMessage) -> usize {
+    msg.payload.len() + headers_size(&msg.headers)
+}
+
+// Combine messages to the same subject into one message. Payloads are framed like json-framed buffers:
+// | length (u32 BE) | payload (length bytes) | length (u32 BE) | payload (length bytes) | ...
+// Headers are taken from the first message, with the timestamps of every message in PrintNanny-Batch-Pts
+pub fn batch(mut msgs: Vec<NatsMessage>) -> NatsMessage {
+    if msgs.len() == 1 {
+        return msgs.remove(0);
+    }
+    let mut payload = Vec::with_capacity(msgs.iter().map(|msg| msg.payload.len() + 4).sum());
+    for msg in msgs.iter() {
+        payload.extend_from_slice(&(msg.payload.len() as u32).to_be_bytes());
+        payload.extend_from_slice(&msg.payload);
+    }
+    let pts: Vec<&str> = msgs
+        .iter()
+        .map(|msg| header(&msg.headers, PTS_HEADER).unwrap_or_default())
+        .collect();
+    let mut headers = msgs[0].headers.clone();
+    headers.push((BATCH_COUNT_HEADER.to_string(), msgs.len().to_string()));
+    headers.push((BATCH_PTS_HEADER.to_string(), pts.join(",")));
+    NatsMessage {
+        subject: msgs[0].subject.clone(),
+        headers,
+        payload,
+    }
+}
+
+// Size added to a batch by a message, including its PrintNanny-Batch-Pts entry
+pub fn frame_size(msg: &NatsMessage) -> usize {
+    msg.payload.len() + 4 + MAX_DECIMAL_LEN + 1
+}
+
+// Size of a batch holding only msg, every following message adds its frame_size
+pub fn batch_size(msg: &NatsMessage) -> usize {
+    let batch_headers = [BATCH_COUNT_HEADER, BATCH_PTS_HEADER]
+        .iter()
+        .map(|name| name.len() + MAX_DECIMAL_LEN + ": \r\n".len())
+        .sum::<usize>();
+    headers_size(&msg.headers) + batch_headers + frame_size(msg)
+}
+
+// Number of messages in a batch, 1 for messages which are not batched
+pub fn batch_len(msg: &NatsMessage) -> usize {
+    header(&msg.headers, BATCH_COUNT_HEADER)
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(1)
+}
+
+// Split a batch into its messages, every message gets the batch's headers with its own PrintNanny-Pts
+// Messages which are not batched are returned unchanged
+pub fn unbatch(msg: NatsMessage) -> Result<Vec<NatsMessage>, SerializationError> {
+    let count = match header(&msg.headers, BATCH_COUNT_HEADER) {
+        Some(count) => count.parse::<usize>().map_err(|_| {
+            SerializationError::InvalidBatch(format!("{} {:?}", BATCH_COUNT_HEADER, count))
+        })?,
+        None => return Ok(vec![msg]),
+    };
+    let pts: Vec<String> = header(&msg.headers, BATCH_PTS_HEADER)
+        .unwrap_or_default()
+        .split(',')
+        .map(|pts| pts.to_string())
+        .collect();
+    let headers = without_headers(
+        &msg.headers,
+        &[
+            BATCH_COUNT_HEADER,
+            BATCH_PTS_HEADER,
+            PTS_HEADER,
+            DTS_HEADER,
+            DURATION_HEADER,
+        ],
+    );
+
+    let mut msgs = vec![];
+    let mut rest = msg.payload.as_slice();
+    while !rest.is_empty() {
+        if rest.len() < 4 {
+            return Err(SerializationError::FramingError {
+                expected: 4,
+                actual: rest.len(),
+            });
+        }
+        let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        rest = &rest[4..];
+        if rest.len() < length {
+            return Err(SerializationError::FramingError {
+                expected: length,
+                actual: rest.len(),
+            });
+        }
+        let mut headers = headers.clone();
+        if let Some(pts) = pts.get(msgs.len()).filter(|pts| !pts.is_empty()) {
+            headers.push((PTS_HEADER.to_string(), pts.clone()));
+        }
+        msgs.push(NatsMessage {
+            subject: msg.subject.clone(),
+            headers,
+            payload: rest[..length].to_vec(),
+        });
+        rest = &rest[length..];
+    }
+    if msgs.len() != count {
+        return Err(SerializationError::InvalidBatch(format!(
+            "expected {} messages, but got {} messages",
+            count,
+            msgs.len()
+        )));
+    }
+    Ok(msgs)
+}
+
+// Split a message into chunks which do not exceed max_payload. Every chunk carries the message's headers,
+// and a Nats-Msg-Id derived from the message's, so JetStream discards duplicate chunks
+// Returns None if the headers alone exceed max_payload
+pub fn chunk(msg: &NatsMessage, max_payload: usize) -> Option<Vec<NatsMessage>> {
+    let id = header(&msg.headers, MSG_ID_HEADER)
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+    let placeholder = "0".repeat(MAX_DECIMAL_LEN);
+    let mut headers = without_headers(&msg.headers, &[MSG_ID_HEADER]);
+    let mut chunk_headers = headers.clone();
+    chunk_headers.extend([
+        (CHUNK_ID_HEADER.to_string(), id.clone()),
+        (CHUNK_INDEX_HEADER.to_string(), placeholder.clone()),
+        (CHUNK_COUNT_HEADER.to_string(), placeholder.clone()),
+    ]);
+    if header(&msg.headers, MSG_ID_HEADER).is_some() {
+        chunk_headers.push((MSG_ID_HEADER.to_string(), format!("{}-{}", id, placeholder)));
+    }
+    let chunk_size = max_payload.checked_sub(headers_size(&chunk_headers))?;
+    if chunk_size == 0 {
+        return None;
+    }
+
+    headers.push((CHUNK_ID_HEADER.to_string(), id.clone()));
+    let chunks: Vec<&[u8]> = msg.payload.chunks(chunk_size).collect();
+    let count = chunks.len();
+    Some(
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, payload)| {
+                let mut headers = headers.clone();
+                headers.push((CHUNK_INDEX_HEADER.to_string(), index.to_string()));
+                headers.push((CHUNK_COUNT_HEADER.to_string(), count.to_string()));
+                if header(&msg.headers, MSG_ID_HEADER).is_some() {
+                    headers.push((MSG_ID_HEADER.to_string(), format!("{}-{}", id, index)));
+                }
+                NatsMessage {
+                    subject: msg.subject.clone(),
+                    headers,
+                    payload: payload.to_vec(),
+                }
+            })
+            .collect(),
+    )
+}
+
+struct PendingMessage {
+    subject: String,
+    id: String,
+    headers: Headers,
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+// Reassembles messages split by chunk, chunks may arrive in any order and more than once
+#[derive(Default)]
+pub struct Reassembler {
+    pending: VecDeque<PendingMessage>,
+}
+
+impl Reassembler {
+    // Returns the message once every chunk was received. Messages which are not chunked are returned unchanged
+    pub fn push(&mut self, msg: NatsMessage) -> Result<Option<NatsMessage>, SerializationError> {
+        let id = match header(&msg.headers, CHUNK_ID_HEADER) {
+            Some(id) => id.to_string(),
+            None => return Ok(Some(msg)),
+        };
+        let number = |name: &str| {
+            header(&msg.headers, name)
+                .and_then(|value| value.parse::<usize>().ok())
+                .ok_or_else(|| SerializationError::InvalidChunk(format!("missing {}", name)))
+        };
+        let (index, count) = (number(CHUNK_INDEX_HEADER)?, number(CHUNK_COUNT_HEADER)?);
+        if index >= count {
+            return Err(SerializationError::InvalidChunk(format!(
+                "chunk {} of {} chunks",
+                index, count
+            )));
+        }
+
+        let position = self
+            .pending
+            .iter()
+            .position(|pending| pending.id == id && pending.subject == msg.subject);
+        let position = match position {
+            Some(position) => position,
+            None => {
+                if self.pending.len() == MAX_PENDING_CHUNKED {
+                    self.pending.pop_front();
+                }
+                self.pending.push_back(PendingMessage {
+                    subject: msg.subject.clone(),
+                    id,
+                    headers: without_headers(
+                        &msg.headers,
+                        &[
+                            CHUNK_ID_HEADER,
+                            CHUNK_INDEX_HEADER,
+                            CHUNK_COUNT_HEADER,
+                            MSG_ID_HEADER,
+                        ],
+                    ),
+                    chunks: vec![None; count],
+                });
+                self.pending.len() - 1
+            }
+        };
+        let pending = &mut self.pending[position];
+        if pending.chunks.len() != count {
+            return Err(SerializationError::InvalidChunk(format!(
+                "expected {} chunks, but got chunk count {}",
+                pending.chunks.len(),
+                count
+            )));
+        }
+        pending.chunks[index] = Some(msg.payload);
+        if pending.chunks.iter().any(|chunk| chunk.is_none()) {
+            return Ok(None);
+        }
+
+        let pending = self.pending.remove(position).unwrap();
+        Ok(Some(NatsMessage {
+            subject: pending.subject,
+            headers: pending.headers,
+            payload: pending.chunks.into_iter().flatten().flatten().collect(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(pts: Option<&str>, payload: &[u8]) -> NatsMessage {
+        let mut headers = vec![(MSG_ID_HEADER.to_string(), "0123-7".to_string())];
+        if let Some(pts) = pts {
+            headers.push((PTS_HEADER.to_string(), pts.to_string()));
+        }
+        NatsMessage {
+            subject: "pi.qc.df".into(),
+            headers,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_batch() {
+        let msgs = vec![
+            msg(Some("1"), b"abc"),
+            msg(None, b""),
+            msg(Some("3"), b"de"),
+        ];
+        let batched = batch(msgs.clone());
+        assert_eq!(header(&batched.headers, BATCH_COUNT_HEADER), Some("3"));
+        assert_eq!(header(&batched.headers, BATCH_PTS_HEADER), Some("1,,3"));
+        assert_eq!(batched.payload.len(), 3 * 4 + 5);
+
+        assert_eq!(batch_len(&batched), 3);
+        assert!(
+            message_size(&batched)
+                <= batch_size(&msgs[0]) + frame_size(&msgs[1]) + frame_size(&msgs[2])
+        );
+        let unbatched = unbatch(batched.clone()).unwrap();
+        assert_eq!(unbatched, msgs);
+
+        let mut truncated = batched;
+        truncated.payload.pop();
+        assert!(matches!(
+            unbatch(truncated),
+            Err(SerializationError::FramingError { .. })
+        ));
+
+        let single = msg(Some("1"), b"abc");
+        assert_eq!(batch(vec![single.clone()]), single);
+        assert_eq!(unbatch(single.clone()).unwrap(), vec![single]);
+    }
+
+    #[test]
+    fn test_chunk_reassemble() {
+        let payload: Vec<u8> = (0..=255).collect();
+        let original = msg(Some("1"), &payload);
+        let max_payload = 256;
+        let chunks = chunk(&original, max_payload).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks
+            .iter()
+            .all(|chunk| message_size(chunk) <= max_payload));
+        assert_eq!(header(&chunks[1].headers, MSG_ID_HEADER), Some("0123-7-1"));
+
+        // chunks arrive out of order, and more than once
+        let mut reassembler = Reassembler::default();
+        let received = chunks
+            .iter()
+            .skip(1)
+            .chain(chunks.iter().skip(1))
+            .chain(chunks.iter().take(1));
+        for chunk in received {
+            if let Some(msg) = reassembler.push(chunk.clone()).unwrap() {
+                assert_eq!(msg.payload, payload);
+                assert_eq!(header(&msg.headers, PTS_HEADER), Some("1"));
+                assert_eq!(header(&msg.headers, CHUNK_ID_HEADER), None);
+                return;
+            }
+        }
+        panic!("chunked message was not reassembled");
+    }
+
+    #[test]
+    fn test_chunk_limits() {
+        let original = msg(None, b"abc");
+        assert_eq!(chunk(&original, 16), None);
+        assert_eq!(
+            Reassembler::default().push(original.clone()).unwrap(),
+            Some(original)
+        );
+    }
+}
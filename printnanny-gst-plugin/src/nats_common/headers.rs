@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+
+use gst::glib;
+use gst::prelude::*;
+
+use crate::caps::{metadata_from_caps, LEGACY_MEDIA_TYPE, SCHEMA_FINGERPRINT_FIELD};
+use crate::ipc::DataframeFormat;
+
+// Header names. Timestamps are decimal nanoseconds, omitted for buffers without a timestamp
+pub const CONTENT_TYPE_HEADER: &str = "Content-Type";
+pub const MSG_ID_HEADER: &str = "Nats-Msg-Id"; // used by JetStream to discard duplicate messages
+pub const PTS_HEADER: &str = "PrintNanny-Pts";
+pub const DTS_HEADER: &str = "PrintNanny-Dts";
+pub const DURATION_HEADER: &str = "PrintNanny-Duration";
+pub const SEQUENCE_HEADER: &str = "PrintNanny-Sequence"; // number of buffers rendered since the element started
+pub const CAMERA_ID_HEADER: &str = "PrintNanny-Camera-Id";
+pub const DEVICE_ID_HEADER: &str = "PrintNanny-Device-Id";
+pub const SCHEMA_FINGERPRINT_HEADER: &str = "PrintNanny-Schema-Fingerprint";
+pub const METADATA_HEADER: &str = "PrintNanny-Metadata"; // JSON object of schema metadata carried in caps of json output, see caps_with_metadata
+pub const CHUNK_ID_HEADER: &str = "PrintNanny-Chunk-Id"; // shared by the chunks of a message larger than the server's max_payload
+pub const CHUNK_INDEX_HEADER: &str = "PrintNanny-Chunk-Index";
+pub const CHUNK_COUNT_HEADER: &str = "PrintNanny-Chunk-Count";
+pub const BATCH_COUNT_HEADER: &str = "PrintNanny-Batch-Count"; // number of messages framed into a batch
+pub const BATCH_PTS_HEADER: &str = "PrintNanny-Batch-Pts"; // comma-separated timestamps of batched messages, empty for messages without a timestamp
+
+pub type Headers = Vec<(String, String)>;
+
+// Headers shared by every message of a stream, derived from caps
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StreamHeaders {
+    pub content_type: Option<String>, // see content_type_from_caps. None for legacy application/octet-stream, detected from buffer contents instead
+    pub schema_fingerprint: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl StreamHeaders {
+    pub fn from_caps(caps: &gst::CapsRef) -> Self {
+        match caps.structure(0) {
+            Some(s) => StreamHeaders {
+                content_type: match s.name() {
+                    LEGACY_MEDIA_TYPE => None,
+                    _ => Some(content_type_from_caps(s)),
+                },
+                schema_fingerprint: s
+                    .get::<String>(SCHEMA_FINGERPRINT_FIELD)
+                    .ok()
+                    .filter(|fingerprint| !fingerprint.is_empty()),
+                metadata: metadata_from_caps(caps),
+            },
+            None => StreamHeaders::default(),
+        }
+    }
+
+    pub fn content_type(&self, payload: &[u8]) -> String {
+        match &self.content_type {
+            Some(content_type) => content_type.clone(),
+            None => DataframeFormat::detect(payload)
+                .map(|format| format.media_type())
+                .unwrap_or(LEGACY_MEDIA_TYPE)
+                .to_string(),
+        }
+    }
+
+    // Value of METADATA_HEADER, or None if caps carry no metadata
+    pub fn metadata_header(&self) -> Option<String> {
+        match self.metadata.is_empty() {
+            true => None,
+            false => serde_json::to_string(&self.metadata).ok(),
+        }
+    }
+}
+
+// Schema metadata from METADATA_HEADER, empty if the header is missing or invalid
+pub fn metadata_from_header(headers: &[(String, String)]) -> BTreeMap<String, String> {
+    header(headers, METADATA_HEADER)
+        .and_then(|value| serde_json::from_str(value).ok())
+        .unwrap_or_default()
+}
+
+// Characters of caps media types and field names
+fn is_caps_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/-_.:+".contains(c))
+}
+
+// Content-Type header value of caps: media type, followed by caps fields as parameters, for example application/json; framing=length-prefixed
+// Only string, integer and boolean fields are included. streamheader buffers are not sent, and metadata is sent in METADATA_HEADER
+pub fn content_type_from_caps(s: &gst::StructureRef) -> String {
+    let mut content_type = s.name().to_string();
+    for (name, value) in s.iter() {
+        let value = match value.type_() {
+            glib::Type::STRING => match value.get::<String>() {
+                Ok(value) => value,
+                Err(_) => continue,
+            },
+            glib::Type::BOOL
+            | glib::Type::I32
+            | glib::Type::U32
+            | glib::Type::I64
+            | glib::Type::U64 => match value.serialize() {
+                Ok(value) => value.to_string(),
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+        // parameter values which are not a token are quoted
+        let is_token = !value.is_empty()
+            && value
+                .chars()
+                .all(|c| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c));
+        match is_token {
+            true => content_type.push_str(&format!("; {}={}", name, value)),
+            false => content_type.push_str(&format!("; {}={:?}", name, value)),
+        }
+    }
+    content_type
+}
+
+// Caps described by a Content-Type header value, parameters are added as string fields
+// None if the media type is not a valid caps name. Parameters which are not valid field names are ignored
+pub fn caps_from_content_type(content_type: &str) -> Option<gst::Caps> {
+    let mut params = content_type.split(';').map(|param| param.trim());
+    let media_type = params
+        .next()
+        .filter(|media_type| is_caps_name(media_type))?;
+    let mut s = gst::Structure::new_empty(media_type);
+    for param in params {
+        if let Some((name, value)) = param.split_once('=') {
+            let name = name.trim();
+            if !is_caps_name(name) {
+                continue;
+            }
+            let value = value.trim();
+            let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+                None => value.to_string(),
+            };
+            s.set(name, value);
+        }
+    }
+    Some(gst::Caps::builder_full().structure(s).build())
+}
+
+// Static headers from a structure like "headers, site=workshop, printer=mk4", string fields are added unquoted
+pub fn extra_headers(structure: &gst::StructureRef) -> Headers {
+    structure
+        .iter()
+        .filter_map(|(name, value)| {
+            let value = match value.get::<String>() {
+                Ok(value) => value,
+                Err(_) => value.serialize().ok()?.to_string(),
+            };
+            Some((name.to_string(), value))
+        })
+        .collect()
+}
+
+// Value of the first header with name
+pub fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header == name)
+        .map(|(_, value)| value.as_str())
+}
+
+// Timestamp header value in nanoseconds
+pub fn clock_time_header(headers: &[(String, String)], name: &str) -> Option<gst::ClockTime> {
+    header(headers, name)
+        .and_then(|value| value.parse::<u64>().ok())
+        .map(gst::ClockTime::from_nseconds)
+}
+
+// Headers without the headers named in names
+pub fn without_headers(headers: &[(String, String)], names: &[&str]) -> Headers {
+    headers
+        .iter()
+        .filter(|(name, _)| !names.contains(&name.as_str()))
+        .cloned()
+        .collect()
+}
+
+// Size of the header block sent with a message: NATS/1.0 status line, a line per header and an empty line
+pub fn headers_size(headers: &[(String, String)]) -> usize {
+    match headers.is_empty() {
+        true => 0,
+        false => {
+            "NATS/1.0\r\n\r\n".len()
+                + headers
+                    .iter()
+                    .map(|(name, value)| name.len() + value.len() + ": \r\n".len())
+                    .sum::<usize>()
+        }
+    }
+}
+
+pub fn headers_from_map(map: &nats::header::HeaderMap) -> Headers {
+    map.iter()
+        .flat_map(|(name, values)| {
+            values
+                .iter()
+                .map(move |value| (name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+pub fn header_map(headers: &[(String, String)]) -> nats::header::HeaderMap {
+    let mut map = nats::header::HeaderMap::new();
+    for (name, value) in headers {
+        map.insert(name, value);
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caps::{arrow_stream_caps, caps_with_metadata};
+    use crate::ipc::IpcCompression;
+
+    #[test]
+    fn test_stream_headers() {
+        gst::init().unwrap();
+
+        let headers =
+            StreamHeaders::from_caps(&arrow_stream_caps(IpcCompression::Lz4, "0123456789abcdef"));
+        assert_eq!(
+            headers.content_type(b"{}"),
+            "application/vnd.apache.arrow.stream; compression=lz4; schema-fingerprint=0123456789abcdef"
+        );
+        assert_eq!(
+            headers.schema_fingerprint.as_deref(),
+            Some("0123456789abcdef")
+        );
+
+        let legacy = StreamHeaders::from_caps(&gst::Caps::new_empty_simple(LEGACY_MEDIA_TYPE));
+        assert_eq!(legacy.schema_fingerprint, None);
+        assert_eq!(legacy.metadata_header(), None);
+
+        let metadata =
+            BTreeMap::from([("printnanny:camera_id".to_string(), "camera0".to_string())]);
+        let json = StreamHeaders::from_caps(&caps_with_metadata(
+            gst::Caps::new_empty_simple("application/json"),
+            &metadata,
+        ));
+        let value = json.metadata_header().unwrap();
+        assert_eq!(value, r#"{"printnanny:camera_id":"camera0"}"#);
+        assert_eq!(
+            metadata_from_header(&[(METADATA_HEADER.to_string(), value)]),
+            metadata
+        );
+        assert_eq!(legacy.content_type(b"[{\"x0\": 0}]"), "application/json");
+        assert_eq!(legacy.content_type(&[0, 1]), LEGACY_MEDIA_TYPE);
+
+        let extra = gst::Structure::builder("headers")
+            .field("site", "workshop")
+            .field("printer", 2i32)
+            .build();
+        assert_eq!(
+            extra_headers(&extra),
+            vec![
+                ("site".to_string(), "workshop".to_string()),
+                ("printer".to_string(), "2".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_content_type_caps() {
+        gst::init().unwrap();
+
+        let caps = gst::Caps::builder("application/json")
+            .field("framing", "length-prefixed")
+            .field("site", "work shop")
+            .field("width", 2i32)
+            .field(
+                "streamheader",
+                gst::Array::from_values([1i32.to_send_value()]),
+            )
+            .build();
+        let content_type = content_type_from_caps(caps.structure(0).unwrap());
+        assert_eq!(
+            content_type,
+            r#"application/json; framing=length-prefixed; site="work shop"; width=2"#
+        );
+
+        let parsed = caps_from_content_type(&content_type).unwrap();
+        let s = parsed.structure(0).unwrap();
+        assert_eq!(s.name(), "application/json");
+        assert_eq!(s.get::<String>("framing").unwrap(), "length-prefixed");
+        assert_eq!(s.get::<String>("site").unwrap(), "work shop");
+        assert_eq!(s.get::<String>("width").unwrap(), "2");
+        assert!(!s.has_field("streamheader"));
+
+        // parameters like charset are kept as fields, invalid parameters are ignored
+        let parsed = caps_from_content_type("application/x-ndjson; charset=utf-8; =x; y").unwrap();
+        assert_eq!(
+            parsed
+                .structure(0)
+                .unwrap()
+                .get::<String>("charset")
+                .unwrap(),
+            "utf-8"
+        );
+        assert_eq!(parsed.structure(0).unwrap().n_fields(), 1);
+        assert!(caps_from_content_type("not a media type").is_none());
+        assert!(caps_from_content_type("").is_none());
+    }
+}
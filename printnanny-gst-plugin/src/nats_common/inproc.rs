@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+
+use super::headers::Headers;
+use super::{subject_matches, NatsMessage};
+
+// In-process stand-in for a NATS server, selected by addresses like inproc://name
+// Messages are delivered to subscribers of the same name within this process, without acknowledgement or persistence
+pub const INPROC_SCHEME: &str = "inproc://";
+pub const MAX_PAYLOAD: usize = 1024 * 1024; // default max_payload of nats-server, so messages are chunked and batched like with a server
+
+type Subscribers = Vec<(String, mpsc::Sender<NatsMessage>)>;
+
+static SERVERS: Lazy<Mutex<HashMap<String, Subscribers>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Server name of an inproc:// address, None for addresses of a NATS server
+pub fn server_name(address: &str) -> Option<&str> {
+    address.strip_prefix(INPROC_SCHEME)
+}
+
+// Receive messages published to server with a subject matching pattern, until the receiver is dropped
+pub fn subscribe(server: &str, pattern: &str) -> mpsc::Receiver<NatsMessage> {
+    let (sender, receiver) = mpsc::channel();
+    SERVERS
+        .lock()
+        .unwrap()
+        .entry(server.to_string())
+        .or_default()
+        .push((pattern.to_string(), sender));
+    receiver
+}
+
+// Returns the number of subscribers which received the message
+pub fn publish(server: &str, subject: &str, headers: Headers, payload: Vec<u8>) -> usize {
+    let mut servers = SERVERS.lock().unwrap();
+    let subscribers = match servers.get_mut(server) {
+        Some(subscribers) => subscribers,
+        None => return 0,
+    };
+    let msg = NatsMessage {
+        subject: subject.to_string(),
+        headers,
+        payload,
+    };
+    let mut delivered = 0;
+    // subscribers whose receiver was dropped are removed
+    subscribers.retain(|(pattern, sender)| {
+        if !subject_matches(pattern, subject) {
+            return true;
+        }
+        match sender.send(msg.clone()) {
+            Ok(_) => {
+                delivered += 1;
+                true
+            }
+            Err(_) => false,
+        }
+    });
+    delivered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inproc_publish() {
+        assert_eq!(server_name("inproc://test"), Some("test"));
+        assert_eq!(server_name("127.0.0.1:4222"), None);
+
+        let receiver = subscribe("test_inproc_publish", "pi.*.df");
+        assert_eq!(
+            publish("test_inproc_publish", "pi.qc.df", vec![], vec![1]),
+            1
+        );
+        assert_eq!(
+            publish("test_inproc_publish", "pi.other", vec![], vec![2]),
+            0
+        );
+        assert_eq!(publish("other_server", "pi.qc.df", vec![], vec![3]), 0);
+        assert_eq!(receiver.try_recv().unwrap().payload, vec![1]);
+        assert!(receiver.try_recv().is_err());
+
+        drop(receiver);
+        assert_eq!(
+            publish("test_inproc_publish", "pi.qc.df", vec![], vec![4]),
+            0
+        );
+    }
+}
@@ -0,0 +1,69 @@
+// Shared by nats_sink, nats_src and the control plane
+pub mod auth;
+pub mod framing;
+pub mod headers;
+pub mod inproc;
+
+use std::time::Duration;
+
+use headers::{header, Headers, MSG_ID_HEADER};
+
+// Message published by nats_sink, or received from a subscription by nats_src
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NatsMessage {
+    pub subject: String,
+    pub headers: Headers,
+    pub payload: Vec<u8>,
+}
+
+impl NatsMessage {
+    pub fn msg_id(&self) -> Option<&str> {
+        header(&self.headers, MSG_ID_HEADER)
+    }
+}
+
+// Delay before reconnect attempt (counted from 1), doubling after every failed attempt up to max
+pub fn reconnect_delay(attempt: usize, min: Duration, max: Duration) -> Duration {
+    let exponent = attempt.saturating_sub(1).min(31) as u32;
+    min.saturating_mul(2u32.pow(exponent)).min(max)
+}
+
+// NATS subject matching, where * matches a single token and > matches one or more trailing tokens
+pub fn subject_matches(pattern: &str, subject: &str) -> bool {
+    let mut subject_tokens = subject.split('.');
+    for pattern_token in pattern.split('.') {
+        match (pattern_token, subject_tokens.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => (),
+            (pattern_token, Some(token)) if pattern_token == token => (),
+            _ => return false,
+        }
+    }
+    subject_tokens.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_delay() {
+        let min = Duration::from_millis(100);
+        let max = Duration::from_secs(1);
+        assert_eq!(reconnect_delay(1, min, max), min);
+        assert_eq!(reconnect_delay(3, min, max), Duration::from_millis(400));
+        assert_eq!(reconnect_delay(5, min, max), max);
+        assert_eq!(reconnect_delay(1000, min, max), max);
+    }
+
+    #[test]
+    fn test_subject_matches() {
+        assert!(subject_matches("pi.qc.df", "pi.qc.df"));
+        assert!(subject_matches("pi.*.df", "pi.qc.df"));
+        assert!(subject_matches("pi.>", "pi.qc.df"));
+        assert!(!subject_matches("pi.>", "pi"));
+        assert!(!subject_matches("pi.*", "pi.qc.df"));
+        assert!(!subject_matches("pi.qc.df", "pi.qc"));
+        assert!(!subject_matches("pi.qc", "pi.qc.df"));
+    }
+}
@@ -0,0 +1,761 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gst::glib;
+use gst::prelude::*;
+
+use super::imp::CAT;
+use super::jetstream::{self, JetStreamOptions};
+use super::kv::{self, KvOptions};
+use super::{NatsOversizePolicy, NatsQueuePolicy};
+use crate::error::{NatsConfigError, PublishError};
+use crate::nats_common::auth::NatsAuth;
+use crate::nats_common::framing;
+use crate::nats_common::headers::{header, header_map, BATCH_COUNT_HEADER};
+use crate::nats_common::inproc;
+use crate::nats_common::{reconnect_delay, NatsMessage};
+
+// Name of the bus message posted when the NATS connection state changes
+pub const CONNECTION_MESSAGE_NAME: &str = "printnanny-nats-connection";
+
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5); // max time to publish queued messages when stopping
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+    Closed, // max-reconnects was reached, or the element was stopped
+}
+
+impl ConnectionState {
+    pub fn nick(&self) -> &'static str {
+        match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Disconnected => "disconnected",
+            ConnectionState::Closed => "closed",
+        }
+    }
+}
+
+pub type Message = NatsMessage;
+
+// Bounded queue of messages waiting for the publisher thread, limited by number of messages and payload bytes
+#[derive(Debug)]
+pub struct PublishQueue {
+    messages: VecDeque<Message>,
+    bytes: usize,
+    max_messages: usize,
+    max_bytes: usize,
+}
+
+impl PublishQueue {
+    pub fn new(max_messages: usize, max_bytes: usize) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            bytes: 0,
+            max_messages,
+            max_bytes,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    fn has_room(&self, size: usize) -> bool {
+        self.messages.len() < self.max_messages && self.bytes + size <= self.max_bytes
+    }
+
+    // Returns the number of dropped messages, or the message if the queue is full and policy is block
+    // Messages larger than max-queue-bytes can never be queued, and are dropped regardless of policy
+    pub fn push(&mut self, msg: Message, policy: NatsQueuePolicy) -> Result<usize, Message> {
+        let size = msg.payload.len();
+        if self.max_messages == 0 || size > self.max_bytes {
+            return Ok(1);
+        }
+        let mut dropped = 0;
+        match policy {
+            NatsQueuePolicy::DropOldest => {
+                while !self.has_room(size) {
+                    self.pop_front();
+                    dropped += 1;
+                }
+            }
+            NatsQueuePolicy::DropNewest if !self.has_room(size) => return Ok(1),
+            NatsQueuePolicy::Block if !self.has_room(size) => return Err(msg),
+            _ => (),
+        }
+        self.bytes += size;
+        self.messages.push_back(msg);
+        Ok(dropped)
+    }
+
+    pub fn front(&self) -> Option<&Message> {
+        self.messages.front()
+    }
+
+    pub fn pop_front(&mut self) -> Option<Message> {
+        let msg = self.messages.pop_front()?;
+        self.bytes -= msg.payload.len();
+        Some(msg)
+    }
+
+    pub fn push_front(&mut self, msg: Message) {
+        self.bytes += msg.payload.len();
+        self.messages.push_front(msg);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionOptions {
+    pub address: String,
+    pub auth: NatsAuth,
+    pub reconnect_delay: Duration,
+    pub reconnect_delay_max: Duration,
+    pub max_reconnects: Option<usize>, // None reconnects forever
+    pub queue_policy: NatsQueuePolicy,
+    pub max_queue_messages: usize,
+    pub max_queue_bytes: usize,
+    pub jetstream: Option<JetStreamOptions>, // None publishes with core NATS, without acknowledgement
+    pub kv: Option<KvOptions>, // key-value bucket which receives the latest value of every key
+    pub oversize_policy: NatsOversizePolicy,
+    pub batch: Option<BatchOptions>, // None publishes every message separately
+}
+
+// Queued messages to the same subject are published as one batch, until linger passed since the first message or the batch reaches max_bytes
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    pub linger: Duration,
+    pub max_bytes: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PublishResult {
+    Queued,
+    Dropped(usize), // number of messages dropped to respect queue limits, including the published message for drop-newest policy
+}
+
+// Message counters, read without locking by the element's read-only properties
+#[derive(Debug, Default)]
+pub struct PublishStats {
+    pub published: AtomicU64,
+    pub dropped: AtomicU64,
+    pub queued: AtomicU64,
+}
+
+impl PublishStats {
+    pub fn reset(&self) {
+        self.published.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+        self.queued.store(0, Ordering::Relaxed);
+    }
+}
+
+// Publishes to a NATS server, or to subscribers within this process for inproc://name addresses
+#[derive(Clone)]
+enum Transport {
+    Nats(nats::Connection),
+    Inproc(String),
+}
+
+impl Transport {
+    fn max_payload(&self) -> usize {
+        match self {
+            Transport::Nats(nc) => nc.max_payload(),
+            Transport::Inproc(_) => inproc::MAX_PAYLOAD,
+        }
+    }
+}
+
+struct Inner {
+    nc: Option<Transport>,
+    state: ConnectionState,
+    queue: PublishQueue,
+    sending: bool, // the publisher thread is sending a message taken from the queue, or writing to the bucket
+    kv_store: Option<nats::kv::Store>,
+    kv_pending: BTreeMap<String, Vec<u8>>, // latest value of every key not yet written to the bucket
+    flushing: bool,                        // unblocks publishers waiting for room in the queue
+    stopping: bool,
+    failed: bool, // a message could not be published, and the error was posted
+}
+
+impl Inner {
+    fn has_work(&self) -> bool {
+        self.state == ConnectionState::Connected
+            && (!self.queue.is_empty() || (self.kv_store.is_some() && !self.kv_pending.is_empty()))
+    }
+}
+
+// NATS connection which is established in the background, retried with exponential backoff
+// Messages are queued and published in order by a publisher thread, so a slow or unreachable server never blocks the streaming thread unless queue-policy is block
+pub struct NatsConnection {
+    options: ConnectionOptions,
+    element: glib::WeakRef<super::NatsSink>,
+    inner: Mutex<Inner>,
+    changed: Condvar, // notified on state, queue and flushing changes
+    stats: Arc<PublishStats>,
+}
+
+impl NatsConnection {
+    // Returns immediately, the connection is established by a background thread
+    pub fn start(
+        element: &super::NatsSink,
+        options: ConnectionOptions,
+        stats: Arc<PublishStats>,
+    ) -> Arc<Self> {
+        let connection = Arc::new(Self {
+            inner: Mutex::new(Inner {
+                nc: None,
+                state: ConnectionState::Connecting,
+                queue: PublishQueue::new(options.max_queue_messages, options.max_queue_bytes),
+                sending: false,
+                kv_store: None,
+                kv_pending: BTreeMap::new(),
+                flushing: false,
+                stopping: false,
+                failed: false,
+            }),
+            options,
+            element: element.downgrade(),
+            changed: Condvar::new(),
+            stats,
+        });
+        let thread_connection = connection.clone();
+        thread::spawn(move || thread_connection.connect_loop());
+        let thread_connection = connection.clone();
+        thread::spawn(move || thread_connection.publish_loop());
+        connection
+    }
+
+    fn connect_loop(self: Arc<Self>) {
+        if let Some(server) = inproc::server_name(&self.options.address) {
+            self.set_state(ConnectionState::Connecting, 1, None);
+            if self.options.jetstream.is_some() || self.options.kv.is_some() {
+                let err = std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "JetStream and key-value buckets are not available with inproc:// addresses",
+                );
+                self.setup_failed("Failed to connect", &err, 1);
+                return;
+            }
+            self.inner.lock().unwrap().nc = Some(Transport::Inproc(server.to_string()));
+            self.set_state(ConnectionState::Connected, 1, None);
+            return;
+        }
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.set_state(ConnectionState::Connecting, attempt, None);
+            let nats_options = match self.nats_options() {
+                Ok(nats_options) => nats_options,
+                Err(err) => {
+                    self.set_state(ConnectionState::Closed, attempt, Some(err.to_string()));
+                    return;
+                }
+            };
+            match nats_options.connect(self.options.address.as_str()) {
+                Ok(nc) => {
+                    let mut inner = self.inner.lock().unwrap();
+                    if inner.stopping {
+                        drop(inner);
+                        nc.close();
+                        return;
+                    }
+                    inner.nc = Some(Transport::Nats(nc.clone()));
+                    drop(inner);
+                    if let Some(js_options) = &self.options.jetstream {
+                        let js = nats::jetstream::new(nc.clone());
+                        if let Err(err) = jetstream::ensure_stream(&js, js_options) {
+                            self.setup_failed("Failed to set up JetStream stream", &err, attempt);
+                            return;
+                        }
+                    }
+                    if let Some(kv_options) = &self.options.kv {
+                        match kv::open_bucket(&nats::jetstream::new(nc), kv_options) {
+                            Ok(store) => self.inner.lock().unwrap().kv_store = Some(store),
+                            Err(err) => {
+                                self.setup_failed("Failed to open key-value bucket", &err, attempt);
+                                return;
+                            }
+                        }
+                    }
+                    self.set_state(ConnectionState::Connected, attempt, None);
+                    return;
+                }
+                Err(err) => {
+                    self.set_state(
+                        ConnectionState::Disconnected,
+                        attempt,
+                        Some(err.to_string()),
+                    );
+                    if self
+                        .options
+                        .max_reconnects
+                        .map_or(false, |max_reconnects| attempt > max_reconnects)
+                    {
+                        self.set_state(ConnectionState::Closed, attempt, None);
+                        return;
+                    }
+                }
+            }
+
+            let delay = reconnect_delay(
+                attempt,
+                self.options.reconnect_delay,
+                self.options.reconnect_delay_max,
+            );
+            let inner = self.inner.lock().unwrap();
+            let (inner, _) = self
+                .changed
+                .wait_timeout_while(inner, delay, |inner| !inner.stopping)
+                .unwrap();
+            if inner.stopping {
+                return;
+            }
+        }
+    }
+
+    // JetStream misconfiguration is not fixed by reconnecting
+    fn setup_failed(&self, reason: &str, err: &std::io::Error, attempt: usize) {
+        if let Some(element) = self.element.upgrade() {
+            gst::element_error!(
+                element,
+                gst::ResourceError::Settings,
+                ["{}: {}", reason, err]
+            );
+        }
+        self.set_state(ConnectionState::Closed, attempt, Some(err.to_string()));
+    }
+
+    // Reconnects after the initial connection are handled by the nats client, using the same backoff
+    fn nats_options(self: &Arc<Self>) -> Result<nats::Options, NatsConfigError> {
+        let (min, max) = (
+            self.options.reconnect_delay,
+            self.options.reconnect_delay_max,
+        );
+        let on_disconnect = Arc::downgrade(self);
+        let on_reconnect = Arc::downgrade(self);
+        let on_close = Arc::downgrade(self);
+        let options = self
+            .options
+            .auth
+            .options()?
+            .with_name("printnanny-gst-plugin")
+            .max_reconnects(self.options.max_reconnects)
+            .reconnect_delay_callback(move |attempt| reconnect_delay(attempt, min, max))
+            .disconnect_callback(move || {
+                if let Some(connection) = on_disconnect.upgrade() {
+                    connection.set_state(ConnectionState::Disconnected, 0, None);
+                }
+            })
+            .reconnect_callback(move || {
+                // wakes the publisher thread, which publishes queued messages
+                if let Some(connection) = on_reconnect.upgrade() {
+                    connection.set_state(ConnectionState::Connected, 0, None);
+                }
+            })
+            .close_callback(move || {
+                if let Some(connection) = on_close.upgrade() {
+                    connection.set_state(ConnectionState::Closed, 0, None);
+                }
+            });
+        Ok(options)
+    }
+
+    // Update state, and post a printnanny-nats-connection message
+    fn set_state(&self, state: ConnectionState, attempt: usize, error: Option<String>) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.stopping && state != ConnectionState::Closed {
+            return;
+        }
+        inner.state = state;
+        let queued = inner.queue.len();
+        self.changed.notify_all();
+        drop(inner);
+
+        let element = match self.element.upgrade() {
+            Some(element) => element,
+            None => return,
+        };
+        match &error {
+            Some(error) => gst::warning!(
+                CAT,
+                obj: &element,
+                "NATS connection to {} {}: {}",
+                self.options.address,
+                state.nick(),
+                error
+            ),
+            None => gst::info!(
+                CAT,
+                obj: &element,
+                "NATS connection to {} {}",
+                self.options.address,
+                state.nick()
+            ),
+        }
+        let mut structure = gst::Structure::builder(CONNECTION_MESSAGE_NAME)
+            .field("state", state.nick())
+            .field("address", &self.options.address)
+            .field("attempt", attempt as u32)
+            .field("queued", queued as u32)
+            .build();
+        if let Some(error) = error {
+            structure.set("error", error);
+        }
+        let msg = gst::message::Element::builder(structure)
+            .src(&element)
+            .build();
+        let _ = element.post_message(msg);
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.inner.lock().unwrap().state
+    }
+
+    // Publish with JetStream acknowledgement if enabled, otherwise fire-and-forget
+    fn send_one(&self, transport: &Transport, msg: &Message) -> Result<(), PublishError> {
+        let nc = match transport {
+            Transport::Nats(nc) => nc,
+            Transport::Inproc(server) => {
+                inproc::publish(
+                    server,
+                    &msg.subject,
+                    msg.headers.clone(),
+                    msg.payload.clone(),
+                );
+                return Ok(());
+            }
+        };
+        match &self.options.jetstream {
+            Some(js_options) => {
+                jetstream::publish(&nats::jetstream::new(nc.clone()), js_options, msg)
+            }
+            None => Ok(nc.publish_with_reply_or_headers(
+                &msg.subject,
+                None,
+                Some(&header_map(&msg.headers)),
+                &msg.payload,
+            )?),
+        }
+    }
+
+    // Messages larger than the server's max_payload are split into chunks, or rejected, according to oversize-policy
+    fn send(&self, transport: &Transport, msg: &Message) -> Result<(), PublishError> {
+        let max_payload = transport.max_payload();
+        let size = framing::message_size(msg);
+        if size <= max_payload {
+            return self.send_one(transport, msg);
+        }
+        let oversize = PublishError::Oversize { size, max_payload };
+        match self.options.oversize_policy {
+            NatsOversizePolicy::Error => Err(oversize),
+            NatsOversizePolicy::Chunk => {
+                let chunks = framing::chunk(msg, max_payload).ok_or(oversize)?;
+                gst::debug!(
+                    CAT,
+                    "Splitting message of {} bytes into {} chunks",
+                    size,
+                    chunks.len()
+                );
+                for chunk in chunks.iter() {
+                    self.send_one(transport, chunk)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Take following queued messages to the same subject into a batch with first, while the batch fits max-batch-bytes and the server's max_payload
+    // Waits for more messages until batch-linger passed since first was taken, or the element is flushing
+    fn take_batch<'a>(
+        &self,
+        mut inner: MutexGuard<'a, Inner>,
+        first: Message,
+        max_payload: usize,
+    ) -> (MutexGuard<'a, Inner>, Message) {
+        let options = match &self.options.batch {
+            // batches which failed to publish are returned to the queue, and not batched again
+            Some(options) if header(&first.headers, BATCH_COUNT_HEADER).is_none() => options,
+            _ => return (inner, first),
+        };
+        let max_bytes = options.max_bytes.min(max_payload);
+        let deadline = Instant::now() + options.linger;
+        let mut size = framing::batch_size(&first);
+        let mut batch = vec![first];
+        loop {
+            while let Some(next) = inner.queue.front() {
+                if next.subject != batch[0].subject
+                    || header(&next.headers, BATCH_COUNT_HEADER).is_some()
+                    || size + framing::frame_size(next) > max_bytes
+                {
+                    return (inner, framing::batch(batch));
+                }
+                size += framing::frame_size(next);
+                batch.push(inner.queue.pop_front().unwrap());
+                // publishers blocked by queue-policy=block wait for room in the queue
+                self.changed.notify_all();
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() || inner.flushing || inner.stopping {
+                return (inner, framing::batch(batch));
+            }
+            inner = self.changed.wait_timeout(inner, timeout).unwrap().0;
+        }
+    }
+
+    // Post an error for a message which can never be published, and fail following renders
+    fn publish_failed(&self, err: &PublishError) {
+        let element = match self.element.upgrade() {
+            Some(element) => element,
+            None => return,
+        };
+        match err {
+            PublishError::Oversize { .. } => gst::element_error!(
+                element,
+                gst::ResourceError::Write,
+                [
+                    "{}. Set oversize-policy=chunk to split large messages into chunks",
+                    err
+                ]
+            ),
+            _ => gst::element_error!(element, gst::ResourceError::Write, ["{}", err]),
+        }
+    }
+
+    // Publish queued messages in order, then write pending key-value bucket values, while connected
+    // A message which failed to publish is returned to the front of the queue, and retried after reconnect-delay
+    // Messages which exceed max_payload or are not acknowledged after jetstream-max-retries are dropped, and an error is posted
+    fn publish_loop(self: Arc<Self>) {
+        loop {
+            let inner = self.inner.lock().unwrap();
+            let mut inner = self
+                .changed
+                .wait_while(inner, |inner| !inner.stopping && !inner.has_work())
+                .unwrap();
+            if inner.stopping {
+                return;
+            }
+            let nc = inner
+                .nc
+                .clone()
+                .expect("connected without a NATS connection");
+            let result = match inner.queue.pop_front() {
+                Some(msg) => {
+                    inner.sending = true;
+                    let (inner, msg) = self.take_batch(inner, msg, nc.max_payload());
+                    self.stats
+                        .queued
+                        .store(inner.queue.len() as u64, Ordering::Relaxed);
+                    drop(inner);
+                    // publishers blocked by queue-policy=block wait for room in the queue
+                    self.changed.notify_all();
+                    let count = framing::batch_len(&msg) as u64;
+                    let result = self.send(&nc, &msg);
+                    let mut inner = self.inner.lock().unwrap();
+                    inner.sending = false;
+                    match result {
+                        Ok(_) => {
+                            self.stats.published.fetch_add(count, Ordering::Relaxed);
+                        }
+                        Err(PublishError::Io(_)) => {
+                            inner.queue.push_front(msg);
+                            self.stats
+                                .queued
+                                .store(inner.queue.len() as u64, Ordering::Relaxed);
+                        }
+                        Err(_) => {
+                            inner.failed = true;
+                            self.stats.dropped.fetch_add(count, Ordering::Relaxed);
+                        }
+                    }
+                    drop(inner);
+                    self.changed.notify_all();
+                    match result {
+                        Ok(_) => Ok(()),
+                        Err(PublishError::Io(err)) => {
+                            Err(format!("Failed to publish NATS message: {}", err))
+                        }
+                        Err(err) => {
+                            self.publish_failed(&err);
+                            Ok(())
+                        }
+                    }
+                }
+                None => {
+                    let store = inner
+                        .kv_store
+                        .clone()
+                        .expect("pending values without a bucket");
+                    let pending = std::mem::take(&mut inner.kv_pending);
+                    inner.sending = true;
+                    drop(inner);
+                    let result = self.write_kv(&store, pending);
+                    self.inner.lock().unwrap().sending = false;
+                    self.changed.notify_all();
+                    result
+                }
+            };
+            if let Err(err) = result {
+                gst::warning!(CAT, "{}", err);
+                let inner = self.inner.lock().unwrap();
+                let _ = self
+                    .changed
+                    .wait_timeout_while(inner, self.options.reconnect_delay, |inner| {
+                        !inner.stopping
+                    })
+                    .unwrap();
+            }
+        }
+    }
+
+    // Values which failed to be written are kept, unless a newer value was put meanwhile
+    fn write_kv(
+        &self,
+        store: &nats::kv::Store,
+        pending: BTreeMap<String, Vec<u8>>,
+    ) -> Result<(), String> {
+        let mut result = Ok(());
+        for (key, value) in pending {
+            if let Err(err) = store.put(&key, &value) {
+                result = Err(format!(
+                    "Failed to write key {} to key-value bucket: {}",
+                    key, err
+                ));
+                self.inner
+                    .lock()
+                    .unwrap()
+                    .kv_pending
+                    .entry(key)
+                    .or_insert(value);
+            }
+        }
+        result
+    }
+
+    // Write the latest value of key to the key-value bucket. Only the latest value of every key is kept until it is written
+    pub fn put_kv(&self, key: String, value: Vec<u8>) {
+        self.inner.lock().unwrap().kv_pending.insert(key, value);
+        self.changed.notify_all();
+    }
+
+    // Queue message for the publisher thread according to queue-policy
+    // With block policy, waits until the queue has room, or the element is flushing
+    pub fn publish(&self, mut msg: Message) -> Result<PublishResult, gst::FlowError> {
+        let mut inner = self.inner.lock().unwrap();
+        loop {
+            if inner.flushing {
+                return Err(gst::FlowError::Flushing);
+            }
+            if inner.failed {
+                return Err(gst::FlowError::Error);
+            }
+            // max-reconnects was reached, queued messages would never be published
+            if inner.state == ConnectionState::Closed {
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                return Ok(PublishResult::Dropped(1));
+            }
+            match inner.queue.push(msg, self.options.queue_policy) {
+                Ok(dropped) => {
+                    self.stats
+                        .queued
+                        .store(inner.queue.len() as u64, Ordering::Relaxed);
+                    self.changed.notify_all();
+                    if dropped == 0 {
+                        return Ok(PublishResult::Queued);
+                    }
+                    self.stats
+                        .dropped
+                        .fetch_add(dropped as u64, Ordering::Relaxed);
+                    return Ok(PublishResult::Dropped(dropped));
+                }
+                Err(blocked) => {
+                    msg = blocked;
+                    gst::trace!(CAT, "NATS message queue is full, waiting");
+                    inner = self.changed.wait(inner).unwrap();
+                }
+            }
+        }
+    }
+
+    pub fn set_flushing(&self, flushing: bool) {
+        self.inner.lock().unwrap().flushing = flushing;
+        self.changed.notify_all();
+    }
+
+    // Publish queued messages while connected, for at most DRAIN_TIMEOUT, then flush and close the connection
+    // Messages still queued are discarded, and their number returned
+    pub fn close(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        inner.flushing = true;
+        self.changed.notify_all();
+        let (mut inner, _) = self
+            .changed
+            .wait_timeout_while(inner, DRAIN_TIMEOUT, |inner| {
+                inner.sending || inner.has_work()
+            })
+            .unwrap();
+        inner.stopping = true;
+        let nc = inner.nc.take();
+        let discarded = inner.queue.len();
+        self.stats
+            .dropped
+            .fetch_add(discarded as u64, Ordering::Relaxed);
+        self.changed.notify_all();
+        drop(inner);
+
+        if let Some(Transport::Nats(nc)) = nc {
+            if let Err(err) = nc.flush_timeout(FLUSH_TIMEOUT) {
+                gst::warning!(CAT, "Failed to flush NATS connection: {}", err);
+            }
+            nc.close();
+        }
+        discarded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(payload: &[u8]) -> Message {
+        Message {
+            subject: "test".into(),
+            payload: payload.to_vec(),
+            headers: vec![],
+        }
+    }
+
+    #[test]
+    fn test_publish_queue_policies() {
+        let mut queue = PublishQueue::new(2, 1024);
+        assert_eq!(queue.push(msg(b"a"), NatsQueuePolicy::DropOldest), Ok(0));
+        assert_eq!(queue.push(msg(b"b"), NatsQueuePolicy::DropOldest), Ok(0));
+        assert_eq!(queue.push(msg(b"c"), NatsQueuePolicy::DropOldest), Ok(1));
+        assert_eq!(queue.push(msg(b"d"), NatsQueuePolicy::DropNewest), Ok(1));
+        assert_eq!(
+            queue.push(msg(b"e"), NatsQueuePolicy::Block),
+            Err(msg(b"e"))
+        );
+        assert_eq!(queue.pop_front(), Some(msg(b"b")));
+        assert_eq!(queue.pop_front(), Some(msg(b"c")));
+        assert!(queue.is_empty());
+
+        // byte limit
+        let mut queue = PublishQueue::new(10, 4);
+        assert_eq!(queue.push(msg(b"abc"), NatsQueuePolicy::DropOldest), Ok(0));
+        assert_eq!(queue.push(msg(b"de"), NatsQueuePolicy::DropOldest), Ok(1));
+        assert_eq!(queue.push(msg(b"toolarge"), NatsQueuePolicy::Block), Ok(1));
+        assert_eq!(queue.len(), 1);
+    }
+}
@@ -3,15 +3,85 @@ use gst::prelude::*;
 use gst::subclass::prelude::*;
 use gst_base::subclass::prelude::*;
 use once_cell::sync::Lazy;
-use std::sync::Mutex;
+use polars::prelude::DataFrame;
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::connection::{
+    BatchOptions, ConnectionOptions, Message, NatsConnection, PublishResult, PublishStats,
+};
+use super::jetstream::JetStreamOptions;
+use super::kv::{latest_window_summary, sanitize_key, KvOptions};
+use super::subject::{partition_dataframe, subject_token, SubjectTemplate};
+use super::{NatsKvFormat, NatsMsgIdMode, NatsOversizePolicy, NatsQueuePolicy};
+use crate::caps::dataframe_sink_caps;
+use crate::ipc::{
+    arrow_streaming_ipc_message_with_compression, dataframe_from_bytes,
+    dataframe_to_arrow_streaming_ipc_message, is_arrow_streaming_ipc_message, metadata_from_bytes,
+    IpcCompression,
+};
+use crate::metadata::{labels_from_metadata, merge_metadata, CAMERA_ID_KEY, DEFAULT_LABELS};
+use crate::nats_common::auth::{auth_properties, NatsAuth};
+use crate::nats_common::headers::{
+    extra_headers, Headers, StreamHeaders, CAMERA_ID_HEADER, CONTENT_TYPE_HEADER, DEVICE_ID_HEADER,
+    DTS_HEADER, DURATION_HEADER, METADATA_HEADER, MSG_ID_HEADER, PTS_HEADER,
+    SCHEMA_FINGERPRINT_HEADER, SEQUENCE_HEADER,
+};
+use crate::roi::class_label;
 
 const DEFAULT_NATS_ADDRESS: &str = "127.0.0.1:4222";
 const DEFAULT_NATS_SUBJECT: &str = "pi.qc.df";
+const DEFAULT_IPC_COMPRESSION: IpcCompression = IpcCompression::Uncompressed;
+const DEFAULT_RECONNECT_DELAY: u64 = 100_000_000; // 100ms
+const DEFAULT_RECONNECT_DELAY_MAX: u64 = 30_000_000_000; // 30s
+const DEFAULT_MAX_RECONNECTS: i32 = -1;
+const DEFAULT_QUEUE_POLICY: NatsQueuePolicy = NatsQueuePolicy::DropOldest;
+const DEFAULT_MAX_QUEUE_SIZE: u32 = 1000;
+const DEFAULT_MAX_QUEUE_BYTES: u64 = 8388608; // 8MiB
+const DEFAULT_JETSTREAM: bool = false;
+const DEFAULT_JETSTREAM_ACK_WAIT: u64 = 5_000_000_000; // 5s
+const DEFAULT_JETSTREAM_MAX_RETRIES: u32 = 3;
+const DEFAULT_MSG_ID_MODE: NatsMsgIdMode = NatsMsgIdMode::Sequence;
+const DEFAULT_CREATE_STREAM: bool = false;
+const DEFAULT_KV_FORMAT: NatsKvFormat = NatsKvFormat::Payload;
+const DEFAULT_KV_WINDOW_COLUMN: &str = "rt";
+const DEFAULT_CREATE_KV_BUCKET: bool = false;
+const DEFAULT_OVERSIZE_POLICY: NatsOversizePolicy = NatsOversizePolicy::Error;
+const DEFAULT_BATCH_LINGER: u64 = 0; // disabled
+const DEFAULT_MAX_BATCH_BYTES: u64 = 65536; // 64KiB
 
 #[derive(Debug, Clone)]
 struct Settings {
     nats_address: String,
     nats_subject: String,
+    ipc_compression: IpcCompression,
+    reconnect_delay: u64, // Delay before the first reconnect attempt, in nanoseconds
+    reconnect_delay_max: u64, // Max delay between reconnect attempts, in nanoseconds
+    max_reconnects: i32,  // -1 reconnects forever
+    queue_policy: NatsQueuePolicy,
+    max_queue_size: u32,  // Max number of messages waiting to be published
+    max_queue_bytes: u64, // Max payload bytes waiting to be published
+    auth: NatsAuth,
+    jetstream: bool,
+    jetstream_ack_wait: u64, // in nanoseconds
+    jetstream_max_retries: u32,
+    msg_id_mode: NatsMsgIdMode,
+    stream_name: Option<String>,
+    create_stream: bool,
+    camera_id: Option<String>,
+    device_id: Option<String>,
+    extra_headers: Option<gst::Structure>,
+    split_column: Option<String>, // publish each partition of the dataframe with equal values of this column separately
+    kv_bucket: Option<String>,
+    kv_key: Option<String>, // None uses the message subject as key
+    kv_format: NatsKvFormat,
+    kv_window_column: String,
+    create_kv_bucket: bool,
+    oversize_policy: NatsOversizePolicy,
+    batch_linger: u64, // in nanoseconds, 0 publishes every message separately
+    max_batch_bytes: u64,
 }
 
 impl Default for Settings {
@@ -19,13 +89,193 @@ impl Default for Settings {
         Settings {
             nats_address: DEFAULT_NATS_ADDRESS.into(),
             nats_subject: DEFAULT_NATS_SUBJECT.into(),
+            ipc_compression: DEFAULT_IPC_COMPRESSION,
+            reconnect_delay: DEFAULT_RECONNECT_DELAY,
+            reconnect_delay_max: DEFAULT_RECONNECT_DELAY_MAX,
+            max_reconnects: DEFAULT_MAX_RECONNECTS,
+            queue_policy: DEFAULT_QUEUE_POLICY,
+            max_queue_size: DEFAULT_MAX_QUEUE_SIZE,
+            max_queue_bytes: DEFAULT_MAX_QUEUE_BYTES,
+            auth: NatsAuth::default(),
+            jetstream: DEFAULT_JETSTREAM,
+            jetstream_ack_wait: DEFAULT_JETSTREAM_ACK_WAIT,
+            jetstream_max_retries: DEFAULT_JETSTREAM_MAX_RETRIES,
+            msg_id_mode: DEFAULT_MSG_ID_MODE,
+            stream_name: None,
+            create_stream: DEFAULT_CREATE_STREAM,
+            camera_id: None,
+            device_id: None,
+            extra_headers: None,
+            split_column: None,
+            kv_bucket: None,
+            kv_key: None,
+            kv_format: DEFAULT_KV_FORMAT,
+            kv_window_column: DEFAULT_KV_WINDOW_COLUMN.into(),
+            create_kv_bucket: DEFAULT_CREATE_KV_BUCKET,
+            oversize_policy: DEFAULT_OVERSIZE_POLICY,
+            batch_linger: DEFAULT_BATCH_LINGER,
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
         }
     }
 }
 
+impl Settings {
+    fn connection_options(&self, subject: &SubjectTemplate) -> ConnectionOptions {
+        ConnectionOptions {
+            address: self.nats_address.clone(),
+            auth: self.auth.clone(),
+            reconnect_delay: Duration::from_nanos(self.reconnect_delay),
+            reconnect_delay_max: Duration::from_nanos(self.reconnect_delay_max),
+            max_reconnects: usize::try_from(self.max_reconnects).ok(),
+            queue_policy: self.queue_policy,
+            max_queue_messages: self.max_queue_size as usize,
+            max_queue_bytes: self.max_queue_bytes as usize,
+            jetstream: self.jetstream_options(subject),
+            kv: self.kv_bucket.clone().map(|bucket| KvOptions {
+                bucket,
+                create_bucket: self.create_kv_bucket,
+            }),
+            oversize_policy: self.oversize_policy,
+            batch: match self.batch_linger {
+                0 => None,
+                linger => Some(BatchOptions {
+                    linger: Duration::from_nanos(linger),
+                    max_bytes: self.max_batch_bytes as usize,
+                }),
+            },
+        }
+    }
+
+    fn jetstream_options(&self, subject: &SubjectTemplate) -> Option<JetStreamOptions> {
+        if !self.jetstream {
+            return None;
+        }
+        Some(JetStreamOptions {
+            ack_wait: Duration::from_nanos(self.jetstream_ack_wait),
+            max_retries: self.jetstream_max_retries,
+            retry_delay: Duration::from_nanos(self.reconnect_delay),
+            retry_delay_max: Duration::from_nanos(self.reconnect_delay_max),
+            stream: self.stream_name.clone(),
+            subject: subject.wildcard(),
+            create_stream: self.create_stream,
+        })
+    }
+
+    // Nats-Msg-Id is unique per session, so restarting the element does not collide with ids published by the previous session within the stream's duplicate window
+    fn msg_id(
+        &self,
+        session_id: &str,
+        sequence: u64,
+        pts: Option<gst::ClockTime>,
+    ) -> Option<String> {
+        match (self.msg_id_mode, pts) {
+            (NatsMsgIdMode::None, _) => None,
+            (NatsMsgIdMode::Pts, Some(pts)) => Some(format!("{}-{}", session_id, pts.nseconds())),
+            _ => Some(format!("{}-{}", session_id, sequence)),
+        }
+    }
+
+    // Value of a nats-subject placeholder, in order of precedence: split-column partition, element properties, caps fields, schema metadata
+    fn subject_var(
+        &self,
+        name: &str,
+        caps: Option<&gst::Caps>,
+        metadata: &BTreeMap<String, String>,
+        partition: Option<&str>,
+    ) -> Option<String> {
+        if let (Some(partition), Some(column)) = (partition, &self.split_column) {
+            if name == column || name == "partition" {
+                return Some(partition.to_string());
+            }
+            // class labels of detection_classes partitions
+            if name == "label" && column == "detection_classes" {
+                let labels = labels_from_metadata(metadata)
+                    .unwrap_or_else(|| DEFAULT_LABELS.iter().map(|l| l.to_string()).collect());
+                return partition
+                    .parse::<i32>()
+                    .ok()
+                    .map(|class| class_label(&labels, class));
+            }
+        }
+        let property = match name {
+            "camera" | "camera-id" => self
+                .camera_id
+                .clone()
+                .or_else(|| metadata.get(CAMERA_ID_KEY).cloned()),
+            "device" | "device-id" => self.device_id.clone(),
+            _ => None,
+        };
+        if property.is_some() {
+            return property;
+        }
+        let caps_field = caps
+            .and_then(|caps| caps.structure(0))
+            .and_then(|s| s.value(name).ok())
+            .and_then(|value| match value.get::<String>() {
+                Ok(value) => Some(value),
+                Err(_) => value.serialize().ok().map(|value| value.to_string()),
+            });
+        if caps_field.is_some() {
+            return caps_field;
+        }
+        metadata
+            .get(name)
+            .or_else(|| metadata.get(&format!("printnanny:{}", name)))
+            .cloned()
+    }
+
+    // camera_id is the value of the camera-id property, or CAMERA_ID_KEY from caps and schema metadata (see subject_var)
+    fn headers(
+        &self,
+        stream_headers: &StreamHeaders,
+        msg_id: Option<String>,
+        sequence: u64,
+        buffer: &gst::BufferRef,
+        payload: &[u8],
+        camera_id: Option<String>,
+    ) -> Headers {
+        let mut headers = vec![(
+            CONTENT_TYPE_HEADER.to_string(),
+            stream_headers.content_type(payload),
+        )];
+        let mut push = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                headers.push((name.to_string(), value));
+            }
+        };
+        push(MSG_ID_HEADER, msg_id);
+        push(PTS_HEADER, buffer.pts().map(|t| t.nseconds().to_string()));
+        push(DTS_HEADER, buffer.dts().map(|t| t.nseconds().to_string()));
+        push(
+            DURATION_HEADER,
+            buffer.duration().map(|t| t.nseconds().to_string()),
+        );
+        push(SEQUENCE_HEADER, Some(sequence.to_string()));
+        push(CAMERA_ID_HEADER, camera_id);
+        push(DEVICE_ID_HEADER, self.device_id.clone());
+        push(
+            SCHEMA_FINGERPRINT_HEADER,
+            stream_headers.schema_fingerprint.clone(),
+        );
+        push(METADATA_HEADER, stream_headers.metadata_header());
+        if let Some(extra) = &self.extra_headers {
+            headers.extend(extra_headers(extra));
+        }
+        headers
+    }
+}
+
 enum State {
     Stopped,
-    Started { nc: nats::Connection },
+    Started {
+        connection: Arc<NatsConnection>,
+        session_id: String,
+        sequence: u64,
+        stream_headers: StreamHeaders,
+        subject: SubjectTemplate,
+        kv_key: Option<SubjectTemplate>,
+        caps: Option<gst::Caps>,
+    },
 }
 
 impl Default for State {
@@ -38,9 +288,10 @@ impl Default for State {
 pub struct NatsSink {
     settings: Mutex<Settings>,
     state: Mutex<State>,
+    stats: Arc<PublishStats>,
 }
 
-static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+pub(super) static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
         "nats_sink",
         gst::DebugColorFlags::empty(),
@@ -48,7 +299,58 @@ static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     )
 });
 
-impl NatsSink {}
+impl NatsSink {
+    // Fill the placeholders of the nats-subject or kv-key property's template
+    fn render_template(
+        &self,
+        settings: &Settings,
+        property: &str,
+        template: &SubjectTemplate,
+        caps: Option<&gst::Caps>,
+        metadata: &BTreeMap<String, String>,
+        partition: Option<&str>,
+    ) -> Result<String, gst::FlowError> {
+        template
+            .render(|name| settings.subject_var(name, caps, metadata, partition))
+            .map_err(|name| {
+                gst::element_error!(
+                    self.instance(),
+                    gst::ResourceError::Settings,
+                    [
+                        "No value for placeholder {{{}}} of {} {}",
+                        name,
+                        property,
+                        template.as_str()
+                    ]
+                );
+                gst::FlowError::Error
+            })
+    }
+
+    // Value written to kv-bucket for a published message
+    fn kv_value(
+        &self,
+        settings: &Settings,
+        msg: &Message,
+        df: &DataFrame,
+        metadata: &BTreeMap<String, String>,
+    ) -> Result<Vec<u8>, gst::FlowError> {
+        match settings.kv_format {
+            NatsKvFormat::Payload => Ok(msg.payload.clone()),
+            NatsKvFormat::Summary => {
+                latest_window_summary(df, &settings.kv_window_column, &msg.headers, metadata)
+                    .map_err(|err| {
+                        gst::element_error!(
+                            self.instance(),
+                            gst::CoreError::Failed,
+                            ["Failed to summarize dataframe: {:?}", err]
+                        );
+                        gst::FlowError::Error
+                    })
+            }
+        }
+    }
+}
 
 #[glib::object_subclass]
 impl ObjectSubclass for NatsSink {
@@ -60,18 +362,152 @@ impl ObjectSubclass for NatsSink {
 impl ObjectImpl for NatsSink {
     fn properties() -> &'static [glib::ParamSpec] {
         static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
-            vec![
+            let mut properties = vec![
                 glib::ParamSpecString::builder("nats-address")
                     .nick("NATS Address")
                     .default_value(DEFAULT_NATS_ADDRESS)
-                    .blurb("NATS server address")
+                    .blurb("NATS server address. inproc://name publishes to nats_src elements subscribed to the same address within this process, used for testing")
                     .build(),
                 glib::ParamSpecString::builder("nats-subject")
                     .nick("NATS Subject")
                     .default_value(DEFAULT_NATS_SUBJECT)
-                    .blurb("NATS subject")
+                    .blurb("NATS subject, optionally with {name} placeholders filled from the camera-id and device-id properties, caps fields or dataframe schema metadata. For example pi.{device}.{camera}.qc")
+                    .build(),
+                glib::ParamSpecEnum::builder::<IpcCompression>("ipc-compression", DEFAULT_IPC_COMPRESSION)
+                    .nick("IPC Compression")
+                    .blurb("Re-encode arrow ipc message payloads with this compression codec before publishing. Payloads are published unchanged when set to none")
+                    .build(),
+                glib::ParamSpecUInt64::builder("reconnect-delay")
+                    .nick("Reconnect Delay")
+                    .blurb("Delay before the first reconnect attempt, doubled after every failed attempt up to reconnect-delay-max. In nanoseconds")
+                    .default_value(DEFAULT_RECONNECT_DELAY)
+                    .build(),
+                glib::ParamSpecUInt64::builder("reconnect-delay-max")
+                    .nick("Reconnect Delay Max")
+                    .blurb("Max delay between reconnect attempts, in nanoseconds")
+                    .default_value(DEFAULT_RECONNECT_DELAY_MAX)
+                    .build(),
+                glib::ParamSpecInt::builder("max-reconnects")
+                    .nick("Max Reconnects")
+                    .blurb("Max number of reconnect attempts before giving up, -1 reconnects forever")
+                    .minimum(-1)
+                    .default_value(DEFAULT_MAX_RECONNECTS)
+                    .build(),
+                glib::ParamSpecEnum::builder::<NatsQueuePolicy>("queue-policy", DEFAULT_QUEUE_POLICY)
+                    .nick("Queue Policy")
+                    .blurb("Handling of new messages when the queue of messages waiting to be published is full. Messages are published by a background thread, so the streaming thread never waits for the NATS server unless set to block")
+                    .build(),
+                glib::ParamSpecUInt::builder("max-queue-size")
+                    .nick("Max Queue Size")
+                    .blurb("Max number of messages waiting to be published, including messages queued while disconnected")
+                    .minimum(1)
+                    .default_value(DEFAULT_MAX_QUEUE_SIZE)
+                    .build(),
+                glib::ParamSpecUInt64::builder("max-queue-bytes")
+                    .nick("Max Queue Bytes")
+                    .blurb("Max payload bytes waiting to be published")
+                    .default_value(DEFAULT_MAX_QUEUE_BYTES)
+                    .build(),
+                glib::ParamSpecBoolean::builder("jetstream")
+                    .nick("JetStream")
+                    .blurb("Publish with JetStream, waiting for the stream to acknowledge every message")
+                    .default_value(DEFAULT_JETSTREAM)
+                    .build(),
+                glib::ParamSpecUInt64::builder("jetstream-ack-wait")
+                    .nick("JetStream Ack Wait")
+                    .blurb("Max time to wait for the stream to acknowledge a message before retrying, in nanoseconds")
+                    .minimum(1)
+                    .default_value(DEFAULT_JETSTREAM_ACK_WAIT)
+                    .build(),
+                glib::ParamSpecUInt::builder("jetstream-max-retries")
+                    .nick("JetStream Max Retries")
+                    .blurb("Max number of times an unacknowledged message is published again, with reconnect-delay backoff between attempts. Messages which are still not acknowledged are dropped, counted in dropped-messages, and an error is posted")
+                    .default_value(DEFAULT_JETSTREAM_MAX_RETRIES)
+                    .build(),
+                glib::ParamSpecEnum::builder::<NatsMsgIdMode>("msg-id-mode", DEFAULT_MSG_ID_MODE)
+                    .nick("Msg Id Mode")
+                    .blurb("Derive the Nats-Msg-Id header from the buffer sequence number or PTS. JetStream discards messages with a duplicate Nats-Msg-Id")
+                    .build(),
+                glib::ParamSpecString::builder("stream-name")
+                    .nick("Stream Name")
+                    .blurb("JetStream stream expected to capture nats-subject, verified when connecting")
+                    .build(),
+                glib::ParamSpecBoolean::builder("create-stream")
+                    .nick("Create Stream")
+                    .blurb("Create stream-name with nats-subject if the stream does not exist")
+                    .default_value(DEFAULT_CREATE_STREAM)
+                    .build(),
+                glib::ParamSpecString::builder("camera-id")
+                    .nick("Camera ID")
+                    .blurb("Camera identifier, sent in the PrintNanny-Camera-Id header. If not set, the camera id in caps or dataframe schema metadata is sent")
                     .build(),
-            ]
+                glib::ParamSpecString::builder("device-id")
+                    .nick("Device ID")
+                    .blurb("Device identifier, sent in the PrintNanny-Device-Id header and used to fill the {device} placeholder of nats-subject")
+                    .build(),
+                glib::ParamSpecString::builder("split-column")
+                    .nick("Split Column")
+                    .blurb("Split dataframes by the values of this column, publishing every partition as a separate arrow ipc message. The partition value fills the {partition} and {<split-column>} placeholders of nats-subject, and detection_classes partitions also fill {label}")
+                    .build(),
+                glib::ParamSpecBoxed::builder::<gst::Structure>("extra-headers")
+                    .nick("Extra Headers")
+                    .blurb("Static headers added to every message, for example extra-headers=\"headers, site=workshop\"")
+                    .build(),
+                glib::ParamSpecString::builder("kv-bucket")
+                    .nick("KV Bucket")
+                    .blurb("JetStream key-value bucket which receives the latest message of every key, in addition to publishing to nats-subject. Subscribers joining late read the current state from the bucket instead of waiting for the next message")
+                    .build(),
+                glib::ParamSpecString::builder("kv-key")
+                    .nick("KV Key")
+                    .blurb("Key written to kv-bucket, with the same placeholders as nats-subject. Defaults to the message subject. Characters which are not allowed in keys are replaced by _")
+                    .build(),
+                glib::ParamSpecEnum::builder::<NatsKvFormat>("kv-format", DEFAULT_KV_FORMAT)
+                    .nick("KV Format")
+                    .blurb("Write the message payload, or a JSON summary of the latest window, to kv-bucket")
+                    .build(),
+                glib::ParamSpecString::builder("kv-window-column")
+                    .nick("KV Window Column")
+                    .default_value(DEFAULT_KV_WINDOW_COLUMN)
+                    .blurb("Column identifying the window of every row. kv-format=summary includes the rows with the max value of this column, or every row if the column is missing")
+                    .build(),
+                glib::ParamSpecBoolean::builder("create-kv-bucket")
+                    .nick("Create KV Bucket")
+                    .blurb("Create kv-bucket if the bucket does not exist")
+                    .default_value(DEFAULT_CREATE_KV_BUCKET)
+                    .build(),
+                glib::ParamSpecEnum::builder::<NatsOversizePolicy>("oversize-policy", DEFAULT_OVERSIZE_POLICY)
+                    .nick("Oversize Policy")
+                    .blurb("Handling of messages larger than the NATS server's max_payload (1MiB by default)")
+                    .build(),
+                glib::ParamSpecUInt64::builder("batch-linger")
+                    .nick("Batch Linger")
+                    .blurb("Max time to wait for more messages to the same subject, which are published together as one batch of length-prefixed frames. nats_src splits batches into separate buffers. 0 publishes every message separately. In nanoseconds")
+                    .default_value(DEFAULT_BATCH_LINGER)
+                    .build(),
+                glib::ParamSpecUInt64::builder("max-batch-bytes")
+                    .nick("Max Batch Bytes")
+                    .blurb("Publish a batch before batch-linger passed once it reaches this size. Batches never exceed the NATS server's max_payload")
+                    .minimum(1)
+                    .default_value(DEFAULT_MAX_BATCH_BYTES)
+                    .build(),
+                glib::ParamSpecUInt64::builder("published-messages")
+                    .nick("Published Messages")
+                    .blurb("Number of messages published since the element started, counting every message of a batch")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecUInt64::builder("dropped-messages")
+                    .nick("Dropped Messages")
+                    .blurb("Number of messages dropped since the element started, because the queue was full, the connection was closed, or messages were still queued when the element stopped")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecUInt64::builder("queued-messages")
+                    .nick("Queued Messages")
+                    .blurb("Number of messages waiting to be published")
+                    .read_only()
+                    .build(),
+            ];
+            properties.extend(auth_properties());
+            properties
         });
 
         PROPERTIES.as_ref()
@@ -87,6 +523,102 @@ impl ObjectImpl for NatsSink {
             "nats-subject" => {
                 settings.nats_subject = value.get::<String>().expect("type checked upstream");
             }
+            "ipc-compression" => {
+                settings.ipc_compression = value
+                    .get::<IpcCompression>()
+                    .expect("type checked upstream");
+            }
+            "reconnect-delay" => {
+                settings.reconnect_delay = value.get::<u64>().expect("type checked upstream");
+            }
+            "reconnect-delay-max" => {
+                settings.reconnect_delay_max = value.get::<u64>().expect("type checked upstream");
+            }
+            "max-reconnects" => {
+                settings.max_reconnects = value.get::<i32>().expect("type checked upstream");
+            }
+            "queue-policy" => {
+                settings.queue_policy = value
+                    .get::<NatsQueuePolicy>()
+                    .expect("type checked upstream");
+            }
+            "max-queue-size" => {
+                settings.max_queue_size = value.get::<u32>().expect("type checked upstream");
+            }
+            "max-queue-bytes" => {
+                settings.max_queue_bytes = value.get::<u64>().expect("type checked upstream");
+            }
+            "jetstream" => {
+                settings.jetstream = value.get::<bool>().expect("type checked upstream");
+            }
+            "jetstream-ack-wait" => {
+                settings.jetstream_ack_wait = value.get::<u64>().expect("type checked upstream");
+            }
+            "jetstream-max-retries" => {
+                settings.jetstream_max_retries = value.get::<u32>().expect("type checked upstream");
+            }
+            "msg-id-mode" => {
+                settings.msg_id_mode = value.get::<NatsMsgIdMode>().expect("type checked upstream");
+            }
+            "stream-name" => {
+                settings.stream_name = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "create-stream" => {
+                settings.create_stream = value.get::<bool>().expect("type checked upstream");
+            }
+            "camera-id" => {
+                settings.camera_id = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "device-id" => {
+                settings.device_id = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "split-column" => {
+                settings.split_column = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "extra-headers" => {
+                settings.extra_headers = value
+                    .get::<Option<gst::Structure>>()
+                    .expect("type checked upstream");
+            }
+            "kv-bucket" => {
+                settings.kv_bucket = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "kv-key" => {
+                settings.kv_key = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "kv-format" => {
+                settings.kv_format = value.get::<NatsKvFormat>().expect("type checked upstream");
+            }
+            "kv-window-column" => {
+                settings.kv_window_column = value.get::<String>().expect("type checked upstream");
+            }
+            "create-kv-bucket" => {
+                settings.create_kv_bucket = value.get::<bool>().expect("type checked upstream");
+            }
+            "oversize-policy" => {
+                settings.oversize_policy = value
+                    .get::<NatsOversizePolicy>()
+                    .expect("type checked upstream");
+            }
+            "batch-linger" => {
+                settings.batch_linger = value.get::<u64>().expect("type checked upstream");
+            }
+            "max-batch-bytes" => {
+                settings.max_batch_bytes = value.get::<u64>().expect("type checked upstream");
+            }
+            name if settings.auth.set_property(name, value) => (),
             _ => unimplemented!("nats_sink does not implement property: {}", pspec.name()),
         };
     }
@@ -97,7 +629,37 @@ impl ObjectImpl for NatsSink {
         match pspec.name() {
             "nats-address" => settings.nats_address.to_value(),
             "nats-subject" => settings.nats_subject.to_value(),
-            _ => unimplemented!("nats_sink does not implement property: {}", pspec.name()),
+            "ipc-compression" => settings.ipc_compression.to_value(),
+            "reconnect-delay" => settings.reconnect_delay.to_value(),
+            "reconnect-delay-max" => settings.reconnect_delay_max.to_value(),
+            "max-reconnects" => settings.max_reconnects.to_value(),
+            "queue-policy" => settings.queue_policy.to_value(),
+            "max-queue-size" => settings.max_queue_size.to_value(),
+            "max-queue-bytes" => settings.max_queue_bytes.to_value(),
+            "jetstream" => settings.jetstream.to_value(),
+            "jetstream-ack-wait" => settings.jetstream_ack_wait.to_value(),
+            "jetstream-max-retries" => settings.jetstream_max_retries.to_value(),
+            "msg-id-mode" => settings.msg_id_mode.to_value(),
+            "stream-name" => settings.stream_name.to_value(),
+            "create-stream" => settings.create_stream.to_value(),
+            "camera-id" => settings.camera_id.to_value(),
+            "device-id" => settings.device_id.to_value(),
+            "split-column" => settings.split_column.to_value(),
+            "extra-headers" => settings.extra_headers.to_value(),
+            "kv-bucket" => settings.kv_bucket.to_value(),
+            "kv-key" => settings.kv_key.to_value(),
+            "kv-format" => settings.kv_format.to_value(),
+            "kv-window-column" => settings.kv_window_column.to_value(),
+            "create-kv-bucket" => settings.create_kv_bucket.to_value(),
+            "oversize-policy" => settings.oversize_policy.to_value(),
+            "batch-linger" => settings.batch_linger.to_value(),
+            "max-batch-bytes" => settings.max_batch_bytes.to_value(),
+            "published-messages" => self.stats.published.load(Ordering::Relaxed).to_value(),
+            "dropped-messages" => self.stats.dropped.load(Ordering::Relaxed).to_value(),
+            "queued-messages" => self.stats.queued.load(Ordering::Relaxed).to_value(),
+            name => settings.auth.property(name).unwrap_or_else(|| {
+                unimplemented!("nats_sink does not implement property: {}", name)
+            }),
         }
     }
 }
@@ -119,7 +681,7 @@ impl ElementImpl for NatsSink {
 
     fn pad_templates() -> &'static [gst::PadTemplate] {
         static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
-            let caps = gst::Caps::new_any();
+            let caps = dataframe_sink_caps();
             let sink_pad_template = gst::PadTemplate::new(
                 "sink",
                 gst::PadDirection::Sink,
@@ -136,6 +698,7 @@ impl ElementImpl for NatsSink {
 }
 
 impl BaseSinkImpl for NatsSink {
+    // The connection is established in the background, so the pipeline starts even if the NATS server is unreachable
     fn start(&self) -> Result<(), gst::ErrorMessage> {
         let mut state = self.state.lock().unwrap();
         if let State::Started { .. } = *state {
@@ -143,27 +706,48 @@ impl BaseSinkImpl for NatsSink {
         }
 
         let element = self.instance();
-
-        let settings = self.settings.lock().unwrap();
-
-        let nc = nats::connect(&settings.nats_address).map_err(|err| {
+        let settings = self.settings.lock().unwrap().clone();
+        let subject = SubjectTemplate::parse(&settings.nats_subject).map_err(|err| {
+            gst::error_msg!(
+                gst::ResourceError::Settings,
+                ["Invalid nats-subject: {}", err]
+            )
+        })?;
+        let kv_key = settings
+            .kv_key
+            .as_deref()
+            .map(SubjectTemplate::parse)
+            .transpose()
+            .map_err(|err| {
+                gst::error_msg!(gst::ResourceError::Settings, ["Invalid kv-key: {}", err])
+            })?;
+        let options = settings.connection_options(&subject);
+        // invalid credentials are a configuration error, which reconnecting would not fix
+        options.auth.validate().map_err(|err| {
             gst::error_msg!(
-                gst::ResourceError::Failed,
-                [
-                    "Failed to open NATS server address {} with error: {}",
-                    &settings.nats_address,
-                    err.to_string(),
-                ]
+                gst::ResourceError::Settings,
+                ["Invalid NATS authentication settings: {}", err]
             )
         })?;
         gst::debug!(
             CAT,
             obj: element,
-            "Opened NATS connection {:?}",
-            &settings.nats_address
+            "Connecting to NATS server {:?}",
+            &options.address
         );
+        self.stats.reset();
+        let connection = NatsConnection::start(&element, options, self.stats.clone());
+        let session_id = format!("{:016x}", rand::random::<u64>());
 
-        *state = State::Started { nc };
+        *state = State::Started {
+            connection,
+            session_id,
+            sequence: 0,
+            stream_headers: StreamHeaders::default(),
+            subject,
+            kv_key,
+            caps: None,
+        };
         gst::info!(CAT, obj: element, "Started");
 
         Ok(())
@@ -174,8 +758,8 @@ impl BaseSinkImpl for NatsSink {
 
         let element = self.instance();
 
-        let nc = match *state {
-            State::Started { ref mut nc } => nc,
+        let connection = match *state {
+            State::Started { ref connection, .. } => connection.clone(),
             State::Stopped => {
                 gst::element_error!(element, gst::CoreError::Failed, ["Not started yet"]);
                 return Err(gst::error_msg!(
@@ -184,38 +768,119 @@ impl BaseSinkImpl for NatsSink {
                 ));
             }
         };
-
-        nc.flush().map_err(|err| {
-            let settings = self.settings.lock().unwrap();
-            gst::error_msg!(
-                gst::ResourceError::Failed,
-                [
-                    "Failed to flush NATS connection {} with error: {}",
-                    settings.nats_address,
-                    err.to_string(),
-                ]
-            )
-        })?;
-
         *state = State::Stopped;
+        drop(state);
+
+        let discarded = connection.close();
+        if discarded > 0 {
+            gst::warning!(
+                CAT,
+                obj: element,
+                "Discarded {} queued messages, NATS server was unreachable",
+                discarded
+            );
+        }
         gst::info!(CAT, obj: element, "Stopped");
 
         Ok(())
     }
 
+    fn set_caps(&self, caps: &gst::Caps) -> Result<(), gst::LoggableError> {
+        if let State::Started {
+            ref mut stream_headers,
+            caps: ref mut current_caps,
+            ..
+        } = *self.state.lock().unwrap()
+        {
+            *stream_headers = StreamHeaders::from_caps(caps);
+            *current_caps = Some(caps.clone());
+            gst::debug!(
+                CAT,
+                obj: self.instance(),
+                "Stream headers {:?}",
+                stream_headers
+            );
+        }
+        Ok(())
+    }
+
+    // Release the streaming thread if it is blocked by queue-policy=block
+    fn unlock(&self) -> Result<(), gst::ErrorMessage> {
+        if let State::Started { ref connection, .. } = *self.state.lock().unwrap() {
+            connection.set_flushing(true);
+        }
+        Ok(())
+    }
+
+    fn unlock_stop(&self) -> Result<(), gst::ErrorMessage> {
+        if let State::Started { ref connection, .. } = *self.state.lock().unwrap() {
+            connection.set_flushing(false);
+        }
+        Ok(())
+    }
+
     fn render(&self, buffer: &gst::Buffer) -> Result<gst::FlowSuccess, gst::FlowError> {
         let mut state = self.state.lock().unwrap();
-        let settings = self.settings.lock().unwrap();
+        // settings are cloned, so property changes do not wait for decoding and splitting the buffer
+        let settings = self.settings.lock().unwrap().clone();
 
         let element = self.instance();
 
-        let nc = match *state {
-            State::Started { ref mut nc } => nc,
+        let (connection, msg_id, sequence, stream_headers, subject, kv_key, caps) = match *state {
+            State::Started {
+                ref connection,
+                ref session_id,
+                ref mut sequence,
+                ref stream_headers,
+                ref mut subject,
+                ref mut kv_key,
+                ref caps,
+            } => {
+                // nats-subject and kv-key may be changed while playing
+                if subject.as_str() != settings.nats_subject {
+                    *subject = SubjectTemplate::parse(&settings.nats_subject).map_err(|err| {
+                        gst::element_error!(
+                            element,
+                            gst::ResourceError::Settings,
+                            ["Invalid nats-subject: {}", err]
+                        );
+                        gst::FlowError::Error
+                    })?;
+                    gst::info!(CAT, obj: element, "Publishing to {}", subject.as_str());
+                }
+                if kv_key.as_ref().map(|key| key.as_str()) != settings.kv_key.as_deref() {
+                    *kv_key = settings
+                        .kv_key
+                        .as_deref()
+                        .map(SubjectTemplate::parse)
+                        .transpose()
+                        .map_err(|err| {
+                            gst::element_error!(
+                                element,
+                                gst::ResourceError::Settings,
+                                ["Invalid kv-key: {}", err]
+                            );
+                            gst::FlowError::Error
+                        })?;
+                }
+                let current = *sequence;
+                *sequence += 1;
+                (
+                    connection.clone(),
+                    settings.msg_id(session_id, current, buffer.pts()),
+                    current,
+                    stream_headers.clone(),
+                    subject.clone(),
+                    kv_key.clone(),
+                    caps.clone(),
+                )
+            }
             State::Stopped => {
                 gst::element_error!(element, gst::CoreError::Failed, ["Not started yet"]);
                 return Err(gst::FlowError::Error);
             }
         };
+        drop(state);
 
         gst::trace!(CAT, obj: element, "Rendering {:?}", buffer);
         let map = buffer.map_readable().map_err(|_| {
@@ -223,15 +888,206 @@ impl BaseSinkImpl for NatsSink {
             gst::FlowError::Error
         })?;
 
-        nc.publish(&settings.nats_subject, map.as_slice())
-            .map_err(|_| {
-                gst::element_error!(
-                    element,
-                    gst::CoreError::Failed,
-                    ["Failed to publish NATS message"]
+        let payload = match settings.ipc_compression {
+            IpcCompression::Uncompressed => map.as_slice().to_vec(),
+            compression if is_arrow_streaming_ipc_message(map.as_slice()) => {
+                arrow_streaming_ipc_message_with_compression(map.as_slice(), compression).map_err(
+                    |err| {
+                        gst::element_error!(
+                            element,
+                            gst::CoreError::Failed,
+                            ["Failed to compress arrow ipc message: {:?}", err]
+                        );
+                        gst::FlowError::Error
+                    },
+                )?
+            }
+            _ => map.as_slice().to_vec(),
+        };
+        // latest value of every key written to kv-bucket
+        let mut kv = vec![];
+        let msgs = match settings.split_column {
+            Some(ref column) => {
+                // partitions are re-encoded as arrow streaming ipc messages, so content type is detected from the payload
+                let stream_headers = StreamHeaders {
+                    content_type: None,
+                    ..stream_headers
+                };
+                let (df, metadata) = dataframe_from_bytes(&payload, None).map_err(|err| {
+                    gst::element_error!(
+                        element,
+                        gst::CoreError::Failed,
+                        ["Failed to read dataframe: {:?}", err]
+                    );
+                    gst::FlowError::Error
+                })?;
+                let partitions = partition_dataframe(&df, column).map_err(|err| {
+                    gst::element_error!(
+                        element,
+                        gst::CoreError::Failed,
+                        ["Failed to split dataframe by column {}: {:?}", column, err]
+                    );
+                    gst::FlowError::Error
+                })?;
+                let metadata = merge_metadata([stream_headers.metadata.clone(), metadata]);
+                let camera_id = settings.subject_var("camera-id", caps.as_ref(), &metadata, None);
+                let mut msgs = vec![];
+                for (partition, mut df) in partitions {
+                    let subject = self.render_template(
+                        &settings,
+                        "nats-subject",
+                        &subject,
+                        caps.as_ref(),
+                        &metadata,
+                        Some(&partition),
+                    )?;
+                    let payload = dataframe_to_arrow_streaming_ipc_message(
+                        &mut df,
+                        Some(metadata.clone()),
+                        settings.ipc_compression,
+                    )
+                    .map_err(|err| {
+                        gst::element_error!(
+                            element,
+                            gst::CoreError::Failed,
+                            ["Failed to serialize dataframe partition: {:?}", err]
+                        );
+                        gst::FlowError::Error
+                    })?;
+                    let msg_id = msg_id
+                        .as_ref()
+                        .map(|msg_id| format!("{}-{}", msg_id, subject_token(&partition)));
+                    let headers = settings.headers(
+                        &stream_headers,
+                        msg_id,
+                        sequence,
+                        buffer,
+                        &payload,
+                        camera_id.clone(),
+                    );
+                    let msg = Message {
+                        subject,
+                        payload,
+                        headers,
+                    };
+                    if settings.kv_bucket.is_some() {
+                        let key = match kv_key {
+                            Some(ref kv_key) => self.render_template(
+                                &settings,
+                                "kv-key",
+                                kv_key,
+                                caps.as_ref(),
+                                &metadata,
+                                Some(&partition),
+                            )?,
+                            None => msg.subject.clone(),
+                        };
+                        let value = self.kv_value(&settings, &msg, &df, &metadata)?;
+                        kv.push((sanitize_key(&key), value));
+                    }
+                    msgs.push(msg);
+                }
+                msgs
+            }
+            None => {
+                // schema metadata is only read from the payload if placeholders and camera id are not filled by properties or caps
+                let rendered = subject.render(|name| {
+                    settings.subject_var(name, caps.as_ref(), &stream_headers.metadata, None)
+                });
+                let camera_id = settings.subject_var(
+                    "camera-id",
+                    caps.as_ref(),
+                    &stream_headers.metadata,
+                    None,
                 );
-                gst::FlowError::Error
-            })?;
+                let metadata = match (&rendered, &camera_id) {
+                    (Ok(_), Some(_)) => stream_headers.metadata.clone(),
+                    _ => merge_metadata([
+                        stream_headers.metadata.clone(),
+                        metadata_from_bytes(&payload),
+                    ]),
+                };
+                let subject = match rendered {
+                    Ok(subject) => subject,
+                    Err(_) => self.render_template(
+                        &settings,
+                        "nats-subject",
+                        &subject,
+                        caps.as_ref(),
+                        &metadata,
+                        None,
+                    )?,
+                };
+                let camera_id = camera_id
+                    .or_else(|| settings.subject_var("camera-id", caps.as_ref(), &metadata, None));
+                let headers = settings.headers(
+                    &stream_headers,
+                    msg_id,
+                    sequence,
+                    buffer,
+                    &payload,
+                    camera_id,
+                );
+                let msg = Message {
+                    subject,
+                    payload,
+                    headers,
+                };
+                if settings.kv_bucket.is_some() {
+                    let (key, value) = match (&kv_key, settings.kv_format) {
+                        (None, NatsKvFormat::Payload) => (msg.subject.clone(), msg.payload.clone()),
+                        // kv-key placeholders and summaries need the dataframe and its schema metadata
+                        (kv_key, _) => {
+                            let (df, metadata) =
+                                dataframe_from_bytes(&msg.payload, None).map_err(|err| {
+                                    gst::element_error!(
+                                        element,
+                                        gst::CoreError::Failed,
+                                        ["Failed to read dataframe: {:?}", err]
+                                    );
+                                    gst::FlowError::Error
+                                })?;
+                            let key = match kv_key {
+                                Some(kv_key) => self.render_template(
+                                    &settings,
+                                    "kv-key",
+                                    kv_key,
+                                    caps.as_ref(),
+                                    &metadata,
+                                    None,
+                                )?,
+                                None => msg.subject.clone(),
+                            };
+                            (key, self.kv_value(&settings, &msg, &df, &metadata)?)
+                        }
+                    };
+                    kv.push((sanitize_key(&key), value));
+                }
+                vec![msg]
+            }
+        };
+
+        // messages are published by the connection's publisher thread, publish errors are not fatal
+        for msg in msgs {
+            match connection.publish(msg)? {
+                PublishResult::Queued => {
+                    gst::trace!(CAT, obj: element, "Queued message, NATS connection is {}", connection.state().nick())
+                }
+                PublishResult::Dropped(dropped) => {
+                    gst::warning!(
+                        CAT,
+                        obj: element,
+                        "Dropped {} messages, NATS connection is {} and queue is full",
+                        dropped,
+                        connection.state().nick()
+                    )
+                }
+            }
+        }
+        // written after the messages were queued
+        for (key, value) in kv {
+            connection.put_kv(key, value);
+        }
 
         Ok(gst::FlowSuccess::Ok)
     }
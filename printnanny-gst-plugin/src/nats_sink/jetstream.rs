@@ -0,0 +1,179 @@
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use nats::jetstream::{JetStream, PublishOptions, StreamConfig};
+
+use super::connection::Message;
+use super::imp::CAT;
+use crate::error::PublishError;
+use crate::nats_common::headers::header_map;
+use crate::nats_common::{reconnect_delay, subject_matches};
+
+#[derive(Debug, Clone)]
+pub struct JetStreamOptions {
+    pub ack_wait: Duration,
+    pub max_retries: u32, // publish attempts after the first failed attempt
+    pub retry_delay: Duration,
+    pub retry_delay_max: Duration,
+    pub stream: Option<String>, // stream expected to capture subject
+    pub subject: String,
+    pub create_stream: bool,
+}
+
+// Verify the configured stream exists and captures subject, creating it if create_stream is set
+pub fn ensure_stream(js: &JetStream, options: &JetStreamOptions) -> io::Result<()> {
+    let subject = options.subject.as_str();
+    let stream = match &options.stream {
+        Some(stream) => stream,
+        None => return Ok(()),
+    };
+    let info = match js.stream_info(stream) {
+        Ok(info) => info,
+        Err(_) if options.create_stream => {
+            gst::info!(
+                CAT,
+                "Creating JetStream stream {} with subject {}",
+                stream,
+                subject
+            );
+            js.add_stream(StreamConfig {
+                name: stream.clone(),
+                subjects: vec![subject.to_string()],
+                ..StreamConfig::default()
+            })?
+        }
+        Err(err) => return Err(err),
+    };
+    if !info
+        .config
+        .subjects
+        .iter()
+        .any(|pattern| subject_matches(pattern, subject))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "JetStream stream {} subjects {:?} do not match subject {}",
+                stream, info.config.subjects, subject
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Publish and wait for the stream's acknowledgement, retrying with backoff on failure
+// Retried messages carry the same Nats-Msg-Id, so the server discards duplicates of messages which were stored but not acknowledged
+pub fn publish(
+    js: &JetStream,
+    options: &JetStreamOptions,
+    msg: &Message,
+) -> Result<(), PublishError> {
+    // Nats-Msg-Id is sent with the other message headers
+    let publish_options = PublishOptions {
+        timeout: Some(options.ack_wait),
+        ..PublishOptions::default()
+    };
+    let message = nats::Message::new(
+        &msg.subject,
+        None,
+        &msg.payload,
+        Some(header_map(&msg.headers)),
+    );
+    let ack = with_retries(options, || {
+        js.publish_message_with_options(&message, &publish_options)
+    })?;
+    if ack.duplicate {
+        gst::debug!(
+            CAT,
+            "JetStream discarded duplicate message {:?} in stream {}",
+            msg.msg_id(),
+            ack.stream
+        );
+    }
+    Ok(())
+}
+
+// Call attempt until it succeeds, at most max_retries times after the first failure
+// The message is rejected with PublishError::Unacknowledged once retries are exhausted, so it does not block the queue
+fn with_retries<T>(
+    options: &JetStreamOptions,
+    mut attempt_fn: impl FnMut() -> io::Result<T>,
+) -> Result<T, PublishError> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match attempt_fn() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt <= options.max_retries => {
+                let delay = reconnect_delay(
+                    attempt as usize,
+                    options.retry_delay,
+                    options.retry_delay_max,
+                );
+                gst::warning!(
+                    CAT,
+                    "JetStream publish attempt {} failed, retrying in {:?}: {}",
+                    attempt,
+                    delay,
+                    err
+                );
+                thread::sleep(delay);
+            }
+            Err(source) => {
+                return Err(PublishError::Unacknowledged {
+                    attempts: attempt,
+                    source,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(max_retries: u32) -> JetStreamOptions {
+        JetStreamOptions {
+            ack_wait: Duration::from_millis(10),
+            max_retries,
+            retry_delay: Duration::from_millis(1),
+            retry_delay_max: Duration::from_millis(1),
+            stream: None,
+            subject: "pi.test".into(),
+            create_stream: false,
+        }
+    }
+
+    #[test]
+    fn test_with_retries() {
+        let mut calls = 0;
+        let result = with_retries(&options(2), || {
+            calls += 1;
+            match calls {
+                3 => Ok(calls),
+                _ => Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_with_retries_exhausted() {
+        gst::init().unwrap();
+        let mut calls = 0;
+        let result: Result<(), PublishError> = with_retries(&options(2), || {
+            calls += 1;
+            Err(io::Error::new(io::ErrorKind::NotFound, "no responders"))
+        });
+        assert_eq!(calls, 3);
+        match result {
+            Err(PublishError::Unacknowledged { attempts, source }) => {
+                assert_eq!(attempts, 3);
+                assert_eq!(source.kind(), io::ErrorKind::NotFound);
+            }
+            other => panic!("expected Unacknowledged, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use nats::jetstream::JetStream;
+use nats::kv::{Config, Store};
+use polars::prelude::*;
+
+use super::imp::CAT;
+use crate::error::SerializationError;
+use crate::ipc::dataframe_to_json_bytearray;
+use crate::nats_common::headers::{Headers, CONTENT_TYPE_HEADER};
+
+#[derive(Debug, Clone)]
+pub struct KvOptions {
+    pub bucket: String,
+    pub create_bucket: bool,
+}
+
+// Open the bucket, creating it with a history of one value per key if create_bucket is set
+pub fn open_bucket(js: &JetStream, options: &KvOptions) -> io::Result<Store> {
+    match js.key_value(&options.bucket) {
+        Ok(store) => Ok(store),
+        Err(_) if options.create_bucket => {
+            gst::info!(CAT, "Creating key-value bucket {}", options.bucket);
+            js.create_key_value(&Config {
+                bucket: options.bucket.clone(),
+                history: 1,
+                ..Config::default()
+            })
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// Keys may contain letters, digits and -/_=. characters, other characters are replaced by _
+pub fn sanitize_key(key: &str) -> String {
+    key.chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c,
+            '-' | '/' | '_' | '=' | '.' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+// JSON object with the message headers, schema metadata and the rows of the latest window
+// The latest window holds the rows with the max value of window_column, or every row if the dataframe has no such column
+pub fn latest_window_summary(
+    df: &DataFrame,
+    window_column: &str,
+    headers: &Headers,
+    metadata: &BTreeMap<String, String>,
+) -> Result<Vec<u8>, SerializationError> {
+    let mut latest = match df.column(window_column) {
+        Ok(_) => df
+            .clone()
+            .lazy()
+            .filter(col(window_column).eq(col(window_column).max()))
+            .collect()?,
+        Err(_) => df.clone(),
+    };
+    let rows: serde_json::Value = match latest.height() {
+        0 => serde_json::Value::Array(vec![]),
+        _ => serde_json::from_slice(&dataframe_to_json_bytearray(&mut latest)?)?,
+    };
+    // the content type describes the published payload, not the summary
+    let headers: BTreeMap<&str, &str> = headers
+        .iter()
+        .filter(|(name, _)| name != CONTENT_TYPE_HEADER)
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+    let summary = serde_json::json!({
+        "headers": headers,
+        "metadata": metadata,
+        "rows": rows,
+    });
+    Ok(serde_json::to_vec(&summary)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nats_common::headers::PTS_HEADER;
+
+    #[test]
+    fn test_sanitize_key() {
+        assert_eq!(sanitize_key("pi.qc.df"), "pi.qc.df");
+        assert_eq!(
+            sanitize_key("latest/camera 0:spaghetti"),
+            "latest/camera_0_spaghetti"
+        );
+    }
+
+    #[test]
+    fn test_latest_window_summary() {
+        let df = df!(
+            "rt" => [1_i64, 2, 2],
+            "detection_classes" => [0, 0, 1],
+            "detection_scores__mean" => [0.1, 0.2, 0.3]
+        )
+        .unwrap();
+        let headers = vec![
+            (
+                CONTENT_TYPE_HEADER.to_string(),
+                "application/vnd.apache.arrow.stream".to_string(),
+            ),
+            (PTS_HEADER.to_string(), "2".to_string()),
+        ];
+        let metadata = BTreeMap::from([("printnanny:camera_id".to_string(), "0".to_string())]);
+
+        let summary = latest_window_summary(&df, "rt", &headers, &metadata).unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&summary).unwrap();
+        assert_eq!(
+            summary["headers"],
+            serde_json::json!({ "PrintNanny-Pts": "2" })
+        );
+        assert_eq!(summary["metadata"]["printnanny:camera_id"], "0");
+        let rows = summary["rows"].as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|row| row["rt"] == 2));
+        assert_eq!(rows[1]["detection_classes"], 1);
+
+        let summary = latest_window_summary(&df, "missing", &headers, &metadata).unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&summary).unwrap();
+        assert_eq!(summary["rows"].as_array().unwrap().len(), 3);
+    }
+}
@@ -1,7 +1,83 @@
 use gst::glib;
 use gst::prelude::*;
 
+mod connection;
 mod imp;
+mod jetstream;
+mod kv;
+mod subject;
+
+// Controls what nats_sink does with new messages when the queue of messages waiting to be published is full, for example while the NATS server is unreachable
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstNatsSinkQueuePolicy")]
+pub enum NatsQueuePolicy {
+    #[enum_value(
+        name = "Drop Oldest: discard the oldest queued message when the queue is full",
+        nick = "drop-oldest"
+    )]
+    DropOldest = 0,
+    #[enum_value(
+        name = "Drop Newest: discard new messages when the queue is full",
+        nick = "drop-newest"
+    )]
+    DropNewest = 1,
+    #[enum_value(
+        name = "Block: block the streaming thread until the queue has room",
+        nick = "block"
+    )]
+    Block = 2,
+}
+
+// Controls how nats_sink derives the Nats-Msg-Id header used by JetStream to discard duplicate messages
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstNatsSinkMsgIdMode")]
+pub enum NatsMsgIdMode {
+    #[enum_value(
+        name = "Sequence: number of buffers rendered since the element started",
+        nick = "sequence"
+    )]
+    Sequence = 0,
+    #[enum_value(
+        name = "PTS: buffer presentation timestamp, falling back to sequence for buffers without a timestamp",
+        nick = "pts"
+    )]
+    Pts = 1,
+    #[enum_value(name = "None: do not set Nats-Msg-Id", nick = "none")]
+    None = 2,
+}
+
+// Controls what nats_sink does with messages larger than the NATS server's max_payload
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstNatsSinkOversizePolicy")]
+pub enum NatsOversizePolicy {
+    #[enum_value(
+        name = "Error: fail with an error naming the message size and the server's max_payload",
+        nick = "error"
+    )]
+    Error = 0,
+    #[enum_value(
+        name = "Chunk: split messages into chunks with PrintNanny-Chunk-* headers, which nats_src reassembles",
+        nick = "chunk"
+    )]
+    Chunk = 1,
+}
+
+// Controls the value nats_sink writes to kv-bucket
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstNatsSinkKvFormat")]
+pub enum NatsKvFormat {
+    #[enum_value(name = "Payload: the published message payload", nick = "payload")]
+    Payload = 0,
+    #[enum_value(
+        name = "Summary: JSON object with message headers, schema metadata and the rows of the latest window",
+        nick = "summary"
+    )]
+    Summary = 1,
+}
 
 glib::wrapper! {
     pub struct NatsSink(ObjectSubclass<imp::NatsSink>) @extends gst_base::BaseSink, gst::Element, gst::Object;
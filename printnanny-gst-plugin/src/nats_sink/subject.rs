@@ -0,0 +1,177 @@
+use polars::prelude::*;
+
+use crate::error::NatsConfigError;
+
+// Value of split-column in every row of a partition, used for rows where split-column is null
+pub const NULL_PARTITION: &str = "null";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder(String),
+}
+
+// NATS subject with {name} placeholders, for example pi.{device}.{camera}.qc.{label}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubjectTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl SubjectTemplate {
+    pub fn parse(template: &str) -> Result<Self, NatsConfigError> {
+        let invalid = |reason: &str| {
+            NatsConfigError::InvalidSubjectTemplate(format!("{:?} {}", template, reason))
+        };
+        let mut segments = vec![];
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err(invalid("has unmatched }"));
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| invalid("has unmatched {"))?;
+            let name = &rest[start + 1..end];
+            if name.is_empty() || name.contains('{') {
+                return Err(invalid("has an empty or nested placeholder"));
+            }
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            segments.push(Segment::Placeholder(name.to_string()));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+        if template.is_empty() || template.split('.').any(|token| token.is_empty()) {
+            return Err(invalid("has an empty token"));
+        }
+        Ok(SubjectTemplate {
+            template: template.to_string(),
+            segments,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    // Fill placeholders with values returned by lookup, or return the name of the first placeholder without a value
+    pub fn render<F>(&self, lookup: F) -> Result<String, String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut subject = String::new();
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Literal(literal) => subject.push_str(literal),
+                Segment::Placeholder(name) => match lookup(name) {
+                    Some(value) => subject.push_str(&subject_token(&value)),
+                    None => return Err(name.clone()),
+                },
+            }
+        }
+        Ok(subject)
+    }
+
+    // Subject with every token containing a placeholder replaced by *, matching all rendered subjects
+    pub fn wildcard(&self) -> String {
+        self.render(|_| Some("{}".into()))
+            .unwrap()
+            .split('.')
+            .map(|token| if token.contains("{}") { "*" } else { token })
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+// Replace characters which are not allowed in a subject token: separators, wildcards and whitespace
+pub fn subject_token(value: &str) -> String {
+    let token: String = value
+        .chars()
+        .map(|c| match c {
+            '.' | '*' | '>' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect();
+    if token.is_empty() {
+        "_".into()
+    } else {
+        token
+    }
+}
+
+// Split dataframe into partitions with equal values of column, in order of first appearance
+pub fn partition_dataframe(
+    df: &DataFrame,
+    column: &str,
+) -> Result<Vec<(String, DataFrame)>, PolarsError> {
+    let values = df.column(column)?.cast(&DataType::Utf8)?;
+    let values = values.utf8()?;
+    let mut partitions: Vec<String> = vec![];
+    for value in values.into_iter() {
+        let value = value.unwrap_or(NULL_PARTITION);
+        if !partitions.iter().any(|partition| partition == value) {
+            partitions.push(value.to_string());
+        }
+    }
+    partitions
+        .into_iter()
+        .map(|partition| {
+            let mask: BooleanChunked = values
+                .into_iter()
+                .map(|value| Some(value.unwrap_or(NULL_PARTITION) == partition))
+                .collect();
+            let df = df.filter(&mask)?;
+            Ok((partition, df))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subject_template() {
+        let template = SubjectTemplate::parse("pi.{device}.{camera}.qc.{label}").unwrap();
+        assert_eq!(template.wildcard(), "pi.*.*.qc.*");
+        let subject = template.render(|name| match name {
+            "device" => Some("octoprint.local".into()),
+            "camera" => Some("camera 0".into()),
+            "label" => Some("spaghetti".into()),
+            _ => None,
+        });
+        assert_eq!(subject.unwrap(), "pi.octoprint_local.camera_0.qc.spaghetti");
+        assert_eq!(template.render(|_| None), Err("device".to_string()));
+
+        let fixed = SubjectTemplate::parse("pi.qc.df").unwrap();
+        assert_eq!(fixed.wildcard(), "pi.qc.df");
+        assert_eq!(fixed.render(|_| None).unwrap(), "pi.qc.df");
+
+        assert!(SubjectTemplate::parse("pi.{device").is_err());
+        assert!(SubjectTemplate::parse("pi.device}").is_err());
+        assert!(SubjectTemplate::parse("pi.{}").is_err());
+        assert!(SubjectTemplate::parse("pi..qc").is_err());
+    }
+
+    #[test]
+    fn test_partition_dataframe() {
+        let df = df!(
+            "detection_classes" => [Some(2), Some(0), Some(2), None],
+            "detection_scores" => [0.9, 0.8, 0.7, 0.6]
+        )
+        .unwrap();
+        let partitions = partition_dataframe(&df, "detection_classes").unwrap();
+        let names: Vec<&str> = partitions.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["2", "0", NULL_PARTITION]);
+        assert_eq!(partitions[0].1.height(), 2);
+        assert_eq!(partitions[1].1.height(), 1);
+        assert_eq!(partitions[2].1.height(), 1);
+        assert!(partition_dataframe(&df, "missing").is_err());
+    }
+}
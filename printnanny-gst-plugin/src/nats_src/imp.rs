@@ -0,0 +1,528 @@
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst_base::prelude::*;
+use gst_base::subclass::base_src::CreateSuccess;
+use gst_base::subclass::prelude::*;
+use once_cell::sync::Lazy;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::subscriber::{ConsumerOptions, Subscriber, SubscriberOptions};
+use super::{NatsDeliverPolicy, NatsTimestampMode};
+use crate::caps::{
+    caps_with_metadata, dataframe_sink_caps, LEGACY_MEDIA_TYPE, SCHEMA_FINGERPRINT_FIELD,
+};
+use crate::nats_common::auth::{auth_properties, NatsAuth};
+use crate::nats_common::framing::{unbatch, Reassembler};
+use crate::nats_common::headers::{
+    caps_from_content_type, clock_time_header, header, metadata_from_header, CONTENT_TYPE_HEADER,
+    DTS_HEADER, DURATION_HEADER, PTS_HEADER, SCHEMA_FINGERPRINT_HEADER,
+};
+use crate::nats_common::NatsMessage;
+
+const DEFAULT_NATS_ADDRESS: &str = "127.0.0.1:4222";
+const DEFAULT_NATS_SUBJECT: &str = "pi.qc.df";
+const DEFAULT_JETSTREAM: bool = false;
+const DEFAULT_DELIVER_POLICY: NatsDeliverPolicy = NatsDeliverPolicy::All;
+const DEFAULT_TIMESTAMP_MODE: NatsTimestampMode = NatsTimestampMode::Headers;
+// create() waits for messages in intervals of this length, checking if the element is flushing in between
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+struct Settings {
+    nats_address: String, // inproc://name subscribes to messages published with nats_common::inproc::publish
+    nats_subject: String,
+    queue_group: Option<String>,
+    jetstream: bool,
+    stream_name: Option<String>,
+    durable_name: Option<String>,
+    deliver_policy: NatsDeliverPolicy,
+    caps: Option<gst::Caps>, // None derives caps from Content-Type and PrintNanny-Schema-Fingerprint headers
+    timestamp_mode: NatsTimestampMode,
+    auth: NatsAuth,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            nats_address: DEFAULT_NATS_ADDRESS.into(),
+            nats_subject: DEFAULT_NATS_SUBJECT.into(),
+            queue_group: None,
+            jetstream: DEFAULT_JETSTREAM,
+            stream_name: None,
+            durable_name: None,
+            deliver_policy: DEFAULT_DELIVER_POLICY,
+            caps: None,
+            timestamp_mode: DEFAULT_TIMESTAMP_MODE,
+            auth: NatsAuth::default(),
+        }
+    }
+}
+
+impl Settings {
+    fn subscriber_options(&self) -> SubscriberOptions {
+        SubscriberOptions {
+            address: self.nats_address.clone(),
+            auth: self.auth.clone(),
+            subject: self.nats_subject.clone(),
+            queue_group: self.queue_group.clone(),
+            jetstream: match self.jetstream {
+                true => Some(ConsumerOptions {
+                    stream: self.stream_name.clone(),
+                    durable_name: self.durable_name.clone(),
+                    deliver_policy: self.deliver_policy,
+                }),
+                false => None,
+            },
+        }
+    }
+}
+
+enum State {
+    Stopped,
+    Started {
+        subscriber: Subscriber,
+        caps: Option<gst::Caps>,
+        // header timestamp of the first timestamped message, and the running time it was received
+        timestamp_offset: Option<(gst::ClockTime, gst::ClockTime)>,
+        reassembler: Reassembler,
+        batched: VecDeque<NatsMessage>, // messages of a received batch which were not pushed yet
+    },
+}
+
+impl Default for State {
+    fn default() -> State {
+        State::Stopped
+    }
+}
+
+#[derive(Default)]
+pub struct NatsSrc {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    flushing: AtomicBool,
+}
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "nats_src",
+        gst::DebugColorFlags::empty(),
+        Some("NATS Source"),
+    )
+});
+
+// Caps described by message headers, application/octet-stream if the message has no Content-Type header
+// Content-Type parameters are caps fields (see content_type_from_caps), and schema metadata of json messages is restored from the PrintNanny-Metadata header
+// None if Content-Type is not a dataframe format accepted by dataframe sinks
+fn caps_from_headers(msg: &NatsMessage) -> Option<gst::Caps> {
+    let mut caps = match header(&msg.headers, CONTENT_TYPE_HEADER) {
+        Some(content_type) => caps_from_content_type(content_type)?,
+        None => gst::Caps::new_empty_simple(LEGACY_MEDIA_TYPE),
+    };
+    if !caps.can_intersect(&dataframe_sink_caps()) {
+        return None;
+    }
+    if let Some(fingerprint) = header(&msg.headers, SCHEMA_FINGERPRINT_HEADER) {
+        caps.make_mut().set(SCHEMA_FINGERPRINT_FIELD, fingerprint);
+    }
+    Some(caps_with_metadata(
+        caps,
+        &metadata_from_header(&msg.headers),
+    ))
+}
+
+impl NatsSrc {
+    fn running_time(&self) -> gst::ClockTime {
+        self.instance()
+            .current_running_time()
+            .unwrap_or(gst::ClockTime::ZERO)
+    }
+
+    fn timestamp(
+        &self,
+        buffer: &mut gst::BufferRef,
+        msg: &NatsMessage,
+        mode: NatsTimestampMode,
+        timestamp_offset: &mut Option<(gst::ClockTime, gst::ClockTime)>,
+    ) {
+        buffer.set_duration(clock_time_header(&msg.headers, DURATION_HEADER));
+        match mode {
+            NatsTimestampMode::Arrival => buffer.set_pts(self.running_time()),
+            NatsTimestampMode::Headers => {
+                let pts = clock_time_header(&msg.headers, PTS_HEADER);
+                let dts = clock_time_header(&msg.headers, DTS_HEADER);
+                if let Some(first) = pts.or(dts) {
+                    let (first, base) =
+                        *timestamp_offset.get_or_insert_with(|| (first, self.running_time()));
+                    let rebase = |t: gst::ClockTime| base + t.saturating_sub(first);
+                    buffer.set_pts(pts.map(rebase));
+                    buffer.set_dts(dts.map(rebase));
+                }
+            }
+        }
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for NatsSrc {
+    const NAME: &'static str = "NatsSrc";
+    type Type = super::NatsSrc;
+    type ParentType = gst_base::PushSrc;
+}
+
+impl ObjectImpl for NatsSrc {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            let mut properties = vec![
+                glib::ParamSpecString::builder("nats-address")
+                    .nick("NATS Address")
+                    .default_value(DEFAULT_NATS_ADDRESS)
+                    .blurb("NATS server address. inproc://name receives messages published within this process, used for testing")
+                    .build(),
+                glib::ParamSpecString::builder("nats-subject")
+                    .nick("NATS Subject")
+                    .default_value(DEFAULT_NATS_SUBJECT)
+                    .blurb("NATS subject, may contain * and > wildcards")
+                    .build(),
+                glib::ParamSpecString::builder("queue-group")
+                    .nick("Queue Group")
+                    .blurb("Share messages with other subscribers in this queue group, every message is received by one member")
+                    .build(),
+                glib::ParamSpecBoolean::builder("jetstream")
+                    .nick("JetStream")
+                    .blurb("Receive messages from a JetStream consumer, acknowledging every message")
+                    .default_value(DEFAULT_JETSTREAM)
+                    .build(),
+                glib::ParamSpecString::builder("stream-name")
+                    .nick("Stream Name")
+                    .blurb("JetStream stream to consume, by default the stream capturing nats-subject")
+                    .build(),
+                glib::ParamSpecString::builder("durable-name")
+                    .nick("Durable Name")
+                    .blurb("Name of a durable JetStream consumer, which resumes after the last acknowledged message when restarted")
+                    .build(),
+                glib::ParamSpecEnum::builder::<NatsDeliverPolicy>("deliver-policy", DEFAULT_DELIVER_POLICY)
+                    .nick("Deliver Policy")
+                    .blurb("Messages delivered by a new JetStream consumer")
+                    .build(),
+                glib::ParamSpecBoxed::builder::<gst::Caps>("caps")
+                    .nick("Caps")
+                    .blurb("Caps of output buffers. By default caps are derived from the Content-Type and PrintNanny-Schema-Fingerprint headers of every message, and messages which are not a dataframe format are skipped")
+                    .build(),
+                glib::ParamSpecEnum::builder::<NatsTimestampMode>("timestamp-mode", DEFAULT_TIMESTAMP_MODE)
+                    .nick("Timestamp Mode")
+                    .blurb("Timestamp buffers with the PrintNanny-Pts header, or the time messages were received")
+                    .build(),
+            ];
+            properties.extend(auth_properties());
+            properties
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn constructed(&self) {
+        self.parent_constructed();
+        let obj = self.instance();
+        obj.set_live(true);
+        obj.set_format(gst::Format::Time);
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+
+        match pspec.name() {
+            "nats-address" => {
+                settings.nats_address = value.get::<String>().expect("type checked upstream");
+            }
+            "nats-subject" => {
+                settings.nats_subject = value.get::<String>().expect("type checked upstream");
+            }
+            "queue-group" => {
+                settings.queue_group = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "jetstream" => {
+                settings.jetstream = value.get::<bool>().expect("type checked upstream");
+            }
+            "stream-name" => {
+                settings.stream_name = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "durable-name" => {
+                settings.durable_name = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "deliver-policy" => {
+                settings.deliver_policy = value
+                    .get::<NatsDeliverPolicy>()
+                    .expect("type checked upstream");
+            }
+            "caps" => {
+                settings.caps = value
+                    .get::<Option<gst::Caps>>()
+                    .expect("type checked upstream");
+            }
+            "timestamp-mode" => {
+                settings.timestamp_mode = value
+                    .get::<NatsTimestampMode>()
+                    .expect("type checked upstream");
+            }
+            name if settings.auth.set_property(name, value) => (),
+            _ => unimplemented!("nats_src does not implement property: {}", pspec.name()),
+        };
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+
+        match pspec.name() {
+            "nats-address" => settings.nats_address.to_value(),
+            "nats-subject" => settings.nats_subject.to_value(),
+            "queue-group" => settings.queue_group.to_value(),
+            "jetstream" => settings.jetstream.to_value(),
+            "stream-name" => settings.stream_name.to_value(),
+            "durable-name" => settings.durable_name.to_value(),
+            "deliver-policy" => settings.deliver_policy.to_value(),
+            "caps" => settings.caps.to_value(),
+            "timestamp-mode" => settings.timestamp_mode.to_value(),
+            name => settings.auth.property(name).unwrap_or_else(|| {
+                unimplemented!("nats_src does not implement property: {}", name)
+            }),
+        }
+    }
+}
+
+impl GstObjectImpl for NatsSrc {}
+
+impl ElementImpl for NatsSrc {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "NATS Source",
+                "Source/NATS",
+                "Read stream from a NATS subject or JetStream consumer",
+                "Leigh Johnson <leigh@printnanny.ai>",
+            )
+        });
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = dataframe_sink_caps();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl BaseSrcImpl for NatsSrc {
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        let mut state = self.state.lock().unwrap();
+        if let State::Started { .. } = *state {
+            unreachable!("NatsSrc already started");
+        }
+
+        let element = self.instance();
+        let options = self.settings.lock().unwrap().subscriber_options();
+        options.auth.validate().map_err(|err| {
+            gst::error_msg!(
+                gst::ResourceError::Settings,
+                ["Invalid NATS authentication settings: {}", err]
+            )
+        })?;
+        gst::debug!(
+            CAT,
+            obj: element,
+            "Subscribing to {} on NATS server {:?}",
+            &options.subject,
+            &options.address
+        );
+        let subscriber = Subscriber::subscribe(&options).map_err(|err| {
+            gst::error_msg!(
+                gst::ResourceError::OpenRead,
+                [
+                    "Failed to subscribe to {} on NATS server {}: {}",
+                    &options.subject,
+                    &options.address,
+                    err
+                ]
+            )
+        })?;
+
+        *state = State::Started {
+            subscriber,
+            caps: None,
+            timestamp_offset: None,
+            reassembler: Reassembler::default(),
+            batched: VecDeque::new(),
+        };
+        gst::info!(CAT, obj: element, "Started");
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        let mut state = self.state.lock().unwrap();
+        if let State::Started { subscriber, .. } = std::mem::take(&mut *state) {
+            subscriber.close();
+        }
+        gst::info!(CAT, obj: self.instance(), "Stopped");
+        Ok(())
+    }
+
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    // Without a caps property, caps are set from the headers of every message in create()
+    fn negotiate(&self) -> Result<(), gst::LoggableError> {
+        match self.settings.lock().unwrap().caps {
+            Some(_) => self.parent_negotiate(),
+            None => Ok(()),
+        }
+    }
+
+    fn caps(&self, filter: Option<&gst::Caps>) -> Option<gst::Caps> {
+        let caps = self
+            .settings
+            .lock()
+            .unwrap()
+            .caps
+            .clone()
+            .unwrap_or_else(dataframe_sink_caps);
+        match filter {
+            Some(filter) => Some(filter.intersect_with_mode(&caps, gst::CapsIntersectMode::First)),
+            None => Some(caps),
+        }
+    }
+
+    fn unlock(&self) -> Result<(), gst::ErrorMessage> {
+        self.flushing.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn unlock_stop(&self) -> Result<(), gst::ErrorMessage> {
+        self.flushing.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl PushSrcImpl for NatsSrc {
+    fn create(
+        &self,
+        _buffer: Option<&mut gst::BufferRef>,
+    ) -> Result<CreateSuccess, gst::FlowError> {
+        let element = self.instance();
+        let settings = self.settings.lock().unwrap().clone();
+        let mut state = self.state.lock().unwrap();
+        let (subscriber, current_caps, timestamp_offset, reassembler, batched) = match *state {
+            State::Started {
+                ref subscriber,
+                ref mut caps,
+                ref mut timestamp_offset,
+                ref mut reassembler,
+                ref mut batched,
+            } => (subscriber, caps, timestamp_offset, reassembler, batched),
+            State::Stopped => {
+                gst::element_error!(element, gst::CoreError::Failed, ["Not started yet"]);
+                return Err(gst::FlowError::Error);
+            }
+        };
+
+        let (mut msg, caps) = loop {
+            if let Some(msg) = batched.pop_front() {
+                let caps = match &settings.caps {
+                    Some(caps) => Some(caps.clone()),
+                    None => caps_from_headers(&msg),
+                };
+                // messages published by other clients to the same subject may carry any Content-Type
+                match caps {
+                    Some(caps) => break (msg, caps),
+                    None => {
+                        gst::warning!(
+                            CAT,
+                            obj: element,
+                            "Skipping message on {} with unsupported Content-Type {:?}",
+                            msg.subject,
+                            header(&msg.headers, CONTENT_TYPE_HEADER)
+                        );
+                        continue;
+                    }
+                }
+            }
+            if self.flushing.load(Ordering::SeqCst) {
+                gst::debug!(CAT, obj: element, "Flushing");
+                return Err(gst::FlowError::Flushing);
+            }
+            let msg = match subscriber.next_timeout(POLL_INTERVAL) {
+                Ok(Some(msg)) => msg,
+                Ok(None) => continue,
+                Err(err) => {
+                    gst::element_error!(
+                        element,
+                        gst::ResourceError::Read,
+                        ["Failed to receive NATS message: {}", err]
+                    );
+                    return Err(gst::FlowError::Error);
+                }
+            };
+            // chunked messages are pushed once every chunk was received, batches are pushed as separate buffers
+            // malformed messages are skipped, they do not fail the pipeline
+            match reassembler
+                .push(msg)
+                .and_then(|msg| msg.map(unbatch).transpose())
+            {
+                Ok(Some(msgs)) => batched.extend(msgs),
+                Ok(None) => gst::trace!(CAT, obj: element, "Received chunk"),
+                Err(err) => gst::warning!(CAT, obj: element, "Skipping message: {}", err),
+            }
+        };
+        gst::trace!(
+            CAT,
+            obj: element,
+            "Received {} bytes on {}",
+            msg.payload.len(),
+            msg.subject
+        );
+
+        if current_caps.as_ref() != Some(&caps) {
+            gst::debug!(CAT, obj: element, "Setting caps {:?}", caps);
+            element.set_caps(&caps).map_err(|_| {
+                gst::element_error!(
+                    element,
+                    gst::CoreError::Negotiation,
+                    ["Failed to negotiate caps {:?}", caps]
+                );
+                gst::FlowError::NotNegotiated
+            })?;
+            *current_caps = Some(caps);
+        }
+
+        let mut buffer = gst::Buffer::from_mut_slice(std::mem::take(&mut msg.payload));
+        self.timestamp(
+            buffer.get_mut().unwrap(),
+            &msg,
+            settings.timestamp_mode,
+            timestamp_offset,
+        );
+
+        Ok(CreateSuccess::NewBuffer(buffer))
+    }
+}
@@ -0,0 +1,54 @@
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+mod subscriber;
+
+// Controls how nats_src timestamps buffers
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstNatsSrcTimestampMode")]
+pub enum NatsTimestampMode {
+    #[enum_value(
+        name = "Headers: PTS, DTS and duration from message headers, offset so the first timestamped message starts at the running time it was received",
+        nick = "headers"
+    )]
+    Headers = 0,
+    #[enum_value(
+        name = "Arrival: PTS is the running time a message was received, duration from message headers",
+        nick = "arrival"
+    )]
+    Arrival = 1,
+}
+
+// Messages delivered by a new JetStream consumer
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstNatsSrcDeliverPolicy")]
+pub enum NatsDeliverPolicy {
+    #[enum_value(name = "All: replay every message stored in the stream", nick = "all")]
+    All = 0,
+    #[enum_value(
+        name = "Last: start with the last message stored in the stream",
+        nick = "last"
+    )]
+    Last = 1,
+    #[enum_value(
+        name = "New: only messages published after the consumer is created",
+        nick = "new"
+    )]
+    New = 2,
+}
+
+glib::wrapper! {
+    pub struct NatsSrc(ObjectSubclass<imp::NatsSrc>) @extends gst_base::PushSrc, gst_base::BaseSrc, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "nats_src",
+        gst::Rank::None,
+        NatsSrc::static_type(),
+    )
+}
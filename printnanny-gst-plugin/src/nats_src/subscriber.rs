@@ -0,0 +1,141 @@
+use std::io;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use nats::jetstream::SubscribeOptions;
+
+use super::NatsDeliverPolicy;
+use crate::nats_common::auth::NatsAuth;
+use crate::nats_common::headers::headers_from_map;
+use crate::nats_common::inproc;
+use crate::nats_common::NatsMessage;
+
+#[derive(Debug, Clone)]
+pub struct ConsumerOptions {
+    pub stream: Option<String>, // bind to this stream instead of looking up the stream capturing subject
+    pub durable_name: Option<String>, // resume from the last acknowledged message when restarted
+    pub deliver_policy: NatsDeliverPolicy,
+}
+
+#[derive(Debug, Clone)]
+pub struct SubscriberOptions {
+    pub address: String,
+    pub auth: NatsAuth,
+    pub subject: String,
+    pub queue_group: Option<String>,
+    pub jetstream: Option<ConsumerOptions>, // None subscribes with core NATS, receiving messages published while subscribed
+}
+
+pub enum Subscriber {
+    Nats {
+        nc: nats::Connection,
+        sub: nats::Subscription,
+    },
+    JetStream {
+        nc: nats::Connection,
+        sub: nats::jetstream::PushSubscription,
+    },
+    Inproc {
+        receiver: mpsc::Receiver<NatsMessage>,
+    },
+}
+
+impl Subscriber {
+    pub fn subscribe(options: &SubscriberOptions) -> io::Result<Self> {
+        if let Some(server) = inproc::server_name(&options.address) {
+            return Ok(Subscriber::Inproc {
+                receiver: inproc::subscribe(server, &options.subject),
+            });
+        }
+
+        let nc = options
+            .auth
+            .options()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?
+            .with_name("printnanny-gst-plugin nats_src")
+            .max_reconnects(None)
+            .connect(&options.address)?;
+        match &options.jetstream {
+            Some(consumer) => {
+                let js = nats::jetstream::new(nc.clone());
+                let mut subscribe_options = match &consumer.stream {
+                    Some(stream) => SubscribeOptions::bind_stream(stream.clone()),
+                    None => SubscribeOptions::new(),
+                };
+                if let Some(durable_name) = &consumer.durable_name {
+                    subscribe_options = subscribe_options.durable_name(durable_name.clone());
+                }
+                subscribe_options = match consumer.deliver_policy {
+                    NatsDeliverPolicy::All => subscribe_options.deliver_all(),
+                    NatsDeliverPolicy::Last => subscribe_options.deliver_last(),
+                    NatsDeliverPolicy::New => subscribe_options.deliver_new(),
+                };
+                let sub = match &options.queue_group {
+                    Some(queue_group) => js.queue_subscribe_with_options(
+                        &options.subject,
+                        queue_group,
+                        &subscribe_options,
+                    )?,
+                    None => js.subscribe_with_options(&options.subject, &subscribe_options)?,
+                };
+                Ok(Subscriber::JetStream { nc, sub })
+            }
+            None => {
+                let sub = match &options.queue_group {
+                    Some(queue_group) => nc.queue_subscribe(&options.subject, queue_group)?,
+                    None => nc.subscribe(&options.subject)?,
+                };
+                Ok(Subscriber::Nats { nc, sub })
+            }
+        }
+    }
+
+    // Wait up to timeout for the next message, None if no message was received
+    // JetStream messages are acknowledged when received
+    pub fn next_timeout(&self, timeout: Duration) -> io::Result<Option<NatsMessage>> {
+        let msg = match self {
+            Subscriber::Nats { sub, .. } => sub.next_timeout(timeout),
+            Subscriber::JetStream { sub, .. } => sub.next_timeout(timeout).and_then(|msg| {
+                msg.ack()?;
+                Ok(msg)
+            }),
+            Subscriber::Inproc { receiver } => {
+                return match receiver.recv_timeout(timeout) {
+                    Ok(msg) => Ok(Some(msg)),
+                    Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                        io::ErrorKind::NotConnected,
+                        "inproc server closed",
+                    )),
+                }
+            }
+        };
+        match msg {
+            Ok(msg) => Ok(Some(NatsMessage {
+                subject: msg.subject,
+                headers: msg
+                    .headers
+                    .as_ref()
+                    .map(headers_from_map)
+                    .unwrap_or_default(),
+                payload: msg.data,
+            })),
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn close(self) {
+        match self {
+            Subscriber::Nats { nc, sub } => {
+                let _ = sub.unsubscribe();
+                nc.close();
+            }
+            Subscriber::JetStream { nc, sub } => {
+                let _ = sub.unsubscribe();
+                nc.close();
+            }
+            Subscriber::Inproc { .. } => (),
+        }
+    }
+}
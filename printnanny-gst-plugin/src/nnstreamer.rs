@@ -13,7 +13,8 @@ use polars::prelude::*;
 
 use libc::{c_char, c_float, c_int, c_void, size_t};
 
-use crate::ipc;
+use crate::ipc::{self, IpcCompression};
+use crate::metadata::{merge_metadata, plugin_metadata};
 
 const NNS_TENSOR_RANK_LIMIT: usize = 4;
 
@@ -93,6 +94,34 @@ pub extern "C" fn printnanny_bb_dataframe_decoder(
     config: *const GstTensorsSettings,
     _data: libc::c_void,
     out_buf: *mut gst_sys::GstBuffer,
+) -> i32 {
+    decode_bb_dataframe(input, config, out_buf, IpcCompression::Uncompressed)
+}
+
+// tensor_decoder custom-code callbacks do not accept options, so a callback is registered for each arrow ipc compression codec
+pub extern "C" fn printnanny_bb_dataframe_decoder_lz4(
+    input: *const GstTensorMemory,
+    config: *const GstTensorsSettings,
+    _data: libc::c_void,
+    out_buf: *mut gst_sys::GstBuffer,
+) -> i32 {
+    decode_bb_dataframe(input, config, out_buf, IpcCompression::Lz4)
+}
+
+pub extern "C" fn printnanny_bb_dataframe_decoder_zstd(
+    input: *const GstTensorMemory,
+    config: *const GstTensorsSettings,
+    _data: libc::c_void,
+    out_buf: *mut gst_sys::GstBuffer,
+) -> i32 {
+    decode_bb_dataframe(input, config, out_buf, IpcCompression::Zstd)
+}
+
+fn decode_bb_dataframe(
+    input: *const GstTensorMemory,
+    config: *const GstTensorsSettings,
+    out_buf: *mut gst_sys::GstBuffer,
+    compression: IpcCompression,
 ) -> i32 {
     let result = catch_unwind(|| {
         let num_tensors = unsafe { (*config).info.num_tensors };
@@ -194,13 +223,17 @@ pub extern "C" fn printnanny_bb_dataframe_decoder(
         )
         .expect("Failed to initialize dataframe");
 
-        let metadata = BTreeMap::from([
-            ("frame_rate_n".to_string(), df_config.rate_n.to_string()),
-            ("frame_rate_d".to_string(), df_config.rate_d.to_string()),
+        let metadata = merge_metadata([
+            plugin_metadata(),
+            BTreeMap::from([
+                ("frame_rate_n".to_string(), df_config.rate_n.to_string()),
+                ("frame_rate_d".to_string(), df_config.rate_d.to_string()),
+            ]),
         ]);
 
-        let arrow_msg = ipc::dataframe_to_arrow_streaming_ipc_message(&mut df, Some(metadata))
-            .expect("Failed to serialize dataframe");
+        let arrow_msg =
+            ipc::dataframe_to_arrow_streaming_ipc_message(&mut df, Some(metadata), compression)
+                .expect("Failed to serialize dataframe");
 
         // derefrence a pointer to GstBuffer, allocate memory from gstreamer memory pool
         let gstbufref = unsafe { gst::BufferRef::from_mut_ptr(out_buf) };
@@ -255,12 +288,32 @@ extern "C" {
 }
 
 pub fn register_nnstreamer_callbacks() {
-    unsafe {
-        let name = CString::new("printnanny_bb_dataframe_decoder").unwrap();
-        nnstreamer_decoder_custom_register(
-            name.as_ptr(),
+    let callbacks: [(
+        &str,
+        extern "C" fn(
+            *const GstTensorMemory,
+            *const GstTensorsSettings,
+            libc::c_void,
+            *mut gst_sys::GstBuffer,
+        ) -> i32,
+    ); 3] = [
+        (
+            "printnanny_bb_dataframe_decoder",
             printnanny_bb_dataframe_decoder,
-            std::ptr::null_mut(),
-        );
+        ),
+        (
+            "printnanny_bb_dataframe_decoder_lz4",
+            printnanny_bb_dataframe_decoder_lz4,
+        ),
+        (
+            "printnanny_bb_dataframe_decoder_zstd",
+            printnanny_bb_dataframe_decoder_zstd,
+        ),
+    ];
+    for (name, callback) in callbacks {
+        let name = CString::new(name).unwrap();
+        unsafe {
+            nnstreamer_decoder_custom_register(name.as_ptr(), callback, std::ptr::null_mut());
+        }
     }
 }
@@ -0,0 +1,261 @@
+use gst::glib;
+use polars::prelude::*;
+
+// Custom GstMeta carrying detections of a video frame, serialized as an arrow streaming ipc message
+pub const DATAFRAME_META_NAME: &str = "PrintNannyDataframeMeta";
+pub const DATAFRAME_META_IPC_FIELD: &str = "arrow-ipc";
+
+// GstVideoRegionOfInterestMeta param structure, describing a single detection
+// roi_type is the detection label, for example "spaghetti"
+pub const DETECTION_PARAM_NAME: &str = "detection";
+pub const DETECTION_PARAM_CLASS_ID: &str = "class-id";
+pub const DETECTION_PARAM_LABEL: &str = "label";
+pub const DETECTION_PARAM_CONFIDENCE: &str = "confidence";
+
+// Must be called before DATAFRAME_META_NAME meta can be added to buffers
+pub fn register_dataframe_meta() {
+    gst::meta::CustomMeta::register(DATAFRAME_META_NAME, &[]);
+}
+
+// A single row of the detection dataframe schema produced by printnanny_bb_dataframe_decoder
+// Box coordinates are normalized to 0 - 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    pub x0: f32,
+    pub y0: f32,
+    pub x1: f32,
+    pub y1: f32,
+    pub class: i32,
+    pub score: f32,
+}
+
+impl Detection {
+    // Pixel coordinates (x, y, width, height) of bounding box in a frame of width x height pixels
+    pub fn rect(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let scale = |v: f32, size: u32| (v.clamp(0.0, 1.0) * size as f32).round() as u32;
+        let (x0, x1) = (scale(self.x0, width), scale(self.x1, width));
+        let (y0, y1) = (scale(self.y0, height), scale(self.y1, height));
+        (x0.min(x1), y0.min(y1), x0.abs_diff(x1), y0.abs_diff(y1))
+    }
+
+    // Normalize pixel coordinates (x, y, width, height) in a frame of width x height pixels
+    pub fn from_rect(
+        rect: (u32, u32, u32, u32),
+        width: u32,
+        height: u32,
+        class: i32,
+        score: f32,
+    ) -> Self {
+        let (x, y, w, h) = rect;
+        let width = width.max(1) as f32;
+        let height = height.max(1) as f32;
+        Self {
+            x0: x as f32 / width,
+            y0: y as f32 / height,
+            x1: (x + w) as f32 / width,
+            y1: (y + h) as f32 / height,
+            class,
+            score,
+        }
+    }
+}
+
+pub fn detections_from_dataframe(df: &DataFrame) -> Result<Vec<Detection>, PolarsError> {
+    let x0 = df.column("detection_boxes_x0")?.f32()?;
+    let y0 = df.column("detection_boxes_y0")?.f32()?;
+    let x1 = df.column("detection_boxes_x1")?.f32()?;
+    let y1 = df.column("detection_boxes_y1")?.f32()?;
+    let classes = df.column("detection_classes")?.i32()?;
+    let scores = df.column("detection_scores")?.f32()?;
+    Ok((0..df.height())
+        .filter_map(|i| {
+            Some(Detection {
+                x0: x0.get(i)?,
+                y0: y0.get(i)?,
+                x1: x1.get(i)?,
+                y1: y1.get(i)?,
+                class: classes.get(i)?,
+                score: scores.get(i)?,
+            })
+        })
+        .collect())
+}
+
+pub fn detections_to_dataframe(detections: &[Detection]) -> Result<DataFrame, PolarsError> {
+    df!(
+        "detection_boxes_x0" => detections.iter().map(|d| d.x0).collect::<Vec<f32>>(),
+        "detection_boxes_y0" => detections.iter().map(|d| d.y0).collect::<Vec<f32>>(),
+        "detection_boxes_x1" => detections.iter().map(|d| d.x1).collect::<Vec<f32>>(),
+        "detection_boxes_y1" => detections.iter().map(|d| d.y1).collect::<Vec<f32>>(),
+        "detection_classes" => detections.iter().map(|d| d.class).collect::<Vec<i32>>(),
+        "detection_scores" => detections.iter().map(|d| d.score).collect::<Vec<f32>>()
+    )
+}
+
+// Label of detection class, or class-{id} if labels do not contain the class
+pub fn class_label(labels: &[String], class: i32) -> String {
+    usize::try_from(class)
+        .ok()
+        .and_then(|i| labels.get(i))
+        .cloned()
+        .unwrap_or_else(|| format!("class-{}", class))
+}
+
+// Attach detection to video buffer as GstVideoRegionOfInterestMeta, with a detection param structure
+pub fn add_roi_meta(
+    buffer: &mut gst::BufferRef,
+    detection: &Detection,
+    label: &str,
+    width: u32,
+    height: u32,
+) {
+    let mut meta =
+        gst_video::VideoRegionOfInterestMeta::add(buffer, label, detection.rect(width, height));
+    meta.add_param(
+        gst::Structure::builder(DETECTION_PARAM_NAME)
+            .field(DETECTION_PARAM_CLASS_ID, detection.class)
+            .field(DETECTION_PARAM_LABEL, label)
+            .field(DETECTION_PARAM_CONFIDENCE, detection.score as f64)
+            .build(),
+    );
+}
+
+// Read GstVideoRegionOfInterestMeta attached by this plugin or other inference elements
+// class is read from the detection param's class-id field, or the position of roi_type in labels (-1 if roi_type is not a known label)
+// score is read from the first confidence or score field of any param, detections without a score are assumed certain
+pub fn detections_from_roi_metas(
+    buffer: &gst::BufferRef,
+    labels: &[String],
+    width: u32,
+    height: u32,
+) -> Vec<Detection> {
+    buffer
+        .iter_meta::<gst_video::VideoRegionOfInterestMeta>()
+        .map(|meta| {
+            let params: Vec<&gst::StructureRef> = meta.params().collect();
+            let class = meta
+                .param(DETECTION_PARAM_NAME)
+                .and_then(|param| param.get::<i32>(DETECTION_PARAM_CLASS_ID).ok())
+                .or_else(|| {
+                    labels
+                        .iter()
+                        .position(|label| label == meta.roi_type())
+                        .map(|class| class as i32)
+                })
+                .unwrap_or(-1);
+            let score = params
+                .iter()
+                .find_map(|param| {
+                    [DETECTION_PARAM_CONFIDENCE, "score"]
+                        .iter()
+                        .find_map(|field| {
+                            param
+                                .get::<f64>(*field)
+                                .ok()
+                                .or_else(|| param.get::<f32>(*field).ok().map(|v| v as f64))
+                        })
+                })
+                .unwrap_or(1.0);
+            Detection::from_rect(meta.rect(), width, height, class, score as f32)
+        })
+        .collect()
+}
+
+// Attach arrow streaming ipc message to video buffer as DATAFRAME_META_NAME custom meta
+pub fn add_dataframe_meta(
+    buffer: &mut gst::BufferRef,
+    msg: Vec<u8>,
+) -> Result<(), glib::BoolError> {
+    let mut meta = gst::meta::CustomMeta::add(buffer, DATAFRAME_META_NAME)?;
+    meta.mut_structure()
+        .set(DATAFRAME_META_IPC_FIELD, glib::Bytes::from_owned(msg));
+    Ok(())
+}
+
+// Arrow streaming ipc message attached with add_dataframe_meta, if any
+pub fn dataframe_meta_bytes(buffer: &gst::BufferRef) -> Option<glib::Bytes> {
+    gst::meta::CustomMeta::from_buffer(buffer, DATAFRAME_META_NAME)
+        .ok()?
+        .structure()
+        .get::<glib::Bytes>(DATAFRAME_META_IPC_FIELD)
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detection_rect() {
+        let detection = Detection {
+            x0: 0.25,
+            y0: 0.5,
+            x1: 0.75,
+            y1: 1.5,
+            class: 2,
+            score: 0.9,
+        };
+        // coordinates are clamped to frame
+        assert_eq!(detection.rect(640, 480), (160, 240, 320, 240));
+
+        let roundtrip = Detection::from_rect((160, 240, 320, 240), 640, 480, 2, 0.9);
+        assert_eq!(roundtrip.x0, 0.25);
+        assert_eq!(roundtrip.y1, 1.0);
+    }
+
+    #[test]
+    fn test_detections_dataframe() {
+        let detections = vec![Detection {
+            x0: 0.1,
+            y0: 0.2,
+            x1: 0.3,
+            y1: 0.4,
+            class: 2,
+            score: 0.9,
+        }];
+        let df = detections_to_dataframe(&detections).unwrap();
+        assert_eq!(detections_from_dataframe(&df).unwrap(), detections);
+
+        let labels = vec!["nozzle".to_string(), "adhesion".to_string()];
+        assert_eq!(class_label(&labels, 1), "adhesion");
+        assert_eq!(class_label(&labels, 7), "class-7");
+    }
+
+    #[test]
+    fn test_detections_from_roi_metas() {
+        gst::init().unwrap();
+        let labels = vec!["nozzle".to_string(), "adhesion".to_string()];
+        let detection = Detection {
+            x0: 0.25,
+            y0: 0.25,
+            x1: 0.5,
+            y1: 0.5,
+            class: 1,
+            score: 0.75,
+        };
+        let mut buffer = gst::Buffer::new();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            add_roi_meta(buffer, &detection, "adhesion", 640, 480);
+            // meta added by another inference element, without a detection param
+            let mut meta =
+                gst_video::VideoRegionOfInterestMeta::add(buffer, "nozzle", (0, 0, 64, 48));
+            meta.add_param(
+                gst::Structure::builder("classification")
+                    .field("score", 0.5f32)
+                    .build(),
+            );
+            gst_video::VideoRegionOfInterestMeta::add(buffer, "unknown", (0, 0, 64, 48));
+        }
+
+        let mut detections = detections_from_roi_metas(&buffer, &labels, 640, 480);
+        detections.sort_by_key(|detection| -detection.class);
+        assert_eq!(detections.len(), 3);
+        assert_eq!(detections[0], detection);
+        assert_eq!(
+            (detections[1].class, detections[1].score, detections[1].x1),
+            (0, 0.5, 0.1)
+        );
+        assert_eq!((detections[2].class, detections[2].score), (-1, 1.0));
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use once_cell::sync::Lazy;
+
+use crate::caps::{arrow_stream_caps, schema_fingerprint, ARROW_STREAM_MEDIA_TYPE};
+use crate::ipc::{dataframe_to_arrow_streaming_ipc_message, IpcCompression};
+use crate::metadata::{
+    merge_metadata, plugin_metadata, read_labels, SourceMetadata, DEFAULT_LABELS,
+};
+use crate::roi::{detections_from_roi_metas, detections_to_dataframe};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "roimeta_dataframe",
+        gst::DebugColorFlags::empty(),
+        Some("PrintNanny region of interest meta to Dataframe"),
+    )
+});
+
+const DEFAULT_IPC_COMPRESSION: IpcCompression = IpcCompression::Uncompressed;
+const DEFAULT_FILTER_THRESHOLD: f32 = 0.0;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    ipc_compression: IpcCompression,
+    filter_threshold: f32, // Detections below this score are not included in output dataframes
+    label_file: Option<String>,
+    labels: Vec<String>, // used to look up detection_classes of region of interest meta without a class-id param
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            ipc_compression: DEFAULT_IPC_COMPRESSION,
+            filter_threshold: DEFAULT_FILTER_THRESHOLD,
+            label_file: None,
+            labels: DEFAULT_LABELS
+                .iter()
+                .map(|label| label.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    video_info: Option<gst_video::VideoInfo>,
+}
+
+pub struct RoiMetaDataframe {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+}
+
+impl RoiMetaDataframe {
+    // Caps events are replaced with arrow streaming ipc caps, all other events are passed through to the source pad
+    fn sink_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        gst::log!(CAT, obj: pad, "Handling event {:?}", event);
+        if let gst::EventView::Caps(caps) = event.view() {
+            let video_info = match gst_video::VideoInfo::from_caps(caps.caps()) {
+                Ok(video_info) => video_info,
+                Err(_) => {
+                    gst::error!(CAT, obj: pad, "Invalid video caps {:?}", caps.caps());
+                    return false;
+                }
+            };
+            self.state.lock().unwrap().video_info = Some(video_info);
+            let caps = Self::output_caps(&self.settings.lock().unwrap());
+            gst::debug!(CAT, obj: pad, "Setting output caps {:?}", caps);
+            return self.srcpad.push_event(gst::event::Caps::new(&caps));
+        }
+        self.srcpad.push_event(event)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        gst::log!(CAT, obj: pad, "Handling event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    // input and output formats are independent, so caps and allocation are answered by this element's pads
+    fn src_query(&self, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
+        gst::log!(CAT, obj: pad, "Handling query {:?}", query);
+        match query.view() {
+            gst::QueryView::Caps(_) | gst::QueryView::AcceptCaps(_) => {
+                pad.query_default(Some(&*self.instance()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
+        gst::log!(CAT, obj: pad, "Handling query {:?}", query);
+        match query.view() {
+            gst::QueryView::Caps(_)
+            | gst::QueryView::AcceptCaps(_)
+            | gst::QueryView::Allocation(_) => pad.query_default(Some(&*self.instance()), query),
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    // Output schema is the same for every buffer, so the schema fingerprint is calculated from an empty detection dataframe
+    fn output_caps(settings: &Settings) -> gst::Caps {
+        let df = detections_to_dataframe(&[]).expect("Failed to initialize dataframe");
+        arrow_stream_caps(
+            settings.ipc_compression,
+            &schema_fingerprint(&df.schema().to_arrow()),
+        )
+    }
+
+    // Frame rate and labels are added to schema metadata, matching printnanny_bb_dataframe_decoder output
+    fn metadata(
+        settings: &Settings,
+        video_info: &gst_video::VideoInfo,
+    ) -> BTreeMap<String, String> {
+        let source = SourceMetadata {
+            labels: Some(settings.labels.clone()),
+            ..SourceMetadata::default()
+        };
+        merge_metadata([
+            plugin_metadata(),
+            BTreeMap::from([
+                (
+                    "frame_rate_n".to_string(),
+                    video_info.fps().numer().to_string(),
+                ),
+                (
+                    "frame_rate_d".to_string(),
+                    video_info.fps().denom().to_string(),
+                ),
+            ]),
+            source.to_map(),
+        ])
+    }
+
+    // One dataframe is pushed per video buffer, with one row per region of interest meta. Buffers without region of interest meta produce an empty dataframe
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        gst::log!(CAT, obj: pad, "Handling buffer {:?}", buffer);
+        let video_info = match self.state.lock().unwrap().video_info.clone() {
+            Some(video_info) => video_info,
+            None => {
+                gst::element_error!(
+                    self.instance(),
+                    gst::CoreError::Negotiation,
+                    ["Received video buffer before caps"]
+                );
+                return Err(gst::FlowError::NotNegotiated);
+            }
+        };
+        let settings = self.settings.lock().unwrap().clone();
+
+        let detections: Vec<_> = detections_from_roi_metas(
+            &buffer,
+            &settings.labels,
+            video_info.width(),
+            video_info.height(),
+        )
+        .into_iter()
+        .filter(|detection| detection.score >= settings.filter_threshold)
+        .collect();
+        let mut df = detections_to_dataframe(&detections).map_err(|err| {
+            gst::error!(CAT, "Failed to create detection dataframe: {}", err);
+            gst::FlowError::Error
+        })?;
+
+        let msg = dataframe_to_arrow_streaming_ipc_message(
+            &mut df,
+            Some(Self::metadata(&settings, &video_info)),
+            settings.ipc_compression,
+        )
+        .map_err(|err| {
+            gst::error!(
+                CAT,
+                "Failed to serialize arrow ipc streaming msg: {:?}",
+                err
+            );
+            gst::FlowError::Error
+        })?;
+
+        let mut output = gst::Buffer::from_slice(msg);
+        {
+            let output = output.get_mut().unwrap();
+            output.set_pts(buffer.pts());
+            output.set_dts(buffer.dts());
+            output.set_duration(buffer.duration());
+        }
+        self.srcpad.push(output)
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RoiMetaDataframe {
+    const NAME: &'static str = "RoiMetaDataframe";
+    type Type = super::RoiMetaDataframe;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let templ = klass.pad_template("src").unwrap();
+        let srcpad = gst::Pad::builder_with_template(&templ, Some("src"))
+            .event_function(|pad, parent, event| {
+                RoiMetaDataframe::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |element| element.src_event(pad, event),
+                )
+            })
+            .query_function(|pad, parent, query| {
+                RoiMetaDataframe::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |element| element.src_query(pad, query),
+                )
+            })
+            .build();
+
+        let templ = klass.pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::builder_with_template(&templ, Some("sink"))
+            .chain_function(|pad, parent, buffer| {
+                RoiMetaDataframe::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |element| element.sink_chain(pad, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                RoiMetaDataframe::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |element| element.sink_event(pad, event),
+                )
+            })
+            .query_function(|pad, parent, query| {
+                RoiMetaDataframe::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |element| element.sink_query(pad, query),
+                )
+            })
+            .build();
+
+        Self {
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+            sinkpad,
+            srcpad,
+        }
+    }
+}
+
+impl ObjectImpl for RoiMetaDataframe {
+    fn constructed(&self) {
+        self.parent_constructed();
+        let obj = self.instance();
+
+        obj.add_pad(&self.sinkpad).unwrap();
+        obj.add_pad(&self.srcpad).unwrap();
+    }
+
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecEnum::builder::<IpcCompression>("ipc-compression", DEFAULT_IPC_COMPRESSION)
+                    .nick("IPC Compression")
+                    .blurb("Compression codec applied to record batches")
+                    .build(),
+                glib::ParamSpecFloat::builder("filter-threshold")
+                    .nick("Filter Threshold")
+                    .blurb("Filter detections where score is below threshold. Float between 0 - 1")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_FILTER_THRESHOLD)
+                    .build(),
+                glib::ParamSpecString::builder("label-file")
+                    .nick("Label File")
+                    .blurb("Path to labels.txt file (one label per line). detection_classes of region of interest meta without a class-id param is the position of its roi type in labels")
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "ipc-compression" => settings.ipc_compression.to_value(),
+            "filter-threshold" => settings.filter_threshold.to_value(),
+            "label-file" => settings.label_file.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "ipc-compression" => {
+                settings.ipc_compression = value
+                    .get::<IpcCompression>()
+                    .expect("type checked upstream");
+            }
+            "filter-threshold" => {
+                settings.filter_threshold = value.get::<f32>().expect("type checked upstream");
+            }
+            "label-file" => {
+                let label_file = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+                settings.labels = match &label_file {
+                    Some(label_file) => match read_labels(label_file) {
+                        Ok(labels) => labels,
+                        Err(err) => {
+                            gst::error!(CAT, "Failed to read label-file {}: {}", label_file, err);
+                            vec![]
+                        }
+                    },
+                    None => DEFAULT_LABELS
+                        .iter()
+                        .map(|label| label.to_string())
+                        .collect(),
+                };
+                settings.label_file = label_file;
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RoiMetaDataframe {}
+
+impl ElementImpl for RoiMetaDataframe {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "PrintNanny region of interest meta to Dataframe",
+                "Filter/Video",
+                "Output detection dataframes from video region of interest meta",
+                "Leigh Johnson <leigh@printnanny.ai>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("video/x-raw").build();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            let caps = gst::Caps::builder(ARROW_STREAM_MEDIA_TYPE).build();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
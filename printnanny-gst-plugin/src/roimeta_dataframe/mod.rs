@@ -0,0 +1,18 @@
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+
+// Reads GstVideoRegionOfInterestMeta from video buffers, and outputs a detection dataframe per buffer (same schema as printnanny_bb_dataframe_decoder)
+glib::wrapper! {
+    pub struct RoiMetaDataframe(ObjectSubclass<imp::RoiMetaDataframe>) @extends gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "roimeta_dataframe",
+        gst::Rank::None,
+        RoiMetaDataframe::static_type(),
+    )
+}
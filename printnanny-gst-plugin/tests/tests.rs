@@ -21,6 +21,19 @@ fn init() {
     });
 }
 
+// nats_sink publishes from a background thread, wait until it published count messages
+fn wait_published(element: &gst::Element, count: u64) {
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while element.property::<u64>("published-messages") < count {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "timed out waiting for {} published messages",
+            count
+        );
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
 // requires nats server to be running, ignore in CI but keep as development helper
 #[ignore]
 #[test]
@@ -71,6 +84,589 @@ fn test_nats_sink() {
     pipeline.set_state(gst::State::Null).unwrap();
 }
 
+#[test]
+fn test_nats_sink_unreachable_server() {
+    init();
+
+    // nothing listens on port 1, so the connection is refused. max-reconnects=0 gives up after the first attempt
+    let mut h = gst_check::Harness::new_parse(
+        "nats_sink nats-address=127.0.0.1:1 max-reconnects=0 max-queue-size=4 queue-policy=drop-oldest",
+    );
+    let bus = gst::Bus::new();
+    h.element().unwrap().set_bus(Some(&bus));
+    h.set_src_caps_str("application/octet-stream");
+    h.play();
+
+    let mut states = vec![];
+    while let Some(msg) = bus.timed_pop_filtered(
+        gst::ClockTime::from_seconds(5),
+        &[gst::MessageType::Element],
+    ) {
+        let s = msg.structure().unwrap();
+        assert_eq!(s.name(), "printnanny-nats-connection");
+        let state = s.get::<String>("state").unwrap();
+        states.push(state.clone());
+        if state == "closed" {
+            break;
+        }
+    }
+    assert_eq!(states, vec!["connecting", "disconnected", "closed"]);
+
+    // publishing while the server is unreachable does not fail the pipeline
+    for i in 0..8 {
+        let mut buffer = gst::Buffer::from_slice(vec![i; 16]);
+        buffer
+            .get_mut()
+            .unwrap()
+            .set_pts(gst::ClockTime::from_mseconds(i as u64));
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+    let element = h.element().unwrap();
+    assert_eq!(element.property::<u64>("dropped-messages"), 8);
+    assert_eq!(element.property::<u64>("published-messages"), 0);
+    assert_eq!(element.property::<u64>("queued-messages"), 0);
+}
+
+// requires nats server with JetStream enabled to be running, ignore in CI but keep as development helper
+#[ignore]
+#[test]
+fn test_nats_sink_jetstream_dedup() {
+    init();
+    let stream_name = "PRINTNANNY_TEST_NATS_SINK";
+    let subject = "pi.test.jetstream";
+    let nc = nats::connect("127.0.0.1:4222").unwrap();
+    let js = nats::jetstream::new(nc);
+    let _ = js.delete_stream(stream_name);
+
+    let mut h = gst_check::Harness::new_parse(&format!(
+        "nats_sink nats-subject={subject} jetstream=true msg-id-mode=pts stream-name={stream_name} create-stream=true"
+    ));
+    let bus = gst::Bus::new();
+    h.element().unwrap().set_bus(Some(&bus));
+    h.set_src_caps_str("application/octet-stream");
+    h.play();
+
+    while let Some(msg) = bus.timed_pop_filtered(
+        gst::ClockTime::from_seconds(5),
+        &[gst::MessageType::Element],
+    ) {
+        let state = msg.structure().unwrap().get::<String>("state").unwrap();
+        if state == "connected" {
+            break;
+        }
+    }
+
+    // the last buffer repeats a pts, so JetStream discards it as a duplicate
+    for pts in [0, 1, 2, 2] {
+        let mut buffer = gst::Buffer::from_slice(vec![pts as u8; 16]);
+        buffer
+            .get_mut()
+            .unwrap()
+            .set_pts(gst::ClockTime::from_mseconds(pts));
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+    wait_published(&h.element().unwrap(), 4);
+
+    let info = js.stream_info(stream_name).unwrap();
+    assert_eq!(info.state.messages, 3);
+    js.delete_stream(stream_name).unwrap();
+}
+
+// requires nats server with JetStream enabled to be running, ignore in CI but keep as development helper
+#[ignore]
+#[test]
+fn test_nats_sink_jetstream_max_retries() {
+    init();
+    // no stream captures the subject, so every publish attempt fails
+    let mut h = gst_check::Harness::new_parse(
+        "nats_sink nats-subject=pi.test.jetstream.nostream jetstream=true jetstream-ack-wait=100000000 jetstream-max-retries=2 reconnect-delay=1000000",
+    );
+    let bus = gst::Bus::new();
+    let element = h.element().unwrap();
+    element.set_bus(Some(&bus));
+    h.set_src_caps_str("application/octet-stream");
+    h.play();
+
+    while let Some(msg) = bus.timed_pop_filtered(
+        gst::ClockTime::from_seconds(5),
+        &[gst::MessageType::Element],
+    ) {
+        let state = msg.structure().unwrap().get::<String>("state").unwrap();
+        if state == "connected" {
+            break;
+        }
+    }
+
+    assert_eq!(
+        h.push(gst::Buffer::from_slice(vec![0u8; 16])),
+        Ok(gst::FlowSuccess::Ok)
+    );
+    // the message is dropped once retries are exhausted, instead of blocking the queue
+    let msg = bus
+        .timed_pop_filtered(gst::ClockTime::from_seconds(5), &[gst::MessageType::Error])
+        .expect("no error posted after jetstream-max-retries");
+    match msg.view() {
+        MessageView::Error(err) => {
+            assert!(err.error().to_string().contains("after 3 attempts"));
+        }
+        _ => unreachable!(),
+    }
+    assert_eq!(element.property::<u64>("dropped-messages"), 1);
+    assert_eq!(element.property::<u64>("published-messages"), 0);
+}
+
+// nats_sink publishes to inproc subscribers, so message headers are checked without a nats server
+#[test]
+fn test_nats_sink_headers() {
+    init();
+    use gstprintnanny::nats_common::{headers, inproc};
+    let subject = "pi.test.headers";
+    let receiver = inproc::subscribe("test_nats_sink_headers", subject);
+
+    let mut h = gst_check::Harness::new_parse(&format!(
+        "nats_sink nats-address=inproc://test_nats_sink_headers nats-subject={subject} camera-id=camera0 device-id=pi0 extra-headers=\"headers, site=workshop\""
+    ));
+    h.set_src_caps_str(
+        "application/vnd.apache.arrow.stream,compression=none,schema-fingerprint=0123456789abcdef",
+    );
+    h.play();
+
+    let mut buffer = gst::Buffer::from_slice(vec![0; 16]);
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_pts(gst::ClockTime::from_mseconds(40));
+        buffer.set_duration(gst::ClockTime::from_mseconds(20));
+    }
+    assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+
+    let msg = receiver
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+    let header = |name: &str| headers::header(&msg.headers, name).map(|value| value.to_string());
+    // caps fields are sent as Content-Type parameters
+    assert_eq!(
+        header("Content-Type").as_deref(),
+        Some("application/vnd.apache.arrow.stream; compression=none; schema-fingerprint=0123456789abcdef")
+    );
+    assert_eq!(header("PrintNanny-Pts").as_deref(), Some("40000000"));
+    assert_eq!(header("PrintNanny-Duration").as_deref(), Some("20000000"));
+    assert_eq!(header("PrintNanny-Dts"), None);
+    assert_eq!(header("PrintNanny-Sequence").as_deref(), Some("0"));
+    assert_eq!(header("PrintNanny-Camera-Id").as_deref(), Some("camera0"));
+    assert_eq!(header("PrintNanny-Device-Id").as_deref(), Some("pi0"));
+    assert_eq!(
+        header("PrintNanny-Schema-Fingerprint").as_deref(),
+        Some("0123456789abcdef")
+    );
+    assert_eq!(header("site").as_deref(), Some("workshop"));
+    assert!(header("Nats-Msg-Id").is_some());
+}
+
+#[test]
+fn test_nats_sink_camera_id_from_metadata() {
+    init();
+    use gstprintnanny::nats_common::{headers, inproc};
+    let subject = "pi.test.camera_id";
+    let receiver = inproc::subscribe("test_nats_sink_camera_id", subject);
+
+    // camera-id property is not set, so the header is filled from schema metadata
+    let mut h = gst_check::Harness::new_parse(&format!(
+        "nats_sink nats-address=inproc://test_nats_sink_camera_id nats-subject={subject}"
+    ));
+    h.set_src_caps_str("application/vnd.apache.arrow.stream");
+    h.play();
+
+    let mut df = df!("x0" => [0i64, 1]).unwrap();
+    let metadata = std::collections::BTreeMap::from([(
+        gstprintnanny::metadata::CAMERA_ID_KEY.to_string(),
+        "camera1".to_string(),
+    )]);
+    let payload = gstprintnanny::ipc::dataframe_to_arrow_streaming_ipc_message(
+        &mut df,
+        Some(metadata),
+        gstprintnanny::ipc::IpcCompression::Uncompressed,
+    )
+    .unwrap();
+    assert_eq!(
+        h.push(gst::Buffer::from_slice(payload)),
+        Ok(gst::FlowSuccess::Ok)
+    );
+
+    let msg = receiver
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(
+        headers::header(&msg.headers, "PrintNanny-Camera-Id"),
+        Some("camera1")
+    );
+}
+
+#[test]
+fn test_nats_sink_invalid_subject_template() {
+    init();
+
+    let h = gst_check::Harness::new_parse("nats_sink nats-subject=pi.{device");
+    let bus = gst::Bus::new();
+    let element = h.element().unwrap();
+    element.set_bus(Some(&bus));
+    assert!(element.set_state(gst::State::Playing).is_err());
+
+    let msg = bus
+        .timed_pop_filtered(gst::ClockTime::from_seconds(5), &[gst::MessageType::Error])
+        .unwrap();
+    match msg.view() {
+        MessageView::Error(err) => assert!(err.error().to_string().contains("nats-subject")),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_nats_sink_split_column() {
+    init();
+    use gstprintnanny::nats_common::inproc;
+    let receiver = inproc::subscribe("test_nats_sink_split", "pi.test.*.qc.*");
+
+    let mut h = gst_check::Harness::new_parse(
+        "nats_sink nats-address=inproc://test_nats_sink_split nats-subject=pi.test.{camera}.qc.{label} camera-id=camera0 split-column=detection_classes",
+    );
+    h.set_src_caps_str("application/vnd.apache.arrow.stream");
+    h.play();
+
+    let mut df = df!(
+        "detection_classes" => [2, 0, 2],
+        "detection_scores" => [0.9, 0.8, 0.7]
+    )
+    .unwrap();
+    let payload = gstprintnanny::ipc::dataframe_to_arrow_streaming_ipc_message(
+        &mut df,
+        None,
+        gstprintnanny::ipc::IpcCompression::Uncompressed,
+    )
+    .unwrap();
+    assert_eq!(
+        h.push(gst::Buffer::from_slice(payload)),
+        Ok(gst::FlowSuccess::Ok)
+    );
+
+    let mut partitions = vec![];
+    for _ in 0..2 {
+        let msg = receiver
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        let (df, _) = gstprintnanny::ipc::dataframe_from_bytes(&msg.payload, None).unwrap();
+        partitions.push((msg.subject, df.height()));
+    }
+    assert_eq!(
+        partitions,
+        vec![
+            ("pi.test.camera0.qc.spaghetti".to_string(), 2),
+            ("pi.test.camera0.qc.nozzle".to_string(), 1)
+        ]
+    );
+}
+
+// requires nats server with JetStream enabled to be running, ignore in CI but keep as development helper
+#[ignore]
+#[test]
+fn test_nats_sink_kv_summary() {
+    init();
+    let bucket = "PRINTNANNY_TEST_NATS_SINK_KV";
+    let nc = nats::connect("127.0.0.1:4222").unwrap();
+    let js = nats::jetstream::new(nc);
+    let _ = js.delete_key_value(bucket);
+
+    let mut h = gst_check::Harness::new_parse(&format!(
+        "nats_sink nats-subject=pi.test.kv kv-bucket={bucket} create-kv-bucket=true kv-key=latest.{{camera}} camera-id=camera0 kv-format=summary"
+    ));
+    let bus = gst::Bus::new();
+    h.element().unwrap().set_bus(Some(&bus));
+    h.set_src_caps_str("application/vnd.apache.arrow.stream");
+    h.play();
+
+    while let Some(msg) = bus.timed_pop_filtered(
+        gst::ClockTime::from_seconds(5),
+        &[gst::MessageType::Element],
+    ) {
+        let state = msg.structure().unwrap().get::<String>("state").unwrap();
+        if state == "connected" {
+            break;
+        }
+    }
+
+    for rt in [1_i64, 2] {
+        let mut df = df!(
+            "rt" => [rt, rt],
+            "detection_classes" => [0, 2],
+            "detection_scores__mean" => [0.9, 0.8]
+        )
+        .unwrap();
+        let payload = gstprintnanny::ipc::dataframe_to_arrow_streaming_ipc_message(
+            &mut df,
+            None,
+            gstprintnanny::ipc::IpcCompression::Uncompressed,
+        )
+        .unwrap();
+        assert_eq!(
+            h.push(gst::Buffer::from_slice(payload)),
+            Ok(gst::FlowSuccess::Ok)
+        );
+    }
+    wait_published(&h.element().unwrap(), 2);
+
+    // late joiners read the latest window without waiting for the next message
+    // values are written after publishing, so wait for the second window's summary
+    let kv = js.key_value(bucket).unwrap();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    let summary = loop {
+        if let Some(summary) = kv.get("latest.camera0").unwrap() {
+            let summary: serde_json::Value = serde_json::from_slice(&summary).unwrap();
+            if summary["rows"][0]["rt"] == 2 {
+                break summary;
+            }
+        }
+        assert!(std::time::Instant::now() < deadline);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+    assert_eq!(summary["headers"]["PrintNanny-Camera-Id"], "camera0");
+    let rows = summary["rows"].as_array().unwrap();
+    assert_eq!(rows.len(), 2);
+    assert!(rows.iter().all(|row| row["rt"] == 2));
+    js.delete_key_value(bucket).unwrap();
+}
+
+#[test]
+fn test_nats_src_inproc() {
+    init();
+    use gstprintnanny::nats_common::inproc;
+
+    let mut h = gst_check::Harness::new_parse(
+        "nats_src nats-address=inproc://test_nats_src nats-subject=pi.*.df",
+    );
+    h.play();
+
+    let headers = |pts: u64| {
+        vec![
+            (
+                "Content-Type".to_string(),
+                "application/vnd.apache.arrow.stream".to_string(),
+            ),
+            (
+                "PrintNanny-Schema-Fingerprint".to_string(),
+                "0123456789abcdef".to_string(),
+            ),
+            ("PrintNanny-Pts".to_string(), pts.to_string()),
+            ("PrintNanny-Duration".to_string(), "20000000".to_string()),
+        ]
+    };
+    // messages which are not a dataframe format are skipped
+    assert_eq!(
+        inproc::publish(
+            "test_nats_src",
+            "pi.qc.df",
+            vec![("Content-Type".to_string(), "text/plain".to_string())],
+            b"hello".to_vec()
+        ),
+        1
+    );
+    assert_eq!(
+        inproc::publish(
+            "test_nats_src",
+            "pi.qc.df",
+            headers(1_000_000_000),
+            vec![0; 8]
+        ),
+        1
+    );
+    assert_eq!(
+        inproc::publish(
+            "test_nats_src",
+            "pi.qc.df",
+            headers(1_040_000_000),
+            vec![1; 8]
+        ),
+        1
+    );
+    // not matching nats-subject
+    assert_eq!(
+        inproc::publish("test_nats_src", "pi.other", headers(0), vec![2; 8]),
+        0
+    );
+
+    let first = h.pull().unwrap();
+    let second = h.pull().unwrap();
+    assert_eq!(first.map_readable().unwrap().as_slice(), &[0; 8]);
+    assert_eq!(second.map_readable().unwrap().as_slice(), &[1; 8]);
+    assert_eq!(
+        second.pts().unwrap() - first.pts().unwrap(),
+        gst::ClockTime::from_mseconds(40)
+    );
+    assert_eq!(first.duration(), Some(gst::ClockTime::from_mseconds(20)));
+
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    let s = caps.structure(0).unwrap();
+    assert_eq!(s.name(), "application/vnd.apache.arrow.stream");
+    assert_eq!(
+        s.get::<String>("schema-fingerprint").unwrap(),
+        "0123456789abcdef"
+    );
+}
+
+#[test]
+fn test_nats_src_inproc_framing() {
+    init();
+    use gstprintnanny::nats_common::{framing, inproc, NatsMessage};
+
+    let mut h = gst_check::Harness::new_parse(
+        "nats_src nats-address=inproc://test_nats_src_framing nats-subject=pi.qc.df",
+    );
+    h.play();
+
+    let msg = |pts: u64, payload: Vec<u8>| NatsMessage {
+        subject: "pi.qc.df".into(),
+        headers: vec![("PrintNanny-Pts".to_string(), pts.to_string())],
+        payload,
+    };
+    let publish = |msg: NatsMessage| {
+        inproc::publish(
+            "test_nats_src_framing",
+            &msg.subject,
+            msg.headers,
+            msg.payload,
+        )
+    };
+
+    // chunks are published in reverse order, the message is pushed once complete
+    let large: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+    let chunks = framing::chunk(&msg(0, large.clone()), 1024).unwrap();
+    assert!(chunks.len() > 1);
+    for chunk in chunks.into_iter().rev() {
+        assert_eq!(publish(chunk), 1);
+    }
+    let batch = framing::batch(vec![msg(1_000_000, vec![1; 8]), msg(2_000_000, vec![2; 8])]);
+    assert_eq!(publish(batch), 1);
+
+    let first = h.pull().unwrap();
+    assert_eq!(first.map_readable().unwrap().as_slice(), large.as_slice());
+    let second = h.pull().unwrap();
+    let third = h.pull().unwrap();
+    assert_eq!(second.map_readable().unwrap().as_slice(), &[1; 8]);
+    assert_eq!(third.map_readable().unwrap().as_slice(), &[2; 8]);
+    assert_eq!(
+        third.pts().unwrap() - second.pts().unwrap(),
+        gst::ClockTime::from_mseconds(1)
+    );
+}
+
+#[test]
+fn test_nats_src_from_nats_sink() {
+    init();
+    let subject = "pi.test.nats_src";
+
+    let mut src = gst_check::Harness::new_parse(&format!(
+        "nats_src nats-address=inproc://test_nats_src_from_nats_sink nats-subject={subject}"
+    ));
+    src.play();
+    let mut sink = gst_check::Harness::new_parse(&format!(
+        "nats_sink nats-address=inproc://test_nats_src_from_nats_sink nats-subject={subject}"
+    ));
+    sink.set_src_caps_str("application/vnd.apache.arrow.stream");
+    sink.play();
+
+    let mut df = df!("detection_classes" => [2, 0]).unwrap();
+    let payload = gstprintnanny::ipc::dataframe_to_arrow_streaming_ipc_message(
+        &mut df,
+        None,
+        gstprintnanny::ipc::IpcCompression::Uncompressed,
+    )
+    .unwrap();
+    let mut buffer = gst::Buffer::from_slice(payload);
+    buffer
+        .get_mut()
+        .unwrap()
+        .set_pts(gst::ClockTime::from_mseconds(40));
+    assert_eq!(sink.push(buffer), Ok(gst::FlowSuccess::Ok));
+
+    let buffer = src.pull().unwrap();
+    let (received, _) =
+        gstprintnanny::ipc::dataframe_from_bytes(buffer.map_readable().unwrap().as_slice(), None)
+            .unwrap();
+    assert_eq!(received, df);
+    let caps = src.sinkpad().unwrap().current_caps().unwrap();
+    assert_eq!(
+        caps.structure(0).unwrap().name(),
+        "application/vnd.apache.arrow.stream"
+    );
+}
+
+#[test]
+fn test_nats_src_from_nats_sink_chunked_batches() {
+    init();
+    let subject = "pi.test.nats_src_framing";
+
+    let mut src = gst_check::Harness::new_parse(&format!("nats_src nats-address=inproc://test_nats_src_from_nats_sink_chunked_batches nats-subject={subject}"));
+    src.play();
+    // 2MiB payloads exceed the default max_payload of 1MiB, small payloads are batched
+    let mut sink = gst_check::Harness::new_parse(&format!(
+        "nats_sink nats-address=inproc://test_nats_src_from_nats_sink_chunked_batches nats-subject={subject} oversize-policy=chunk batch-linger=100000000"
+    ));
+    sink.set_src_caps_str("application/octet-stream");
+    sink.play();
+
+    let payloads = vec![vec![0; 2 * 1024 * 1024], vec![1; 8], vec![2; 8]];
+    for payload in payloads.iter() {
+        assert_eq!(
+            sink.push(gst::Buffer::from_slice(payload.clone())),
+            Ok(gst::FlowSuccess::Ok)
+        );
+    }
+    wait_published(&sink.element().unwrap(), 3);
+
+    for payload in payloads.iter() {
+        let buffer = src.pull().unwrap();
+        assert_eq!(
+            buffer.map_readable().unwrap().as_slice(),
+            payload.as_slice()
+        );
+    }
+}
+
+// requires nats server to be running, ignore in CI but keep as development helper
+#[ignore]
+#[test]
+fn test_control_serve() {
+    init();
+    use gstprintnanny::control::{self, ControlReply};
+
+    let pipeline = gst::Pipeline::new(None);
+    let sink = gst::ElementFactory::make("fakesink")
+        .name("fakesink__control")
+        .build()
+        .unwrap();
+    pipeline.add(&sink).unwrap();
+    let subject = "pi.test.control";
+    let _handle = control::serve(pipeline.upcast_ref(), "127.0.0.1:4222", subject);
+
+    // requests published before the serve thread subscribed are not answered, so retry until a reply comes back
+    let nc = nats::connect("127.0.0.1:4222").unwrap();
+    let request =
+        br#"{"op": "set", "element": "fakesink__control", "property": "num-buffers", "value": 8}"#;
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    let msg = loop {
+        match nc.request_timeout(subject, request, std::time::Duration::from_millis(100)) {
+            Ok(msg) => break msg,
+            Err(err) => assert!(
+                std::time::Instant::now() < deadline,
+                "no reply to control request: {}",
+                err
+            ),
+        }
+    };
+    let reply: ControlReply = serde_json::from_slice(&msg.data).unwrap();
+    assert!(reply.ok);
+    assert_eq!(reply.value, Some(8.into()));
+    assert_eq!(sink.property::<i32>("num-buffers"), 8);
+}
+
 #[test]
 fn test_nnstreamer_callback() {
     init();
@@ -117,6 +713,151 @@ fn test_nnstreamer_callback() {
     assert_eq!(num_buffers, expected_buffers);
 }
 
+#[test]
+fn test_mqtt_sink_unreachable_broker() {
+    init();
+
+    // nothing listens on port 1, so the connection is refused
+    let mut h = gst_check::Harness::new_parse(
+        "mqtt_sink mqtt-address=127.0.0.1:1 max-queue-size=4 reconnect-delay=100000000",
+    );
+    let bus = gst::Bus::new();
+    h.element().unwrap().set_bus(Some(&bus));
+    h.set_src_caps_str("application/octet-stream");
+    h.play();
+
+    let mut states = vec![];
+    while let Some(msg) = bus.timed_pop_filtered(
+        gst::ClockTime::from_seconds(5),
+        &[gst::MessageType::Element],
+    ) {
+        let s = msg.structure().unwrap();
+        assert_eq!(s.name(), "printnanny-mqtt-connection");
+        let state = s.get::<String>("state").unwrap();
+        states.push(state.clone());
+        if state == "disconnected" {
+            assert!(s.get::<String>("error").is_ok());
+            break;
+        }
+    }
+    assert_eq!(states, vec!["connecting", "disconnected"]);
+
+    // publishing while the broker is unreachable does not fail the pipeline, messages beyond max-queue-size are dropped
+    for i in 0..8 {
+        assert_eq!(
+            h.push(gst::Buffer::from_slice(vec![i; 16])),
+            Ok(gst::FlowSuccess::Ok)
+        );
+    }
+    let element = h.element().unwrap();
+    assert_eq!(element.property::<u64>("dropped-messages"), 4);
+    assert_eq!(element.property::<u64>("published-messages"), 0);
+}
+
+// requires mqtt broker to be running, ignore in CI but keep as development helper
+#[ignore]
+#[test]
+fn test_mqtt_sink_homeassistant() {
+    init();
+
+    let mut options = rumqttc::MqttOptions::new("printnanny-test-subscriber", "127.0.0.1", 1883);
+    options.set_max_packet_size(1048576, 1048576);
+    let (mut client, mut connection) = rumqttc::Client::new(options, 10);
+    client
+        .subscribe("printnanny/test/#", rumqttc::QoS::AtLeastOnce)
+        .unwrap();
+    client
+        .subscribe("homeassistant/+/test-device/#", rumqttc::QoS::AtLeastOnce)
+        .unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for event in connection.iter() {
+            match event {
+                Ok(rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish))) => {
+                    if tx.send((publish.topic, publish.payload.to_vec())).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => (),
+                Err(_) => break,
+            }
+        }
+    });
+    // receive messages until a message to topic, with payload if given, arrives
+    let mut received = std::collections::BTreeMap::new();
+    fn receive_until(
+        rx: &std::sync::mpsc::Receiver<(String, Vec<u8>)>,
+        received: &mut std::collections::BTreeMap<String, Vec<u8>>,
+        topic: &str,
+        payload: Option<&[u8]>,
+    ) {
+        loop {
+            let (received_topic, received_payload) = rx
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .unwrap_or_else(|_| panic!("timed out waiting for message to {}", topic));
+            let done = received_topic == topic
+                && payload.map_or(true, |payload| payload == received_payload.as_slice());
+            received.insert(received_topic, received_payload);
+            if done {
+                break;
+            }
+        }
+    }
+
+    let mut h = gst_check::Harness::new_parse(
+        "mqtt_sink topic=printnanny/test/df qos=at-least-once will-topic=printnanny/test/status homeassistant-discovery=true device-id=test-device device-name=Test",
+    );
+    h.set_src_caps_str("application/vnd.apache.arrow.stream");
+    h.play();
+    // the online payload is published after the discovery configs are retained
+    receive_until(
+        &rx,
+        &mut received,
+        "printnanny/test/status",
+        Some(b"online".as_slice()),
+    );
+
+    let mut df = df!(
+        "rt" => [1_i64, 2],
+        "failure_score" => [0.1, 0.8],
+        "failure_alert" => [false, true]
+    )
+    .unwrap();
+    let payload = gstprintnanny::ipc::dataframe_to_arrow_streaming_ipc_message(
+        &mut df,
+        None,
+        gstprintnanny::ipc::IpcCompression::Uncompressed,
+    )
+    .unwrap();
+    assert_eq!(
+        h.push(gst::Buffer::from_slice(payload.clone())),
+        Ok(gst::FlowSuccess::Ok)
+    );
+    receive_until(&rx, &mut received, "printnanny/test/df/state", None);
+    assert_eq!(received["printnanny/test/df"], payload);
+    let state: serde_json::Value =
+        serde_json::from_slice(&received["printnanny/test/df/state"]).unwrap();
+    assert_eq!(
+        state,
+        serde_json::json!({ "failure_score": 0.8, "failure_alert": true })
+    );
+    let config: serde_json::Value =
+        serde_json::from_slice(&received["homeassistant/sensor/test-device/failure_score/config"])
+            .unwrap();
+    assert_eq!(config["state_topic"], "printnanny/test/df/state");
+    assert_eq!(config["availability_topic"], "printnanny/test/status");
+    assert!(received.contains_key("homeassistant/binary_sensor/test-device/failure_alert/config"));
+
+    // the offline payload is published when the element stops
+    drop(h);
+    receive_until(
+        &rx,
+        &mut received,
+        "printnanny/test/status",
+        Some(b"offline".as_slice()),
+    );
+}
+
 // TODO: test flakes on:
 // `Err` value: ComputeError(Borrowed("empty container given"))'
 #[ignore]
@@ -187,16 +928,18 @@ fn test_dataframe_filesink() {
 }
 
 #[test]
-fn test_dataframe_agg() {
+fn test_dataframe_parquetsink() {
     init();
-
     let base_path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     let model_path: PathBuf = base_path.join("fixtures/model.tflite");
+    let tmp_dir = tempdir::TempDir::new("test_dataframe_parquetsink").unwrap();
 
-    let expected_buffers = 512;
-    let expected_columns = 21;
+    let dataframe_location = format!("{}/videotestsrc_%05d.parquet", tmp_dir.path().display());
     let num_detections = 40;
-    let max_duration = "10s";
+    let expected_buffers = 16;
+    // 5 buffers per file, last file contains 1 buffer
+    let max_file_rows = num_detections * 5;
+    let expected_files = 4;
 
     let pipeline_str = format!(
         "videotestsrc num-buffers={expected_buffers} \
@@ -205,27 +948,229 @@ fn test_dataframe_agg() {
         ! videoconvert \
         ! tensor_converter \
         ! capsfilter caps=other/tensors,num_tensors=1,format=static \
-        ! queue leaky=2 \
         ! tensor_filter framework=tensorflow2-lite model={model_file} output=4:{num_detections}:1:1,{num_detections}:1:1:1,{num_detections}:1:1:1,1:1:1:1 outputname=detection_boxes,detection_classes,detection_scores,num_detections outputtype=float32,float32,float32,float32 \
-        ! queue \
         ! tensor_decoder mode=custom-code option1=printnanny_bb_dataframe_decoder \
-        ! queue \
-        ! dataframe_agg filter-threshold=0.0001 window-interval=100ms window-period=100ms max-size-duration={max_duration}",
+        ! dataframe_parquetsink location={dataframe_location} max-file-rows={max_file_rows} compression=zstd
+        ",
+        expected_buffers = expected_buffers,
+        num_detections = num_detections,
+        tensor_width = 320,
+        tensor_height = 320,
+        model_file = model_path.display()
+    );
+
+    let pipeline = gst::parse_launch(&pipeline_str).expect("Failed to construct pipeline");
+    pipeline.set_state(gst::State::Playing).unwrap();
+    let bus = pipeline.bus().unwrap();
+    for msg in bus.iter_timed(gst::ClockTime::NONE) {
+        match msg.view() {
+            MessageView::Error(err) => panic!("Pipeline error: {:?}", err),
+            MessageView::Eos(..) => break,
+            _ => {}
+        }
+    }
+    pipeline.set_state(gst::State::Null).unwrap();
+
+    let pattern = format!("{}/videotestsrc*.parquet", tmp_dir.path().display());
+    let dataframes: Vec<LazyFrame> = glob::glob(&pattern)
+        .expect("Failed to parse glob pattern")
+        .map(|p| {
+            let f = File::open(p.unwrap()).expect("file not found");
+            ParquetReader::new(f).finish().unwrap().lazy()
+        })
+        .collect();
+    assert_eq!(dataframes.len(), expected_files);
+
+    let df = concat(&dataframes, true, true).unwrap().collect().unwrap();
+    assert_eq!(df.shape(), (expected_buffers * num_detections, 7));
+}
+
+#[test]
+fn test_dataframe_roimeta() {
+    init();
+
+    let base_path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let model_path: PathBuf = base_path.join("fixtures/model.tflite");
+
+    let expected_buffers = 4;
+    let num_detections = 40;
+
+    let pipeline_str = format!(
+        "videotestsrc num-buffers={expected_buffers} \
+        ! capsfilter caps=video/x-raw,width={tensor_width},height={tensor_height},format=RGB \
+        ! tee name=t \
+        t. ! queue ! roi.video_sink \
+        t. ! queue \
+        ! tensor_converter \
+        ! capsfilter caps=other/tensors,num_tensors=1,format=static \
+        ! tensor_filter framework=tensorflow2-lite model={model_file} output=4:{num_detections}:1:1,{num_detections}:1:1:1,{num_detections}:1:1:1,1:1:1:1 outputname=detection_boxes,detection_classes,detection_scores,num_detections outputtype=float32,float32,float32,float32 \
+        ! tensor_decoder mode=custom-code option1=printnanny_bb_dataframe_decoder \
+        ! roi.dataframe_sink \
+        dataframe_roimeta name=roi filter-threshold=0.0001",
         expected_buffers = expected_buffers,
         num_detections = num_detections,
         tensor_width = 320,
         tensor_height = 320,
         model_file = model_path.display(),
-        max_duration = max_duration
     );
     let mut h = gst_check::Harness::new_parse(&pipeline_str);
-    let bus = gst::Bus::new();
-    let element = h.element().unwrap();
-    element.set_bus(Some(&bus));
     h.play();
 
-    let max_duration_ns = Duration::parse(max_duration).nanoseconds();
-
+    let mut num_buffers = 0;
+    while let Some(buffer) = h.pull_until_eos().unwrap() {
+        let rois: Vec<_> = buffer
+            .iter_meta::<gst_video::VideoRegionOfInterestMeta>()
+            .collect();
+        assert!(!rois.is_empty());
+        for roi in rois {
+            let (x, y, w, h) = roi.rect();
+            assert!(x + w <= 320 && y + h <= 320);
+            let param = roi.param(gstprintnanny::roi::DETECTION_PARAM_NAME).unwrap();
+            assert_eq!(
+                param
+                    .get::<String>(gstprintnanny::roi::DETECTION_PARAM_LABEL)
+                    .unwrap(),
+                roi.roi_type()
+            );
+        }
+
+        let msg = gstprintnanny::roi::dataframe_meta_bytes(&buffer).unwrap();
+        let (df, _) = gstprintnanny::ipc::dataframe_from_bytes(&msg, None).unwrap();
+        assert_eq!(df.height(), num_detections);
+        num_buffers += 1;
+    }
+    assert_eq!(num_buffers, expected_buffers);
+}
+
+#[test]
+fn test_roimeta_dataframe() {
+    init();
+
+    let base_path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let model_path: PathBuf = base_path.join("fixtures/model.tflite");
+
+    let expected_buffers = 4;
+    let num_detections = 40;
+
+    // detections attached by dataframe_roimeta are read back into dataframes
+    let pipeline_str = format!(
+        "videotestsrc num-buffers={expected_buffers} \
+        ! capsfilter caps=video/x-raw,width={tensor_width},height={tensor_height},format=RGB,framerate=15/1 \
+        ! tee name=t \
+        t. ! queue ! roi.video_sink \
+        t. ! queue \
+        ! tensor_converter \
+        ! capsfilter caps=other/tensors,num_tensors=1,format=static \
+        ! tensor_filter framework=tensorflow2-lite model={model_file} output=4:{num_detections}:1:1,{num_detections}:1:1:1,{num_detections}:1:1:1,1:1:1:1 outputname=detection_boxes,detection_classes,detection_scores,num_detections outputtype=float32,float32,float32,float32 \
+        ! tensor_decoder mode=custom-code option1=printnanny_bb_dataframe_decoder \
+        ! roi.dataframe_sink \
+        dataframe_roimeta name=roi filter-threshold=0.0001 \
+        ! roimeta_dataframe",
+        expected_buffers = expected_buffers,
+        num_detections = num_detections,
+        tensor_width = 320,
+        tensor_height = 320,
+        model_file = model_path.display(),
+    );
+    let mut h = gst_check::Harness::new_parse(&pipeline_str);
+    h.play();
+
+    let mut num_buffers = 0;
+    while let Some(buffer) = h.pull_until_eos().unwrap() {
+        let map = buffer.map_readable().unwrap();
+        let (df, metadata) =
+            gstprintnanny::ipc::dataframe_from_bytes(map.as_slice(), None).unwrap();
+        assert!(df.height() > 0);
+        assert_eq!(
+            df.get_column_names(),
+            vec![
+                "detection_boxes_x0",
+                "detection_boxes_y0",
+                "detection_boxes_x1",
+                "detection_boxes_y1",
+                "detection_classes",
+                "detection_scores"
+            ]
+        );
+        let scores = df.column("detection_scores").unwrap();
+        assert!(scores.min::<f32>().unwrap() >= 0.0001);
+        assert_eq!(metadata["frame_rate_n"], "15");
+        assert_eq!(
+            metadata["printnanny:labels"],
+            "nozzle,adhesion,spaghetti,print,raft"
+        );
+        num_buffers += 1;
+    }
+    assert_eq!(num_buffers, expected_buffers);
+}
+
+// Live videotestsrc ! tensor_filter ! tensor_decoder ! dataframe_agg pipeline producing num_buffers frames at 15 fps
+// agg_args are appended to the dataframe_agg element, and may link further elements with "! ..."
+fn dataframe_agg_harness(num_buffers: usize, agg_args: &str) -> gst_check::Harness {
+    let base_path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let model_path: PathBuf = base_path.join("fixtures/model.tflite");
+    let num_detections = 40;
+
+    let pipeline_str = format!(
+        "videotestsrc is-live=true num-buffers={num_buffers} \
+        ! capsfilter caps=video/x-raw,width={tensor_width},height={tensor_height},format=RGB,framerate=15/1 \
+        ! videoscale \
+        ! videoconvert \
+        ! tensor_converter \
+        ! capsfilter caps=other/tensors,num_tensors=1,format=static \
+        ! tensor_filter framework=tensorflow2-lite model={model_file} output=4:{num_detections}:1:1,{num_detections}:1:1:1,{num_detections}:1:1:1,1:1:1:1 outputname=detection_boxes,detection_classes,detection_scores,num_detections outputtype=float32,float32,float32,float32 \
+        ! tensor_decoder mode=custom-code option1=printnanny_bb_dataframe_decoder \
+        ! dataframe_agg filter-threshold=0.0001 window-interval=100ms window-period=100ms {agg_args}",
+        num_buffers = num_buffers,
+        num_detections = num_detections,
+        tensor_width = 320,
+        tensor_height = 320,
+        model_file = model_path.display(),
+        agg_args = agg_args,
+    );
+    gst_check::Harness::new_parse(&pipeline_str)
+}
+
+#[test]
+fn test_dataframe_agg() {
+    init();
+
+    let base_path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let model_path: PathBuf = base_path.join("fixtures/model.tflite");
+
+    let expected_buffers = 512;
+    let expected_columns = 23;
+    let num_detections = 40;
+    let max_duration = "10s";
+
+    let pipeline_str = format!(
+        "videotestsrc num-buffers={expected_buffers} \
+        ! capsfilter caps=video/x-raw,width={tensor_width},height={tensor_height},format=RGB \
+        ! videoscale \
+        ! videoconvert \
+        ! tensor_converter \
+        ! capsfilter caps=other/tensors,num_tensors=1,format=static \
+        ! queue leaky=2 \
+        ! tensor_filter framework=tensorflow2-lite model={model_file} output=4:{num_detections}:1:1,{num_detections}:1:1:1,{num_detections}:1:1:1,1:1:1:1 outputname=detection_boxes,detection_classes,detection_scores,num_detections outputtype=float32,float32,float32,float32 \
+        ! queue \
+        ! tensor_decoder mode=custom-code option1=printnanny_bb_dataframe_decoder \
+        ! queue \
+        ! dataframe_agg filter-threshold=0.0001 window-interval=100ms window-period=100ms max-size-duration={max_duration}",
+        expected_buffers = expected_buffers,
+        num_detections = num_detections,
+        tensor_width = 320,
+        tensor_height = 320,
+        model_file = model_path.display(),
+        max_duration = max_duration
+    );
+    let mut h = gst_check::Harness::new_parse(&pipeline_str);
+    let bus = gst::Bus::new();
+    let element = h.element().unwrap();
+    element.set_bus(Some(&bus));
+    h.play();
+
+    let max_duration_ns = Duration::parse(max_duration).nanoseconds();
+
     let mut num_buffers = 0;
     while let Some(buffer) = h.pull_until_eos().unwrap() {
         let cursor = buffer.as_cursor_readable();
@@ -255,6 +1200,385 @@ fn test_dataframe_agg() {
     }
 }
 
+#[test]
+fn test_dataframe_agg_quality_message() {
+    init();
+
+    let expected_buffers = 16;
+
+    // every detection counts as failure evidence, so the first completed window raises the alert
+    let mut h = dataframe_agg_harness(
+        expected_buffers,
+        "score-weights=\"nozzle=1,adhesion=1,spaghetti=1,print=1,raft=1\" score-decay=0 \
+            score-alert-threshold=0.0001 score-clear-threshold=0",
+    );
+    let bus = gst::Bus::new();
+    let element = h.element().unwrap();
+    element.set_bus(Some(&bus));
+    h.play();
+
+    let mut num_buffers = 0;
+    while let Some(buffer) = h.pull_until_eos().unwrap() {
+        let cursor = buffer.as_cursor_readable();
+        let df = IpcStreamReader::new(cursor)
+            .finish()
+            .expect("Failed to extract dataframe");
+        assert!(df.column("failure_score").is_ok());
+        assert!(df.column("failure_alert").is_ok());
+        num_buffers += 1;
+    }
+    assert_eq!(num_buffers, expected_buffers);
+
+    let mut messages = vec![];
+    while let Some(msg) = bus.pop_filtered(&[gst::MessageType::Element]) {
+        let s = msg.structure().unwrap();
+        if s.name() == "printnanny-quality" {
+            messages.push((
+                s.get::<f64>("score").unwrap(),
+                s.get::<bool>("alert").unwrap(),
+            ));
+        }
+    }
+    assert!(!messages.is_empty(), "no printnanny-quality message posted");
+    let (score, alert) = messages[0];
+    assert!(score > 0.0);
+    assert!(alert);
+}
+
+#[test]
+fn test_dataframe_agg_interval() {
+    init();
+
+    let expected_buffers = 32;
+    let expected_columns = 24;
+
+    let mut h = dataframe_agg_harness(expected_buffers, "emit-mode=interval");
+    let bus = gst::Bus::new();
+    let element = h.element().unwrap();
+    element.set_bus(Some(&bus));
+    h.play();
+
+    let mut num_buffers = 0;
+    while let Some(buffer) = h.pull_until_eos().unwrap() {
+        let cursor = buffer.as_cursor_readable();
+        let df = IpcStreamReader::new(cursor)
+            .finish()
+            .expect("Failed to extract dataframe");
+
+        let (_rows, columns) = df.shape();
+        println!("Pulled dataframe from buffer {:?}", df);
+        assert_eq!(columns, expected_columns);
+        assert!(df.column("heartbeat").is_ok());
+        num_buffers += 1;
+    }
+    assert!(num_buffers > 0);
+}
+
+#[test]
+fn test_dataframe_agg_anomaly() {
+    init();
+
+    let expected_buffers = 32;
+
+    // with anomaly-threshold=0, any deviation from the baseline is flagged once detectors are warmed up
+    let mut h = dataframe_agg_harness(
+        expected_buffers,
+        "emit-mode=interval anomaly-mode=ewma anomaly-threshold=0",
+    );
+    let bus = gst::Bus::new();
+    let element = h.element().unwrap();
+    element.set_bus(Some(&bus));
+    h.play();
+
+    let mut num_buffers = 0;
+    while let Some(buffer) = h.pull_until_eos().unwrap() {
+        let cursor = buffer.as_cursor_readable();
+        let df = IpcStreamReader::new(cursor)
+            .finish()
+            .expect("Failed to extract dataframe");
+        for label in ["nozzle", "adhesion", "spaghetti", "print", "raft"] {
+            for stat in ["count", "mean"] {
+                let name = format!("{}__{}__anomaly", label, stat);
+                assert_eq!(df.column(&name).unwrap().dtype(), &DataType::Boolean);
+            }
+        }
+        num_buffers += 1;
+    }
+    assert!(num_buffers > 0);
+
+    let mut anomalies = vec![];
+    while let Some(msg) = bus.pop_filtered(&[gst::MessageType::Element]) {
+        let s = msg.structure().unwrap();
+        if s.name() == "printnanny-anomaly" {
+            anomalies.push(s.get::<String>("series").unwrap());
+        }
+    }
+    assert!(
+        !anomalies.is_empty(),
+        "no printnanny-anomaly message posted"
+    );
+}
+
+#[test]
+fn test_dataframe_agg_json_framed() {
+    init();
+
+    let expected_buffers = 16;
+
+    let mut h = dataframe_agg_harness(expected_buffers, "output-type=json-framed");
+    h.play();
+
+    let mut num_buffers = 0;
+    while let Some(buffer) = h.pull_until_eos().unwrap() {
+        let map = buffer.map_readable().unwrap();
+        let (length, payload) = map.as_slice().split_at(4);
+        let length = u32::from_be_bytes(length.try_into().unwrap());
+        assert_eq!(length as usize, payload.len());
+
+        let rows: serde_json::Value = serde_json::from_slice(payload).unwrap();
+        assert!(rows.is_array());
+        num_buffers += 1;
+    }
+    assert_eq!(num_buffers, expected_buffers);
+
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    let s = caps.structure(0).unwrap();
+    assert_eq!(s.name(), "application/json");
+    assert_eq!(s.get::<&str>("framing").unwrap(), "length-prefixed");
+}
+
+#[test]
+fn test_dataframe_agg_metadata() {
+    init();
+
+    let base_path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let model_path: PathBuf = base_path.join("fixtures/model.tflite");
+    let label_path: PathBuf = base_path.join("fixtures/labels.txt");
+
+    let expected_buffers = 4;
+
+    let mut h = dataframe_agg_harness(
+        expected_buffers,
+        &format!(
+            "model-file={} label-file={} camera-id=camera0",
+            model_path.display(),
+            label_path.display()
+        ),
+    );
+    h.play();
+
+    let mut num_buffers = 0;
+    while let Some(buffer) = h.pull_until_eos().unwrap() {
+        let map = buffer.map_readable().unwrap();
+        let (_, metadata) = gstprintnanny::ipc::dataframe_from_bytes(map.as_slice(), None).unwrap();
+        assert_eq!(metadata["printnanny:camera_id"], "camera0");
+        assert_eq!(
+            metadata["printnanny:labels"],
+            "nozzle,adhesion,spaghetti,print,raft"
+        );
+        assert_eq!(metadata["printnanny:schema_version"], "1");
+        assert_eq!(metadata["printnanny:model_sha256"].len(), 64);
+        // merged from decoder metadata
+        assert_eq!(metadata["frame_rate_n"], "15");
+        num_buffers += 1;
+    }
+    assert_eq!(num_buffers, expected_buffers);
+}
+
+#[test]
+fn test_dataframe_agg_json_metadata() {
+    init();
+
+    let base_path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let model_path: PathBuf = base_path.join("fixtures/model.tflite");
+    let tmp_dir = tempdir::TempDir::new("test_dataframe_agg_json_metadata").unwrap();
+    let label_path = tmp_dir.path().join("labels.txt");
+    fs::write(&label_path, "nozzle\nbed\nstringing\n").unwrap();
+
+    let mut h = gst_check::Harness::new("dataframe_agg");
+    let element = h.element().unwrap();
+    element.set_property_from_str("output-type", "json");
+    element.set_property("model-file", model_path.to_str().unwrap());
+    element.set_property("label-file", label_path.to_str().unwrap());
+    element.set_property("camera-id", "camera0");
+    h.set_src_caps_str("application/x-ndjson");
+    h.play();
+
+    let rows = [
+        r#"{"detection_boxes_x0": 0.1, "detection_boxes_y0": 0.1, "detection_boxes_x1": 0.5, "detection_boxes_y1": 0.5, "detection_classes": 2, "detection_scores": 0.9}"#,
+        r#"{"detection_boxes_x0": 0.2, "detection_boxes_y0": 0.2, "detection_boxes_x1": 0.6, "detection_boxes_y1": 0.6, "detection_classes": 1, "detection_scores": 0.8}"#,
+    ];
+    let buffer = gst::Buffer::from_slice(rows.join("\n").into_bytes());
+    assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+
+    // aggregation columns are named by label-file labels
+    let buffer = h.pull().unwrap();
+    let map = buffer.map_readable().unwrap();
+    let rows: serde_json::Value = serde_json::from_slice(map.as_slice()).unwrap();
+    let row = &rows.as_array().unwrap()[0];
+    assert!(row.get("stringing__count").is_some());
+    assert!(row.get("bed__mean").is_some());
+    assert!(row.get("spaghetti__count").is_none());
+
+    // json has no schema, so metadata is carried by caps
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    assert_eq!(caps.structure(0).unwrap().name(), "application/json");
+    let metadata = gstprintnanny::caps::metadata_from_caps(&caps);
+    assert_eq!(metadata["printnanny:camera_id"], "camera0");
+    assert_eq!(metadata["printnanny:labels"], "nozzle,bed,stringing");
+    assert_eq!(metadata["printnanny:model_sha256"].len(), 64);
+}
+
+#[test]
+fn test_dataframe_agg_ndjson() {
+    init();
+
+    let expected_buffers = 16;
+
+    let mut h = dataframe_agg_harness(
+        expected_buffers,
+        "output-type=ndjson json-timestamp-format=iso8601",
+    );
+    h.play();
+
+    let mut num_buffers = 0;
+    while let Some(buffer) = h.pull_until_eos().unwrap() {
+        let map = buffer.map_readable().unwrap();
+        for line in std::str::from_utf8(map.as_slice()).unwrap().lines() {
+            let row: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert!(row["rt"].as_str().unwrap().starts_with("PT"));
+        }
+        num_buffers += 1;
+    }
+    assert_eq!(num_buffers, expected_buffers);
+
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    assert_eq!(caps.structure(0).unwrap().name(), "application/x-ndjson");
+}
+
+#[test]
+fn test_dataframe_agg_json_input() {
+    init();
+
+    let mut h = gst_check::Harness::new("dataframe_agg");
+    h.set_src_caps_str("application/x-ndjson");
+    h.play();
+
+    // JSON numbers are decoded as f64/i64 columns
+    let rows = [
+        r#"{"detection_boxes_x0": 0.1, "detection_boxes_y0": 0.1, "detection_boxes_x1": 0.5, "detection_boxes_y1": 0.5, "detection_classes": 2, "detection_scores": 0.9}"#,
+        r#"{"detection_boxes_x0": 0.2, "detection_boxes_y0": 0.2, "detection_boxes_x1": 0.6, "detection_boxes_y1": 0.6, "detection_classes": 1, "detection_scores": 0.8}"#,
+    ];
+    let buffer = gst::Buffer::from_slice(rows.join("\n").into_bytes());
+    assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+
+    let buffer = h.pull().unwrap();
+    let cursor = buffer.as_cursor_readable();
+    let df = IpcStreamReader::new(cursor)
+        .finish()
+        .expect("Failed to extract dataframe");
+    let count = |label: &str| -> u32 {
+        df.column(&format!("{}__count", label))
+            .unwrap()
+            .cast(&DataType::UInt32)
+            .unwrap()
+            .u32()
+            .unwrap()
+            .into_iter()
+            .map(|count| count.unwrap_or_default())
+            .sum()
+    };
+    assert!(count("spaghetti") > 0);
+    assert!(count("adhesion") > 0);
+
+    // dataframes which can't be cast to the detection schema are an error, not a panic
+    let buffer = gst::Buffer::from_slice(br#"{"detection_scores": "high"}"#.to_vec());
+    assert_eq!(h.push(buffer), Err(gst::FlowError::Error));
+}
+
+#[test]
+fn test_dataframe_agg_negotiate_output_type() {
+    init();
+
+    let expected_buffers = 16;
+
+    // output-type is not set, and downstream only accepts json
+    let mut h = dataframe_agg_harness(expected_buffers, "! capsfilter caps=application/json");
+    h.play();
+
+    let mut num_buffers = 0;
+    while let Some(buffer) = h.pull_until_eos().unwrap() {
+        let map = buffer.map_readable().unwrap();
+        let rows: serde_json::Value = serde_json::from_slice(map.as_slice()).unwrap();
+        assert!(rows.is_array());
+        num_buffers += 1;
+    }
+    assert_eq!(num_buffers, expected_buffers);
+
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    assert_eq!(caps.structure(0).unwrap().name(), "application/json");
+}
+
+#[test]
+fn test_dataframe_agg_explicit_output_type_not_negotiated() {
+    init();
+
+    // output-type is set explicitly, and downstream does not accept it
+    let mut h = gst_check::Harness::new_parse(
+        "dataframe_agg output-type=ndjson ! capsfilter caps=application/json",
+    );
+    let bus = gst::Bus::new();
+    let element = h.element().unwrap();
+    element.set_bus(Some(&bus));
+    h.play();
+
+    let caps = gst::Caps::builder("application/x-ndjson").build();
+    assert!(!h.push_event(gst::event::Caps::new(&caps)));
+
+    let msg = bus
+        .timed_pop_filtered(gst::ClockTime::from_seconds(5), &[gst::MessageType::Error])
+        .unwrap();
+    match msg.view() {
+        MessageView::Error(err) => assert!(err.error().matches(gst::CoreError::Negotiation)),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_dataframe_agg_continuous() {
+    init();
+
+    let expected_buffers = 16;
+
+    let mut h = dataframe_agg_harness(expected_buffers, "ipc-stream-mode=continuous");
+    h.play();
+
+    // concatenated buffers are a single arrow ipc stream, starting with one schema message
+    let mut stream: Vec<u8> = vec![];
+    let mut num_headers = 0;
+    let mut num_buffers = 0;
+    while let Some(buffer) = h.pull_until_eos().unwrap() {
+        if buffer.flags().contains(gst::BufferFlags::HEADER) {
+            num_headers += 1;
+        } else {
+            num_buffers += 1;
+        }
+        stream.extend(buffer.map_readable().unwrap().as_slice());
+    }
+    assert_eq!(num_headers, 1);
+    assert_eq!(num_buffers, expected_buffers);
+
+    let df = IpcStreamReader::new(std::io::Cursor::new(stream))
+        .finish()
+        .unwrap();
+    assert!(df.height() > 0);
+
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    let s = caps.structure(0).unwrap();
+    assert_eq!(s.name(), "application/vnd.apache.arrow.stream");
+    assert!(s.has_field("streamheader"));
+}
+
 // requires websocket-tcp-server bin to be running, ignore in CI but keep as development helper
 #[ignore]
 #[test]